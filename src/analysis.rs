@@ -0,0 +1,140 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Error, ErrorKind, Read};
+
+use crate::de::{Archive, Kind};
+
+/// Symlink chains longer than this fail to resolve on Linux with `ELOOP`.
+pub const MAX_SYMLINK_HOPS: usize = 40;
+
+/// How every symlink of an archive resolves within it, as computed by [`max_symlink_chain`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SymlinkChains {
+    chains: Vec<(String, usize)>,
+    loops: Vec<String>,
+}
+
+impl SymlinkChains {
+    /// The symlinks that resolve, with how many symlinks are followed to do so counting
+    /// themselves, in archive order. Resolution stops at targets that are absolute, missing or
+    /// outside of the archive.
+    #[inline]
+    pub fn chains(&self) -> &[(String, usize)] {
+        &self.chains
+    }
+
+    /// The symlinks that never resolve, either because they form a cycle or because resolving
+    /// them takes more than [`MAX_SYMLINK_HOPS`] hops.
+    #[inline]
+    pub fn loops(&self) -> &[String] {
+        &self.loops
+    }
+
+    /// The symlink with the longest chain, the first one in archive order on ties.
+    pub fn longest(&self) -> Option<(&str, usize)> {
+        self.chains
+            .iter()
+            .rev()
+            .max_by_key(|(_, len)| *len)
+            .map(|(path, len)| (path.as_str(), *len))
+    }
+
+    /// Fails on the first loop, or on the first chain longer than `limit`.
+    pub fn check(&self, limit: usize) -> io::Result<()> {
+        if let Some(path) = self.loops.first() {
+            let message = format!("Symlink {:?} never resolves", path);
+            return Err(Error::new(ErrorKind::InvalidData, message));
+        }
+        if let Some((path, len)) = self.chains.iter().find(|(_, len)| *len > limit) {
+            let message = format!(
+                "Symlink {:?} resolves through {} symlinks, more than the limit of {}",
+                path, len, limit
+            );
+            return Err(Error::new(ErrorKind::InvalidData, message));
+        }
+        Ok(())
+    }
+}
+
+enum Node {
+    Other,
+    Directory,
+    Symlink(String),
+}
+
+/// Resolves every symlink in the archive read from `reader` against the other entries of the
+/// archive, without touching the disk.
+pub fn max_symlink_chain<R: Read>(reader: R) -> io::Result<SymlinkChains> {
+    let mut archive = Archive::new(reader);
+    let mut nodes = HashMap::new();
+    let mut symlinks = Vec::new();
+
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let path = entry.nar_path();
+        let node = match entry.kind() {
+            Kind::Dir => Node::Directory,
+            Kind::Symlink { target } => {
+                symlinks.push(path.clone());
+                Node::Symlink(target.to_utf8()?.to_owned())
+            }
+            Kind::File { .. } | Kind::Unknown { .. } => Node::Other,
+        };
+        nodes.insert(path, node);
+    }
+
+    let mut result = SymlinkChains::default();
+    for path in symlinks {
+        match chain_len(&nodes, &path) {
+            Some(len) => result.chains.push((path, len)),
+            None => result.loops.push(path),
+        }
+    }
+    Ok(result)
+}
+
+/// Counts the symlinks followed while resolving `path` the way the kernel would, or returns
+/// `None` if it never resolves.
+fn chain_len(nodes: &HashMap<String, Node>, path: &str) -> Option<usize> {
+    let mut pending: Vec<&str> = split(path).rev().collect();
+    let mut resolved: Vec<&str> = Vec::new();
+    let mut seen = HashSet::new();
+    let mut hops = 0;
+
+    while let Some(name) = pending.pop() {
+        match name {
+            "." => continue,
+            ".." => match resolved.pop() {
+                Some(_) => continue,
+                None => break,
+            },
+            _ => {}
+        }
+
+        let candidate = resolved
+            .iter()
+            .chain(Some(&name))
+            .copied()
+            .collect::<Vec<_>>()
+            .join("/");
+        match nodes.get(&candidate) {
+            Some(Node::Directory) => resolved.push(name),
+            Some(Node::Symlink(target)) => {
+                hops += 1;
+                if hops > MAX_SYMLINK_HOPS || !seen.insert((candidate, pending.clone())) {
+                    return None;
+                }
+                if target.starts_with('/') {
+                    break;
+                }
+                pending.extend(split(target).rev());
+            }
+            Some(Node::Other) | None => break,
+        }
+    }
+
+    Some(hops)
+}
+
+fn split(path: &str) -> impl DoubleEndedIterator<Item = &str> {
+    path.split('/').filter(|name| !name.is_empty())
+}
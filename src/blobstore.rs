@@ -0,0 +1,154 @@
+use std::fs::{self, File};
+use std::io::{self, Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::hash::{hash_flat_reader, NarHasher, Sha256Hash};
+use crate::temp::{InDirectory, TempProvider};
+
+pub use self::gc::GcReport;
+
+mod gc;
+
+const BLOBS_DIR: &str = "sha256";
+const TEMP_DIR: &str = "tmp";
+
+/// An on-disk content-addressed store of blobs keyed by their SHA-256 hash.
+#[derive(Clone, Debug)]
+pub struct BlobStore {
+    root: PathBuf,
+    temp: InDirectory,
+}
+
+impl BlobStore {
+    pub fn open<P: AsRef<Path>>(root: P) -> io::Result<Self> {
+        let root = root.as_ref().to_owned();
+        fs::create_dir_all(root.join(BLOBS_DIR))?;
+        fs::create_dir_all(root.join(TEMP_DIR))?;
+        fs::create_dir_all(root.join(gc::ROOTS_DIR))?;
+        let temp = InDirectory(root.join(TEMP_DIR));
+        Ok(BlobStore { root, temp })
+    }
+
+    #[inline]
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn blob_path(&self, hash: &Sha256Hash) -> PathBuf {
+        let hex = hash.to_hex();
+        self.root.join(BLOBS_DIR).join(&hex[..2]).join(hex)
+    }
+
+    pub fn contains(&self, hash: &Sha256Hash) -> bool {
+        self.blob_path(hash).is_file()
+    }
+
+    pub fn put(&self, data: &[u8]) -> io::Result<Sha256Hash> {
+        self.put_reader(data).map(|(hash, _)| hash)
+    }
+
+    /// Streams `reader` into the store, returning the hash and length of the blob. Blobs are
+    /// written to a temporary file and renamed into place, so readers never see partial data.
+    pub fn put_reader<R: Read>(&self, mut reader: R) -> io::Result<(Sha256Hash, u64)> {
+        self.put_with(|writer| io::copy(&mut reader, writer).map(|_| ()))
+    }
+
+    pub fn put_path<P: AsRef<Path>>(&self, path: P) -> io::Result<(Sha256Hash, u64)> {
+        self.put_with(|writer| crate::to_writer(writer, path))
+    }
+
+    fn put_with<F>(&self, write: F) -> io::Result<(Sha256Hash, u64)>
+    where
+        F: FnOnce(&mut HashingWriter<File>) -> io::Result<()>,
+    {
+        let (temp_path, file) = self.temp.create_temp_file(&self.root)?;
+        let mut writer = HashingWriter {
+            inner: file,
+            hasher: NarHasher::new(),
+        };
+
+        let result = write(&mut writer)
+            .and_then(|_| writer.inner.sync_all())
+            .and_then(|_| {
+                let (hash, len) = writer.hasher.finish();
+                let dst = self.blob_path(&hash);
+                if dst.is_file() {
+                    fs::remove_file(&temp_path)?;
+                } else {
+                    fs::create_dir_all(dst.parent().expect("blob paths have a parent"))?;
+                    fs::rename(&temp_path, &dst)?;
+                }
+                Ok((hash, len))
+            });
+
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result
+    }
+
+    pub fn open_blob(&self, hash: &Sha256Hash) -> io::Result<File> {
+        File::open(self.blob_path(hash)).map_err(|err| match err.kind() {
+            ErrorKind::NotFound => {
+                Error::new(ErrorKind::NotFound, format!("Blob {} not found", hash))
+            }
+            _ => err,
+        })
+    }
+
+    /// Reads a blob into memory, checking that its contents still match its hash.
+    pub fn get(&self, hash: &Sha256Hash) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.open_blob(hash)?.read_to_end(&mut data)?;
+        if hash_flat_reader(&data[..])? != *hash {
+            return Err(corrupt(hash));
+        }
+        Ok(data)
+    }
+
+    pub fn copy_to<W: Write>(&self, hash: &Sha256Hash, writer: &mut W) -> io::Result<u64> {
+        let mut writer = HashingWriter {
+            inner: writer,
+            hasher: NarHasher::new(),
+        };
+        io::copy(&mut self.open_blob(hash)?, &mut writer)?;
+
+        let (actual, len) = writer.hasher.finish();
+        if actual != *hash {
+            return Err(corrupt(hash));
+        }
+        Ok(len)
+    }
+
+    pub fn verify(&self, hash: &Sha256Hash) -> io::Result<()> {
+        if hash_flat_reader(self.open_blob(hash)?)? != *hash {
+            return Err(corrupt(hash));
+        }
+        Ok(())
+    }
+
+    pub fn remove(&self, hash: &Sha256Hash) -> io::Result<()> {
+        fs::remove_file(self.blob_path(hash))
+    }
+}
+
+struct HashingWriter<W> {
+    inner: W,
+    hasher: NarHasher,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn corrupt(hash: &Sha256Hash) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("Blob {} is corrupt", hash))
+}
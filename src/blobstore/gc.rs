@@ -0,0 +1,119 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Error, ErrorKind, Write};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use super::BlobStore;
+use crate::hash::Sha256Hash;
+use crate::temp::TempProvider;
+
+pub(super) const ROOTS_DIR: &str = "roots";
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct GcReport {
+    pub live: u64,
+    pub removed: Vec<Sha256Hash>,
+    pub freed_bytes: u64,
+}
+
+impl BlobStore {
+    /// Pins `hashes` under the root `name`, replacing any previous contents of that root. Blobs
+    /// reachable from a root survive garbage collection.
+    pub fn pin_root<I>(&self, name: &str, hashes: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = Sha256Hash>,
+    {
+        let path = self.root_path(name)?;
+        let (temp_path, mut file) = self.temp.create_temp_file(&path)?;
+        let result = (|| {
+            for hash in hashes {
+                writeln!(file, "{}", hash)?;
+            }
+            file.sync_all()?;
+            fs::rename(&temp_path, &path)
+        })();
+
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result
+    }
+
+    pub fn unpin_root(&self, name: &str) -> io::Result<()> {
+        fs::remove_file(self.root_path(name)?)
+    }
+
+    pub fn roots(&self) -> io::Result<Vec<String>> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(self.root.join(ROOTS_DIR))? {
+            names.push(entry?.file_name().to_string_lossy().into_owned());
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    pub fn root_hashes(&self, name: &str) -> io::Result<Vec<Sha256Hash>> {
+        fs::read_to_string(self.root_path(name)?)?
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(str::parse)
+            .collect()
+    }
+
+    pub fn list(&self) -> io::Result<Vec<Sha256Hash>> {
+        let mut hashes = Vec::new();
+        for shard in fs::read_dir(self.root.join(super::BLOBS_DIR))? {
+            for blob in fs::read_dir(shard?.path())? {
+                let name = blob?.file_name();
+                let name = name.to_string_lossy();
+                if let Ok(hash) = format!("sha256:{}", name).parse() {
+                    hashes.push(hash);
+                }
+            }
+        }
+        Ok(hashes)
+    }
+
+    /// Removes every blob not reachable from a pinned root. Blobs modified less than `grace` ago
+    /// are kept, so that a writer racing with the collector can pin what it just stored.
+    pub fn collect_garbage(&self, grace: Duration) -> io::Result<GcReport> {
+        let mut marked = HashSet::new();
+        for name in self.roots()? {
+            marked.extend(self.root_hashes(&name)?);
+        }
+
+        let cutoff = SystemTime::now()
+            .checked_sub(grace)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+
+        let mut report = GcReport::default();
+        for hash in self.list()? {
+            let path = self.blob_path(&hash);
+            let metadata = fs::metadata(&path)?;
+            if marked.contains(&hash) || metadata.modified()? > cutoff {
+                report.live += 1;
+                continue;
+            }
+
+            match fs::remove_file(&path) {
+                Ok(()) => {
+                    report.freed_bytes += metadata.len();
+                    report.removed.push(hash);
+                }
+                Err(ref e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(report)
+    }
+
+    fn root_path(&self, name: &str) -> io::Result<PathBuf> {
+        if name.is_empty() || name.starts_with('.') || name.contains('/') || name.contains('\0') {
+            let message = format!("Invalid root name {:?}", name);
+            return Err(Error::new(ErrorKind::InvalidInput, message));
+        }
+        Ok(self.root.join(ROOTS_DIR).join(name))
+    }
+}
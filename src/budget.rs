@@ -0,0 +1,141 @@
+use std::fmt::{self, Debug, Formatter};
+use std::io::{self, Error, ErrorKind};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+/// A shared cap on the memory held by bounded buffers across the crate, such as parser scratch
+/// space, file contents held by archive entries and chunks queued in a pipeline.
+///
+/// Cloning a budget yields another handle to the same pool, so one budget can be handed to
+/// every archive and pipeline serving a request. The default budget is unlimited but still
+/// tracks usage.
+#[derive(Clone, Default)]
+pub struct MemoryBudget {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    limit: Option<u64>,
+    used: Mutex<u64>,
+    released: Condvar,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: u64) -> Self {
+        MemoryBudget {
+            inner: Arc::new(Inner {
+                limit: Some(limit),
+                ..Inner::default()
+            }),
+        }
+    }
+
+    pub fn unlimited() -> Self {
+        MemoryBudget::default()
+    }
+
+    #[inline]
+    pub fn limit(&self) -> Option<u64> {
+        self.inner.limit
+    }
+
+    /// Bytes currently reserved through any handle to this budget.
+    pub fn used(&self) -> u64 {
+        *self.lock()
+    }
+
+    /// Reserves `len` bytes, failing with [`ErrorKind::OutOfMemory`] if that would exceed the
+    /// limit. The bytes are released when the reservation is dropped.
+    pub fn try_reserve(&self, len: u64) -> io::Result<Reservation> {
+        let mut used = self.lock();
+        if !self.fits(*used, len) {
+            return Err(self.exceeded(*used, len));
+        }
+        *used += len;
+        Ok(self.reservation(len))
+    }
+
+    /// Reserves `len` bytes, waiting for other reservations to be released if necessary. Fails
+    /// straight away if `len` alone exceeds the limit.
+    pub fn reserve(&self, len: u64) -> io::Result<Reservation> {
+        if !self.fits(0, len) {
+            return Err(self.exceeded(0, len));
+        }
+
+        let mut used = self.lock();
+        while !self.fits(*used, len) {
+            used = self
+                .inner
+                .released
+                .wait(used)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        *used += len;
+        Ok(self.reservation(len))
+    }
+
+    fn fits(&self, used: u64, len: u64) -> bool {
+        match self.inner.limit {
+            Some(limit) => matches!(used.checked_add(len), Some(total) if total <= limit),
+            None => true,
+        }
+    }
+
+    fn exceeded(&self, used: u64, len: u64) -> Error {
+        let message = format!(
+            "Memory budget of {} bytes exceeded: requested {} with {} in use",
+            self.inner.limit.unwrap_or(u64::MAX),
+            len,
+            used
+        );
+        Error::new(ErrorKind::OutOfMemory, message)
+    }
+
+    fn reservation(&self, len: u64) -> Reservation {
+        Reservation {
+            budget: self.clone(),
+            len,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, u64> {
+        self.inner.used.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Debug for MemoryBudget {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        fmt.debug_struct(stringify!(MemoryBudget))
+            .field("limit", &self.inner.limit)
+            .field("used", &self.used())
+            .finish()
+    }
+}
+
+/// Bytes drawn from a [`MemoryBudget`], returned to it on drop.
+#[derive(Debug)]
+pub struct Reservation {
+    budget: MemoryBudget,
+    len: u64,
+}
+
+impl Reservation {
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            *self.budget.lock() -= self.len;
+            self.budget.inner.released.notify_all();
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Error, ErrorKind};
+use std::str::FromStr;
+
+use crate::store_path::StoreDir;
+
+pub const CACHE_INFO_FILE: &str = "nix-cache-info";
+
+/// The contents of a binary cache's `nix-cache-info` file.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CacheInfo {
+    pub store_dir: StoreDir,
+    pub want_mass_query: bool,
+    pub priority: Option<u32>,
+}
+
+impl Display for CacheInfo {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "StoreDir: {}", self.store_dir)?;
+        writeln!(f, "WantMassQuery: {}", u8::from(self.want_mass_query))?;
+        if let Some(priority) = self.priority {
+            writeln!(f, "Priority: {}", priority)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses `nix-cache-info`. Unknown keys are ignored, as Nix does.
+impl FromStr for CacheInfo {
+    type Err = Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        let mut info = CacheInfo::default();
+
+        for line in s.lines().filter(|line| !line.trim().is_empty()) {
+            let (key, value) = match line.find(':') {
+                Some(i) => (&line[..i], line[i + 1..].trim()),
+                None => {
+                    let message = format!("Invalid nix-cache-info line {:?}", line);
+                    return Err(Error::new(ErrorKind::InvalidData, message));
+                }
+            };
+
+            let invalid = || {
+                let message = format!("Invalid value {:?} for nix-cache-info key {}", value, key);
+                Error::new(ErrorKind::InvalidData, message)
+            };
+
+            match key {
+                "StoreDir" => info.store_dir = value.parse().map_err(|_| invalid())?,
+                "WantMassQuery" => {
+                    info.want_mass_query = match value {
+                        "1" => true,
+                        "0" => false,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "Priority" => info.priority = Some(value.parse().map_err(|_| invalid())?),
+                _ => {}
+            }
+        }
+
+        Ok(info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_cache_info() {
+        let text = "StoreDir: /nix/store\nWantMassQuery: 1\nPriority: 40\n";
+        let info: CacheInfo = text.parse().unwrap();
+        assert_eq!(
+            info,
+            CacheInfo {
+                store_dir: StoreDir::default(),
+                want_mass_query: true,
+                priority: Some(40),
+            }
+        );
+        assert_eq!(info.to_string(), text);
+    }
+
+    #[test]
+    fn ignores_unknown_keys_and_rejects_bad_values() {
+        let info: CacheInfo = "StoreDir: /gnu/store\nFoo: bar\n".parse().unwrap();
+        assert_eq!(info.store_dir.as_str(), "/gnu/store");
+        assert!(!info.want_mass_query);
+        assert_eq!(info.priority, None);
+
+        assert!("WantMassQuery: yes".parse::<CacheInfo>().is_err());
+        assert!("Priority: high".parse::<CacheInfo>().is_err());
+        assert!("garbage".parse::<CacheInfo>().is_err());
+    }
+}
@@ -0,0 +1,168 @@
+#[cfg(any(feature = "fs", feature = "json"))]
+use std::io::{self, Error, ErrorKind};
+#[cfg(feature = "fs")]
+use std::io::{Read, Write};
+
+#[cfg(feature = "json")]
+use serde::{Deserialize, Serialize};
+
+pub use self::aligned::EntryAlignedChunker;
+pub use self::cdc::{Chunker, ChunkerParams};
+pub use self::pipeline::{ChunkPipeline, ChunkSink, ProcessedChunk};
+
+#[cfg(feature = "fs")]
+use crate::blobstore::BlobStore;
+#[cfg(feature = "fs")]
+use crate::hash::NarHasher;
+use crate::hash::Sha256Hash;
+
+mod aligned;
+mod cdc;
+mod pipeline;
+
+const MANIFEST_VERSION: u32 = 1;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "json", derive(Deserialize, Serialize))]
+pub struct ChunkRef {
+    pub hash: Sha256Hash,
+    pub size: u64,
+}
+
+/// Describes how to rebuild a NAR from chunks held in a [`BlobStore`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "json", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "json", serde(rename_all = "camelCase"))]
+pub struct Manifest {
+    version: u32,
+    nar_hash: Sha256Hash,
+    nar_size: u64,
+    chunks: Vec<ChunkRef>,
+}
+
+impl Manifest {
+    pub fn new(nar_hash: Sha256Hash, nar_size: u64, chunks: Vec<ChunkRef>) -> Self {
+        Manifest {
+            version: MANIFEST_VERSION,
+            nar_hash,
+            nar_size,
+            chunks,
+        }
+    }
+
+    #[inline]
+    pub fn nar_hash(&self) -> &Sha256Hash {
+        &self.nar_hash
+    }
+
+    #[inline]
+    pub fn nar_size(&self) -> u64 {
+        self.nar_size
+    }
+
+    #[inline]
+    pub fn chunks(&self) -> &[ChunkRef] {
+        &self.chunks
+    }
+
+    pub fn chunk_hashes(&self) -> impl Iterator<Item = Sha256Hash> + '_ {
+        self.chunks.iter().map(|chunk| chunk.hash)
+    }
+
+    #[cfg(feature = "json")]
+    pub fn from_json(json: &str) -> io::Result<Self> {
+        let manifest: Manifest =
+            serde_json::from_str(json).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        if manifest.version != MANIFEST_VERSION {
+            let message = format!("Unsupported manifest version {}", manifest.version);
+            return Err(Error::new(ErrorKind::InvalidData, message));
+        }
+        Ok(manifest)
+    }
+
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("manifest is always representable as JSON")
+    }
+}
+
+/// Splits the NAR read from `reader` into content-defined chunks, storing each one in `store`.
+#[cfg(feature = "fs")]
+pub fn chunk_and_store<R: Read>(
+    reader: R,
+    store: &BlobStore,
+    params: ChunkerParams,
+) -> io::Result<Manifest> {
+    store_chunks(Chunker::new(reader, params), store)
+}
+
+/// Like [`chunk_and_store`], but packs runs of small entries into shared chunks cut at entry
+/// boundaries, which dedups better for store paths made up of many tiny files. The manifest is
+/// reassembled the same way.
+#[cfg(feature = "fs")]
+pub fn chunk_and_store_aligned<R: Read>(
+    reader: R,
+    store: &BlobStore,
+    params: ChunkerParams,
+) -> io::Result<Manifest> {
+    store_chunks(EntryAlignedChunker::new(reader, params), store)
+}
+
+#[cfg(feature = "fs")]
+fn store_chunks<I>(chunker: I, store: &BlobStore) -> io::Result<Manifest>
+where
+    I: Iterator<Item = io::Result<Vec<u8>>>,
+{
+    let mut hasher = NarHasher::new();
+    let mut chunks = Vec::new();
+
+    for chunk in chunker {
+        let chunk = chunk?;
+        hasher.update(&chunk);
+        let hash = store.put(&chunk)?;
+        chunks.push(ChunkRef {
+            hash,
+            size: chunk.len() as u64,
+        });
+    }
+
+    let (nar_hash, nar_size) = hasher.finish();
+    Ok(Manifest::new(nar_hash, nar_size, chunks))
+}
+
+/// Writes the NAR described by `manifest` to `writer`, checking every chunk as well as the final
+/// NAR hash and size.
+#[cfg(feature = "fs")]
+pub fn reassemble<W: Write>(
+    manifest: &Manifest,
+    store: &BlobStore,
+    writer: &mut W,
+) -> io::Result<()> {
+    let mut hasher = NarHasher::new();
+
+    for chunk in &manifest.chunks {
+        let data = store.get(&chunk.hash)?;
+        if data.len() as u64 != chunk.size {
+            let message = format!(
+                "Chunk {} has size {}, expected {}",
+                chunk.hash,
+                data.len(),
+                chunk.size
+            );
+            return Err(Error::new(ErrorKind::InvalidData, message));
+        }
+        hasher.update(&data);
+        writer.write_all(&data)?;
+    }
+
+    let (nar_hash, nar_size) = hasher.finish();
+    if nar_hash != manifest.nar_hash || nar_size != manifest.nar_size {
+        let message = format!(
+            "Reassembled NAR has hash {} and size {}, expected {} and {}",
+            nar_hash, nar_size, manifest.nar_hash, manifest.nar_size
+        );
+        return Err(Error::new(ErrorKind::InvalidData, message));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,118 @@
+use std::collections::VecDeque;
+use std::io::{self, Read};
+
+use super::ChunkerParams;
+use crate::wire;
+
+/// Tags longer than this are never structural, so they are streamed instead of buffered.
+const MAX_TAG_LEN: u64 = 1024;
+const COPY_BUFFER_LEN: usize = 64 * 1024;
+
+/// Splits a NAR into chunks whose boundaries fall between directory entries wherever possible.
+///
+/// Consecutive small entries are grouped into one chunk until it reaches the average chunk size,
+/// while entries larger than the maximum chunk size are cut by content-defined chunking. Chunks
+/// are still contiguous ranges of the original NAR, so concatenating them restores it exactly.
+#[derive(Debug)]
+pub struct EntryAlignedChunker<R> {
+    reader: R,
+    params: ChunkerParams,
+    pending: Vec<u8>,
+    ready: VecDeque<Vec<u8>>,
+    payload_next: bool,
+    eof: bool,
+}
+
+impl<R: Read> EntryAlignedChunker<R> {
+    pub fn new(reader: R, params: ChunkerParams) -> Self {
+        EntryAlignedChunker {
+            reader,
+            params,
+            pending: Vec::new(),
+            ready: VecDeque::new(),
+            payload_next: false,
+            eof: false,
+        }
+    }
+
+    pub fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            if let Some(chunk) = self.ready.pop_front() {
+                return Ok(Some(chunk));
+            }
+            if self.eof {
+                return Ok(Some(std::mem::take(&mut self.pending)).filter(|c| !c.is_empty()));
+            }
+            self.read_token()?;
+        }
+    }
+
+    fn read_token(&mut self) -> io::Result<()> {
+        let len = match wire::read_len_prefix(&mut self.reader)? {
+            Some(len) => len,
+            None => {
+                self.eof = true;
+                return Ok(());
+            }
+        };
+
+        let is_payload = std::mem::take(&mut self.payload_next);
+        if is_payload || len > MAX_TAG_LEN {
+            self.push(&len.to_le_bytes());
+            return self.copy_token_body(len);
+        }
+
+        let mut token = vec![0; wire::untrusted_encoded_len(len)? as usize];
+        token[..8].copy_from_slice(&len.to_le_bytes());
+        self.reader.read_exact(&mut token[8..])?;
+
+        let tag = &token[8..8 + len as usize];
+        if tag == b"entry" && self.pending.len() >= self.params.avg_size() {
+            self.ready.push_back(std::mem::take(&mut self.pending));
+        }
+        self.payload_next = matches!(tag, b"contents" | b"target" | b"name");
+        self.push(&token);
+        Ok(())
+    }
+
+    fn copy_token_body(&mut self, len: u64) -> io::Result<()> {
+        let mut remaining = wire::untrusted_encoded_len(len)? - 8;
+        let mut buffer = vec![0; COPY_BUFFER_LEN];
+        while remaining > 0 {
+            let n = remaining.min(COPY_BUFFER_LEN as u64) as usize;
+            self.reader.read_exact(&mut buffer[..n])?;
+            self.push(&buffer[..n]);
+            remaining -= n as u64;
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.pending.extend_from_slice(bytes);
+        while self.pending.len() >= self.params.max_size() {
+            let len = self
+                .params
+                .cut(&self.pending, false)
+                .unwrap_or_else(|| self.params.max_size());
+            let rest = self.pending.split_off(len);
+            self.ready
+                .push_back(std::mem::replace(&mut self.pending, rest));
+        }
+    }
+}
+
+impl<R: Read> Iterator for EntryAlignedChunker<R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_chunk() {
+            Ok(chunk) => chunk.map(Ok),
+            Err(err) => {
+                self.eof = true;
+                self.ready.clear();
+                self.pending.clear();
+                Some(Err(err))
+            }
+        }
+    }
+}
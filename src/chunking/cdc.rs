@@ -0,0 +1,221 @@
+use std::io::{self, ErrorKind, Read};
+
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state = 0x9e37_79b9_7f4a_7c15u64;
+    let mut i = 0;
+    while i < 256 {
+        // splitmix64
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ChunkerParams {
+    min_size: usize,
+    avg_size: usize,
+    max_size: usize,
+}
+
+impl ChunkerParams {
+    /// Creates parameters for content-defined chunking. `avg_size` is rounded down to a power of
+    /// two, and the sizes are clamped so that `min_size <= avg_size <= max_size`.
+    pub fn new(min_size: usize, avg_size: usize, max_size: usize) -> Self {
+        let avg_size = match avg_size.max(64) {
+            avg if avg.is_power_of_two() => avg,
+            avg => avg.next_power_of_two() >> 1,
+        };
+        ChunkerParams {
+            min_size: min_size.min(avg_size),
+            avg_size,
+            max_size: max_size.max(avg_size),
+        }
+    }
+
+    #[inline]
+    pub fn min_size(&self) -> usize {
+        self.min_size
+    }
+
+    #[inline]
+    pub fn avg_size(&self) -> usize {
+        self.avg_size
+    }
+
+    #[inline]
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Returns the length of the first chunk in `data`, which holds at most `max_size` bytes.
+    /// If `eof` is false and no boundary is found, `None` is returned to request more input.
+    pub(crate) fn cut(&self, data: &[u8], eof: bool) -> Option<usize> {
+        if data.len() <= self.min_size {
+            return if eof && !data.is_empty() {
+                Some(data.len())
+            } else {
+                None
+            };
+        }
+
+        // Normalized chunking: a stricter mask before the average size and a looser one after
+        // it keeps chunk sizes concentrated around the average.
+        let bits = self.avg_size.trailing_zeros();
+        let mask_strict = (1u64 << (bits + 1)) - 1;
+        let mask_loose = (1u64 << bits.saturating_sub(1)) - 1;
+
+        let end = data.len().min(self.max_size);
+        let mut hash = 0u64;
+        for (i, &byte) in data.iter().enumerate().take(end).skip(self.min_size) {
+            hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+            let mask = if i < self.avg_size {
+                mask_strict
+            } else {
+                mask_loose
+            };
+            if hash & mask == 0 {
+                return Some(i + 1);
+            }
+        }
+
+        if end == self.max_size || eof {
+            Some(end)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for ChunkerParams {
+    fn default() -> Self {
+        ChunkerParams::new(16 * 1024, 64 * 1024, 256 * 1024)
+    }
+}
+
+#[derive(Debug)]
+pub struct Chunker<R> {
+    reader: R,
+    params: ChunkerParams,
+    buffer: Vec<u8>,
+    eof: bool,
+}
+
+impl<R: Read> Chunker<R> {
+    pub fn new(reader: R, params: ChunkerParams) -> Self {
+        Chunker {
+            reader,
+            params,
+            buffer: Vec::with_capacity(params.max_size),
+            eof: false,
+        }
+    }
+
+    pub fn next_chunk(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            if let Some(len) = self.params.cut(&self.buffer, self.eof) {
+                let rest = self.buffer.split_off(len);
+                return Ok(Some(std::mem::replace(&mut self.buffer, rest)));
+            }
+            if self.eof {
+                return Ok(None);
+            }
+            self.fill()?;
+        }
+    }
+
+    fn fill(&mut self) -> io::Result<()> {
+        let start = self.buffer.len();
+        self.buffer.resize(self.params.max_size, 0);
+        let result = loop {
+            match self.reader.read(&mut self.buffer[start..]) {
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                result => break result,
+            }
+        };
+
+        match result {
+            Ok(n) => {
+                self.buffer.truncate(start + n);
+                self.eof = n == 0;
+                Ok(())
+            }
+            Err(e) => {
+                self.buffer.truncate(start);
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for Chunker<R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_chunk().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pseudo_random(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn respects_size_bounds_and_reassembles() {
+        let data = pseudo_random(1 << 20, 1);
+        let params = ChunkerParams::new(2048, 8192, 32768);
+        let chunks: Vec<_> = Chunker::new(&data[..], params)
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        assert!(chunks.len() > 1);
+        let (last, rest) = chunks.split_last().unwrap();
+        assert!(rest.iter().all(|c| c.len() > 2048 && c.len() <= 32768));
+        assert!(!last.is_empty() && last.len() <= 32768);
+        assert_eq!(chunks.concat(), data);
+    }
+
+    #[test]
+    fn boundaries_resynchronize_after_insertion() {
+        let data = pseudo_random(1 << 19, 2);
+        let mut shifted = b"inserted prefix".to_vec();
+        shifted.extend_from_slice(&data);
+
+        let params = ChunkerParams::new(1024, 4096, 16384);
+        let chunks = |bytes: &[u8]| -> Vec<Vec<u8>> {
+            Chunker::new(bytes, params).map(Result::unwrap).collect()
+        };
+
+        let original = chunks(&data);
+        let shifted = chunks(&shifted);
+        let shared = original.iter().filter(|c| shifted.contains(c)).count();
+        assert!(shared * 10 >= original.len() * 8);
+    }
+
+    #[test]
+    fn rounds_average_to_power_of_two() {
+        let params = ChunkerParams::new(100, 5000, 10);
+        assert_eq!(params.avg_size(), 4096);
+        assert_eq!(params.min_size(), 100);
+        assert_eq!(params.max_size(), 4096);
+    }
+}
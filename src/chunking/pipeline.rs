@@ -0,0 +1,267 @@
+use std::io::{self, Error, ErrorKind, Read};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use super::{ChunkRef, Chunker, ChunkerParams, Manifest};
+#[cfg(feature = "fs")]
+use crate::blobstore::BlobStore;
+use crate::budget::{MemoryBudget, Reservation};
+use crate::hash::{self, NarHasher, Sha256Hash};
+
+const CHUNKS_PER_THREAD: usize = 4;
+
+type Job = (usize, Arc<Vec<u8>>, Reservation);
+
+/// Destination of the chunks produced by a [`ChunkPipeline`]. Chunks are handed over from the
+/// worker threads in no particular order.
+pub trait ChunkSink: Sync {
+    fn put_chunk(&self, chunk: &ProcessedChunk) -> io::Result<()>;
+}
+
+#[cfg(feature = "fs")]
+impl ChunkSink for BlobStore {
+    fn put_chunk(&self, chunk: &ProcessedChunk) -> io::Result<()> {
+        self.put(chunk.data()).map(|_| ())
+    }
+}
+
+impl<F: Fn(&ProcessedChunk) -> io::Result<()> + Sync> ChunkSink for F {
+    fn put_chunk(&self, chunk: &ProcessedChunk) -> io::Result<()> {
+        self(chunk)
+    }
+}
+
+/// A chunk that has been hashed, and compressed if the pipeline was asked to.
+#[derive(Debug)]
+pub struct ProcessedChunk {
+    index: usize,
+    hash: Sha256Hash,
+    data: Arc<Vec<u8>>,
+    compressed: Option<Vec<u8>>,
+}
+
+impl ProcessedChunk {
+    /// Position of the chunk within the manifest.
+    #[inline]
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    #[inline]
+    pub fn hash(&self) -> &Sha256Hash {
+        &self.hash
+    }
+
+    /// The uncompressed bytes of the chunk, which `hash` was computed over.
+    #[inline]
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    #[inline]
+    pub fn compressed(&self) -> Option<&[u8]> {
+        self.compressed.as_deref()
+    }
+}
+
+/// Chunks a NAR on the calling thread while hashing and compressing the chunks on a pool of
+/// workers, and the whole NAR on a thread of its own.
+///
+/// Only a bounded number of chunks is held in memory at once, so a slow sink or hasher applies
+/// backpressure to the reader instead of buffering the archive.
+#[derive(Clone, Debug)]
+pub struct ChunkPipeline {
+    params: ChunkerParams,
+    threads: usize,
+    max_in_flight: usize,
+    memory_budget: MemoryBudget,
+    #[cfg(feature = "zstd")]
+    zstd_level: Option<i32>,
+}
+
+impl ChunkPipeline {
+    pub fn new(params: ChunkerParams) -> Self {
+        ChunkPipeline {
+            params,
+            threads: 0,
+            max_in_flight: 0,
+            memory_budget: MemoryBudget::default(),
+            #[cfg(feature = "zstd")]
+            zstd_level: None,
+        }
+    }
+
+    /// Sets the number of worker threads. Defaults to 0, which uses the available parallelism.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads;
+    }
+
+    /// Sets the maximum number of chunks held in memory at once. Defaults to 0, which allows a
+    /// few chunks per worker thread.
+    pub fn set_max_in_flight(&mut self, chunks: usize) {
+        self.max_in_flight = chunks;
+    }
+
+    /// Draws the chunker's buffer and every chunk in flight from `budget`. When the budget is
+    /// exhausted, reading waits for chunks to be processed.
+    pub fn set_memory_budget(&mut self, budget: MemoryBudget) {
+        self.memory_budget = budget;
+    }
+
+    /// Compresses every chunk with zstd at `level` before it is handed to the sink.
+    #[cfg(feature = "zstd")]
+    pub fn set_zstd_level(&mut self, level: Option<i32>) {
+        self.zstd_level = level;
+    }
+
+    #[inline]
+    pub fn params(&self) -> ChunkerParams {
+        self.params
+    }
+
+    /// Splits the NAR read from `reader` into content-defined chunks, passing each one to
+    /// `sink`. The manifest is identical to the one produced by [`chunk_and_store`].
+    ///
+    /// [`chunk_and_store`]: super::chunk_and_store
+    pub fn run<R, S>(&self, reader: R, sink: &S) -> io::Result<Manifest>
+    where
+        R: Read,
+        S: ChunkSink + ?Sized,
+    {
+        let threads = match self.threads {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        };
+        let max_in_flight = match self.max_in_flight {
+            0 => threads * CHUNKS_PER_THREAD,
+            n => n,
+        };
+
+        // The chunker's buffer stays reserved throughout, so the budget must also leave room for
+        // at least one chunk in flight or reading could never make progress.
+        let max_chunk_len = self.params.max_size() as u64;
+        if matches!(self.memory_budget.limit(), Some(limit) if limit / 2 < max_chunk_len) {
+            let message = "Memory budget cannot hold two chunks of the maximum size";
+            return Err(Error::new(ErrorKind::OutOfMemory, message));
+        }
+        let _buffer = self.memory_budget.try_reserve(max_chunk_len)?;
+
+        thread::scope(|scope| {
+            let (jobs, queue) = mpsc::channel::<Job>();
+            let queue = Arc::new(Mutex::new(queue));
+            let (report, results) = mpsc::channel();
+            for _ in 0..threads {
+                let queue = queue.clone();
+                let report = report.clone();
+                scope.spawn(move || self.process_chunks(&queue, &report, sink));
+            }
+            drop(report);
+
+            let (to_hasher, hasher_queue) = mpsc::sync_channel::<Arc<Vec<u8>>>(max_in_flight);
+            let hasher = scope.spawn(move || {
+                let mut hasher = NarHasher::new();
+                for chunk in hasher_queue {
+                    hasher.update(&chunk);
+                }
+                hasher.finish()
+            });
+
+            let mut chunks = Vec::new();
+            let mut in_flight = 0;
+            for chunk in Chunker::new(reader, self.params) {
+                while in_flight >= max_in_flight {
+                    collect(&results, &mut chunks)?;
+                    in_flight -= 1;
+                }
+
+                let chunk = chunk?;
+                let reservation = self.memory_budget.reserve(chunk.len() as u64)?;
+                let chunk = Arc::new(chunk);
+                let index = chunks.len();
+                chunks.push(None);
+                let stopped = || Error::other("Chunk pipeline stopped early");
+                to_hasher.send(chunk.clone()).map_err(|_| stopped())?;
+                jobs.send((index, chunk, reservation))
+                    .map_err(|_| stopped())?;
+                in_flight += 1;
+            }
+
+            drop(jobs);
+            drop(to_hasher);
+            for _ in 0..in_flight {
+                collect(&results, &mut chunks)?;
+            }
+
+            let (nar_hash, nar_size) = hasher
+                .join()
+                .map_err(|_| Error::other("NAR hasher panicked"))?;
+            let chunks = chunks
+                .into_iter()
+                .map(|c| c.expect("every chunk is collected"));
+            Ok(Manifest::new(nar_hash, nar_size, chunks.collect()))
+        })
+    }
+
+    fn process_chunks<S: ChunkSink + ?Sized>(
+        &self,
+        queue: &Mutex<Receiver<Job>>,
+        report: &Sender<(usize, io::Result<ChunkRef>)>,
+        sink: &S,
+    ) {
+        loop {
+            let (index, data, reservation) = match queue.lock().map(|queue| queue.recv()) {
+                Ok(Ok(job)) => job,
+                _ => return,
+            };
+
+            let result = self.process_chunk(index, data, sink);
+            drop(reservation);
+            if report.send((index, result)).is_err() {
+                return;
+            }
+        }
+    }
+
+    fn process_chunk<S: ChunkSink + ?Sized>(
+        &self,
+        index: usize,
+        data: Arc<Vec<u8>>,
+        sink: &S,
+    ) -> io::Result<ChunkRef> {
+        let chunk = ProcessedChunk {
+            index,
+            hash: hash::hash_flat_reader(&data[..])?,
+            compressed: self.compress(&data)?,
+            data,
+        };
+        sink.put_chunk(&chunk)?;
+        Ok(ChunkRef {
+            hash: chunk.hash,
+            size: chunk.data.len() as u64,
+        })
+    }
+
+    #[cfg(feature = "zstd")]
+    fn compress(&self, data: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        self.zstd_level
+            .map(|level| crate::compression::zstd::compress(data, level))
+            .transpose()
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    fn compress(&self, _data: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+}
+
+fn collect(
+    results: &Receiver<(usize, io::Result<ChunkRef>)>,
+    chunks: &mut [Option<ChunkRef>],
+) -> io::Result<()> {
+    let (index, result) = results
+        .recv()
+        .map_err(|_| Error::other("Chunk pipeline workers stopped early"))?;
+    chunks[index] = Some(result?);
+    Ok(())
+}
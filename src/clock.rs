@@ -0,0 +1,36 @@
+use std::fmt::Debug;
+
+use filetime::FileTime;
+
+/// Source of the timestamps written while unpacking, so that canonicalization can target an
+/// epoch other than the Unix one, e.g. `SOURCE_DATE_EPOCH`.
+pub trait Clock: Debug + Send + Sync {
+    /// The modification time given to unpacked paths when mtimes are canonicalized.
+    fn canonical_mtime(&self) -> FileTime;
+}
+
+/// Canonicalizes to the Unix epoch, as Nix does.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct UnixEpoch;
+
+impl Clock for UnixEpoch {
+    fn canonical_mtime(&self) -> FileTime {
+        FileTime::zero()
+    }
+}
+
+/// Canonicalizes to a configured point in time.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FixedEpoch(pub FileTime);
+
+impl FixedEpoch {
+    pub fn from_unix_time(seconds: i64) -> Self {
+        FixedEpoch(FileTime::from_unix_time(seconds, 0))
+    }
+}
+
+impl Clock for FixedEpoch {
+    fn canonical_mtime(&self) -> FileTime {
+        self.0
+    }
+}
@@ -0,0 +1,7 @@
+pub use self::limits::{CompressedInput, DecompressionLimits, LimitedDecoder};
+
+mod limits;
+#[cfg(feature = "xz")]
+pub mod xz;
+#[cfg(feature = "zstd")]
+pub mod zstd;
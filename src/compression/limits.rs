@@ -0,0 +1,224 @@
+use std::io::{self, Error, ErrorKind, Read};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+const DEFAULT_MAX_RATIO: u64 = 1024;
+const DEFAULT_RATIO_GRACE_LEN: u64 = 1024 * 1024;
+
+/// Bounds on how much a decoder may expand its input, guarding against decompression bombs from
+/// untrusted sources. By default output may be at most 1024 times larger than the input consumed
+/// so far, once past the first MiB, and its total size is unbounded.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DecompressionLimits {
+    max_len: Option<u64>,
+    max_ratio: Option<u64>,
+    ratio_grace_len: u64,
+}
+
+impl DecompressionLimits {
+    pub fn new() -> Self {
+        DecompressionLimits::default()
+    }
+
+    /// Caps the total number of decompressed bytes.
+    pub fn set_max_len(&mut self, len: Option<u64>) {
+        self.max_len = len;
+    }
+
+    /// Caps the ratio of decompressed to compressed bytes.
+    pub fn set_max_ratio(&mut self, ratio: Option<u64>) {
+        self.max_ratio = ratio;
+    }
+
+    /// Sets how many decompressed bytes may be produced before the ratio is enforced, since a
+    /// stream header alone can legitimately expand to far more than the ratio allows.
+    pub fn set_ratio_grace_len(&mut self, len: u64) {
+        self.ratio_grace_len = len;
+    }
+
+    #[inline]
+    pub fn max_len(&self) -> Option<u64> {
+        self.max_len
+    }
+
+    #[inline]
+    pub fn max_ratio(&self) -> Option<u64> {
+        self.max_ratio
+    }
+
+    #[inline]
+    pub fn ratio_grace_len(&self) -> u64 {
+        self.ratio_grace_len
+    }
+
+    /// Builds a decoder over `reader` with `decoder`, failing reads from it as soon as either
+    /// limit is exceeded.
+    pub fn wrap<R, D, F>(&self, reader: R, decoder: F) -> io::Result<LimitedDecoder<D>>
+    where
+        R: Read,
+        D: Read,
+        F: FnOnce(CompressedInput<R>) -> io::Result<D>,
+    {
+        let consumed = Arc::new(AtomicU64::new(0));
+        let input = CompressedInput {
+            inner: reader,
+            consumed: consumed.clone(),
+        };
+        Ok(LimitedDecoder {
+            inner: decoder(input)?,
+            consumed,
+            produced: 0,
+            limits: *self,
+        })
+    }
+}
+
+impl Default for DecompressionLimits {
+    fn default() -> Self {
+        DecompressionLimits {
+            max_len: None,
+            max_ratio: Some(DEFAULT_MAX_RATIO),
+            ratio_grace_len: DEFAULT_RATIO_GRACE_LEN,
+        }
+    }
+}
+
+/// The compressed side of a [`LimitedDecoder`], counting the bytes its decoder consumes.
+#[derive(Debug)]
+pub struct CompressedInput<R> {
+    inner: R,
+    consumed: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for CompressedInput<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.consumed.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+#[derive(Debug)]
+pub struct LimitedDecoder<D> {
+    inner: D,
+    consumed: Arc<AtomicU64>,
+    produced: u64,
+    limits: DecompressionLimits,
+}
+
+impl<D> LimitedDecoder<D> {
+    /// Returns the number of compressed bytes consumed so far.
+    pub fn compressed_len(&self) -> u64 {
+        self.consumed.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of decompressed bytes produced so far.
+    #[inline]
+    pub fn decompressed_len(&self) -> u64 {
+        self.produced
+    }
+
+    pub fn into_inner(self) -> D {
+        self.inner
+    }
+
+    fn check(&self) -> io::Result<()> {
+        if let Some(max) = self.limits.max_len.filter(|&max| self.produced > max) {
+            let message = format!("Decompressed data exceeds the limit of {} bytes", max);
+            return Err(Error::new(ErrorKind::InvalidData, message));
+        }
+
+        let consumed = self.compressed_len();
+        let over_ratio = |ratio: &u64| self.produced > consumed.saturating_mul(*ratio);
+        if self.produced > self.limits.ratio_grace_len {
+            if let Some(ratio) = self.limits.max_ratio.filter(over_ratio) {
+                let message = format!(
+                    "Decompression ratio exceeds {}:1 ({} bytes from {} compressed bytes)",
+                    ratio, self.produced, consumed
+                );
+                return Err(Error::new(ErrorKind::InvalidData, message));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<D: Read> Read for LimitedDecoder<D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.produced += n as u64;
+        self.check()?;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stands in for a real decoder: each input byte expands to `factor` zeroes.
+    struct Expand<R> {
+        inner: R,
+        factor: usize,
+        pending: usize,
+    }
+
+    impl<R: Read> Read for Expand<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.pending == 0 {
+                let mut byte = [0];
+                if self.inner.read(&mut byte)? == 0 {
+                    return Ok(0);
+                }
+                self.pending = self.factor;
+            }
+            let n = buf.len().min(self.pending);
+            buf[..n].iter_mut().for_each(|b| *b = 0);
+            self.pending -= n;
+            Ok(n)
+        }
+    }
+
+    fn decode(limits: DecompressionLimits, input: &[u8], factor: usize) -> io::Result<u64> {
+        let mut decoder = limits.wrap(input, |inner| {
+            Ok(Expand {
+                inner,
+                factor,
+                pending: 0,
+            })
+        })?;
+        io::copy(&mut decoder, &mut io::sink())?;
+        assert_eq!(decoder.compressed_len(), input.len() as u64);
+        Ok(decoder.decompressed_len())
+    }
+
+    #[test]
+    fn enforces_ratio_after_grace() {
+        let mut limits = DecompressionLimits::new();
+        limits.set_ratio_grace_len(4096);
+        limits.set_max_ratio(Some(100));
+
+        assert_eq!(decode(limits, &[0; 100], 100).unwrap(), 10_000);
+        assert_eq!(decode(limits, &[0; 2], 2000).unwrap(), 4000);
+        let err = decode(limits, &[0; 100], 101).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err
+            .to_string()
+            .starts_with("Decompression ratio exceeds 100:1"));
+    }
+
+    #[test]
+    fn enforces_absolute_cap() {
+        let mut limits = DecompressionLimits::new();
+        limits.set_max_ratio(None);
+        limits.set_max_len(Some(1000));
+
+        assert_eq!(decode(limits, &[0; 10], 100).unwrap(), 1000);
+        let err = decode(limits, &[0; 11], 100).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Decompressed data exceeds the limit of 1000 bytes"
+        );
+    }
+}
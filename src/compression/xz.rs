@@ -0,0 +1,24 @@
+use std::io::{self, Read, Write};
+
+use xz2::read::XzDecoder;
+use xz2::write::XzEncoder;
+
+use super::{CompressedInput, DecompressionLimits, LimitedDecoder};
+
+pub const DEFAULT_LEVEL: u32 = 6;
+
+pub fn encoder<W: Write>(writer: W, level: u32) -> XzEncoder<W> {
+    XzEncoder::new(writer, level)
+}
+
+pub fn decoder<R: Read>(reader: R) -> XzDecoder<R> {
+    XzDecoder::new(reader)
+}
+
+/// Like [`decoder`], but fails once the output outgrows `limits`.
+pub fn decoder_with_limits<R: Read>(
+    reader: R,
+    limits: &DecompressionLimits,
+) -> io::Result<LimitedDecoder<XzDecoder<CompressedInput<R>>>> {
+    limits.wrap(reader, |input| Ok(decoder(input)))
+}
@@ -0,0 +1,136 @@
+use std::io::{self, BufReader, Error, ErrorKind, Read, Write};
+
+use ::zstd::stream::{Decoder, Encoder};
+
+pub use self::seekable::{SeekableReader, SeekableWriter};
+
+use super::{CompressedInput, DecompressionLimits, LimitedDecoder};
+use crate::chunking::{Chunker, ChunkerParams};
+
+mod seekable;
+
+pub const DEFAULT_LEVEL: i32 = 3;
+
+const DEFAULT_MAX_SAMPLE_LEN: usize = 128 * 1024;
+
+pub fn encoder<W: Write>(writer: W, level: i32) -> io::Result<Encoder<'static, W>> {
+    Encoder::new(writer, level)
+}
+
+pub fn decoder<R: Read>(reader: R) -> io::Result<Decoder<'static, BufReader<R>>> {
+    Decoder::new(reader)
+}
+
+/// Like [`decoder`], but fails once the output outgrows `limits`.
+pub fn decoder_with_limits<R: Read>(
+    reader: R,
+    limits: &DecompressionLimits,
+) -> io::Result<LimitedDecoder<Decoder<'static, BufReader<CompressedInput<R>>>>> {
+    limits.wrap(reader, decoder)
+}
+
+pub fn compress(data: &[u8], level: i32) -> io::Result<Vec<u8>> {
+    let mut encoder = encoder(Vec::new(), level)?;
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Dictionary {
+    bytes: Vec<u8>,
+}
+
+impl Dictionary {
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Dictionary { bytes }
+    }
+
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn encoder<W: Write>(&self, writer: W, level: i32) -> io::Result<Encoder<'static, W>> {
+        Encoder::with_dictionary(writer, level, &self.bytes)
+    }
+
+    pub fn decoder<R: Read>(&self, reader: R) -> io::Result<Decoder<'static, BufReader<R>>> {
+        Decoder::with_dictionary(BufReader::new(reader), &self.bytes)
+    }
+
+    pub fn compress(&self, data: &[u8], level: i32) -> io::Result<Vec<u8>> {
+        let mut encoder = self.encoder(Vec::new(), level)?;
+        encoder.write_all(data)?;
+        encoder.finish()
+    }
+
+    pub fn decompress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decompressed = Vec::new();
+        self.decoder(data)?.read_to_end(&mut decompressed)?;
+        Ok(decompressed)
+    }
+}
+
+/// Collects samples from a corpus of NARs and trains a shared zstd dictionary from them.
+#[derive(Clone, Debug, Default)]
+pub struct DictionaryTrainer {
+    samples: Vec<Vec<u8>>,
+    max_sample_len: Option<usize>,
+}
+
+impl DictionaryTrainer {
+    pub fn new() -> Self {
+        DictionaryTrainer::default()
+    }
+
+    /// Caps the number of bytes taken from each sample (128 KiB by default).
+    pub fn set_max_sample_len(&mut self, len: usize) {
+        self.max_sample_len = Some(len);
+    }
+
+    #[inline]
+    pub fn sample_count(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn add_sample(&mut self, sample: &[u8]) {
+        let len = sample.len().min(self.max_sample_len());
+        if len > 0 {
+            self.samples.push(sample[..len].to_vec());
+        }
+    }
+
+    /// Adds the beginning of a whole NAR as a single sample.
+    pub fn add_nar<R: Read>(&mut self, reader: R) -> io::Result<()> {
+        let mut sample = Vec::new();
+        reader
+            .take(self.max_sample_len() as u64)
+            .read_to_end(&mut sample)?;
+        self.add_sample(&sample);
+        Ok(())
+    }
+
+    /// Adds every content-defined chunk of a NAR as a separate sample, matching how chunks are
+    /// compressed when stored individually.
+    pub fn add_nar_chunks<R: Read>(&mut self, reader: R, params: ChunkerParams) -> io::Result<()> {
+        for chunk in Chunker::new(reader, params) {
+            self.add_sample(&chunk?);
+        }
+        Ok(())
+    }
+
+    pub fn train(&self, max_dict_size: usize) -> io::Result<Dictionary> {
+        if self.samples.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "No samples to train on",
+            ));
+        }
+        let bytes = ::zstd::dict::from_samples(&self.samples, max_dict_size)?;
+        Ok(Dictionary::from_bytes(bytes))
+    }
+
+    fn max_sample_len(&self) -> usize {
+        self.max_sample_len.unwrap_or(DEFAULT_MAX_SAMPLE_LEN)
+    }
+}
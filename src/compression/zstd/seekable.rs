@@ -0,0 +1,256 @@
+use std::convert::TryInto;
+use std::io::{self, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::listing::{Listing, Node};
+
+const SKIPPABLE_MAGIC: u32 = 0x184d_2a5e;
+const SEEKABLE_MAGIC: u32 = 0x8f92_eab1;
+const FOOTER_LEN: usize = 9;
+const SKIPPABLE_HEADER_LEN: usize = 8;
+const DEFAULT_FRAME_LEN: usize = 256 * 1024;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Frame {
+    compressed_offset: u64,
+    compressed_len: u32,
+    decompressed_offset: u64,
+    decompressed_len: u32,
+}
+
+/// Writes data in the zstd seekable format: independent frames of bounded size followed by a
+/// seek table in a skippable frame. Any zstd decoder can still decompress the whole stream.
+#[derive(Debug)]
+pub struct SeekableWriter<W: Write> {
+    inner: W,
+    level: i32,
+    frame_len: usize,
+    buffer: Vec<u8>,
+    table: Vec<(u32, u32)>,
+}
+
+impl<W: Write> SeekableWriter<W> {
+    pub fn new(inner: W, level: i32) -> Self {
+        SeekableWriter::with_frame_len(inner, level, DEFAULT_FRAME_LEN)
+    }
+
+    /// Uses frames holding at most `frame_len` bytes of uncompressed data; smaller frames make
+    /// random access cheaper at the expense of compression ratio.
+    pub fn with_frame_len(inner: W, level: i32, frame_len: usize) -> Self {
+        let frame_len = frame_len.clamp(1, u32::MAX as usize / 2);
+        SeekableWriter {
+            inner,
+            level,
+            frame_len,
+            buffer: Vec::with_capacity(frame_len),
+            table: Vec::new(),
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_frame()?;
+
+        let entries_len = self.table.len() * 8;
+        let mut trailer = Vec::with_capacity(SKIPPABLE_HEADER_LEN + entries_len + FOOTER_LEN);
+        trailer.extend_from_slice(&SKIPPABLE_MAGIC.to_le_bytes());
+        trailer.extend_from_slice(&((entries_len + FOOTER_LEN) as u32).to_le_bytes());
+        for (compressed, decompressed) in &self.table {
+            trailer.extend_from_slice(&compressed.to_le_bytes());
+            trailer.extend_from_slice(&decompressed.to_le_bytes());
+        }
+        trailer.extend_from_slice(&(self.table.len() as u32).to_le_bytes());
+        trailer.push(0);
+        trailer.extend_from_slice(&SEEKABLE_MAGIC.to_le_bytes());
+
+        self.inner.write_all(&trailer)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn write_frame(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let compressed = ::zstd::bulk::compress(&self.buffer, self.level)?;
+        let compressed_len: u32 = compressed
+            .len()
+            .try_into()
+            .map_err(|_| Error::other("Compressed frame is too large"))?;
+        self.inner.write_all(&compressed)?;
+        self.table.push((compressed_len, self.buffer.len() as u32));
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for SeekableWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.frame_len - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+        if self.buffer.len() == self.frame_len {
+            self.write_frame()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[derive(Debug)]
+pub struct SeekableReader<R> {
+    inner: R,
+    frames: Vec<Frame>,
+    cached: Option<(usize, Vec<u8>)>,
+}
+
+impl<R: Read + Seek> SeekableReader<R> {
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let invalid = |what: &str| Error::new(ErrorKind::InvalidData, what.to_owned());
+
+        let end = inner.seek(SeekFrom::End(0))?;
+        if end < (SKIPPABLE_HEADER_LEN + FOOTER_LEN) as u64 {
+            return Err(invalid("Missing zstd seek table"));
+        }
+
+        let mut footer = [0; FOOTER_LEN];
+        inner.seek(SeekFrom::End(-(FOOTER_LEN as i64)))?;
+        inner.read_exact(&mut footer)?;
+        if u32::from_le_bytes(footer[5..9].try_into().unwrap()) != SEEKABLE_MAGIC {
+            return Err(invalid("Missing zstd seek table"));
+        }
+
+        let count = u32::from_le_bytes(footer[..4].try_into().unwrap()) as u64;
+        let entry_len = if footer[4] & 0x80 != 0 { 12 } else { 8 };
+        if footer[4] & 0x7c != 0 {
+            return Err(invalid("Unsupported zstd seek table descriptor"));
+        }
+
+        let table_len = count * entry_len;
+        let trailer_len = SKIPPABLE_HEADER_LEN as u64 + table_len + FOOTER_LEN as u64;
+        if trailer_len > end {
+            return Err(invalid("Truncated zstd seek table"));
+        }
+
+        let mut table = vec![0; table_len as usize + SKIPPABLE_HEADER_LEN];
+        inner.seek(SeekFrom::Start(end - trailer_len))?;
+        inner.read_exact(&mut table)?;
+        if u32::from_le_bytes(table[..4].try_into().unwrap()) != SKIPPABLE_MAGIC {
+            return Err(invalid("Corrupt zstd seek table"));
+        }
+
+        let mut frames = Vec::with_capacity(count as usize);
+        let (mut compressed_offset, mut decompressed_offset) = (0u64, 0u64);
+        for entry in table[SKIPPABLE_HEADER_LEN..].chunks_exact(entry_len as usize) {
+            let compressed_len = u32::from_le_bytes(entry[..4].try_into().unwrap());
+            let decompressed_len = u32::from_le_bytes(entry[4..8].try_into().unwrap());
+            frames.push(Frame {
+                compressed_offset,
+                compressed_len,
+                decompressed_offset,
+                decompressed_len,
+            });
+            compressed_offset += compressed_len as u64;
+            decompressed_offset += decompressed_len as u64;
+        }
+
+        if compressed_offset != end - trailer_len {
+            return Err(invalid("Zstd seek table does not match the frames"));
+        }
+
+        Ok(SeekableReader {
+            inner,
+            frames,
+            cached: None,
+        })
+    }
+
+    pub fn decompressed_len(&self) -> u64 {
+        self.frames
+            .last()
+            .map(|f| f.decompressed_offset + f.decompressed_len as u64)
+            .unwrap_or(0)
+    }
+
+    /// Reads `len` decompressed bytes starting at `offset`, decompressing only the frames that
+    /// overlap the range.
+    pub fn read_range(&mut self, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        let end = offset
+            .checked_add(len)
+            .filter(|&end| end <= self.decompressed_len())
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Range is past the end of the archive",
+                )
+            })?;
+
+        let first = self
+            .frames
+            .partition_point(|f| f.decompressed_offset + f.decompressed_len as u64 <= offset);
+
+        let mut data = Vec::with_capacity(len as usize);
+        let mut index = first;
+        while (data.len() as u64) < len {
+            let frame = self.frames[index];
+            let start = offset.max(frame.decompressed_offset) - frame.decompressed_offset;
+            let stop = end.min(frame.decompressed_offset + frame.decompressed_len as u64)
+                - frame.decompressed_offset;
+            let decompressed = self.frame(index)?;
+            data.extend_from_slice(&decompressed[start as usize..stop as usize]);
+            index += 1;
+        }
+
+        Ok(data)
+    }
+
+    /// Reads the contents of the regular file at `path`, using the offsets recorded in `listing`.
+    pub fn read_file<P: AsRef<Path>>(&mut self, listing: &Listing, path: P) -> io::Result<Vec<u8>> {
+        let path = path.as_ref();
+        match listing.get(path) {
+            Some(Node::Regular {
+                size,
+                nar_offset: Some(offset),
+                ..
+            }) => self.read_range(*offset, *size),
+            Some(Node::Regular { .. }) => {
+                let message = format!("Listing has no offset for {:?}", path);
+                Err(Error::new(ErrorKind::InvalidInput, message))
+            }
+            Some(_) => {
+                let message = format!("{:?} is not a regular file", path);
+                Err(Error::new(ErrorKind::InvalidInput, message))
+            }
+            None => {
+                let message = format!("{:?} not found in listing", path);
+                Err(Error::new(ErrorKind::NotFound, message))
+            }
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn frame(&mut self, index: usize) -> io::Result<&[u8]> {
+        if self.cached.as_ref().map(|(i, _)| *i) != Some(index) {
+            let frame = self.frames[index];
+            let mut compressed = vec![0; frame.compressed_len as usize];
+            self.inner.seek(SeekFrom::Start(frame.compressed_offset))?;
+            self.inner.read_exact(&mut compressed)?;
+            let decompressed =
+                ::zstd::bulk::decompress(&compressed, frame.decompressed_len as usize)?;
+            if decompressed.len() != frame.decompressed_len as usize {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Zstd frame has wrong size",
+                ));
+            }
+            self.cached = Some((index, decompressed));
+        }
+
+        Ok(&self.cached.as_ref().expect("frame was just cached").1)
+    }
+}
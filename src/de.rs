@@ -1,31 +1,101 @@
+use std::borrow::Cow;
 use std::cell::{Cell, RefCell};
-use std::fmt::{self, Debug, Formatter};
-use std::fs::{self, OpenOptions};
-use std::future::Future;
+use std::fmt::{self, Debug, Display, Formatter};
 use std::io::{self, Error, ErrorKind, Read, Write};
 use std::marker::PhantomData;
-use std::os::unix::fs::OpenOptionsExt;
 use std::path::{Component, Path, PathBuf};
-use std::pin::Pin;
-
-use filetime::FileTime;
-use genawaiter::sync::Gen;
-
-use crate::{NIX_VERSION_MAGIC, PAD_LEN};
-
-type Co<'a> = genawaiter::sync::Co<io::Result<Entry<'a>>>;
+use std::sync::{Arc, Mutex};
+
+use self::parser::{Node, Parser, Token};
+use crate::budget::{MemoryBudget, Reservation};
+#[cfg(feature = "fs")]
+use crate::clock::Clock;
+use crate::listing::Listing;
+use crate::merkle::MerkleTree;
+#[cfg(feature = "fs")]
+use crate::temp::TempProvider;
+#[cfg(feature = "fs")]
+use crate::vfs::Filesystem;
+use crate::{wire, SymlinkTarget, Warning, PAD_LEN};
+
+#[cfg(feature = "tokio")]
+pub use self::async_archive::{AsyncArchive, AsyncEntries};
+#[cfg(feature = "stream")]
+pub use self::blocking::BlockingEntries;
+pub use self::case::CaseCollision;
+pub use self::events::{Event, Events};
+pub use self::limits::{ParseLimits, PathLimits};
+pub use self::map::{to_map, FileEntry};
+#[cfg(feature = "fs")]
+pub use self::materialize::SymlinkPolicy;
+pub use self::meta::{to_meta, EntryMeta, EntryType};
+pub use self::options::UnpackOptions;
+pub(crate) use self::parser::validate_entry_name;
+pub use self::root_file::{FileInfo, RootKind};
+pub use self::sink::{BlackHole, ExtractSink};
+#[cfg(feature = "fs")]
+pub use self::slice::ArchiveSlice;
+#[cfg(feature = "fs")]
+pub use self::unpack::UnpackLog;
+
+#[cfg(feature = "tokio")]
+mod async_archive;
+#[cfg(feature = "stream")]
+mod blocking;
+mod case;
+mod events;
+mod limits;
+mod map;
+#[cfg(feature = "fs")]
+mod materialize;
+mod meta;
+mod options;
+mod parser;
+mod root_file;
+mod sink;
+#[cfg(feature = "fs")]
+mod slice;
+#[cfg(feature = "fs")]
+mod unpack;
+#[cfg(feature = "fs")]
+mod verify;
+
+const MAX_FOUND_LEN: usize = 256;
 
 #[derive(Debug)]
 struct ArchiveInner<R: ?Sized> {
-    canonicalize_mtime: bool,
-    remove_xattrs: bool,
+    options: UnpackOptions,
+    warnings: Arc<Mutex<Vec<Warning>>>,
     position: Cell<u64>,
+    /// Bytes already consumed from `reader` by a peek that must be read again.
+    replay: RefCell<io::Cursor<Vec<u8>>>,
     reader: RefCell<R>,
 }
 
-impl<'a, R: ?Sized + Read> Read for &'a ArchiveInner<R> {
+impl<R: ?Sized + Read> Read for &ArchiveInner<R> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let bytes_read = self.reader.borrow_mut().read(buf)?;
+        let mut replay = self.replay.borrow_mut();
+        let bytes_read = if replay.position() < replay.get_ref().len() as u64 {
+            replay.read(buf)?
+        } else {
+            let mut reader = self.reader.borrow_mut();
+            loop {
+                match reader.read(buf) {
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    // The parser cannot resume mid-token, so name the cause rather than letting
+                    // the archive look truncated.
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                        let message = format!(
+                            "Reader would block at offset {}; archives must be read from \
+                             blocking readers",
+                            self.position.get()
+                        );
+                        return Err(Error::new(ErrorKind::WouldBlock, message));
+                    }
+                    result => break result?,
+                }
+            }
+        };
         self.position.set(self.position.get() + bytes_read as u64);
         Ok(bytes_read)
     }
@@ -35,23 +105,37 @@ pub struct Archive<R: ?Sized + Read> {
     inner: ArchiveInner<R>,
 }
 
+/// An archive over a boxed reader. Archives read from files, sockets and decompressors all share
+/// this one type, so applications juggling many reader types compile its methods only once.
+pub type DynArchive<'a> = Archive<Box<dyn Read + Send + 'a>>;
+
 impl<R: Read> Archive<R> {
     pub fn new(reader: R) -> Self {
+        Archive::with_options(reader, UnpackOptions::default())
+    }
+
+    pub fn with_options(reader: R, options: UnpackOptions) -> Self {
         Archive {
             inner: ArchiveInner {
-                canonicalize_mtime: true,
-                remove_xattrs: true,
+                options,
+                warnings: Arc::default(),
                 position: Cell::new(0),
+                replay: RefCell::new(io::Cursor::new(Vec::new())),
                 reader: RefCell::new(reader),
             },
         }
     }
 
+    #[inline]
+    pub fn options(&self) -> &UnpackOptions {
+        &self.inner.options
+    }
+
     pub fn into_inner(self) -> R {
         self.inner.reader.into_inner()
     }
 
-    pub fn entries(&mut self) -> io::Result<Entries<R>> {
+    pub fn entries(&mut self) -> io::Result<Entries<'_, R>> {
         let archive: &mut Archive<dyn Read> = self;
         archive.entries_inner().map(|iter| Entries {
             iter,
@@ -59,39 +143,204 @@ impl<R: Read> Archive<R> {
         })
     }
 
+    /// Like [`entries`](Archive::entries), but pairs every entry with its
+    /// [`depth`](Entry::depth).
+    pub fn entries_with_depth(&mut self) -> io::Result<EntriesWithDepth<'_, R>> {
+        self.entries().map(|inner| EntriesWithDepth { inner })
+    }
+
     pub fn set_canonicalize_mtime(&mut self, canonicalize: bool) {
-        self.inner.canonicalize_mtime = canonicalize;
+        self.inner.options.canonicalize_mtime = canonicalize;
+    }
+
+    pub fn set_case_collision(&mut self, policy: CaseCollision) {
+        self.inner.options.case_collision = policy;
+    }
+
+    /// Sets the size of the buffer that [`unpack_root_file`](Archive::unpack_root_file) streams
+    /// file contents through (64 KiB by default). Other reads and unpacking hold the contents of
+    /// each file in memory whole, so they are unaffected.
+    pub fn set_copy_buffer_len(&mut self, len: usize) {
+        self.inner.options.set_copy_buffer_len(len);
+    }
+
+    /// See [`UnpackOptions::set_lenient`].
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.inner.options.lenient = lenient;
+    }
+
+    pub fn set_parse_limits(&mut self, limits: ParseLimits) {
+        self.inner.options.parse_limits = limits;
+    }
+
+    pub fn set_path_limits(&mut self, limits: PathLimits) {
+        self.inner.options.path_limits = limits;
     }
 
     pub fn set_remove_xattrs(&mut self, remove: bool) {
-        self.inner.remove_xattrs = remove;
+        self.inner.options.remove_xattrs = remove;
+    }
+
+    pub fn set_replace_directories(&mut self, replace: bool) {
+        self.inner.options.replace_directories = replace;
+    }
+
+    /// Draws the scratch space for every token, and the contents of regular files for as long
+    /// as their entries are alive, from `budget`.
+    pub fn set_memory_budget(&mut self, budget: MemoryBudget) {
+        self.inner.options.memory_budget = budget;
     }
 
+    /// Keeps unpacking past entries that fail to be written, reporting every failure together in
+    /// an `UnpackFailures` error once the archive has been consumed. Malformed archives still
+    /// abort immediately.
+    pub fn set_continue_on_error(&mut self, continue_on_error: bool) {
+        self.inner.options.continue_on_error = continue_on_error;
+    }
+
+    pub fn set_rollback_on_error(&mut self, rollback: bool) {
+        self.inner.options.rollback_on_error = rollback;
+    }
+
+    pub fn set_strict_utf8_symlinks(&mut self, strict: bool) {
+        self.inner.options.set_strict_utf8_symlinks(strict);
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn set_temp_provider<T: TempProvider + 'static>(&mut self, provider: T) {
+        self.inner.options.set_temp_provider(provider);
+    }
+
+    /// Applies to [`unpack`](Archive::unpack) and its variants, which copy symlinks only once
+    /// every other entry has been unpacked. Entries unpacked one by one keep their symlinks.
+    #[cfg(feature = "fs")]
+    pub fn set_symlink_policy(&mut self, policy: SymlinkPolicy) {
+        self.inner.options.symlink_policy = policy;
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.inner.options.set_clock(clock);
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn set_filesystem<F: Filesystem + 'static>(&mut self, filesystem: F) {
+        self.inner.options.set_filesystem(filesystem);
+    }
+
+    #[cfg(feature = "fs")]
     pub fn unpack<P: AsRef<Path>>(&mut self, dst: P) -> io::Result<()> {
         let archive: &mut Archive<dyn Read> = self;
-        archive.unpack_inner(dst.as_ref())
+        archive.unpack_inner(dst.as_ref(), &mut UnpackLog::new())
+    }
+
+    /// Drains the warnings raised so far while parsing or unpacking this archive.
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        take_warnings(&self.inner.warnings)
+    }
+
+    pub fn listing(&mut self) -> io::Result<Listing> {
+        Listing::from_entries(self.entries()?)
+    }
+
+    pub fn merkle_tree(&mut self) -> io::Result<MerkleTree> {
+        MerkleTree::from_entries(self.entries()?)
+    }
+
+    pub fn verify_listing(&mut self, expected: &Listing) -> io::Result<()> {
+        expected.verify(&self.listing()?)
+    }
+
+    pub fn extract_to<S: ExtractSink + ?Sized>(&mut self, mut sink: &mut S) -> io::Result<()> {
+        let archive: &mut Archive<dyn Read> = self;
+        archive.extract_inner(&mut sink)
+    }
+
+    /// Peeks at the root node without consuming it, so the archive can still be read normally
+    /// afterwards.
+    pub fn root_kind(&mut self) -> io::Result<RootKind> {
+        let archive: &mut Archive<dyn Read> = self;
+        archive.root_kind_inner()
+    }
+
+    /// Streams the contents of an archive whose root is a regular file directly into `writer`.
+    pub fn unpack_root_file<W: Write>(&mut self, mut writer: W) -> io::Result<FileInfo> {
+        let archive: &mut Archive<dyn Read> = self;
+        archive.unpack_root_file_inner(&mut writer)
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn unpack_atomic<P: AsRef<Path>>(&mut self, dst: P) -> io::Result<()> {
+        let archive: &mut Archive<dyn Read> = self;
+        archive.unpack_atomic_inner(dst.as_ref())
+    }
+
+    /// Sets how many threads hash on-disk files in `verify_tree` (0, the default, uses the
+    /// available parallelism).
+    pub fn set_verify_threads(&mut self, threads: usize) {
+        self.inner.options.verify_threads = threads;
+    }
+
+    /// Checks that the tree at `root` matches the archive exactly, hashing file contents on a
+    /// pool of threads while the archive is still being parsed.
+    #[cfg(feature = "fs")]
+    pub fn verify_tree<P: AsRef<Path>>(&mut self, root: P) -> io::Result<()> {
+        let archive: &mut Archive<dyn Read> = self;
+        archive.verify_tree_inner(root.as_ref())
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn unpack_with_log<P: AsRef<Path>>(
+        &mut self,
+        dst: P,
+        log: &mut UnpackLog,
+    ) -> io::Result<()> {
+        let archive: &mut Archive<dyn Read> = self;
+        archive.unpack_inner(dst.as_ref(), log)
+    }
+}
+
+impl<'a> DynArchive<'a> {
+    pub fn new_dyn(reader: Box<dyn Read + Send + 'a>) -> Self {
+        Archive::new(reader)
+    }
+
+    pub fn with_options_dyn(reader: Box<dyn Read + Send + 'a>, options: UnpackOptions) -> Self {
+        Archive::with_options(reader, options)
     }
 }
 
 impl<'a> Archive<dyn Read + 'a> {
-    fn entries_inner(&mut self) -> io::Result<Box<dyn Iterator<Item = io::Result<Entry>> + '_>> {
+    fn entries_inner(
+        &mut self,
+    ) -> io::Result<Box<dyn Iterator<Item = io::Result<Entry<'_>>> + '_>> {
         if self.inner.position.get() != 0 {
             let message = "Cannot call `entries` unless reader is in position 0";
-            return Err(Error::new(ErrorKind::Other, message));
-        }
-
-        if self.read_bytes_padded()? != NIX_VERSION_MAGIC {
-            return Err(Error::new(ErrorKind::Other, "Not a valid NAR archive"));
+            return Err(Error::other(message));
         }
 
-        let gen = Gen::new(move |co| parse(co, self));
-        Ok(Box::new(gen.into_iter()))
+        let mut parser = Parser::new(self.inner.options.clone());
+        parser.feed(self.read_token()?, &self.inner.warnings)?;
+        Ok(Box::new(ParseEntries {
+            archive: self,
+            parser,
+        }))
     }
 
-    fn unpack_inner(&mut self, dst: &Path) -> io::Result<()> {
+    fn extract_inner(&mut self, sink: &mut dyn ExtractSink) -> io::Result<()> {
         for entry in self.entries_inner()? {
-            let mut file = entry?;
-            file.unpack_in(dst)?;
+            let entry = entry?;
+            match &entry.kind {
+                EntryKind::Directory => sink.create_dir(&entry.name)?,
+                EntryKind::Regular {
+                    executable, data, ..
+                } => sink.create_file(&entry.name, *executable, data)?,
+                EntryKind::Symlink { target } => sink.create_symlink(&entry.name, target)?,
+                EntryKind::Unknown { type_name, .. } => {
+                    let message = format!("Cannot extract unrecognized node type `{}`", type_name);
+                    return Err(Error::other(message));
+                }
+            }
         }
         Ok(())
     }
@@ -102,156 +351,93 @@ impl<'a> Archive<dyn Read + 'a> {
     }
 
     fn read_bytes_padded(&self) -> io::Result<Vec<u8>> {
-        let mut len_buffer = [0u8; PAD_LEN];
-        (&self.inner).read_exact(&mut len_buffer[..])?;
-        let len = u64::from_le_bytes(len_buffer);
-
-        let mut data_buffer = vec![0u8; len as usize];
-        (&self.inner).read_exact(&mut data_buffer)?;
-
-        let remainder = data_buffer.len() % PAD_LEN;
-        if remainder > 0 {
-            let mut buffer = [0u8; PAD_LEN];
-            let padding = &mut buffer[0..PAD_LEN - remainder];
-            (&self.inner).read_exact(padding)?;
-            if !buffer.iter().all(|b| *b == 0) {
-                return Err(Error::new(ErrorKind::Other, "Bad archive padding"));
-            }
+        self.read_token().map(|token| token.bytes)
+    }
+
+    /// Reads a token, drawing its length from the memory budget before allocating it.
+    fn read_token(&self) -> io::Result<Token> {
+        let offset = self.inner.position.get();
+        let mut reader = &self.inner;
+        let mut len = [0; PAD_LEN];
+        reader.read_exact(&mut len)?;
+        let len = u64::from_le_bytes(len);
+
+        let reservation = self.inner.options.memory_budget.try_reserve(len)?;
+        let mut bytes = Vec::new();
+        (&mut reader).take(len).read_to_end(&mut bytes)?;
+        if (bytes.len() as u64) < len {
+            let message = format!(
+                "Archive ended {} bytes into a {}-byte token",
+                bytes.len(),
+                len
+            );
+            return Err(Error::new(ErrorKind::UnexpectedEof, message));
         }
-
-        Ok(data_buffer)
+        wire::read_padding(&mut reader, len)?;
+        Ok(Token {
+            bytes,
+            offset,
+            reservation: Some(reservation),
+        })
     }
 }
 
-impl<'a, R: Read> Debug for Archive<R> {
+impl<R: Read> Debug for Archive<R> {
     fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
         fmt.debug_struct(stringify!(Archive))
-            .field("canonicalize_mtime", &self.inner.canonicalize_mtime)
-            .field("remove_xattrs", &self.inner.remove_xattrs)
+            .field("options", &self.inner.options)
+            .field("warnings", &self.inner.warnings)
             .field("position", &self.inner.position)
             .finish()
     }
 }
 
-async fn parse(mut co: Co<'_>, archive: &Archive<dyn Read + '_>) {
-    if let Err(err) = try_parse(&mut co, archive, PathBuf::new()).await {
-        co.yield_(Err(err)).await;
-    }
+fn warn(warnings: &Mutex<Vec<Warning>>, warning: Warning) {
+    warnings
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(warning);
 }
 
-async fn try_parse(
-    co: &mut Co<'_>,
-    archive: &Archive<dyn Read + '_>,
-    path: PathBuf,
-) -> io::Result<()> {
-    if archive.read_utf8_padded()? != "(" {
-        return Err(Error::new(ErrorKind::Other, "Missing open tag"));
-    }
+fn take_warnings(warnings: &Mutex<Vec<Warning>>) -> Vec<Warning> {
+    std::mem::take(&mut *warnings.lock().unwrap_or_else(|e| e.into_inner()))
+}
 
-    if archive.read_utf8_padded()? != "type" {
-        return Err(Error::new(ErrorKind::Other, "Missing type tag"));
+/// Decodes a symlink target, which is kept as raw bytes unless `strict` requires UTF-8.
+fn symlink_target(target: Vec<u8>, strict: bool) -> io::Result<SymlinkTarget> {
+    if strict {
+        let target =
+            String::from_utf8(target).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        Ok(SymlinkTarget::from(target))
+    } else {
+        Ok(SymlinkTarget::from_bytes(target))
     }
+}
 
-    match archive.read_utf8_padded()?.as_str() {
-        "regular" => {
-            let mut executable = false;
-            let mut tag = archive.read_utf8_padded()?;
-
-            if tag == "executable" {
-                executable = true;
-                if archive.read_utf8_padded()? != "" {
-                    return Err(Error::new(ErrorKind::Other, "Incorrect executable tag"));
-                }
-                tag = archive.read_utf8_padded()?;
-            }
-
-            let data = if tag == "contents" {
-                archive.read_bytes_padded()?
-            } else {
-                return Err(Error::new(ErrorKind::Other, "Missing contents tag"));
-            };
+#[derive(Clone, Debug)]
+pub struct PathComponents<'a> {
+    inner: std::path::Components<'a>,
+}
 
-            if archive.read_utf8_padded()? != ")" {
-                return Err(Error::new(ErrorKind::Other, "Missing regular close tag"));
-            }
+impl<'a> Iterator for PathComponents<'a> {
+    type Item = &'a str;
 
-            co.yield_(Ok(Entry::new(
-                path,
-                EntryKind::Regular { executable, data },
-                archive,
-            )))
-            .await;
-        }
-        "symlink" => {
-            let target = if archive.read_utf8_padded()? == "target" {
-                archive.read_utf8_padded().map(PathBuf::from)?
-            } else {
-                return Err(Error::new(ErrorKind::Other, "Missing target tag"));
-            };
-
-            if archive.read_utf8_padded()? != ")" {
-                return Err(Error::new(ErrorKind::Other, "Missing symlink close tag"));
-            }
-
-            co.yield_(Ok(Entry::new(path, EntryKind::Symlink { target }, archive)))
-                .await;
-        }
-        "directory" => {
-            co.yield_(Ok(Entry::new(path.clone(), EntryKind::Directory, archive)))
-                .await;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(component_name)
+    }
+}
 
-            loop {
-                match archive.read_utf8_padded()?.as_str() {
-                    "entry" => {
-                        if archive.read_utf8_padded()? != "(" {
-                            return Err(Error::new(ErrorKind::Other, "Missing nested open tag"));
-                        }
-
-                        let entry_name = if archive.read_utf8_padded()? == "name" {
-                            let name = archive.read_utf8_padded()?;
-                            match name.as_str() {
-                                "" => {
-                                    return Err(Error::new(ErrorKind::Other, "Entry name is empty"))
-                                }
-                                "/" => {
-                                    return Err(Error::new(ErrorKind::Other, "Invalid name `/`"))
-                                }
-                                "~" => {
-                                    return Err(Error::new(ErrorKind::Other, "Invalid name `~`"))
-                                }
-                                "." => {
-                                    return Err(Error::new(ErrorKind::Other, "Invalid name `.`"))
-                                }
-                                ".." => {
-                                    return Err(Error::new(ErrorKind::Other, "Invalid name `..`"))
-                                }
-                                _ => name,
-                            }
-                        } else {
-                            return Err(Error::new(ErrorKind::Other, "Missing name field"));
-                        };
-
-                        if archive.read_utf8_padded()? != "node" {
-                            return Err(Error::new(ErrorKind::Other, "Missing node field"));
-                        }
-
-                        let child_entry: Pin<Box<dyn Future<Output = _>>> =
-                            Box::pin(try_parse(co, archive, path.join(entry_name)));
-                        child_entry.await?;
-
-                        if archive.read_utf8_padded()? != ")" {
-                            return Err(Error::new(ErrorKind::Other, "Missing nested close tag"));
-                        }
-                    }
-                    ")" => break,
-                    _ => return Err(Error::new(ErrorKind::Other, "Incorrect directory field")),
-                }
-            }
-        }
-        _ => return Err(Error::new(ErrorKind::Other, "Unrecognized file type")),
+impl DoubleEndedIterator for PathComponents<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(component_name)
     }
+}
 
-    Ok(())
+fn component_name(component: Component<'_>) -> &str {
+    match component {
+        Component::Normal(name) => name.to_str().expect("entry names are validated UTF-8"),
+        _ => unreachable!("entry names only contain normal components"),
+    }
 }
 
 pub struct Entries<'a, R: 'a + Read> {
@@ -267,27 +453,102 @@ impl<'a, R: Read> Iterator for Entries<'a, R> {
     }
 }
 
+/// Drives a [`Parser`] from the reader of an archive.
+struct ParseEntries<'a> {
+    archive: &'a Archive<dyn Read + 'a>,
+    parser: Parser,
+}
+
+impl<'a> Iterator for ParseEntries<'a> {
+    type Item = io::Result<Entry<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let inner = &self.archive.inner;
+        while !self.parser.is_done() {
+            let node = self
+                .archive
+                .read_token()
+                .and_then(|token| self.parser.feed(token, &inner.warnings));
+            match node {
+                Ok(Some(node)) => {
+                    return Some(Ok(Entry::new(node, &inner.options, &inner.warnings)))
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    self.parser.abort();
+                    return Some(Err(err));
+                }
+            }
+        }
+        None
+    }
+}
+
 impl<'a, R: Read> Debug for Entries<'a, R> {
     fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
         write!(fmt, stringify!(Entries))
     }
 }
 
+#[derive(Debug)]
+pub struct EntriesWithDepth<'a, R: 'a + Read> {
+    inner: Entries<'a, R>,
+}
+
+impl<'a, R: Read> Iterator for EntriesWithDepth<'a, R> {
+    type Item = io::Result<(usize, Entry<'a>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.inner.next()?;
+        Some(entry.map(|entry| (entry.depth, entry)))
+    }
+}
+
 pub struct Entry<'a> {
     name: PathBuf,
-    kind: EntryKind,
+    depth: usize,
+    pub(crate) kind: EntryKind,
+    #[cfg(feature = "fs")]
     canonicalize_mtime: bool,
+    #[cfg(feature = "fs")]
+    clock: Arc<dyn Clock>,
+    #[cfg(feature = "fs")]
+    filesystem: Arc<dyn Filesystem>,
+    #[cfg(feature = "fs")]
+    path_limits: PathLimits,
+    #[cfg(feature = "fs")]
     remove_xattrs: bool,
+    #[cfg(feature = "fs")]
+    replace_directories: bool,
+    #[cfg(feature = "fs")]
+    warnings: Arc<Mutex<Vec<Warning>>>,
+    /// Memory budget held for the contents of regular files, released when the entry is dropped.
+    _reservation: Option<Reservation>,
     _marker: PhantomData<&'a ()>,
 }
 
 impl<'a> Entry<'a> {
-    fn new(name: PathBuf, kind: EntryKind, archive: &Archive<dyn Read + '_>) -> Self {
+    #[cfg_attr(not(feature = "fs"), allow(unused_variables))]
+    fn new(node: Node, options: &UnpackOptions, warnings: &Arc<Mutex<Vec<Warning>>>) -> Self {
         Entry {
-            name,
-            kind,
-            canonicalize_mtime: archive.inner.canonicalize_mtime,
-            remove_xattrs: archive.inner.remove_xattrs,
+            depth: node.path.components().count(),
+            name: node.path,
+            kind: node.kind,
+            #[cfg(feature = "fs")]
+            canonicalize_mtime: options.canonicalize_mtime,
+            #[cfg(feature = "fs")]
+            clock: options.clock.clone(),
+            #[cfg(feature = "fs")]
+            filesystem: options.filesystem.clone(),
+            #[cfg(feature = "fs")]
+            path_limits: options.path_limits,
+            #[cfg(feature = "fs")]
+            remove_xattrs: options.remove_xattrs,
+            #[cfg(feature = "fs")]
+            replace_directories: options.replace_directories,
+            #[cfg(feature = "fs")]
+            warnings: warnings.clone(),
+            _reservation: node.reservation,
             _marker: PhantomData,
         }
     }
@@ -297,141 +558,121 @@ impl<'a> Entry<'a> {
         &self.name
     }
 
+    /// How deeply the entry is nested: 0 for the root, 1 for its entries and so on.
     #[inline]
-    pub fn is_dir(&self) -> bool {
-        match &self.kind {
-            EntryKind::Directory => true,
-            _ => false,
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Yields the names leading from the archive root to this entry, which is empty for the root
+    /// itself. Each component is validated UTF-8 that is never empty, `.` or `..`, and never
+    /// contains `/` or NUL, regardless of the host platform's path rules.
+    pub fn path_components(&self) -> PathComponents<'_> {
+        PathComponents {
+            inner: self.name.components(),
         }
     }
 
-    #[inline]
-    pub fn is_executable(&self) -> bool {
+    /// The entry's path within the archive with components joined by `/`, and the empty string
+    /// for the root.
+    pub fn nar_path(&self) -> String {
+        self.path_components().collect::<Vec<_>>().join("/")
+    }
+
+    pub fn kind(&self) -> Kind<'_> {
         match &self.kind {
-            EntryKind::Regular { executable, .. } => *executable,
-            _ => false,
+            EntryKind::Directory => Kind::Dir,
+            EntryKind::Regular {
+                executable, data, ..
+            } => Kind::File {
+                executable: *executable,
+                size: data.len() as u64,
+            },
+            EntryKind::Symlink { target } => Kind::Symlink { target },
+            EntryKind::Unknown { type_name, .. } => Kind::Unknown { type_name },
         }
     }
 
     #[inline]
-    pub fn is_file(&self) -> bool {
+    pub fn data(&self) -> Option<&[u8]> {
         match &self.kind {
-            EntryKind::Regular { executable, .. } => !executable,
-            _ => false,
+            EntryKind::Regular { data, .. } => Some(data),
+            _ => None,
         }
     }
 
-    #[inline]
-    pub fn is_symlink(&self) -> bool {
+    /// Returns a reader bounded to the contents of a regular file entry, which can be handed to
+    /// third-party code without it ever reaching the rest of the archive. The contents are
+    /// consumed from the archive, padding included, before the entry is yielded, so the parser
+    /// is unaffected however much of the reader is used.
+    pub fn take_reader(&self) -> io::Result<io::Take<&[u8]>> {
         match &self.kind {
-            EntryKind::Symlink { .. } => true,
-            _ => false,
+            EntryKind::Regular { data, .. } => Ok((&data[..]).take(data.len() as u64)),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Not a regular file: {}", self.name.display()),
+            )),
         }
     }
 
-    pub fn set_canonicalize_mtime(&mut self, canonicalize: bool) {
-        self.canonicalize_mtime = canonicalize;
+    #[inline]
+    pub fn symlink_target(&self) -> Option<&Path> {
+        match &self.kind {
+            EntryKind::Symlink { target } => Some(target.as_path()),
+            _ => None,
+        }
     }
 
-    pub fn set_remove_xattrs(&mut self, remove: bool) {
-        self.remove_xattrs = remove;
+    #[inline]
+    pub fn is_dir(&self) -> bool {
+        matches!(&self.kind, EntryKind::Directory)
     }
 
-    pub fn unpack_in<P: AsRef<Path>>(&mut self, dst: P) -> io::Result<()> {
-        let path = if self.name.as_os_str().is_empty() {
-            dst.as_ref().to_owned()
-        } else {
-            dst.as_ref().join(&self.name)
-        };
-
-        for component in path.components() {
-            if let Component::Prefix(_) | Component::RootDir | Component::ParentDir = component {
-                let message = format!("Invalid path component in {:?}", path);
-                return Err(Error::new(ErrorKind::Other, message));
-            }
-        }
-
-        // If the timestamp of our parent has been canonicalized, we want to keep it that way after
-        // we unpack, whether we choose to canonicalize as well or not.
-        let recanonicalize_parent = path
-            .parent()
-            .filter(|_| !self.name.as_os_str().is_empty())
-            .and_then(|p| fs::symlink_metadata(p).ok())
-            .filter(|m| {
-                FileTime::from_creation_time(&m)
-                    .filter(|time| *time == FileTime::zero())
-                    .is_some()
-            });
-
-        match &mut self.kind {
-            EntryKind::Directory => Self::unpack_dir(&path)?,
-            EntryKind::Regular { executable, data } => Self::unpack_file(&path, *executable, data)?,
-            EntryKind::Symlink { target } => Self::unpack_symlink(&path, target)?,
-        }
-
-        if self.remove_xattrs {
-            #[cfg(all(unix, feature = "xattr"))]
-            for attr in xattr::list(&path)? {
-                xattr::remove(&path, attr)?;
-            }
-        }
-
-        if self.canonicalize_mtime {
-            let metadata = fs::symlink_metadata(&path)?;
-            let atime = FileTime::from_last_access_time(&metadata);
-            filetime::set_symlink_file_times(&path, atime, FileTime::zero())?;
+    #[inline]
+    pub fn is_executable(&self) -> bool {
+        match &self.kind {
+            EntryKind::Regular { executable, .. } => *executable,
+            _ => false,
         }
+    }
 
-        if let Some(metadata) = recanonicalize_parent {
-            if let Some(parent) = path.parent() {
-                let atime = FileTime::from_last_access_time(&metadata);
-                filetime::set_symlink_file_times(&parent, atime, FileTime::zero())?;
-            }
+    #[inline]
+    pub fn is_file(&self) -> bool {
+        match &self.kind {
+            EntryKind::Regular { executable, .. } => !executable,
+            _ => false,
         }
-
-        Ok(())
     }
 
-    fn unpack_dir(dst: &Path) -> io::Result<()> {
-        fs::create_dir(&dst).or_else(|err| {
-            if err.kind() == ErrorKind::AlreadyExists {
-                let prev = fs::metadata(&dst);
-                if prev.map(|m| m.is_dir()).unwrap_or(false) {
-                    return Ok(());
-                }
-            }
-            Err(Error::new(
-                err.kind(),
-                format!("{} when creating dir {}", err, dst.display()),
-            ))
-        })
+    #[inline]
+    pub fn is_symlink(&self) -> bool {
+        matches!(&self.kind, EntryKind::Symlink { .. })
     }
 
-    fn unpack_file(dst: &Path, executable: bool, data: &mut Vec<u8>) -> io::Result<()> {
-        if dst.exists() {
-            fs::remove_file(&dst)?;
-        }
-
-        let mut opt = OpenOptions::new();
-        opt.create_new(true).write(true);
-
-        if executable {
-            opt.mode(0o555);
-        } else {
-            opt.mode(0o444);
-        }
-
-        let mut file = opt.open(&dst)?;
-        file.write_all(data.as_slice())?;
-        Ok(())
+    #[inline]
+    pub fn is_unknown(&self) -> bool {
+        matches!(&self.kind, EntryKind::Unknown { .. })
     }
 
-    fn unpack_symlink(dst: &Path, target: &Path) -> io::Result<()> {
-        if fs::symlink_metadata(&dst).is_ok() {
-            fs::remove_file(&dst)?;
+    pub fn write_raw_node<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        match &self.kind {
+            EntryKind::Unknown {
+                type_name,
+                raw_tokens,
+            } => {
+                wire::write_token(writer, b"(")?;
+                wire::write_token(writer, b"type")?;
+                wire::write_token(writer, type_name.as_bytes())?;
+                for token in raw_tokens {
+                    wire::write_token(writer, token)?;
+                }
+                wire::write_token(writer, b")")
+            }
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Only unrecognized nodes can be re-emitted verbatim",
+            )),
         }
-
-        std::os::unix::fs::symlink(target, dst)
     }
 }
 
@@ -444,10 +685,28 @@ impl<'a> Debug for Entry<'a> {
     }
 }
 
-enum EntryKind {
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Kind<'a> {
+    Dir,
+    File { executable: bool, size: u64 },
+    Symlink { target: &'a SymlinkTarget },
+    Unknown { type_name: &'a str },
+}
+
+pub(crate) enum EntryKind {
     Directory,
-    Regular { executable: bool, data: Vec<u8> },
-    Symlink { target: PathBuf },
+    Regular {
+        executable: bool,
+        data: Vec<u8>,
+        offset: u64,
+    },
+    Symlink {
+        target: SymlinkTarget,
+    },
+    Unknown {
+        type_name: String,
+        raw_tokens: Vec<Vec<u8>>,
+    },
 }
 
 impl Debug for EntryKind {
@@ -463,6 +722,177 @@ impl Debug for EntryKind {
                 .debug_struct(stringify!(Symlink))
                 .field("target", target)
                 .finish(),
+            Unknown { type_name, .. } => fmt
+                .debug_struct(stringify!(Unknown))
+                .field("type_name", type_name)
+                .finish(),
         }
     }
 }
+
+/// A malformed archive, located by the entry being parsed and the offset of the offending token.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseError {
+    message: Cow<'static, str>,
+    path: PathBuf,
+    offset: u64,
+    expected: &'static [&'static str],
+    found: Vec<u8>,
+}
+
+impl ParseError {
+    fn new<M, F>(
+        message: M,
+        path: &Path,
+        offset: u64,
+        expected: &'static [&'static str],
+        found: F,
+    ) -> Self
+    where
+        M: Into<Cow<'static, str>>,
+        F: Into<Vec<u8>>,
+    {
+        let mut found = found.into();
+        found.truncate(MAX_FOUND_LEN);
+        ParseError {
+            message: message.into(),
+            path: path.to_owned(),
+            offset,
+            expected,
+            found,
+        }
+    }
+
+    /// Path of the entry being parsed, relative to the archive root.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Byte offset of the offending token, including its length prefix.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Tags that would have been accepted, if the error is a tag mismatch.
+    pub fn expected(&self) -> &[&'static str] {
+        self.expected
+    }
+
+    /// The offending token, truncated to its first 256 bytes.
+    pub fn found(&self) -> &[u8] {
+        &self.found
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        fmt.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Self {
+        Error::other(err)
+    }
+}
+
+/// Every entry that failed to unpack when continuing past errors, in archive order.
+#[derive(Debug)]
+pub struct UnpackFailures {
+    failures: Vec<(PathBuf, Error)>,
+}
+
+impl UnpackFailures {
+    pub fn failures(&self) -> &[(PathBuf, Error)] {
+        &self.failures
+    }
+
+    pub fn into_failures(self) -> Vec<(PathBuf, Error)> {
+        self.failures
+    }
+}
+
+impl Display for UnpackFailures {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        write!(fmt, "{} entries failed to unpack", self.failures.len())?;
+        if let Some((path, err)) = self.failures.first() {
+            write!(fmt, ", first {}: {}", path.display(), err)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for UnpackFailures {}
+
+impl From<UnpackFailures> for Error {
+    fn from(err: UnpackFailures) -> Self {
+        Error::other(err)
+    }
+}
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum UnpackError {
+    CaseCollision(PathBuf),
+    NameTooLong { path: PathBuf, limit: usize },
+    PathTooLong { path: PathBuf, limit: usize },
+    TraversesSymlink(PathBuf),
+    WouldReplaceDirectory(PathBuf),
+}
+
+impl UnpackError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            UnpackError::CaseCollision(_) => ErrorKind::AlreadyExists,
+            UnpackError::NameTooLong { .. } | UnpackError::PathTooLong { .. } => {
+                ErrorKind::InvalidInput
+            }
+            UnpackError::TraversesSymlink(_) => ErrorKind::InvalidData,
+            UnpackError::WouldReplaceDirectory(_) => ErrorKind::AlreadyExists,
+        }
+    }
+}
+
+impl Display for UnpackError {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match self {
+            UnpackError::CaseCollision(path) => write!(
+                fmt,
+                "Entry {} collides with a sibling differing only in case",
+                path.display()
+            ),
+            UnpackError::NameTooLong { path, limit } => write!(
+                fmt,
+                "A component of {} exceeds the {} byte file name limit",
+                path.display(),
+                limit
+            ),
+            UnpackError::PathTooLong { path, limit } => write!(
+                fmt,
+                "Path {} exceeds the {} byte path length limit",
+                path.display(),
+                limit
+            ),
+            UnpackError::TraversesSymlink(path) => write!(
+                fmt,
+                "Refusing to unpack {} through a symlink",
+                path.display()
+            ),
+            UnpackError::WouldReplaceDirectory(path) => write!(
+                fmt,
+                "Unpacking would replace existing directory {}",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for UnpackError {}
+
+impl From<UnpackError> for Error {
+    fn from(err: UnpackError) -> Self {
+        Error::new(err.kind(), err)
+    }
+}
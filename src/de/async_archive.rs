@@ -0,0 +1,293 @@
+use std::convert::TryFrom;
+use std::fmt::{self, Debug, Formatter};
+use std::io::{self, Error, ErrorKind, SeekFrom};
+#[cfg(feature = "fs")]
+use std::io::{Cursor, Read};
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt};
+#[cfg(feature = "fs")]
+use tokio::sync::mpsc;
+
+use super::parser::{Parser, Token};
+#[cfg(feature = "fs")]
+use super::Archive;
+use super::{take_warnings, Entry, UnpackOptions};
+use crate::listing::{Listing, Node};
+use crate::{wire, Warning, PAD_LEN};
+
+/// Chunks read ahead of the blocking unpacker by [`AsyncArchive::unpack`].
+#[cfg(feature = "fs")]
+const QUEUE_LEN: usize = 16;
+
+/// An archive read from an [`AsyncRead`], parsed without blocking the runtime polling it.
+///
+/// Entries are parsed by the same grammar as [`Archive`](super::Archive), so the parse limits,
+/// memory budget and leniency of the options are honored alike.
+pub struct AsyncArchive<R> {
+    options: UnpackOptions,
+    warnings: Arc<Mutex<Vec<Warning>>>,
+    position: u64,
+    reader: R,
+}
+
+impl<R: AsyncRead + Unpin> AsyncArchive<R> {
+    pub fn new(reader: R) -> Self {
+        AsyncArchive::with_options(reader, UnpackOptions::default())
+    }
+
+    pub fn with_options(reader: R, options: UnpackOptions) -> Self {
+        AsyncArchive {
+            options,
+            warnings: Arc::default(),
+            position: 0,
+            reader,
+        }
+    }
+
+    #[inline]
+    pub fn options(&self) -> &UnpackOptions {
+        &self.options
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    pub fn entries(&mut self) -> io::Result<AsyncEntries<'_, R>> {
+        self.check_start("entries")?;
+        let parser = Parser::new(self.options.clone());
+        Ok(AsyncEntries {
+            archive: self,
+            parser,
+        })
+    }
+
+    /// Unpacks the archive into `dst` exactly as [`Archive::unpack`](super::Archive::unpack)
+    /// would with the same options. The unpacker runs on the blocking thread pool of the runtime,
+    /// fed with chunks read from this archive.
+    #[cfg(feature = "fs")]
+    pub async fn unpack<P: AsRef<Path>>(&mut self, dst: P) -> io::Result<()> {
+        self.check_start("unpack")?;
+
+        let dst = dst.as_ref().to_owned();
+        let options = self.options.clone();
+        let (sender, receiver) = mpsc::channel(QUEUE_LEN);
+        let task = tokio::task::spawn_blocking(move || {
+            let reader = ChannelReader {
+                receiver,
+                chunk: Cursor::default(),
+            };
+            let mut archive = Archive::with_options(reader, options);
+            let result = archive.unpack(&dst);
+            (result, archive.take_warnings())
+        });
+        tokio::pin!(task);
+
+        let copy_buffer_len = self.options.copy_buffer_len();
+        let feed = async {
+            loop {
+                let mut chunk = vec![0; copy_buffer_len];
+                let chunk = match self.reader.read(&mut chunk).await {
+                    Ok(0) => break,
+                    Ok(read) => {
+                        self.position += read as u64;
+                        chunk.truncate(read);
+                        Ok(chunk)
+                    }
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(e) => Err(e),
+                };
+                let failed = chunk.is_err();
+                // The unpacker hung up after finishing or failing, so nothing else is needed.
+                if sender.send(chunk).await.is_err() || failed {
+                    break;
+                }
+            }
+            drop(sender);
+        };
+
+        let joined = tokio::select! {
+            joined = &mut task => joined,
+            _ = feed => task.await,
+        };
+        let (result, warnings) =
+            joined.map_err(|e| Error::other(format!("Unpacker failed: {}", e)))?;
+        self.warnings
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .extend(warnings);
+        result
+    }
+
+    /// Drains the warnings raised so far while parsing or unpacking this archive.
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        take_warnings(&self.warnings)
+    }
+
+    fn check_start(&self, method: &str) -> io::Result<()> {
+        if self.position != 0 {
+            let message = format!("Cannot call `{}` unless reader is in position 0", method);
+            return Err(Error::other(message));
+        }
+        Ok(())
+    }
+
+    /// Reads a token, drawing its length from the memory budget before buffering it.
+    async fn read_token(&mut self) -> io::Result<Token> {
+        let offset = self.position;
+        let len = self.read_len().await?;
+
+        let reservation = self.options.memory_budget.try_reserve(len)?;
+        let mut bytes = Vec::new();
+        (&mut self.reader).take(len).read_to_end(&mut bytes).await?;
+        self.position += bytes.len() as u64;
+        if (bytes.len() as u64) < len {
+            let message = format!(
+                "Archive ended {} bytes into a {}-byte token",
+                bytes.len(),
+                len
+            );
+            return Err(Error::new(ErrorKind::UnexpectedEof, message));
+        }
+        self.read_padding(len).await?;
+
+        Ok(Token {
+            bytes,
+            offset,
+            reservation: Some(reservation),
+        })
+    }
+
+    async fn read_len(&mut self) -> io::Result<u64> {
+        let mut len = [0; PAD_LEN];
+        self.read_exact(&mut len).await?;
+        Ok(u64::from_le_bytes(len))
+    }
+
+    async fn read_padding(&mut self, len: u64) -> io::Result<()> {
+        let mut padding = [0u8; PAD_LEN];
+        let padding = &mut padding[..wire::pad_len(len)];
+        self.read_exact(padding).await?;
+        if !padding.iter().all(|b| *b == 0) {
+            return Err(Error::other("Bad archive padding"));
+        }
+        Ok(())
+    }
+
+    async fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        self.reader.read_exact(buf).await?;
+        self.position += buf.len() as u64;
+        Ok(())
+    }
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> AsyncArchive<R> {
+    /// Lists the archive, seeking past the contents of regular files instead of reading them, so
+    /// that listing through a ranged reader only fetches the structure of the archive.
+    pub async fn listing(&mut self) -> io::Result<Listing> {
+        self.check_start("listing")?;
+
+        let mut parser = Parser::new(self.options.clone());
+        let mut nodes = Vec::new();
+        let mut skipped = None;
+        while !parser.is_done() {
+            let node = if parser.expects_contents() {
+                let offset = self.position;
+                let len = self.skip_token().await?;
+                skipped = Some(len);
+                parser.skip_contents(offset)?;
+                None
+            } else {
+                let token = self.read_token().await?;
+                parser.feed(token, &self.warnings)?
+            };
+
+            if let Some(node) = node {
+                let mut listed = Node::from_kind(&node.kind)?;
+                if let (Node::Regular { size, .. }, Some(len)) = (&mut listed, skipped.take()) {
+                    *size = len;
+                }
+                nodes.push(Ok((node.path, listed)));
+            }
+        }
+
+        Listing::from_nodes(nodes)
+    }
+
+    /// Seeks past a token, returning its length.
+    async fn skip_token(&mut self) -> io::Result<u64> {
+        let len = self.read_len().await?;
+        let delta = i64::try_from(len).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        self.reader.seek(SeekFrom::Current(delta)).await?;
+        self.position += len;
+        self.read_padding(len).await?;
+        Ok(len)
+    }
+}
+
+impl<R> Debug for AsyncArchive<R> {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        fmt.debug_struct(stringify!(AsyncArchive))
+            .field("options", &self.options)
+            .field("warnings", &self.warnings)
+            .field("position", &self.position)
+            .finish()
+    }
+}
+
+/// The entries of an [`AsyncArchive`], in archive order. Parsing stops after the first error.
+#[derive(Debug)]
+pub struct AsyncEntries<'a, R> {
+    archive: &'a mut AsyncArchive<R>,
+    parser: Parser,
+}
+
+impl<R: AsyncRead + Unpin> AsyncEntries<'_, R> {
+    /// Parses the next entry. Its contents stay drawn from the memory budget until it is dropped.
+    pub async fn next(&mut self) -> Option<io::Result<Entry<'static>>> {
+        let archive = &mut *self.archive;
+        while !self.parser.is_done() {
+            let node = match archive.read_token().await {
+                Ok(token) => self.parser.feed(token, &archive.warnings),
+                Err(err) => Err(err),
+            };
+            match node {
+                Ok(Some(node)) => {
+                    return Some(Ok(Entry::new(node, &archive.options, &archive.warnings)))
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    self.parser.abort();
+                    return Some(Err(err));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Reads the chunks sent by [`AsyncArchive::unpack`] on a blocking thread.
+#[cfg(feature = "fs")]
+struct ChannelReader {
+    receiver: mpsc::Receiver<io::Result<Vec<u8>>>,
+    chunk: Cursor<Vec<u8>>,
+}
+
+#[cfg(feature = "fs")]
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = Read::read(&mut self.chunk, buf)?;
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            match self.receiver.blocking_recv() {
+                Some(chunk) => self.chunk = Cursor::new(chunk?),
+                None => return Ok(0),
+            }
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use futures_core::Stream;
+
+use super::map::take_file_entry;
+use super::{Archive, FileEntry};
+
+const QUEUE_LEN: usize = 16;
+
+impl<R: Read + Send + 'static> Archive<R> {
+    /// Parses the archive on a dedicated thread, yielding its entries as a [`Stream`] that never
+    /// blocks the async runtime polling it. At most 16 entries are parsed ahead of the consumer,
+    /// and dropping the stream stops the thread after the entry it is parsing.
+    pub fn into_blocking_task(self) -> BlockingEntries {
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_LEN);
+        let waker = Arc::new(Mutex::new(None::<Waker>));
+
+        let wake = waker.clone();
+        thread::spawn(move || {
+            let mut archive = self;
+            let send = |item| {
+                let sent = sender.send(item).is_ok();
+                if let Some(waker) = wake.lock().unwrap_or_else(|e| e.into_inner()).take() {
+                    waker.wake();
+                }
+                sent
+            };
+
+            let entries = match archive.entries() {
+                Ok(entries) => entries,
+                Err(err) => {
+                    send(Err(err));
+                    return;
+                }
+            };
+            for entry in entries {
+                let item = entry.and_then(|mut entry| {
+                    let file = take_file_entry(&mut entry.kind)?;
+                    Ok((entry.name().to_owned(), file))
+                });
+                let failed = item.is_err();
+                if !send(item) || failed {
+                    break;
+                }
+            }
+            // Wake the consumer once more so that it observes the end of the stream.
+            drop(sender);
+            if let Some(waker) = wake.lock().unwrap_or_else(|e| e.into_inner()).take() {
+                waker.wake();
+            }
+        });
+
+        BlockingEntries { receiver, waker }
+    }
+}
+
+/// The entries of an archive parsed on a background thread, created by
+/// [`Archive::into_blocking_task`]. Each item is an entry's path and contents, as collected by
+/// [`to_map`](super::to_map), and the stream ends after the first error.
+#[derive(Debug)]
+pub struct BlockingEntries {
+    receiver: Receiver<io::Result<(PathBuf, FileEntry)>>,
+    waker: Arc<Mutex<Option<Waker>>>,
+}
+
+impl Stream for BlockingEntries {
+    type Item = io::Result<(PathBuf, FileEntry)>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.receiver.try_recv() {
+            Ok(item) => return Poll::Ready(Some(item)),
+            Err(TryRecvError::Disconnected) => return Poll::Ready(None),
+            Err(TryRecvError::Empty) => {}
+        }
+
+        // Check again after registering, in case the parser sent an item in between.
+        *self.waker.lock().unwrap_or_else(|e| e.into_inner()) = Some(cx.waker().clone());
+        match self.receiver.try_recv() {
+            Ok(item) => Poll::Ready(Some(item)),
+            Err(TryRecvError::Disconnected) => Poll::Ready(None),
+            Err(TryRecvError::Empty) => Poll::Pending,
+        }
+    }
+}
@@ -0,0 +1,127 @@
+#[cfg(feature = "fs")]
+use std::collections::{HashMap, HashSet};
+#[cfg(feature = "fs")]
+use std::io;
+#[cfg(feature = "fs")]
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "fs")]
+use super::UnpackError;
+#[cfg(feature = "fs")]
+use crate::Warning;
+
+#[cfg(feature = "fs")]
+const CASE_HACK_SUFFIX: &str = "~nix~case~hack~";
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum CaseCollision {
+    #[default]
+    Allow,
+    Error,
+    CaseHack,
+}
+
+#[derive(Debug, Default)]
+#[cfg(feature = "fs")]
+pub(crate) struct CaseFolder {
+    seen: HashMap<PathBuf, HashSet<String>>,
+    collisions: HashMap<PathBuf, u64>,
+    renamed_dirs: HashMap<PathBuf, PathBuf>,
+    warnings: Vec<Warning>,
+}
+
+#[cfg(feature = "fs")]
+impl CaseFolder {
+    /// Returns the path `name` should be unpacked to, taking any directories renamed by the case
+    /// hack into account.
+    pub fn resolve(
+        &mut self,
+        name: &Path,
+        is_dir: bool,
+        policy: CaseCollision,
+    ) -> io::Result<PathBuf> {
+        let (parent, file_name) = match (name.parent(), name.file_name()) {
+            (Some(parent), Some(file_name)) => (parent, file_name.to_string_lossy()),
+            _ => return Ok(name.to_owned()),
+        };
+
+        let parent = self
+            .renamed_dirs
+            .get(parent)
+            .cloned()
+            .unwrap_or_else(|| parent.to_owned());
+
+        let folded = file_name.to_lowercase();
+        let seen = self.seen.entry(parent.clone()).or_default();
+        let resolved = if seen.insert(folded.clone()) {
+            parent.join(&*file_name)
+        } else {
+            let resolved = match policy {
+                CaseCollision::Allow => parent.join(&*file_name),
+                CaseCollision::Error => {
+                    return Err(UnpackError::CaseCollision(parent.join(&*file_name)).into());
+                }
+                CaseCollision::CaseHack => {
+                    let count = self.collisions.entry(parent.join(&folded)).or_insert(0);
+                    *count += 1;
+                    let hacked = format!("{}{}{}", file_name, CASE_HACK_SUFFIX, count);
+                    parent.join(hacked)
+                }
+            };
+            self.warnings.push(Warning::CaseCollision {
+                path: name.to_owned(),
+                unpacked_as: resolved.clone(),
+            });
+            resolved
+        };
+
+        if is_dir && resolved != name {
+            self.renamed_dirs.insert(name.to_owned(), resolved.clone());
+        }
+
+        Ok(resolved)
+    }
+
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        std::mem::take(&mut self.warnings)
+    }
+}
+
+#[cfg(all(test, feature = "fs"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_case_hack_to_colliding_names_and_descendants() {
+        let mut folder = CaseFolder::default();
+        let policy = CaseCollision::CaseHack;
+
+        let first = folder.resolve(Path::new("Foo"), true, policy).unwrap();
+        assert_eq!(first, Path::new("Foo"));
+
+        let second = folder.resolve(Path::new("foo"), true, policy).unwrap();
+        assert_eq!(second, Path::new("foo~nix~case~hack~1"));
+
+        let third = folder.resolve(Path::new("FOO"), false, policy).unwrap();
+        assert_eq!(third, Path::new("FOO~nix~case~hack~2"));
+
+        let child = folder.resolve(Path::new("foo/bar"), false, policy).unwrap();
+        assert_eq!(child, Path::new("foo~nix~case~hack~1/bar"));
+    }
+
+    #[test]
+    fn tracks_names_per_directory() {
+        let mut folder = CaseFolder::default();
+        let policy = CaseCollision::Error;
+
+        folder
+            .resolve(Path::new("a/readme"), false, policy)
+            .unwrap();
+        folder
+            .resolve(Path::new("b/README"), false, policy)
+            .unwrap();
+        assert!(folder
+            .resolve(Path::new("a/README"), false, policy)
+            .is_err());
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::VecDeque;
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+use super::{Archive, Entries, Entry};
+
+/// A step of a walk through an archive, produced by [`Archive::events`].
+#[derive(Debug)]
+pub enum Event<'a> {
+    Entry(Entry<'a>),
+    /// Every entry of the directory at this path has been yielded. Directories close innermost
+    /// first, and the root closes last with the empty path.
+    DirectoryClosed(PathBuf),
+}
+
+#[derive(Debug)]
+pub struct Events<'a, R: 'a + Read> {
+    entries: Entries<'a, R>,
+    /// Depths and paths of the directories whose entries may still be coming.
+    open_dirs: Vec<(usize, PathBuf)>,
+    closed: VecDeque<PathBuf>,
+    next: Option<Entry<'a>>,
+    done: bool,
+}
+
+impl<R: Read> Archive<R> {
+    /// Like [`entries`](Archive::entries), but also reports when each directory is finished,
+    /// which is as soon as the entry following its last descendant has been read.
+    pub fn events(&mut self) -> io::Result<Events<'_, R>> {
+        self.entries().map(|entries| Events {
+            entries,
+            open_dirs: Vec::new(),
+            closed: VecDeque::new(),
+            next: None,
+            done: false,
+        })
+    }
+}
+
+impl<'a, R: Read> Events<'a, R> {
+    fn close_until(&mut self, depth: usize) {
+        while let Some((open, _)) = self.open_dirs.last() {
+            if *open < depth {
+                break;
+            }
+            let (_, path) = self.open_dirs.pop().expect("checked above");
+            self.closed.push_back(path);
+        }
+    }
+}
+
+impl<'a, R: Read> Iterator for Events<'a, R> {
+    type Item = io::Result<Event<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(path) = self.closed.pop_front() {
+                return Some(Ok(Event::DirectoryClosed(path)));
+            }
+            if let Some(entry) = self.next.take() {
+                if entry.is_dir() {
+                    self.open_dirs
+                        .push((entry.depth(), entry.name().to_owned()));
+                }
+                return Some(Ok(Event::Entry(entry)));
+            }
+            if self.done {
+                return None;
+            }
+
+            match self.entries.next() {
+                Some(Ok(entry)) => {
+                    self.close_until(entry.depth());
+                    self.next = Some(entry);
+                }
+                Some(Err(err)) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+                None => {
+                    self.done = true;
+                    self.close_until(0);
+                }
+            }
+        }
+    }
+}
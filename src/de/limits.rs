@@ -0,0 +1,148 @@
+use std::io;
+use std::path::Path;
+
+use super::UnpackError;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PathLimits {
+    max_path_len: Option<usize>,
+    max_name_len: Option<usize>,
+}
+
+impl PathLimits {
+    pub const fn unix() -> Self {
+        PathLimits {
+            max_path_len: Some(4096),
+            max_name_len: Some(255),
+        }
+    }
+
+    pub const fn windows() -> Self {
+        PathLimits {
+            max_path_len: Some(260),
+            max_name_len: Some(255),
+        }
+    }
+
+    pub const fn unlimited() -> Self {
+        PathLimits {
+            max_path_len: None,
+            max_name_len: None,
+        }
+    }
+
+    pub const fn with_max_path_len(mut self, len: Option<usize>) -> Self {
+        self.max_path_len = len;
+        self
+    }
+
+    pub const fn with_max_name_len(mut self, len: Option<usize>) -> Self {
+        self.max_name_len = len;
+        self
+    }
+
+    #[inline]
+    pub fn max_path_len(&self) -> Option<usize> {
+        self.max_path_len
+    }
+
+    #[inline]
+    pub fn max_name_len(&self) -> Option<usize> {
+        self.max_name_len
+    }
+
+    pub fn check(&self, path: &Path) -> io::Result<()> {
+        if let Some(limit) = self.max_path_len {
+            // Leave room for the trailing NUL the OS expects.
+            if path.as_os_str().as_encoded_bytes().len() >= limit {
+                let path = path.to_owned();
+                return Err(UnpackError::PathTooLong { path, limit }.into());
+            }
+        }
+
+        if let Some(limit) = self.max_name_len {
+            let too_long = path
+                .components()
+                .any(|c| c.as_os_str().as_encoded_bytes().len() > limit);
+            if too_long {
+                let path = path.to_owned();
+                return Err(UnpackError::NameTooLong { path, limit }.into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for PathLimits {
+    fn default() -> Self {
+        PathLimits::unix()
+    }
+}
+
+/// Bounds on the shape of an archive enforced while it is parsed, before anything is unpacked,
+/// protecting extraction targets and whatever indexes a listing from pathological archives.
+/// Nothing is limited by default.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ParseLimits {
+    max_dir_entries: Option<usize>,
+    max_path_len: Option<usize>,
+}
+
+impl ParseLimits {
+    pub const fn unlimited() -> Self {
+        ParseLimits {
+            max_dir_entries: None,
+            max_path_len: None,
+        }
+    }
+
+    /// Caps the number of entries in any single directory.
+    pub const fn with_max_dir_entries(mut self, len: Option<usize>) -> Self {
+        self.max_dir_entries = len;
+        self
+    }
+
+    /// Caps the length in bytes of every entry's path relative to the archive root.
+    pub const fn with_max_path_len(mut self, len: Option<usize>) -> Self {
+        self.max_path_len = len;
+        self
+    }
+
+    #[inline]
+    pub fn max_dir_entries(&self) -> Option<usize> {
+        self.max_dir_entries
+    }
+
+    #[inline]
+    pub fn max_path_len(&self) -> Option<usize> {
+        self.max_path_len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_paths_over_limit() {
+        let limits = PathLimits::windows();
+        let short = Path::new("C:/store").join("a".repeat(200));
+        assert!(limits.check(&short).is_ok());
+
+        let long = short.join("b".repeat(100));
+        assert!(limits.check(&long).is_err());
+        assert!(PathLimits::unlimited().check(&long).is_ok());
+    }
+
+    #[test]
+    fn rejects_names_over_limit() {
+        let limits = PathLimits::unix();
+        assert!(limits
+            .check(&Path::new("dir").join("x".repeat(255)))
+            .is_ok());
+        assert!(limits
+            .check(&Path::new("dir").join("x".repeat(256)))
+            .is_err());
+    }
+}
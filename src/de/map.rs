@@ -0,0 +1,49 @@
+use std::collections::BTreeMap;
+use std::io::{self, Error, Read};
+use std::path::PathBuf;
+
+use super::{Archive, EntryKind};
+use crate::SymlinkTarget;
+
+/// A single node of an archive as collected by [`to_map`].
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum FileEntry {
+    Directory,
+    Regular { executable: bool, contents: Vec<u8> },
+    Symlink { target: SymlinkTarget },
+}
+
+/// Reads a whole archive into a map from entry paths to their contents, sorted by path. The root
+/// has the empty path, and directories are included so that empty ones are not lost.
+pub fn to_map<R: Read>(reader: R) -> io::Result<BTreeMap<PathBuf, FileEntry>> {
+    let mut archive = Archive::new(reader);
+    let mut map = BTreeMap::new();
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let file = take_file_entry(&mut entry.kind)?;
+        map.insert(entry.name().to_owned(), file);
+    }
+
+    Ok(map)
+}
+
+/// Moves the contents out of an entry, leaving a regular file's data empty.
+pub(super) fn take_file_entry(kind: &mut EntryKind) -> io::Result<FileEntry> {
+    match kind {
+        EntryKind::Directory => Ok(FileEntry::Directory),
+        EntryKind::Regular {
+            executable, data, ..
+        } => Ok(FileEntry::Regular {
+            executable: *executable,
+            contents: std::mem::take(data),
+        }),
+        EntryKind::Symlink { target } => Ok(FileEntry::Symlink {
+            target: target.clone(),
+        }),
+        EntryKind::Unknown { type_name, .. } => {
+            let message = format!("Cannot load unrecognized node type `{}`", type_name);
+            Err(Error::other(message))
+        }
+    }
+}
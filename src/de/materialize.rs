@@ -0,0 +1,160 @@
+use std::io::{self, Error};
+use std::path::{Component, Path, PathBuf};
+
+use filetime::FileTime;
+
+use super::{UnpackError, UnpackLog};
+use crate::vfs::{FileKind, Filesystem};
+use crate::{SymlinkTarget, Warning};
+
+/// How symlinks are unpacked, for destinations that cannot hold them.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum SymlinkPolicy {
+    #[default]
+    Preserve,
+    /// Replaces every symlink with a copy of the file or directory it points to, failing if the
+    /// target is missing or outside the tree being unpacked.
+    Copy,
+    /// Like `Copy`, but leaves out symlinks that cannot be copied with a warning.
+    CopyOrSkip,
+}
+
+/// A symlink whose copy is made once the rest of the archive has been unpacked, since its target
+/// may come later in the archive.
+#[derive(Debug)]
+pub(super) struct DeferredSymlink {
+    /// The name of the entry within the archive.
+    pub name: PathBuf,
+    pub path: PathBuf,
+    pub target: SymlinkTarget,
+}
+
+/// Copies the targets of `links` into place below `root`, returning the links that could not be
+/// copied along with why. Links pointing at or into other links are copied after them, and those
+/// that never become resolvable, such as cycles, count as missing.
+pub(super) fn materialize(
+    fs: &dyn Filesystem,
+    root: &Path,
+    mut links: Vec<DeferredSymlink>,
+    canonical_mtime: Option<FileTime>,
+    log: &mut UnpackLog,
+) -> Vec<(DeferredSymlink, Error)> {
+    let mut failures = Vec::new();
+
+    loop {
+        let ready = links.iter().position(|link| {
+            resolve(root, link).is_some_and(|source| {
+                fs.metadata(&source).is_ok() && !links.iter().any(|l| l.path.starts_with(&source))
+            })
+        });
+        let link = match ready {
+            Some(i) => links.remove(i),
+            None => break,
+        };
+
+        let source = resolve(root, &link).expect("checked above");
+        if let Err(err) = copy_into_place(fs, &source, &link.path, canonical_mtime, log) {
+            failures.push((link, err));
+        }
+    }
+
+    for link in links {
+        let message = format!(
+            "Cannot copy symlink {} to {:?}: target is missing or outside the unpacked tree",
+            link.path.display(),
+            link.target.as_path()
+        );
+        failures.push((link, Error::other(message)));
+    }
+    failures
+}
+
+pub(super) fn skipped(link: DeferredSymlink) -> Warning {
+    Warning::SymlinkSkipped {
+        path: link.path,
+        target: link.target.into_path_buf(),
+    }
+}
+
+/// Finds where a relative symlink points below `root` without consulting the filesystem.
+fn resolve(root: &Path, link: &DeferredSymlink) -> Option<PathBuf> {
+    let mut resolved: Vec<Component> = link
+        .path
+        .strip_prefix(root)
+        .ok()?
+        .parent()?
+        .components()
+        .collect();
+    for component in link.target.as_path().components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop()?;
+            }
+            Component::Normal(_) => resolved.push(component),
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(root.join(resolved.iter().collect::<PathBuf>()))
+}
+
+fn copy_into_place(
+    fs: &dyn Filesystem,
+    source: &Path,
+    dst: &Path,
+    canonical_mtime: Option<FileTime>,
+    log: &mut UnpackLog,
+) -> io::Result<()> {
+    if let Some(parent) = dst.parent() {
+        let metadata = fs.metadata(parent)?;
+        if metadata.is_symlink() {
+            return Err(UnpackError::TraversesSymlink(dst.to_owned()).into());
+        }
+        copy(fs, source, dst, canonical_mtime, log)?;
+        if let Some(time) = canonical_mtime {
+            fs.set_times(parent, metadata.accessed(), time)?;
+        }
+        Ok(())
+    } else {
+        copy(fs, source, dst, canonical_mtime, log)
+    }
+}
+
+fn copy(
+    fs: &dyn Filesystem,
+    source: &Path,
+    dst: &Path,
+    canonical_mtime: Option<FileTime>,
+    log: &mut UnpackLog,
+) -> io::Result<()> {
+    let metadata = fs.metadata(source)?;
+    match metadata.kind() {
+        FileKind::File => {
+            let contents = fs.read_file(source)?;
+            fs.create_file(dst, metadata.executable(), &contents)?;
+            log.created.push(dst.to_owned());
+        }
+        FileKind::Directory => {
+            fs.create_dir(dst)?;
+            log.created.push(dst.to_owned());
+            for name in fs.read_dir(source)? {
+                copy(
+                    fs,
+                    &source.join(&name),
+                    &dst.join(&name),
+                    canonical_mtime,
+                    log,
+                )?;
+            }
+        }
+        FileKind::Symlink | FileKind::Other => {
+            let message = format!("Cannot copy {} in place of a symlink", source.display());
+            return Err(Error::other(message));
+        }
+    }
+
+    if let Some(time) = canonical_mtime {
+        fs.set_times(dst, metadata.accessed(), time)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,71 @@
+use std::io::{self, Read};
+
+#[cfg(feature = "json")]
+use serde::{Deserialize, Serialize};
+
+use super::{Archive, Entry, EntryKind};
+
+/// The type of node an [`EntryMeta`] describes.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "json", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "json", serde(rename_all = "lowercase"))]
+pub enum EntryType {
+    Directory,
+    Regular,
+    Symlink,
+    Unknown,
+}
+
+/// A snapshot of an entry without its contents, cheap to clone and store, for building external
+/// indexes of many archives.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "json", derive(Deserialize, Serialize))]
+pub struct EntryMeta {
+    /// Path within the archive with components joined by `/`, empty for the root.
+    pub path: String,
+    pub kind: EntryType,
+    /// Size of a regular file's contents, and 0 for every other node.
+    pub size: u64,
+    pub executable: bool,
+    /// Byte offset of a regular file's contents within the archive.
+    pub offset: Option<u64>,
+}
+
+impl Entry<'_> {
+    pub fn meta(&self) -> EntryMeta {
+        let (kind, size, executable, offset) = match &self.kind {
+            EntryKind::Directory => (EntryType::Directory, 0, false, None),
+            EntryKind::Regular {
+                executable,
+                data,
+                offset,
+            } => (
+                EntryType::Regular,
+                data.len() as u64,
+                *executable,
+                Some(*offset),
+            ),
+            EntryKind::Symlink { .. } => (EntryType::Symlink, 0, false, None),
+            EntryKind::Unknown { .. } => (EntryType::Unknown, 0, false, None),
+        };
+
+        EntryMeta {
+            path: self.nar_path(),
+            kind,
+            size,
+            executable,
+            offset,
+        }
+    }
+}
+
+/// Reads the metadata of every entry in an archive, in archive order. Contents are released as
+/// soon as each entry has been read.
+pub fn to_meta<R: Read>(reader: R) -> io::Result<Vec<EntryMeta>> {
+    let mut archive = Archive::new(reader);
+    let mut metas = Vec::new();
+    for entry in archive.entries()? {
+        metas.push(entry?.meta());
+    }
+    Ok(metas)
+}
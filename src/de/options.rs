@@ -0,0 +1,278 @@
+#[cfg(feature = "fs")]
+use std::io;
+use std::io::Read;
+#[cfg(feature = "fs")]
+use std::path::Path;
+#[cfg(feature = "fs")]
+use std::sync::Arc;
+
+#[cfg(feature = "fs")]
+use super::SymlinkPolicy;
+use super::{Archive, CaseCollision, DynArchive, ParseLimits, PathLimits};
+use crate::budget::MemoryBudget;
+#[cfg(feature = "fs")]
+use crate::clock::{Clock, UnixEpoch};
+#[cfg(feature = "fs")]
+use crate::temp::{SameFilesystem, TempProvider};
+#[cfg(feature = "fs")]
+use crate::vfs::{Filesystem, RealFilesystem};
+
+/// Unpacking configuration that can be built once and applied to any number of archives. It is
+/// `Send + Sync` and cloning it only bumps a reference count, so a single instance can be shared
+/// by every thread of a server.
+#[derive(Clone, Debug)]
+pub struct UnpackOptions {
+    pub(super) canonicalize_mtime: bool,
+    pub(super) case_collision: CaseCollision,
+    #[cfg(feature = "fs")]
+    pub(super) clock: Arc<dyn Clock>,
+    pub(super) continue_on_error: bool,
+    pub(super) copy_buffer_len: usize,
+    #[cfg(feature = "fs")]
+    pub(super) filesystem: Arc<dyn Filesystem>,
+    pub(super) lenient: bool,
+    pub(super) memory_budget: MemoryBudget,
+    pub(super) parse_limits: ParseLimits,
+    pub(super) path_limits: PathLimits,
+    pub(super) remove_xattrs: bool,
+    pub(super) replace_directories: bool,
+    pub(super) rollback_on_error: bool,
+    pub(super) strict_utf8_symlinks: bool,
+    #[cfg(feature = "fs")]
+    pub(super) symlink_policy: SymlinkPolicy,
+    #[cfg(feature = "fs")]
+    pub(super) temp_provider: Arc<dyn TempProvider>,
+    pub(super) verify_threads: usize,
+}
+
+impl UnpackOptions {
+    pub fn new() -> Self {
+        UnpackOptions::default()
+    }
+
+    pub fn set_canonicalize_mtime(&mut self, canonicalize: bool) {
+        self.canonicalize_mtime = canonicalize;
+    }
+
+    pub fn set_case_collision(&mut self, policy: CaseCollision) {
+        self.case_collision = policy;
+    }
+
+    /// Sets the time that mtimes are canonicalized to, the Unix epoch by default.
+    #[cfg(feature = "fs")]
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Arc::new(clock);
+    }
+
+    pub fn set_continue_on_error(&mut self, continue_on_error: bool) {
+        self.continue_on_error = continue_on_error;
+    }
+
+    /// Sets the size of the buffer that [`Archive::unpack_root_file`] streams file contents
+    /// through, and of the chunks `AsyncArchive::unpack` reads (64 KiB by default). Unpacking
+    /// otherwise holds the contents of each file in memory whole, so it is unaffected.
+    pub fn set_copy_buffer_len(&mut self, len: usize) {
+        self.copy_buffer_len = len.max(1);
+    }
+
+    /// Routes every filesystem operation made while unpacking entries through `filesystem`.
+    /// Atomic unpacking still stages through the [`TempProvider`], which uses the real disk.
+    #[cfg(feature = "fs")]
+    pub fn set_filesystem<F: Filesystem + 'static>(&mut self, filesystem: F) {
+        self.filesystem = Arc::new(filesystem);
+    }
+
+    /// Tolerates nodes of unrecognized types, keeping their tokens verbatim, and directory
+    /// entries out of canonical order, which are reported as [`Warning::NonCanonicalOrder`].
+    /// Duplicate entry names are rejected either way.
+    ///
+    /// [`Warning::NonCanonicalOrder`]: crate::Warning::NonCanonicalOrder
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
+    }
+
+    pub fn set_memory_budget(&mut self, budget: MemoryBudget) {
+        self.memory_budget = budget;
+    }
+
+    pub fn set_parse_limits(&mut self, limits: ParseLimits) {
+        self.parse_limits = limits;
+    }
+
+    pub fn set_path_limits(&mut self, limits: PathLimits) {
+        self.path_limits = limits;
+    }
+
+    pub fn set_remove_xattrs(&mut self, remove: bool) {
+        self.remove_xattrs = remove;
+    }
+
+    pub fn set_replace_directories(&mut self, replace: bool) {
+        self.replace_directories = replace;
+    }
+
+    pub fn set_rollback_on_error(&mut self, rollback: bool) {
+        self.rollback_on_error = rollback;
+    }
+
+    /// Rejects symlink targets that are not valid UTF-8, rather than unpacking their raw bytes.
+    pub fn set_strict_utf8_symlinks(&mut self, strict: bool) {
+        self.strict_utf8_symlinks = strict;
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn set_symlink_policy(&mut self, policy: SymlinkPolicy) {
+        self.symlink_policy = policy;
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn set_temp_provider<T: TempProvider + 'static>(&mut self, provider: T) {
+        self.temp_provider = Arc::new(provider);
+    }
+
+    pub fn set_verify_threads(&mut self, threads: usize) {
+        self.verify_threads = threads;
+    }
+
+    #[inline]
+    pub fn canonicalize_mtime(&self) -> bool {
+        self.canonicalize_mtime
+    }
+
+    #[inline]
+    pub fn case_collision(&self) -> CaseCollision {
+        self.case_collision
+    }
+
+    #[cfg(feature = "fs")]
+    #[inline]
+    pub fn clock(&self) -> &dyn Clock {
+        &*self.clock
+    }
+
+    #[inline]
+    pub fn continue_on_error(&self) -> bool {
+        self.continue_on_error
+    }
+
+    #[inline]
+    pub fn copy_buffer_len(&self) -> usize {
+        self.copy_buffer_len
+    }
+
+    #[cfg(feature = "fs")]
+    #[inline]
+    pub fn filesystem(&self) -> &dyn Filesystem {
+        &*self.filesystem
+    }
+
+    #[inline]
+    pub fn lenient(&self) -> bool {
+        self.lenient
+    }
+
+    #[inline]
+    pub fn memory_budget(&self) -> &MemoryBudget {
+        &self.memory_budget
+    }
+
+    #[inline]
+    pub fn parse_limits(&self) -> ParseLimits {
+        self.parse_limits
+    }
+
+    #[inline]
+    pub fn path_limits(&self) -> PathLimits {
+        self.path_limits
+    }
+
+    #[inline]
+    pub fn remove_xattrs(&self) -> bool {
+        self.remove_xattrs
+    }
+
+    #[inline]
+    pub fn replace_directories(&self) -> bool {
+        self.replace_directories
+    }
+
+    #[inline]
+    pub fn rollback_on_error(&self) -> bool {
+        self.rollback_on_error
+    }
+
+    #[inline]
+    pub fn strict_utf8_symlinks(&self) -> bool {
+        self.strict_utf8_symlinks
+    }
+
+    #[cfg(feature = "fs")]
+    #[inline]
+    pub fn symlink_policy(&self) -> SymlinkPolicy {
+        self.symlink_policy
+    }
+
+    #[inline]
+    pub fn verify_threads(&self) -> usize {
+        self.verify_threads
+    }
+
+    #[cfg(feature = "fs")]
+    #[inline]
+    pub fn temp_provider(&self) -> &dyn TempProvider {
+        &*self.temp_provider
+    }
+
+    /// Wraps `reader` in an `Archive` configured with a copy of these options.
+    pub fn archive<R: Read>(&self, reader: R) -> Archive<R> {
+        Archive::with_options(reader, self.clone())
+    }
+
+    /// Like [`archive`](UnpackOptions::archive), but boxes `reader` into a [`DynArchive`].
+    pub fn archive_dyn<'a>(&self, reader: Box<dyn Read + Send + 'a>) -> DynArchive<'a> {
+        Archive::with_options_dyn(reader, self.clone())
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn unpack<R: Read, P: AsRef<Path>>(&self, reader: R, dst: P) -> io::Result<()> {
+        self.archive(reader).unpack(dst)
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn unpack_atomic<R: Read, P: AsRef<Path>>(&self, reader: R, dst: P) -> io::Result<()> {
+        self.archive(reader).unpack_atomic(dst)
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn verify_tree<R: Read, P: AsRef<Path>>(&self, reader: R, root: P) -> io::Result<()> {
+        self.archive(reader).verify_tree(root)
+    }
+}
+
+impl Default for UnpackOptions {
+    fn default() -> Self {
+        UnpackOptions {
+            canonicalize_mtime: true,
+            case_collision: CaseCollision::default(),
+            #[cfg(feature = "fs")]
+            clock: Arc::new(UnixEpoch),
+            continue_on_error: false,
+            copy_buffer_len: crate::wire::DEFAULT_COPY_BUFFER_LEN,
+            #[cfg(feature = "fs")]
+            filesystem: Arc::new(RealFilesystem),
+            lenient: false,
+            memory_budget: MemoryBudget::default(),
+            parse_limits: ParseLimits::default(),
+            path_limits: PathLimits::default(),
+            remove_xattrs: true,
+            replace_directories: false,
+            rollback_on_error: false,
+            strict_utf8_symlinks: false,
+            #[cfg(feature = "fs")]
+            symlink_policy: SymlinkPolicy::default(),
+            #[cfg(feature = "fs")]
+            temp_provider: Arc::new(SameFilesystem),
+            verify_threads: 0,
+        }
+    }
+}
@@ -0,0 +1,387 @@
+use std::borrow::Cow;
+use std::io::{self, Error, ErrorKind};
+use std::mem;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use super::{symlink_target, warn, EntryKind, ParseError, UnpackOptions};
+use crate::budget::Reservation;
+use crate::{Warning, NIX_VERSION_MAGIC, PAD_LEN};
+
+/// A token read from an archive, together with its offset and the memory budget held for it.
+#[derive(Debug)]
+pub(super) struct Token {
+    pub bytes: Vec<u8>,
+    pub offset: u64,
+    pub reservation: Option<Reservation>,
+}
+
+/// A node parsed from an archive, in archive order.
+#[derive(Debug)]
+pub(super) struct Node {
+    pub path: PathBuf,
+    pub kind: EntryKind,
+    /// Memory budget held for the contents of regular files.
+    pub reservation: Option<Reservation>,
+}
+
+/// The archive grammar as a state machine fed one token at a time, so that blocking and async
+/// readers share a single parser. Each reader only reads tokens and feeds them in.
+#[derive(Debug)]
+pub(super) struct Parser {
+    state: State,
+    stack: Vec<OpenDir>,
+    options: UnpackOptions,
+}
+
+#[derive(Debug)]
+enum State {
+    Magic,
+    Open(PathBuf),
+    Type(PathBuf),
+    TypeName(PathBuf),
+    RegularTag {
+        path: PathBuf,
+        executable: bool,
+    },
+    ExecutableEmpty(PathBuf),
+    Contents {
+        path: PathBuf,
+        executable: bool,
+    },
+    RegularClose {
+        node: Box<Node>,
+    },
+    SymlinkTag(PathBuf),
+    SymlinkTarget(PathBuf),
+    SymlinkClose {
+        node: Box<Node>,
+    },
+    Unknown {
+        node: Box<Node>,
+        depth: usize,
+    },
+    /// The next field of the innermost open directory.
+    Field,
+    EntryOpen,
+    EntryName,
+    EntryNameValue,
+    EntryNode(PathBuf),
+    NestedClose,
+    Done,
+}
+
+/// A directory whose entries are still being parsed.
+#[derive(Debug)]
+struct OpenDir {
+    path: PathBuf,
+    prev_name: Option<String>,
+    len: usize,
+}
+
+impl Parser {
+    pub fn new(options: UnpackOptions) -> Self {
+        Parser {
+            state: State::Magic,
+            stack: Vec::new(),
+            options,
+        }
+    }
+
+    #[inline]
+    pub fn is_done(&self) -> bool {
+        matches!(self.state, State::Done)
+    }
+
+    /// Stops parsing, after the reader failed to produce the next token.
+    pub fn abort(&mut self) {
+        self.state = State::Done;
+    }
+
+    /// Whether the next token is the contents of a regular file, which readers may skip over
+    /// with [`Parser::skip_contents`] instead of reading.
+    #[cfg(feature = "tokio")]
+    #[inline]
+    pub fn expects_contents(&self) -> bool {
+        matches!(self.state, State::Contents { .. })
+    }
+
+    /// Accepts the file contents at `offset` without their bytes, leaving the data of the parsed
+    /// entry empty.
+    #[cfg(feature = "tokio")]
+    pub fn skip_contents(&mut self, offset: u64) -> io::Result<()> {
+        assert!(self.expects_contents(), "not at file contents");
+        let token = Token {
+            bytes: Vec::new(),
+            offset,
+            reservation: None,
+        };
+        self.feed(token, &Mutex::default()).map(|_| ())
+    }
+
+    /// Advances the parser past `token`, returning the node it completes, if any. After an
+    /// error, the parser is done.
+    pub fn feed(
+        &mut self,
+        token: Token,
+        warnings: &Mutex<Vec<Warning>>,
+    ) -> io::Result<Option<Node>> {
+        let result = self.try_feed(token, warnings);
+        if result.is_err() {
+            self.state = State::Done;
+        }
+        result
+    }
+
+    fn try_feed(
+        &mut self,
+        token: Token,
+        warnings: &Mutex<Vec<Warning>>,
+    ) -> io::Result<Option<Node>> {
+        let Token {
+            bytes,
+            offset,
+            reservation,
+        } = token;
+        let expect = |path: &PathBuf, expected: &'static [&'static str], message: &'static str| {
+            if expected.iter().any(|tag| tag.as_bytes() == &bytes[..]) {
+                Ok(())
+            } else {
+                let err = ParseError::new(message, path, offset, expected, bytes.clone());
+                Err(Error::from(err))
+            }
+        };
+
+        match mem::replace(&mut self.state, State::Done) {
+            State::Magic => {
+                if bytes != NIX_VERSION_MAGIC {
+                    return Err(Error::other("Not a valid NAR archive"));
+                }
+                self.state = State::Open(PathBuf::new());
+            }
+            State::Open(path) => {
+                expect(&path, &["("], "Missing open tag")?;
+                self.state = State::Type(path);
+            }
+            State::Type(path) => {
+                expect(&path, &["type"], "Missing type tag")?;
+                self.state = State::TypeName(path);
+            }
+            State::TypeName(path) => {
+                let type_name =
+                    String::from_utf8(bytes).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+                match type_name.as_str() {
+                    "regular" => {
+                        self.state = State::RegularTag {
+                            path,
+                            executable: false,
+                        }
+                    }
+                    "symlink" => self.state = State::SymlinkTag(path),
+                    "directory" => {
+                        self.stack.push(OpenDir {
+                            path: path.clone(),
+                            prev_name: None,
+                            len: 0,
+                        });
+                        self.state = State::Field;
+                        return Ok(Some(Node::new(path, EntryKind::Directory)));
+                    }
+                    _ if self.options.lenient => {
+                        // Preserve the body of unrecognized nodes verbatim, relying only on the
+                        // parentheses being balanced to find where the node ends.
+                        let kind = EntryKind::Unknown {
+                            type_name,
+                            raw_tokens: Vec::new(),
+                        };
+                        let node = Box::new(Node::new(path, kind));
+                        self.state = State::Unknown { node, depth: 0 };
+                    }
+                    _ => {
+                        let expected = &["regular", "symlink", "directory"];
+                        let message = "Unrecognized file type";
+                        let err = ParseError::new(message, &path, offset, expected, type_name);
+                        return Err(err.into());
+                    }
+                }
+            }
+            State::RegularTag { path, executable } => match &bytes[..] {
+                b"executable" if !executable => self.state = State::ExecutableEmpty(path),
+                b"contents" => self.state = State::Contents { path, executable },
+                _ => {
+                    let expected = &["contents"];
+                    let err =
+                        ParseError::new("Missing contents tag", &path, offset, expected, bytes);
+                    return Err(err.into());
+                }
+            },
+            State::ExecutableEmpty(path) => {
+                expect(&path, &[""], "Incorrect executable tag")?;
+                self.state = State::RegularTag {
+                    path,
+                    executable: true,
+                };
+            }
+            State::Contents { path, executable } => {
+                let kind = EntryKind::Regular {
+                    executable,
+                    data: bytes,
+                    offset: offset + PAD_LEN as u64,
+                };
+                let mut node = Box::new(Node::new(path, kind));
+                node.reservation = reservation;
+                self.state = State::RegularClose { node };
+            }
+            State::RegularClose { node } => {
+                expect(&node.path, &[")"], "Missing regular close tag")?;
+                return self.close_node(*node).map(Some);
+            }
+            State::SymlinkTag(path) => {
+                expect(&path, &["target"], "Missing target tag")?;
+                self.state = State::SymlinkTarget(path);
+            }
+            State::SymlinkTarget(path) => {
+                let target = symlink_target(bytes, self.options.strict_utf8_symlinks)?;
+                let node = Box::new(Node::new(path, EntryKind::Symlink { target }));
+                self.state = State::SymlinkClose { node };
+            }
+            State::SymlinkClose { node } => {
+                expect(&node.path, &[")"], "Missing symlink close tag")?;
+                return self.close_node(*node).map(Some);
+            }
+            State::Unknown { mut node, depth } => {
+                let depth = match &bytes[..] {
+                    b"(" => depth + 1,
+                    b")" if depth == 0 => return self.close_node(*node).map(Some),
+                    b")" => depth - 1,
+                    _ => depth,
+                };
+                if let EntryKind::Unknown { raw_tokens, .. } = &mut node.kind {
+                    raw_tokens.push(bytes);
+                }
+                self.state = State::Unknown { node, depth };
+            }
+            State::Field => {
+                let limits = self.options.parse_limits;
+                let dir = self.stack.last_mut().expect("no open directory");
+                match &bytes[..] {
+                    b"entry" => {
+                        dir.len += 1;
+                        if let Some(max) = limits.max_dir_entries().filter(|&max| dir.len > max) {
+                            let message = format!("Directory has more than {} entries", max);
+                            let err = ParseError::new(message, &dir.path, offset, &[], bytes);
+                            return Err(err.into());
+                        }
+                        self.state = State::EntryOpen;
+                    }
+                    b")" => {
+                        self.stack.pop();
+                        self.state = self.after_node();
+                    }
+                    _ => {
+                        let message = "Incorrect directory field";
+                        let expected = &["entry", ")"];
+                        let err = ParseError::new(message, &dir.path, offset, expected, bytes);
+                        return Err(err.into());
+                    }
+                }
+            }
+            State::EntryOpen => {
+                let path = &self.stack.last().expect("no open directory").path;
+                expect(path, &["("], "Missing nested open tag")?;
+                self.state = State::EntryName;
+            }
+            State::EntryName => {
+                let path = &self.stack.last().expect("no open directory").path;
+                expect(path, &["name"], "Missing name field")?;
+                self.state = State::EntryNameValue;
+            }
+            State::EntryNameValue => {
+                let limits = self.options.parse_limits;
+                let lenient = self.options.lenient;
+                let dir = self.stack.last_mut().expect("no open directory");
+                let name =
+                    String::from_utf8(bytes).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+                if let Err(message) = validate_entry_name(&name) {
+                    let err = ParseError::new(message, &dir.path, offset, &[], name);
+                    return Err(err.into());
+                }
+
+                if let Some(prev) = dir.prev_name.as_ref().filter(|prev| **prev >= name) {
+                    if *prev == name || !lenient {
+                        let message = if *prev == name {
+                            "Duplicate entry name"
+                        } else {
+                            "Entry is out of canonical order"
+                        };
+                        let err = ParseError::new(message, &dir.path, offset, &[], name);
+                        return Err(err.into());
+                    }
+                    let path = dir.path.join(&name);
+                    warn(warnings, Warning::NonCanonicalOrder { path });
+                }
+
+                let child_path = dir.path.join(&name);
+                let too_long = |&max: &usize| child_path.as_os_str().len() > max;
+                if let Some(max) = limits.max_path_len().filter(too_long) {
+                    let message = format!("Entry path exceeds {} bytes", max);
+                    let err = ParseError::new(message, &dir.path, offset, &[], name);
+                    return Err(err.into());
+                }
+                dir.prev_name = Some(name);
+                self.state = State::EntryNode(child_path);
+            }
+            State::EntryNode(child_path) => {
+                let path = &self.stack.last().expect("no open directory").path;
+                expect(path, &["node"], "Missing node field")?;
+                self.state = State::Open(child_path);
+            }
+            State::NestedClose => {
+                let path = &self.stack.last().expect("no open directory").path;
+                expect(path, &[")"], "Missing nested close tag")?;
+                self.state = State::Field;
+            }
+            State::Done => return Err(Error::other("Archive has already been parsed")),
+        }
+
+        Ok(None)
+    }
+
+    fn close_node(&mut self, node: Node) -> io::Result<Node> {
+        self.state = self.after_node();
+        Ok(node)
+    }
+
+    /// The state after a node ends: the close tag of the directory entry wrapping it, if any.
+    fn after_node(&self) -> State {
+        if self.stack.is_empty() {
+            State::Done
+        } else {
+            State::NestedClose
+        }
+    }
+}
+
+impl Node {
+    fn new(path: PathBuf, kind: EntryKind) -> Self {
+        Node {
+            path,
+            kind,
+            reservation: None,
+        }
+    }
+}
+
+/// Checks that `name` can name a directory entry, explaining why not otherwise. Writers share it
+/// so that they never produce archives the parser rejects.
+pub(crate) fn validate_entry_name(name: &str) -> Result<(), Cow<'static, str>> {
+    match name {
+        "" => Err("Entry name is empty".into()),
+        "/" => Err("Invalid name `/`".into()),
+        "~" => Err("Invalid name `~`".into()),
+        "." => Err("Invalid name `.`".into()),
+        ".." => Err("Invalid name `..`".into()),
+        _ if name.contains(&['/', '\0'][..]) => Err(format!("Invalid name {:?}", name).into()),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,187 @@
+use std::io::{self, Error, ErrorKind, Read, Write};
+
+use super::{symlink_target, Archive, ArchiveInner};
+use crate::{wire, SymlinkTarget, NIX_VERSION_MAGIC, PAD_LEN};
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct FileInfo {
+    size: u64,
+    executable: bool,
+}
+
+impl FileInfo {
+    #[inline]
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    #[inline]
+    pub fn executable(&self) -> bool {
+        self.executable
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RootKind {
+    Directory,
+    File { executable: bool, size: u64 },
+    Symlink { target: SymlinkTarget },
+    Unknown { type_name: String },
+}
+
+/// Records everything read through it so that the bytes can be handed back to the archive.
+struct Recorder<'r, 'a> {
+    inner: &'r ArchiveInner<dyn Read + 'a>,
+    bytes: Vec<u8>,
+}
+
+impl Read for Recorder<'_, '_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = (&mut self.inner).read(buf)?;
+        self.bytes.extend_from_slice(&buf[..bytes_read]);
+        Ok(bytes_read)
+    }
+}
+
+impl Recorder<'_, '_> {
+    fn read_utf8(&mut self) -> io::Result<String> {
+        let bytes = wire::read_token(self, wire::MAX_METADATA_TOKEN_LEN)?;
+        String::from_utf8(bytes).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+}
+
+impl<'a> Archive<dyn Read + 'a> {
+    pub(super) fn root_kind_inner(&mut self) -> io::Result<RootKind> {
+        if self.inner.position.get() != 0 {
+            let message = "Cannot call `root_kind` unless reader is in position 0";
+            return Err(Error::other(message));
+        }
+
+        let mut recorder = Recorder {
+            inner: &self.inner,
+            bytes: Vec::new(),
+        };
+        let result = peek_root(&mut recorder);
+
+        let mut replay = self.inner.replay.borrow_mut();
+        let start = replay.position() as usize;
+        let mut bytes = recorder.bytes;
+        bytes.extend_from_slice(&replay.get_ref()[start..]);
+        *replay = io::Cursor::new(bytes);
+        self.inner.position.set(0);
+
+        result
+    }
+
+    pub(super) fn unpack_root_file_inner(
+        &mut self,
+        writer: &mut dyn Write,
+    ) -> io::Result<FileInfo> {
+        if self.inner.position.get() != 0 {
+            let message = "Cannot call `unpack_root_file` unless reader is in position 0";
+            return Err(Error::other(message));
+        }
+
+        if self.read_bytes_padded()? != NIX_VERSION_MAGIC {
+            return Err(Error::other("Not a valid NAR archive"));
+        }
+
+        if self.read_utf8_padded()? != "(" {
+            return Err(Error::other("Missing open tag"));
+        }
+
+        if self.read_utf8_padded()? != "type" {
+            return Err(Error::other("Missing type tag"));
+        }
+
+        let type_name = self.read_utf8_padded()?;
+        if type_name != "regular" {
+            let message = format!("Archive root is a {}, not a regular file", type_name);
+            return Err(Error::new(ErrorKind::InvalidData, message));
+        }
+
+        let mut executable = false;
+        let mut tag = self.read_utf8_padded()?;
+        if tag == "executable" {
+            executable = true;
+            if !self.read_utf8_padded()?.is_empty() {
+                return Err(Error::other("Incorrect executable tag"));
+            }
+            tag = self.read_utf8_padded()?;
+        }
+
+        if tag != "contents" {
+            return Err(Error::other("Missing contents tag"));
+        }
+
+        let mut reader = &self.inner;
+        let mut len_buffer = [0u8; PAD_LEN];
+        reader.read_exact(&mut len_buffer)?;
+        let size = u64::from_le_bytes(len_buffer);
+
+        let buffer_len = self.inner.options.copy_buffer_len;
+        let copied = wire::copy_buffered(&mut (&mut reader).take(size), writer, buffer_len)?;
+        if copied != size {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "Truncated file contents",
+            ));
+        }
+        wire::read_padding(&mut reader, size)?;
+
+        if self.read_utf8_padded()? != ")" {
+            return Err(Error::other("Missing regular close tag"));
+        }
+
+        Ok(FileInfo { size, executable })
+    }
+}
+
+fn peek_root(reader: &mut Recorder) -> io::Result<RootKind> {
+    if wire::read_token(reader, wire::MAX_METADATA_TOKEN_LEN)? != NIX_VERSION_MAGIC {
+        return Err(Error::other("Not a valid NAR archive"));
+    }
+
+    if reader.read_utf8()? != "(" {
+        return Err(Error::other("Missing open tag"));
+    }
+
+    if reader.read_utf8()? != "type" {
+        return Err(Error::other("Missing type tag"));
+    }
+
+    let type_name = reader.read_utf8()?;
+    match type_name.as_str() {
+        "directory" => Ok(RootKind::Directory),
+        "regular" => {
+            let mut executable = false;
+            let mut tag = reader.read_utf8()?;
+            if tag == "executable" {
+                executable = true;
+                if !reader.read_utf8()?.is_empty() {
+                    return Err(Error::other("Incorrect executable tag"));
+                }
+                tag = reader.read_utf8()?;
+            }
+
+            if tag != "contents" {
+                return Err(Error::other("Missing contents tag"));
+            }
+
+            let mut len_buffer = [0u8; PAD_LEN];
+            reader.read_exact(&mut len_buffer)?;
+            let size = u64::from_le_bytes(len_buffer);
+            Ok(RootKind::File { executable, size })
+        }
+        "symlink" => {
+            if reader.read_utf8()? != "target" {
+                return Err(Error::other("Missing target tag"));
+            }
+            let strict = reader.inner.options.strict_utf8_symlinks;
+            let target = wire::read_token(reader, wire::MAX_METADATA_TOKEN_LEN)?;
+            let target = symlink_target(target, strict)?;
+            Ok(RootKind::Symlink { target })
+        }
+        _ => Ok(RootKind::Unknown { type_name }),
+    }
+}
@@ -0,0 +1,78 @@
+use std::io;
+use std::path::Path;
+
+use crate::SymlinkTarget;
+
+pub trait ExtractSink {
+    fn create_dir(&mut self, path: &Path) -> io::Result<()>;
+
+    fn create_file(&mut self, path: &Path, executable: bool, contents: &[u8]) -> io::Result<()>;
+
+    fn create_symlink(&mut self, path: &Path, target: &SymlinkTarget) -> io::Result<()>;
+}
+
+impl<S: ExtractSink + ?Sized> ExtractSink for &mut S {
+    fn create_dir(&mut self, path: &Path) -> io::Result<()> {
+        (**self).create_dir(path)
+    }
+
+    fn create_file(&mut self, path: &Path, executable: bool, contents: &[u8]) -> io::Result<()> {
+        (**self).create_file(path, executable, contents)
+    }
+
+    fn create_symlink(&mut self, path: &Path, target: &SymlinkTarget) -> io::Result<()> {
+        (**self).create_symlink(path, target)
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct BlackHole {
+    directories: u64,
+    files: u64,
+    symlinks: u64,
+    bytes: u64,
+}
+
+impl BlackHole {
+    pub fn new() -> Self {
+        BlackHole::default()
+    }
+
+    #[inline]
+    pub fn directories(&self) -> u64 {
+        self.directories
+    }
+
+    #[inline]
+    pub fn files(&self) -> u64 {
+        self.files
+    }
+
+    #[inline]
+    pub fn symlinks(&self) -> u64 {
+        self.symlinks
+    }
+
+    #[inline]
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+}
+
+impl ExtractSink for BlackHole {
+    fn create_dir(&mut self, _path: &Path) -> io::Result<()> {
+        self.directories += 1;
+        Ok(())
+    }
+
+    fn create_file(&mut self, _path: &Path, _executable: bool, contents: &[u8]) -> io::Result<()> {
+        self.files += 1;
+        self.bytes += contents.len() as u64;
+        Ok(())
+    }
+
+    fn create_symlink(&mut self, _path: &Path, _target: &SymlinkTarget) -> io::Result<()> {
+        self.symlinks += 1;
+        Ok(())
+    }
+}
@@ -0,0 +1,173 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Error, ErrorKind, Read, Write};
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::path::Path;
+
+use super::Archive;
+use crate::narmeta::{MetaEntry, MetaKind, NarMeta};
+
+impl Archive<File> {
+    /// Indexes the whole archive file and divides its entries into at most `n` contiguous ranges
+    /// holding roughly the same number of content bytes.
+    ///
+    /// Each slice reads through its own clone of the file descriptor with positioned reads, so
+    /// slices share no state with each other or with this archive and can be processed on
+    /// separate threads.
+    pub fn try_split(&self, n: usize) -> io::Result<Vec<ArchiveSlice>> {
+        if n == 0 {
+            let message = "Cannot split an archive into zero slices";
+            return Err(Error::new(ErrorKind::InvalidInput, message));
+        }
+
+        let (file, meta) = self.index()?;
+        let total: u64 = meta.entries().iter().map(weight).sum();
+        let target = total.div_ceil(n as u64);
+
+        let mut slices = Vec::new();
+        let mut entries = Vec::new();
+        let mut filled = 0;
+        for entry in meta.entries() {
+            entries.push(entry.clone());
+            filled += weight(entry);
+            if filled >= target && slices.len() + 1 < n {
+                let file = file.try_clone()?;
+                slices.push(ArchiveSlice::new(file, std::mem::take(&mut entries)));
+                filled = 0;
+            }
+        }
+        if !entries.is_empty() {
+            slices.push(ArchiveSlice::new(file, entries));
+        }
+
+        Ok(slices)
+    }
+
+    /// Like [`try_split`](Archive::try_split), but orders entries by descending `priority` of
+    /// their paths, so that unpacking the slices on separate threads extracts e.g. `bin/*` before
+    /// the documentation. Each entry goes to the slice holding the fewest content bytes so far,
+    /// spreading urgent entries across every slice; entries of equal priority keep their archive
+    /// order.
+    pub fn try_split_by_priority<F>(&self, n: usize, priority: F) -> io::Result<Vec<ArchiveSlice>>
+    where
+        F: Fn(&str) -> i32,
+    {
+        if n == 0 {
+            let message = "Cannot split an archive into zero slices";
+            return Err(Error::new(ErrorKind::InvalidInput, message));
+        }
+
+        let (file, meta) = self.index()?;
+        let mut ordered: Vec<&MetaEntry> = meta.entries().iter().collect();
+        ordered.sort_by_key(|entry| std::cmp::Reverse(priority(entry.path())));
+
+        let mut buckets: Vec<(u64, Vec<MetaEntry>)> = vec![(0, Vec::new()); n];
+        for entry in ordered {
+            let (filled, entries) = buckets
+                .iter_mut()
+                .min_by_key(|(filled, _)| *filled)
+                .expect("n is not zero");
+            *filled += weight(entry);
+            entries.push(entry.clone());
+        }
+
+        let mut slices = Vec::new();
+        for (_, entries) in buckets.into_iter().filter(|(_, e)| !e.is_empty()) {
+            slices.push(ArchiveSlice::new(file.try_clone()?, entries));
+        }
+        Ok(slices)
+    }
+
+    fn index(&self) -> io::Result<(File, NarMeta)> {
+        let file = self.inner.reader.borrow().try_clone()?;
+        let meta = NarMeta::generate(PositionedReader {
+            file: &file,
+            offset: 0,
+        })?;
+        Ok((file, meta))
+    }
+}
+
+fn weight(entry: &MetaEntry) -> u64 {
+    match entry.kind() {
+        MetaKind::Regular { size, .. } => size + 1,
+        _ => 1,
+    }
+}
+
+/// Entries from an archive file, produced by [`Archive::try_split`] or
+/// [`Archive::try_split_by_priority`].
+#[derive(Debug)]
+pub struct ArchiveSlice {
+    file: File,
+    entries: Vec<MetaEntry>,
+}
+
+impl ArchiveSlice {
+    fn new(file: File, entries: Vec<MetaEntry>) -> Self {
+        ArchiveSlice { file, entries }
+    }
+
+    /// The entries in this slice, in the order they are unpacked.
+    #[inline]
+    pub fn entries(&self) -> &[MetaEntry] {
+        &self.entries
+    }
+
+    /// Reads the contents of a regular file entry from this slice.
+    pub fn read_file(&self, entry: &MetaEntry) -> io::Result<Vec<u8>> {
+        match entry.kind() {
+            MetaKind::Regular { offset, size, .. } => {
+                let mut contents = vec![0; *size as usize];
+                self.file.read_exact_at(&mut contents, *offset)?;
+                Ok(contents)
+            }
+            _ => Err(Error::other(format!(
+                "Not a regular file: {}",
+                entry.path()
+            ))),
+        }
+    }
+
+    /// Unpacks the entries of this slice below `dst`, creating any parent directories that
+    /// belong to other slices.
+    pub fn unpack_in<P: AsRef<Path>>(&self, dst: P) -> io::Result<()> {
+        for entry in &self.entries {
+            let path = if entry.path().is_empty() {
+                dst.as_ref().to_owned()
+            } else {
+                dst.as_ref().join(entry.path())
+            };
+            if let Some(parent) = path.parent().filter(|_| !entry.path().is_empty()) {
+                fs::create_dir_all(parent)?;
+            }
+
+            match entry.kind() {
+                MetaKind::Directory => fs::create_dir_all(&path)?,
+                MetaKind::Regular { executable, .. } => {
+                    let mode = if *executable { 0o555 } else { 0o444 };
+                    let mut file = OpenOptions::new()
+                        .create_new(true)
+                        .write(true)
+                        .mode(mode)
+                        .open(&path)?;
+                    file.write_all(&self.read_file(entry)?)?;
+                }
+                MetaKind::Symlink { target } => std::os::unix::fs::symlink(target, &path)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+struct PositionedReader<'a> {
+    file: &'a File,
+    offset: u64,
+}
+
+impl Read for PositionedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.file.read_at(buf, self.offset)?;
+        self.offset += len as u64;
+        Ok(len)
+    }
+}
@@ -0,0 +1,298 @@
+use std::fs;
+use std::io::{self, Error, ErrorKind, Read};
+use std::path::{Component, Path, PathBuf};
+
+use super::case::CaseFolder;
+use super::materialize::{self, DeferredSymlink, SymlinkPolicy};
+use super::{warn, Archive, Entry, EntryKind, PathLimits, UnpackError, UnpackFailures};
+use crate::vfs::{Filesystem, RealFilesystem};
+use crate::Warning;
+
+impl<'a> Archive<dyn Read + 'a> {
+    pub(super) fn unpack_atomic_inner(&mut self, dst: &Path) -> io::Result<()> {
+        if fs::symlink_metadata(dst).is_ok() {
+            let message = format!("Destination {} already exists", dst.display());
+            return Err(Error::new(ErrorKind::AlreadyExists, message));
+        }
+
+        let staging = self.inner.options.temp_provider.create_temp_dir(dst)?;
+        let staged = staging.join("out");
+        let result = self
+            .unpack_inner(&staged, &mut UnpackLog::new())
+            .and_then(|_| fs::rename(&staged, dst));
+        let cleanup = fs::remove_dir_all(&staging);
+        result.and(cleanup)
+    }
+
+    pub(super) fn unpack_inner(&mut self, dst: &Path, log: &mut UnpackLog) -> io::Result<()> {
+        let rollback_on_error = self.inner.options.rollback_on_error;
+        let result = self.unpack_entries(dst, log);
+        if result.is_err() && rollback_on_error {
+            let _ = log.rollback_in(&*self.inner.options.filesystem);
+        }
+        result
+    }
+
+    fn unpack_entries(&mut self, dst: &Path, log: &mut UnpackLog) -> io::Result<()> {
+        let case_collision = self.inner.options.case_collision;
+        let continue_on_error = self.inner.options.continue_on_error;
+        let symlink_policy = self.inner.options.symlink_policy;
+        let warnings = self.inner.warnings.clone();
+        let mut case_folder = CaseFolder::default();
+        let mut failures = Vec::new();
+        let mut deferred = Vec::new();
+
+        for entry in self.entries_inner()? {
+            let mut file = entry?;
+            let result = case_folder
+                .resolve(&file.name, file.is_dir(), case_collision)
+                .and_then(|name| {
+                    file.name = name;
+                    match &file.kind {
+                        EntryKind::Symlink { target }
+                            if symlink_policy != SymlinkPolicy::Preserve =>
+                        {
+                            let path = if file.name.as_os_str().is_empty() {
+                                dst.to_owned()
+                            } else {
+                                dst.join(&file.name)
+                            };
+                            file.path_limits.check(&path)?;
+                            deferred.push(DeferredSymlink {
+                                name: file.name.clone(),
+                                path,
+                                target: target.clone(),
+                            });
+                            Ok(())
+                        }
+                        _ => file.unpack_in_logged(dst, log),
+                    }
+                });
+            for warning in case_folder.take_warnings() {
+                warn(&warnings, warning);
+            }
+
+            match result {
+                Err(err) if continue_on_error => failures.push((file.name, err)),
+                result => result?,
+            }
+        }
+
+        if !deferred.is_empty() {
+            let options = &self.inner.options;
+            let canonical_mtime =
+                Some(options.clock.canonical_mtime()).filter(|_| options.canonicalize_mtime);
+            let fs = &*options.filesystem;
+            for (link, err) in materialize::materialize(fs, dst, deferred, canonical_mtime, log) {
+                if symlink_policy == SymlinkPolicy::CopyOrSkip {
+                    warn(&warnings, materialize::skipped(link));
+                } else if continue_on_error {
+                    failures.push((link.name, err));
+                } else {
+                    return Err(err);
+                }
+            }
+        }
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(UnpackFailures { failures }.into())
+        }
+    }
+}
+
+impl<'a> Entry<'a> {
+    pub fn set_canonicalize_mtime(&mut self, canonicalize: bool) {
+        self.canonicalize_mtime = canonicalize;
+    }
+
+    pub fn set_path_limits(&mut self, limits: PathLimits) {
+        self.path_limits = limits;
+    }
+
+    pub fn set_remove_xattrs(&mut self, remove: bool) {
+        self.remove_xattrs = remove;
+    }
+
+    pub fn set_replace_directories(&mut self, replace: bool) {
+        self.replace_directories = replace;
+    }
+
+    pub fn unpack_in<P: AsRef<Path>>(&mut self, dst: P) -> io::Result<()> {
+        self.unpack_in_logged(dst, &mut UnpackLog::new())
+    }
+
+    pub fn unpack_in_logged<P: AsRef<Path>>(
+        &mut self,
+        dst: P,
+        log: &mut UnpackLog,
+    ) -> io::Result<()> {
+        let path = if self.name.as_os_str().is_empty() {
+            dst.as_ref().to_owned()
+        } else {
+            dst.as_ref().join(&self.name)
+        };
+
+        for component in self.name.components() {
+            if let Component::Prefix(_) | Component::RootDir | Component::ParentDir = component {
+                let message = format!("Invalid path component in {:?}", path);
+                return Err(Error::other(message));
+            }
+        }
+
+        self.path_limits.check(&path)?;
+
+        // Never write through a symlink, whether it was unpacked by an earlier entry or already
+        // present in the destination.
+        let fs = &*self.filesystem;
+        let mut ancestor = dst.as_ref().to_owned();
+        let mut components = self.name.components();
+        components.next_back();
+        for component in components {
+            ancestor.push(component);
+            let is_symlink = fs
+                .metadata(&ancestor)
+                .map(|m| m.is_symlink())
+                .unwrap_or(false);
+            if is_symlink {
+                return Err(UnpackError::TraversesSymlink(path).into());
+            }
+        }
+
+        // If the timestamp of our parent has been canonicalized, we want to keep it that way after
+        // we unpack, whether we choose to canonicalize as well or not.
+        let canonical_mtime = self.clock.canonical_mtime();
+        let recanonicalize_parent = path
+            .parent()
+            .filter(|_| !self.name.as_os_str().is_empty())
+            .and_then(|p| fs.metadata(p).ok())
+            .filter(|m| m.created() == Some(canonical_mtime));
+
+        let existed = fs.metadata(&path).is_ok();
+        let result = match &mut self.kind {
+            EntryKind::Directory => Self::unpack_dir(fs, &path),
+            EntryKind::Regular {
+                executable, data, ..
+            } => Self::remove_existing(fs, &path, self.replace_directories)
+                .and_then(|_| fs.create_file(&path, *executable, data)),
+            EntryKind::Symlink { target } => {
+                Self::remove_existing(fs, &path, self.replace_directories)
+                    .and_then(|_| fs.create_symlink(&path, target.as_path()))
+            }
+            EntryKind::Unknown { type_name, .. } => {
+                let message = format!("Cannot unpack unrecognized node type `{}`", type_name);
+                Err(Error::other(message))
+            }
+        };
+
+        // Record the path even if unpacking failed partway, so a rollback can clean it up.
+        if !existed && fs.metadata(&path).is_ok() {
+            log.created.push(path.clone());
+        }
+        result?;
+
+        if self.remove_xattrs {
+            for attr in fs.xattrs(&path)? {
+                fs.remove_xattr(&path, &attr)?;
+                let path = path.clone();
+                warn(&self.warnings, Warning::XattrDropped { path, name: attr });
+            }
+        }
+
+        if self.canonicalize_mtime {
+            let metadata = fs.metadata(&path)?;
+            fs.set_times(&path, metadata.accessed(), canonical_mtime)?;
+        }
+
+        if let Some(metadata) = recanonicalize_parent {
+            if let Some(parent) = path.parent() {
+                fs.set_times(parent, metadata.accessed(), canonical_mtime)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn unpack_dir(fs: &dyn Filesystem, dst: &Path) -> io::Result<()> {
+        fs.create_dir(dst).or_else(|err| {
+            if err.kind() == ErrorKind::AlreadyExists {
+                match fs.metadata(dst) {
+                    Ok(m) if m.is_dir() => return Ok(()),
+                    Ok(m) if m.is_symlink() => {
+                        return Err(UnpackError::TraversesSymlink(dst.to_owned()).into());
+                    }
+                    _ => {}
+                }
+            }
+            Err(Error::new(
+                err.kind(),
+                format!("{} when creating dir {}", err, dst.display()),
+            ))
+        })
+    }
+
+    fn remove_existing(
+        fs: &dyn Filesystem,
+        dst: &Path,
+        replace_directories: bool,
+    ) -> io::Result<()> {
+        match fs.metadata(dst) {
+            Ok(metadata) if metadata.is_dir() => {
+                if replace_directories {
+                    fs.remove_dir_all(dst)
+                } else {
+                    Err(UnpackError::WouldReplaceDirectory(dst.to_owned()).into())
+                }
+            }
+            Ok(_) => fs.remove_file(dst),
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct UnpackLog {
+    pub(super) created: Vec<PathBuf>,
+}
+
+impl UnpackLog {
+    pub fn new() -> Self {
+        UnpackLog::default()
+    }
+
+    #[inline]
+    pub fn created(&self) -> &[PathBuf] {
+        &self.created
+    }
+
+    pub fn clear(&mut self) {
+        self.created.clear();
+    }
+
+    /// Removes every recorded path in the reverse order of creation, continuing past failures and
+    /// returning the first error encountered, if any.
+    pub fn rollback(&mut self) -> io::Result<()> {
+        self.rollback_in(&RealFilesystem)
+    }
+
+    /// Like [`rollback`](UnpackLog::rollback), for paths created in another filesystem.
+    pub fn rollback_in(&mut self, fs: &dyn Filesystem) -> io::Result<()> {
+        let mut first_error = None;
+
+        while let Some(path) = self.created.pop() {
+            let result = match fs.metadata(&path) {
+                Ok(metadata) if metadata.is_dir() => fs.remove_dir(&path),
+                Ok(_) => fs.remove_file(&path),
+                Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(()),
+                Err(err) => Err(err),
+            };
+
+            if let Err(err) = result {
+                first_error.get_or_insert(err);
+            }
+        }
+
+        first_error.map_or(Ok(()), Err)
+    }
+}
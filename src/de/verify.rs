@@ -0,0 +1,155 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Error, ErrorKind, Read};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use super::{Archive, EntryKind};
+use crate::hash;
+
+const JOBS_PER_THREAD: usize = 4;
+
+struct Job {
+    name: PathBuf,
+    path: PathBuf,
+    data: Vec<u8>,
+}
+
+impl<'a> Archive<dyn Read + 'a> {
+    pub(super) fn verify_tree_inner(&mut self, root: &Path) -> io::Result<()> {
+        let threads = match self.inner.options.verify_threads {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        };
+
+        let (jobs, queue) = mpsc::sync_channel::<Job>(threads * JOBS_PER_THREAD);
+        let queue = Arc::new(Mutex::new(queue));
+        let (report, failures) = mpsc::channel();
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                let queue = queue.clone();
+                let report = report.clone();
+                thread::spawn(move || hash_files(&queue, &report))
+            })
+            .collect();
+        drop(report);
+
+        let result = self.verify_entries(root, &jobs);
+        drop(jobs);
+        for worker in workers {
+            worker
+                .join()
+                .map_err(|_| Error::other("Verification worker panicked"))?;
+        }
+
+        result?;
+        match failures.recv() {
+            Ok(err) => Err(err),
+            Err(_) => Ok(()),
+        }
+    }
+
+    fn verify_entries(&mut self, root: &Path, jobs: &SyncSender<Job>) -> io::Result<()> {
+        let mut seen = HashSet::new();
+
+        for entry in self.entries_inner()? {
+            let entry = entry?;
+            let path = root.join(&entry.name);
+            let metadata = fs::symlink_metadata(&path)
+                .map_err(|e| mismatch(&entry.name, format!("cannot stat ({})", e)))?;
+            let file_type = metadata.file_type();
+
+            match entry.kind {
+                EntryKind::Directory if file_type.is_dir() => {}
+                EntryKind::Regular {
+                    executable, data, ..
+                } if file_type.is_file() => {
+                    if metadata.len() != data.len() as u64 {
+                        let what =
+                            format!("expected size {}, found {}", data.len(), metadata.len());
+                        return Err(mismatch(&entry.name, what));
+                    }
+                    let actual = metadata.permissions().mode() & 0o111 != 0;
+                    if actual != executable {
+                        let what = format!("expected executable {}, found {}", executable, actual);
+                        return Err(mismatch(&entry.name, what));
+                    }
+                    let job = Job {
+                        name: entry.name.clone(),
+                        path,
+                        data,
+                    };
+                    // Workers only hang up early after reporting a failure, which is picked up
+                    // once the stream has been drained.
+                    if jobs.send(job).is_err() {
+                        break;
+                    }
+                }
+                EntryKind::Symlink { ref target } if file_type.is_symlink() => {
+                    let actual = fs::read_link(&path)?;
+                    if actual != target.as_path() {
+                        let what = format!("expected target {:?}, found {:?}", target, actual);
+                        return Err(mismatch(&entry.name, what));
+                    }
+                }
+                EntryKind::Unknown { ref type_name, .. } => {
+                    let message = format!("Cannot verify unrecognized node type `{}`", type_name);
+                    return Err(Error::other(message));
+                }
+                _ => return Err(mismatch(&entry.name, "node types differ".to_owned())),
+            }
+
+            seen.insert(entry.name);
+        }
+
+        find_unexpected(root, Path::new(""), &seen)
+    }
+}
+
+fn hash_files(queue: &Mutex<Receiver<Job>>, report: &mpsc::Sender<Error>) {
+    loop {
+        let job = match queue.lock().map(|queue| queue.recv()) {
+            Ok(Ok(job)) => job,
+            _ => return,
+        };
+
+        let result = hash::hash_flat_file(&job.path).and_then(|actual| {
+            let expected = hash::hash_flat_reader(&job.data[..])?;
+            if actual == expected {
+                Ok(())
+            } else {
+                let what = format!("expected contents {}, found {}", expected, actual);
+                Err(mismatch(&job.name, what))
+            }
+        });
+
+        if let Err(err) = result {
+            let _ = report.send(err);
+            return;
+        }
+    }
+}
+
+fn find_unexpected(root: &Path, name: &Path, seen: &HashSet<PathBuf>) -> io::Result<()> {
+    let path = root.join(name);
+    if !fs::symlink_metadata(&path)?.is_dir() {
+        return Ok(());
+    }
+
+    for child in fs::read_dir(&path)? {
+        let child = name.join(child?.file_name());
+        if !seen.contains(&child) {
+            return Err(mismatch(&child, "unexpected entry".to_owned()));
+        }
+        find_unexpected(root, &child, seen)?;
+    }
+    Ok(())
+}
+
+fn mismatch(name: &Path, what: String) -> Error {
+    let message = format!("Tree mismatch at {:?}: {}", name, what);
+    Error::new(ErrorKind::InvalidData, message)
+}
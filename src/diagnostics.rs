@@ -0,0 +1,91 @@
+use std::fmt::Write;
+use std::io;
+use std::path::Path;
+
+use crate::de::{ParseError, UnpackError, UnpackFailures};
+use crate::wire;
+
+const BYTES_PER_LINE: usize = 16;
+const MAX_DUMP_LEN: usize = 64;
+
+/// Renders an error returned by this crate as a multi-line report, including the entry path,
+/// byte offset, expected and found tags, and a hexdump of the offending region where known.
+pub fn render(err: &io::Error) -> String {
+    let mut out = format!("error: {}\n", err);
+
+    let inner = match err.get_ref() {
+        Some(inner) => inner,
+        None => return out,
+    };
+
+    if let Some(err) = inner.downcast_ref::<ParseError>() {
+        render_parse_error(&mut out, err);
+    } else if let Some(err) = inner.downcast_ref::<UnpackError>() {
+        let path = match err {
+            UnpackError::CaseCollision(path)
+            | UnpackError::NameTooLong { path, .. }
+            | UnpackError::PathTooLong { path, .. }
+            | UnpackError::TraversesSymlink(path)
+            | UnpackError::WouldReplaceDirectory(path) => path,
+        };
+        let _ = writeln!(out, "  --> {}", path.display());
+    } else if let Some(err) = inner.downcast_ref::<UnpackFailures>() {
+        for (path, err) in err.failures() {
+            let _ = writeln!(out, "  --> {}: {}", path.display(), err);
+        }
+    }
+
+    out
+}
+
+fn render_parse_error(out: &mut String, err: &ParseError) {
+    let _ = writeln!(out, "  --> {}", path_chain(err.path()));
+    let _ = writeln!(out, "   at byte {} (0x{:x})", err.offset(), err.offset());
+
+    if !err.expected().is_empty() {
+        let expected: Vec<_> = err.expected().iter().map(|t| format!("{:?}", t)).collect();
+        let _ = writeln!(out, "   expected: {}", expected.join(" or "));
+    }
+    let _ = writeln!(
+        out,
+        "      found: {:?}",
+        String::from_utf8_lossy(err.found())
+    );
+
+    // Re-encode the token to show the bytes exactly as they appear in the archive.
+    let mut encoded = Vec::new();
+    let _ = wire::write_token(&mut encoded, err.found());
+    let shown = encoded.len().min(MAX_DUMP_LEN);
+    out.push('\n');
+    for (i, line) in encoded[..shown].chunks(BYTES_PER_LINE).enumerate() {
+        let offset = err.offset() + (i * BYTES_PER_LINE) as u64;
+        let hex: Vec<_> = line.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = line
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        let _ = writeln!(
+            out,
+            "   {:08x}  {:<width$}  |{}|",
+            offset,
+            hex.join(" "),
+            ascii,
+            width = BYTES_PER_LINE * 3 - 1
+        );
+    }
+    if shown < encoded.len() {
+        let _ = writeln!(out, "   ... {} more bytes", encoded.len() - shown);
+    }
+}
+
+fn path_chain(path: &Path) -> String {
+    let mut chain = vec!["<root>".to_owned()];
+    chain.extend(path.iter().map(|c| c.to_string_lossy().into_owned()));
+    chain.join(" > ")
+}
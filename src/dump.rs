@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+use std::io::{self, Error, ErrorKind, Read, Write};
+
+use serde_json::{Map, Value};
+
+use crate::de::{validate_entry_name, EntryKind};
+use crate::hash::hash_flat_reader;
+use crate::tree::{target_problem, NarTree, Node};
+use crate::{encoding, Archive};
+
+/// How [`nar_to_json`] represents the contents of regular files.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ContentEncoding {
+    /// The contents themselves, as standard padded base64 in a `contents` field.
+    Base64,
+    /// Nothing beyond the size of each file.
+    Skip,
+    /// The SHA-256 of the contents in SRI form, in a `hash` field.
+    Hash,
+}
+
+/// Writes the structure of an archive as a single JSON document, entry by entry as it is read.
+///
+/// Nodes use the same shape as `nix nar ls --json --recursive`: every node has a `type`,
+/// directories map names to nodes under `entries`, symlinks have a `target` and regular files
+/// have a `size`, a `narOffset`, an `executable` flag when set, and their contents as chosen by
+/// `contents`.
+pub fn nar_to_json<R, W>(reader: R, writer: &mut W, contents: ContentEncoding) -> io::Result<()>
+where
+    R: Read,
+    W: Write,
+{
+    let mut archive = Archive::new(reader);
+
+    // Depths of the directories whose objects are still open, and whether each has any entries.
+    let mut open_dirs: Vec<(usize, bool)> = Vec::new();
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let depth = entry.depth();
+        while let Some(&(open, _)) = open_dirs.last() {
+            if open < depth {
+                break;
+            }
+            open_dirs.pop();
+            writer.write_all(b"}}")?;
+        }
+
+        if let Some(name) = entry.path_components().next_back() {
+            if let Some((_, has_entries)) = open_dirs.last_mut() {
+                if *has_entries {
+                    writer.write_all(b",")?;
+                }
+                *has_entries = true;
+            }
+            write_string(writer, name)?;
+            writer.write_all(b":")?;
+        }
+
+        match &entry.kind {
+            EntryKind::Directory => {
+                writer.write_all(br#"{"type":"directory","entries":{"#)?;
+                open_dirs.push((depth, false));
+            }
+            EntryKind::Regular {
+                executable,
+                data,
+                offset,
+            } => {
+                write!(
+                    writer,
+                    r#"{{"type":"regular","size":{},"narOffset":{}"#,
+                    data.len(),
+                    offset
+                )?;
+                if *executable {
+                    writer.write_all(br#","executable":true"#)?;
+                }
+                match contents {
+                    ContentEncoding::Base64 => {
+                        writer.write_all(br#","contents":"#)?;
+                        write_string(writer, &encoding::to_base64(data))?;
+                    }
+                    ContentEncoding::Skip => {}
+                    ContentEncoding::Hash => {
+                        writer.write_all(br#","hash":"#)?;
+                        write_string(writer, &hash_flat_reader(&data[..])?.to_sri())?;
+                    }
+                }
+                writer.write_all(b"}")?;
+            }
+            EntryKind::Symlink { target } => {
+                writer.write_all(br#"{"type":"symlink","target":"#)?;
+                write_string(writer, target.to_utf8()?)?;
+                writer.write_all(b"}")?;
+            }
+            EntryKind::Unknown { type_name, .. } => {
+                let message = format!("Cannot dump unrecognized node type `{}`", type_name);
+                return Err(Error::other(message));
+            }
+        }
+    }
+
+    for _ in open_dirs {
+        writer.write_all(b"}}")?;
+    }
+    Ok(())
+}
+
+/// Converts a document in the format written by [`nar_to_json`] back into a canonical archive.
+///
+/// Regular files must carry their `contents` as base64. Any `size` or `hash` given alongside
+/// must agree with the contents, while `narOffset` is ignored so that edited documents need not
+/// keep offsets up to date. Unknown fields are rejected to catch typos.
+pub fn json_to_nar<R, W>(reader: R, writer: &mut W) -> io::Result<()>
+where
+    R: Read,
+    W: Write,
+{
+    let document: Value = serde_json::from_reader(reader)?;
+    let root = node_from_json(&document, "")?;
+    NarTree::new(root).to_writer(writer)
+}
+
+fn node_from_json(value: &Value, path: &str) -> io::Result<Node> {
+    let invalid = |problem: String| {
+        let message = format!("Invalid node at {:?}: {}", path, problem);
+        Error::new(ErrorKind::InvalidData, message)
+    };
+
+    let object = value
+        .as_object()
+        .ok_or_else(|| invalid("expected an object".to_owned()))?;
+    let string = |field: &str| match object.get(field) {
+        Some(Value::String(s)) => Ok(Some(s.as_str())),
+        Some(_) => Err(invalid(format!("`{}` must be a string", field))),
+        None => Ok(None),
+    };
+    let required =
+        |field: &str| string(field)?.ok_or_else(|| invalid(format!("missing `{}`", field)));
+    let allow = |fields: &[&str]| match object.keys().find(|key| !fields.contains(&key.as_str())) {
+        Some(key) => Err(invalid(format!("unexpected field `{}`", key))),
+        None => Ok(()),
+    };
+
+    match required("type")? {
+        "directory" => {
+            allow(&["type", "entries"])?;
+            let entries = match object.get("entries") {
+                Some(Value::Object(entries)) => entries,
+                Some(_) => return Err(invalid("`entries` must be an object".to_owned())),
+                None => return Err(invalid("missing `entries`".to_owned())),
+            };
+            Ok(Node::Directory {
+                entries: entries_from_json(entries, path)?,
+            })
+        }
+        "regular" => {
+            allow(&[
+                "type",
+                "size",
+                "narOffset",
+                "executable",
+                "contents",
+                "hash",
+            ])?;
+            let contents = encoding::from_base64(required("contents")?)
+                .ok_or_else(|| invalid("`contents` is not valid base64".to_owned()))?;
+
+            let executable = match object.get("executable") {
+                Some(Value::Bool(executable)) => *executable,
+                Some(_) => return Err(invalid("`executable` must be a boolean".to_owned())),
+                None => false,
+            };
+            if let Some(size) = object.get("size") {
+                if size.as_u64() != Some(contents.len() as u64) {
+                    let problem = format!("`size` is {} but contents are {}", size, contents.len());
+                    return Err(invalid(problem));
+                }
+            }
+            if let Some(hash) = string("hash")? {
+                let actual = hash_flat_reader(&contents[..])?.to_sri();
+                if hash != actual {
+                    let problem = format!("`hash` is {} but contents hash to {}", hash, actual);
+                    return Err(invalid(problem));
+                }
+            }
+
+            Ok(Node::Regular {
+                executable,
+                contents,
+            })
+        }
+        "symlink" => {
+            allow(&["type", "target"])?;
+            let target = required("target")?;
+            if let Some(problem) = target_problem(target) {
+                return Err(invalid(problem.to_owned()));
+            }
+            Ok(Node::Symlink {
+                target: target.to_owned(),
+            })
+        }
+        other => Err(invalid(format!("unknown node type `{}`", other))),
+    }
+}
+
+fn entries_from_json(
+    entries: &Map<String, Value>,
+    path: &str,
+) -> io::Result<BTreeMap<String, Node>> {
+    let mut nodes = BTreeMap::new();
+    for (name, value) in entries {
+        let child = if path.is_empty() {
+            name.clone()
+        } else {
+            format!("{}/{}", path, name)
+        };
+        if let Err(problem) = validate_entry_name(name) {
+            let message = format!("Invalid node at {:?}: {}", child, problem);
+            return Err(Error::new(ErrorKind::InvalidData, message));
+        }
+        nodes.insert(name.clone(), node_from_json(value, &child)?);
+    }
+    Ok(nodes)
+}
+
+fn write_string<W: Write + ?Sized>(writer: &mut W, s: &str) -> io::Result<()> {
+    serde_json::to_writer(writer, s).map_err(Error::from)
+}
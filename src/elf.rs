@@ -0,0 +1,213 @@
+//! Just enough of an ELF reader to pull linking information out of file contents held in memory.
+//!
+//! The reference scanner only needs the interpreter, so the rest is unused unless the `elf`
+//! feature is enabled.
+
+#![cfg_attr(not(feature = "elf"), allow(dead_code))]
+
+use std::convert::{TryFrom, TryInto};
+
+#[cfg(feature = "elf")]
+pub use self::report::{ElfReport, FileReport};
+
+#[cfg(feature = "elf")]
+mod report;
+
+const MAGIC: &[u8; 4] = b"\x7fELF";
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_INTERP: u32 = 3;
+const DT_NULL: u64 = 0;
+const DT_NEEDED: u64 = 1;
+const DT_STRTAB: u64 = 5;
+const DT_RPATH: u64 = 15;
+const DT_RUNPATH: u64 = 29;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Elf<'a> {
+    data: &'a [u8],
+    is_64: bool,
+    little_endian: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ProgramHeader {
+    pub kind: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub file_size: u64,
+}
+
+impl<'a> Elf<'a> {
+    pub fn is_elf(data: &[u8]) -> bool {
+        data.starts_with(MAGIC)
+    }
+
+    /// Returns `None` if `data` is not an ELF image or its header is truncated.
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        if !Elf::is_elf(data) {
+            return None;
+        }
+        let is_64 = match data.get(4)? {
+            1 => false,
+            2 => true,
+            _ => return None,
+        };
+        let little_endian = match data.get(5)? {
+            1 => true,
+            2 => false,
+            _ => return None,
+        };
+        let header_len = if is_64 { 64 } else { 52 };
+        if data.len() < header_len {
+            return None;
+        }
+        Some(Elf {
+            data,
+            is_64,
+            little_endian,
+        })
+    }
+
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + '_ {
+        let (offset, entry_len, count) = if self.is_64 {
+            (self.word(0x20), self.u16(0x36), self.u16(0x38))
+        } else {
+            (self.word(0x1c), self.u16(0x2a), self.u16(0x2c))
+        };
+        let (offset, entry_len) = (offset.unwrap_or(0), u64::from(entry_len.unwrap_or(0)));
+        let count = if entry_len == 0 {
+            0
+        } else {
+            count.unwrap_or(0)
+        };
+
+        (0..u64::from(count)).map_while(move |i| {
+            let at = usize::try_from(offset.checked_add(i * entry_len)?).ok()?;
+            if self.is_64 {
+                Some(ProgramHeader {
+                    kind: self.u32(at)?,
+                    offset: self.u64(at + 0x08)?,
+                    vaddr: self.u64(at + 0x10)?,
+                    file_size: self.u64(at + 0x20)?,
+                })
+            } else {
+                Some(ProgramHeader {
+                    kind: self.u32(at)?,
+                    offset: self.u32(at + 0x04)?.into(),
+                    vaddr: self.u32(at + 0x08)?.into(),
+                    file_size: self.u32(at + 0x10)?.into(),
+                })
+            }
+        })
+    }
+
+    /// The program interpreter requested through `PT_INTERP`, without its trailing NUL.
+    pub fn interpreter(&self) -> Option<&'a [u8]> {
+        let header = self.program_headers().find(|h| h.kind == PT_INTERP)?;
+        let bytes = self.slice(header.offset, header.file_size)?;
+        Some(bytes.split(|&b| b == 0).next().unwrap_or(bytes))
+    }
+
+    /// The libraries listed in `DT_NEEDED` entries of the dynamic section, in order. Statically
+    /// linked images and images with a malformed dynamic section have none.
+    pub fn needed(&self) -> Vec<&'a [u8]> {
+        self.dynamic_strings(&[DT_NEEDED])
+            .into_iter()
+            .map(|(_, bytes)| bytes)
+            .collect()
+    }
+
+    /// The `DT_RPATH` and `DT_RUNPATH` strings, along with their offsets into the file.
+    pub fn run_paths(&self) -> Vec<(usize, &'a [u8])> {
+        self.dynamic_strings(&[DT_RPATH, DT_RUNPATH])
+    }
+
+    /// Looks up the string table entries referenced by dynamic section entries tagged with one of
+    /// `tags`, returning the file offset of each string along with its bytes.
+    fn dynamic_strings(&self, tags: &[u64]) -> Vec<(usize, &'a [u8])> {
+        let dynamic = match self.program_headers().find(|h| h.kind == PT_DYNAMIC) {
+            Some(header) => header,
+            None => return Vec::new(),
+        };
+
+        let word_len = if self.is_64 { 8 } else { 4 };
+        let mut strtab = None;
+        let mut names = Vec::new();
+        let entries = (0..dynamic.file_size / (2 * word_len)).map_while(|i| {
+            let at = usize::try_from(dynamic.offset.checked_add(i * 2 * word_len)?).ok()?;
+            Some((self.word(at)?, self.word(at + word_len as usize)?))
+        });
+        for (tag, value) in entries {
+            match tag {
+                DT_NULL => break,
+                DT_STRTAB => strtab = self.file_offset(value),
+                tag if tags.contains(&tag) => names.push(value),
+                _ => {}
+            }
+        }
+
+        let strtab = match strtab {
+            Some(strtab) => strtab,
+            None => return Vec::new(),
+        };
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let start = usize::try_from(strtab.checked_add(name)?).ok()?;
+                let bytes = self.data.get(start..)?;
+                Some((start, bytes.split(|&b| b == 0).next()?))
+            })
+            .collect()
+    }
+
+    /// Maps a virtual address to an offset into the file through the loadable segments.
+    fn file_offset(&self, vaddr: u64) -> Option<u64> {
+        self.program_headers()
+            .filter(|h| h.kind == PT_LOAD)
+            .find(|h| vaddr >= h.vaddr && vaddr - h.vaddr < h.file_size)
+            .map(|h| vaddr - h.vaddr + h.offset)
+    }
+
+    pub fn slice(&self, offset: u64, len: u64) -> Option<&'a [u8]> {
+        let start = usize::try_from(offset).ok()?;
+        let end = start.checked_add(usize::try_from(len).ok()?)?;
+        self.data.get(start..end)
+    }
+
+    /// Reads an address-sized field at `at`.
+    fn word(&self, at: usize) -> Option<u64> {
+        if self.is_64 {
+            self.u64(at)
+        } else {
+            self.u32(at).map(u64::from)
+        }
+    }
+
+    fn u16(&self, at: usize) -> Option<u16> {
+        let bytes = self.data.get(at..at + 2)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, at: usize) -> Option<u32> {
+        let bytes = self.data.get(at..at + 4)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    fn u64(&self, at: usize) -> Option<u64> {
+        let bytes = self.data.get(at..at + 8)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u64::from_le_bytes(bytes)
+        } else {
+            u64::from_be_bytes(bytes)
+        })
+    }
+}
@@ -0,0 +1,107 @@
+use std::io::{self, Error, ErrorKind, Read};
+
+use serde::{Deserialize, Serialize};
+
+use super::Elf;
+use crate::de::EntryKind;
+use crate::Archive;
+
+const REPORT_VERSION: u32 = 1;
+
+/// Linking information for every regular file in a NAR, meant to be published next to it.
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct ElfReport {
+    version: u32,
+    files: Vec<FileReport>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileReport {
+    path: String,
+    is_elf: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    interpreter: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    needed: Vec<String>,
+}
+
+impl FileReport {
+    /// Path within the archive with components joined by `/`, empty for a lone root file.
+    #[inline]
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    #[inline]
+    pub fn is_elf(&self) -> bool {
+        self.is_elf
+    }
+
+    #[inline]
+    pub fn interpreter(&self) -> Option<&str> {
+        self.interpreter.as_deref()
+    }
+
+    /// Libraries from the `DT_NEEDED` entries, in the order the dynamic section lists them.
+    #[inline]
+    pub fn needed(&self) -> &[String] {
+        &self.needed
+    }
+}
+
+impl ElfReport {
+    /// Analyzes every regular file in the NAR read from `reader`, in archive order.
+    pub fn generate<R: Read>(reader: R) -> io::Result<Self> {
+        let mut files = Vec::new();
+        let mut archive = Archive::new(reader);
+
+        for entry in archive.entries()? {
+            let entry = entry?;
+            let data = match &entry.kind {
+                EntryKind::Regular { data, .. } => data,
+                _ => continue,
+            };
+
+            let elf = Elf::parse(data);
+            let lossy = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+            files.push(FileReport {
+                path: entry.nar_path(),
+                is_elf: elf.is_some(),
+                interpreter: elf.and_then(|elf| elf.interpreter()).map(lossy),
+                needed: elf.map_or_else(Vec::new, |elf| {
+                    elf.needed().into_iter().map(lossy).collect()
+                }),
+            });
+        }
+
+        Ok(ElfReport {
+            version: REPORT_VERSION,
+            files,
+        })
+    }
+
+    #[inline]
+    pub fn files(&self) -> &[FileReport] {
+        &self.files
+    }
+
+    pub fn get(&self, path: &str) -> Option<&FileReport> {
+        let path = path.trim_matches('/');
+        self.files.iter().find(|file| file.path == path)
+    }
+
+    pub fn from_json(json: &str) -> io::Result<Self> {
+        let report: ElfReport =
+            serde_json::from_str(json).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        if report.version != REPORT_VERSION {
+            let message = format!("Unsupported ELF report version {}", report.version);
+            return Err(Error::new(ErrorKind::InvalidData, message));
+        }
+        Ok(report)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("ELF report is always representable as JSON")
+    }
+}
@@ -0,0 +1,133 @@
+const NIX_BASE32_ALPHABET: &[u8; 32] = b"0123456789abcdfghijklmnpqrsvwxyz";
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub(crate) const fn nix_base32_len(len: usize) -> usize {
+    (len * 8 - 1) / 5 + 1
+}
+
+/// Encodes `bytes` using Nix's base32 variant, which emits the least significant bits last.
+pub(crate) fn to_nix_base32(bytes: &[u8]) -> String {
+    let len = nix_base32_len(bytes.len());
+    let mut encoded = String::with_capacity(len);
+    for n in (0..len).rev() {
+        let bit = n * 5;
+        let (i, j) = (bit / 8, bit % 8);
+        let low = bytes[i] >> j;
+        let high = match bytes.get(i + 1) {
+            Some(next) if j > 3 => next << (8 - j),
+            _ => 0,
+        };
+        encoded.push(NIX_BASE32_ALPHABET[((low | high) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+pub(crate) fn from_nix_base32(encoded: &str, bytes: &mut [u8]) -> Option<()> {
+    if encoded.len() != nix_base32_len(bytes.len()) {
+        return None;
+    }
+
+    bytes.iter_mut().for_each(|b| *b = 0);
+    for (n, c) in encoded.bytes().rev().enumerate() {
+        let digit = NIX_BASE32_ALPHABET.iter().position(|&a| a == c)? as u16;
+        let bit = n * 5;
+        let (i, j) = (bit / 8, bit % 8);
+        let value = digit << j;
+        bytes[i] |= value as u8;
+        let carry = (value >> 8) as u8;
+        match bytes.get_mut(i + 1) {
+            Some(next) => *next |= carry,
+            None if carry != 0 => return None,
+            None => {}
+        }
+    }
+    Some(())
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(crate) fn from_hex(encoded: &str, bytes: &mut [u8]) -> Option<()> {
+    if encoded.len() != bytes.len() * 2 || !encoded.is_ascii() {
+        return None;
+    }
+
+    for (byte, pair) in bytes.iter_mut().zip(encoded.as_bytes().chunks_exact(2)) {
+        let pair = std::str::from_utf8(pair).ok()?;
+        *byte = u8::from_str_radix(pair, 16).ok()?;
+    }
+    Some(())
+}
+
+pub(crate) fn to_base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |acc, (i, &b)| acc | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let index = (group >> (18 - 6 * i)) & 0x3f;
+                encoded.push(BASE64_ALPHABET[index as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+pub(crate) fn from_base64(encoded: &str) -> Option<Vec<u8>> {
+    if !encoded.len().is_multiple_of(4) {
+        return None;
+    }
+
+    let mut bytes = Vec::with_capacity(encoded.len() / 4 * 3);
+    for chunk in encoded.as_bytes().chunks_exact(4) {
+        let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 {
+            return None;
+        }
+
+        let mut group = 0u32;
+        for (i, &c) in chunk[..4 - padding].iter().enumerate() {
+            let digit = BASE64_ALPHABET.iter().position(|&a| a == c)? as u32;
+            group |= digit << (18 - 6 * i);
+        }
+        let decoded = group.to_be_bytes();
+        bytes.extend_from_slice(&decoded[1..4 - padding]);
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_nix_base32() {
+        let bytes: Vec<u8> = (0..32u8).map(|b| b.wrapping_mul(37)).collect();
+        let encoded = to_nix_base32(&bytes);
+        assert_eq!(encoded.len(), 52);
+
+        let mut decoded = [0; 32];
+        from_nix_base32(&encoded, &mut decoded).unwrap();
+        assert_eq!(&decoded[..], &bytes[..]);
+
+        assert!(from_nix_base32("e", &mut [0; 1]).is_none());
+        assert!(from_nix_base32("zz", &mut [0; 1]).is_none());
+    }
+
+    #[test]
+    fn round_trips_base64() {
+        for len in 0..8 {
+            let bytes: Vec<u8> = (0..len).map(|b| 250 - b).collect();
+            assert_eq!(from_base64(&to_base64(&bytes)).unwrap(), bytes);
+        }
+        assert_eq!(to_base64(b"foob"), "Zm9vYg==");
+        assert!(from_base64("Zm9").is_none());
+    }
+}
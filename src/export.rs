@@ -0,0 +1,240 @@
+//! The multi-path stream format produced by `nix-store --export` and consumed by `--import`.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+#[cfg(feature = "fs")]
+use std::fs;
+use std::io::{self, Error, ErrorKind, Write};
+#[cfg(feature = "fs")]
+use std::io::{Read, Seek, SeekFrom};
+#[cfg(feature = "fs")]
+use std::path::Path;
+#[cfg(feature = "fs")]
+use std::sync::Arc;
+
+#[cfg(feature = "fs")]
+use self::framing::NarFrameReader;
+use crate::store_path::StoreDir;
+#[cfg(feature = "fs")]
+use crate::temp::{SystemTemp, TempProvider};
+use crate::wire;
+
+#[cfg(feature = "fs")]
+mod framing;
+
+pub(crate) const EXPORT_MAGIC: u64 = 0x4558_494e;
+
+/// Computes the closure of `roots` under `references`, ordered so that every path comes after
+/// all of the paths it references. Shared dependencies appear once; self-references are ignored.
+pub fn closure<I, S, F>(
+    store_dir: &StoreDir,
+    roots: I,
+    mut references: F,
+) -> io::Result<Vec<String>>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+    F: FnMut(&str) -> io::Result<Vec<String>>,
+{
+    let mut ordered = Vec::new();
+    let mut done = HashSet::new();
+    let mut visiting = HashSet::new();
+
+    let roots: BTreeSet<String> = roots.into_iter().map(|r| r.as_ref().to_owned()).collect();
+    for root in roots {
+        visit(
+            store_dir,
+            &root,
+            &mut references,
+            &mut visiting,
+            &mut done,
+            &mut ordered,
+        )?;
+    }
+
+    Ok(ordered)
+}
+
+fn visit<F>(
+    store_dir: &StoreDir,
+    path: &str,
+    references: &mut F,
+    visiting: &mut HashSet<String>,
+    done: &mut HashSet<String>,
+    ordered: &mut Vec<String>,
+) -> io::Result<()>
+where
+    F: FnMut(&str) -> io::Result<Vec<String>>,
+{
+    if done.contains(path) {
+        return Ok(());
+    }
+    if !visiting.insert(path.to_owned()) {
+        let message = format!("Reference cycle involving {}", path);
+        return Err(Error::new(ErrorKind::InvalidData, message));
+    }
+
+    store_dir.parse_path(path)?;
+    let refs: BTreeSet<String> = references(path)?.into_iter().collect();
+    for reference in refs.iter().filter(|r| *r != path) {
+        visit(store_dir, reference, references, visiting, done, ordered)?;
+    }
+
+    visiting.remove(path);
+    done.insert(path.to_owned());
+    ordered.push(path.to_owned());
+    Ok(())
+}
+
+/// Writes the closure of `roots` as an export stream, packing each path from the filesystem.
+/// Returns the exported paths in the order they were written.
+#[cfg(feature = "fs")]
+pub fn export_closure<W, I, S, F>(
+    writer: &mut W,
+    store_dir: &StoreDir,
+    roots: I,
+    references: F,
+) -> io::Result<Vec<String>>
+where
+    W: Write,
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+    F: FnMut(&str) -> io::Result<Vec<String>>,
+{
+    export_closure_with(writer, store_dir, roots, references, |path, mut out| {
+        crate::ser::to_writer(&mut out, Path::new(path))
+    })
+}
+
+/// Like [`export_closure`], but obtains each path's NAR from `write_nar` instead of the
+/// filesystem.
+pub fn export_closure_with<W, I, S, F, N>(
+    writer: &mut W,
+    store_dir: &StoreDir,
+    roots: I,
+    mut references: F,
+    mut write_nar: N,
+) -> io::Result<Vec<String>>
+where
+    W: Write,
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+    F: FnMut(&str) -> io::Result<Vec<String>>,
+    N: FnMut(&str, &mut dyn Write) -> io::Result<()>,
+{
+    let mut refs_of = HashMap::new();
+    let paths = closure(store_dir, roots, |path| {
+        let refs = references(path)?;
+        refs_of.insert(
+            path.to_owned(),
+            refs.iter().cloned().collect::<BTreeSet<_>>(),
+        );
+        Ok(refs)
+    })?;
+
+    for path in &paths {
+        let refs = &refs_of[path];
+
+        writer.write_all(&1u64.to_le_bytes())?;
+        write_nar(path, writer)?;
+        writer.write_all(&EXPORT_MAGIC.to_le_bytes())?;
+        wire::write_token(writer, path.as_bytes())?;
+        writer.write_all(&(refs.len() as u64).to_le_bytes())?;
+        for reference in refs {
+            wire::write_token(writer, reference.as_bytes())?;
+        }
+        wire::write_token(writer, b"")?;
+        writer.write_all(&0u64.to_le_bytes())?;
+    }
+
+    writer.write_all(&0u64.to_le_bytes())?;
+    writer.flush()?;
+    Ok(paths)
+}
+
+/// Reads a `nix-store --export` stream one store path at a time.
+///
+/// The metadata of each path follows its NAR in the stream, so every NAR is first spooled to a
+/// temporary file (never held in memory) and then handed to the callback with its metadata.
+#[derive(Debug)]
+#[cfg(feature = "fs")]
+pub struct ImportStream<R> {
+    reader: R,
+    store_dir: StoreDir,
+    temp_provider: Arc<dyn TempProvider>,
+}
+
+#[cfg(feature = "fs")]
+impl<R: Read> ImportStream<R> {
+    pub fn new(reader: R) -> Self {
+        ImportStream {
+            reader,
+            store_dir: StoreDir::default(),
+            temp_provider: Arc::new(SystemTemp),
+        }
+    }
+
+    pub fn set_store_dir(&mut self, store_dir: StoreDir) {
+        self.store_dir = store_dir;
+    }
+
+    pub fn set_temp_provider<T: TempProvider + 'static>(&mut self, provider: T) {
+        self.temp_provider = Arc::new(provider);
+    }
+
+    /// Calls `f` with the store path, references and NAR of each path in the stream, returning
+    /// the number of paths imported. Any part of a NAR left unread by `f` is skipped.
+    pub fn for_each<F>(&mut self, mut f: F) -> io::Result<usize>
+    where
+        F: FnMut(&str, &[String], &mut dyn Read) -> io::Result<()>,
+    {
+        let mut count = 0;
+        while read_u64(&mut self.reader)? == 1 {
+            let (temp_path, mut spool) =
+                self.temp_provider.create_temp_file(Path::new("import"))?;
+            let result = (|| {
+                io::copy(&mut NarFrameReader::new(&mut self.reader), &mut spool)?;
+                let (path, references) = self.read_trailer()?;
+                spool.seek(SeekFrom::Start(0))?;
+                f(&path, &references, &mut spool)
+            })();
+            let _ = fs::remove_file(&temp_path);
+            result?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn read_trailer(&mut self) -> io::Result<(String, Vec<String>)> {
+        if read_u64(&mut self.reader)? != EXPORT_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "Missing export magic"));
+        }
+
+        let path = self.read_store_path()?;
+        let count = read_u64(&mut self.reader)?;
+        let references = (0..count)
+            .map(|_| self.read_store_path())
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let _deriver = wire::read_token(&mut self.reader, wire::MAX_METADATA_TOKEN_LEN)?;
+        if read_u64(&mut self.reader)? == 1 {
+            let _signature = wire::read_token(&mut self.reader, wire::MAX_METADATA_TOKEN_LEN)?;
+        }
+
+        Ok((path, references))
+    }
+
+    fn read_store_path(&mut self) -> io::Result<String> {
+        let token = wire::read_token(&mut self.reader, wire::MAX_METADATA_TOKEN_LEN)?;
+        let path = String::from_utf8(token)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "Store path is not UTF-8"))?;
+        self.store_dir.parse_path(&path)?;
+        Ok(path)
+    }
+}
+
+#[cfg(feature = "fs")]
+fn read_u64<R: Read + ?Sized>(reader: &mut R) -> io::Result<u64> {
+    let mut buffer = [0u8; 8];
+    reader.read_exact(&mut buffer)?;
+    Ok(u64::from_le_bytes(buffer))
+}
@@ -0,0 +1,138 @@
+use std::io::{self, Read};
+
+use crate::wire;
+
+const MAX_TAG_LEN: u64 = 32;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum State {
+    Length,
+    Data { remaining: u64, len: u64 },
+    Done,
+}
+
+/// Passes through exactly one NAR from `inner`, reporting end-of-file after the closing
+/// parenthesis of the root node. File contents are streamed rather than buffered.
+#[derive(Debug)]
+pub(crate) struct NarFrameReader<'a, R: ?Sized> {
+    inner: &'a mut R,
+    state: State,
+    pending: Vec<u8>,
+    consumed: usize,
+    depth: u64,
+    expect_payload: bool,
+}
+
+impl<'a, R: Read + ?Sized> NarFrameReader<'a, R> {
+    pub fn new(inner: &'a mut R) -> Self {
+        NarFrameReader {
+            inner,
+            state: State::Length,
+            pending: Vec::new(),
+            consumed: 0,
+            depth: 0,
+            expect_payload: false,
+        }
+    }
+
+    fn advance(&mut self) -> io::Result<()> {
+        self.pending.clear();
+        self.consumed = 0;
+
+        let mut len_bytes = [0u8; 8];
+        self.inner.read_exact(&mut len_bytes)?;
+        let len = u64::from_le_bytes(len_bytes);
+        self.pending.extend_from_slice(&len_bytes);
+
+        if self.expect_payload || len > MAX_TAG_LEN {
+            self.state = State::Data {
+                remaining: len,
+                len,
+            };
+            return Ok(());
+        }
+
+        let mut token = vec![0u8; len as usize];
+        self.inner.read_exact(&mut token)?;
+        wire::read_padding(self.inner, len)?;
+        self.pending.extend_from_slice(&token);
+        self.pending
+            .resize(self.pending.len() + wire::pad_len(len), 0);
+
+        match &token[..] {
+            b"(" => self.depth += 1,
+            b")" => {
+                self.depth = self.depth.saturating_sub(1);
+                if self.depth == 0 {
+                    self.state = State::Done;
+                }
+            }
+            b"contents" | b"target" | b"name" => self.expect_payload = true,
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a, R: Read + ?Sized> Read for NarFrameReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.consumed < self.pending.len() {
+                let n = buf.len().min(self.pending.len() - self.consumed);
+                buf[..n].copy_from_slice(&self.pending[self.consumed..self.consumed + n]);
+                self.consumed += n;
+                return Ok(n);
+            }
+
+            match self.state {
+                State::Done => return Ok(0),
+                State::Length => self.advance()?,
+                State::Data { remaining: 0, len } => {
+                    wire::read_padding(self.inner, len)?;
+                    self.pending.clear();
+                    self.pending.resize(wire::pad_len(len), 0);
+                    self.consumed = 0;
+                    self.expect_payload = false;
+                    self.state = State::Length;
+                }
+                State::Data { remaining, len } => {
+                    let want = (buf.len() as u64).min(remaining) as usize;
+                    let n = self.inner.read(&mut buf[..want])?;
+                    if n == 0 {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    self.state = State::Data {
+                        remaining: remaining - n as u64,
+                        len,
+                    };
+                    return Ok(n);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stops_at_end_of_nar() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("contents"), "(").unwrap();
+        std::os::unix::fs::symlink(")", dir.path().join("name")).unwrap();
+        let nar = crate::to_vec(dir.path()).unwrap();
+
+        let mut stream = nar.clone();
+        stream.extend_from_slice(b"trailing");
+        let mut input = &stream[..];
+
+        let mut copied = Vec::new();
+        NarFrameReader::new(&mut input)
+            .read_to_end(&mut copied)
+            .unwrap();
+        assert_eq!(copied, nar);
+        assert_eq!(input, b"trailing");
+    }
+}
@@ -0,0 +1,155 @@
+use std::io::{self, Error, Write};
+use std::mem;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+const BLOCK_LEN: usize = 64 * 1024;
+
+/// A writer that duplicates everything written to it into several sinks, each driven by its own
+/// thread through a bounded queue, so that a slow sink (e.g. an xz encoder) applies
+/// backpressure instead of buffering without limit.
+#[derive(Debug)]
+pub struct FanOut {
+    senders: Vec<SyncSender<Arc<Vec<u8>>>>,
+    queue_len: usize,
+    buffer: Vec<u8>,
+}
+
+impl FanOut {
+    /// Creates a fan-out writer whose sinks each queue at most `queue_len` 64 KiB blocks.
+    pub fn new(queue_len: usize) -> Self {
+        FanOut {
+            senders: Vec::new(),
+            queue_len: queue_len.max(1),
+            buffer: Vec::with_capacity(BLOCK_LEN),
+        }
+    }
+
+    pub fn add_sink<W>(&mut self, sink: W) -> Sink<W>
+    where
+        W: Write + Send + 'static,
+    {
+        self.add_sink_with(sink, |mut sink| sink.flush().map(|_| sink))
+    }
+
+    /// Adds a sink whose final value is produced by `finish` once all data has been written,
+    /// e.g. to finalize a compressor.
+    pub fn add_sink_with<W, T, F>(&mut self, mut sink: W, finish: F) -> Sink<T>
+    where
+        W: Write + Send + 'static,
+        T: Send + 'static,
+        F: FnOnce(W) -> io::Result<T> + Send + 'static,
+    {
+        let (sender, receiver): (_, Receiver<Arc<Vec<u8>>>) = mpsc::sync_channel(self.queue_len);
+        self.senders.push(sender);
+
+        let handle = thread::spawn(move || {
+            for block in receiver {
+                sink.write_all(&block)?;
+            }
+            finish(sink)
+        });
+
+        Sink { handle }
+    }
+
+    /// Flushes buffered data to every sink and closes their queues. The results are retrieved
+    /// through [`Sink::join`].
+    pub fn finish(mut self) -> io::Result<()> {
+        self.send_block()?;
+        self.senders.clear();
+        Ok(())
+    }
+
+    fn send_block(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let block = Arc::new(mem::replace(
+            &mut self.buffer,
+            Vec::with_capacity(BLOCK_LEN),
+        ));
+        for sender in &self.senders {
+            sender
+                .send(block.clone())
+                .map_err(|_| Error::other("Fan-out sink stopped early"))?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for FanOut {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(BLOCK_LEN - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+        if self.buffer.len() == BLOCK_LEN {
+            self.send_block()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_block()
+    }
+}
+
+#[derive(Debug)]
+pub struct Sink<T> {
+    handle: JoinHandle<io::Result<T>>,
+}
+
+impl<T> Sink<T> {
+    /// Waits for the sink's thread and returns its result. Call after [`FanOut::finish`].
+    pub fn join(self) -> io::Result<T> {
+        self.handle
+            .join()
+            .unwrap_or_else(|_| Err(Error::other("Fan-out sink panicked")))
+    }
+}
+
+#[cfg(all(feature = "zstd", feature = "xz"))]
+#[derive(Debug)]
+pub struct Artifacts<Z, X> {
+    pub nar_hash: crate::hash::Sha256Hash,
+    pub nar_size: u64,
+    pub zstd: Z,
+    pub xz: X,
+}
+
+/// Packs `path` once, producing its NAR hash and size together with zstd and xz compressed
+/// copies written to `zstd` and `xz`.
+#[cfg(all(feature = "fs", feature = "zstd", feature = "xz"))]
+pub fn pack_artifacts<P, Z, X>(path: P, zstd: Z, xz: X) -> io::Result<Artifacts<Z, X>>
+where
+    P: AsRef<std::path::Path>,
+    Z: Write + Send + 'static,
+    X: Write + Send + 'static,
+{
+    use crate::compression;
+    use crate::hash::NarHasher;
+
+    let mut fanout = FanOut::new(16);
+    let hasher = fanout.add_sink(NarHasher::new());
+    let zstd = fanout.add_sink_with(
+        compression::zstd::encoder(zstd, compression::zstd::DEFAULT_LEVEL)?,
+        |encoder| encoder.finish(),
+    );
+    let xz = fanout.add_sink_with(
+        compression::xz::encoder(xz, compression::xz::DEFAULT_LEVEL),
+        |encoder| encoder.finish(),
+    );
+
+    let packed = crate::to_writer(&mut fanout, path).and_then(|_| fanout.finish());
+    let (hasher, zstd, xz) = (hasher.join(), zstd.join(), xz.join());
+    packed?;
+
+    let (nar_hash, nar_size) = hasher?.finish();
+    Ok(Artifacts {
+        nar_hash,
+        nar_size,
+        zstd: zstd?,
+        xz: xz?,
+    })
+}
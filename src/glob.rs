@@ -0,0 +1,46 @@
+/// Matches `text` against a pattern in which `*` stands for any run of bytes, `/` included, and
+/// `?` for any single byte.
+pub(crate) fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_globs() {
+        assert!(glob_match(b"/nix/store/*-source", b"/nix/store/abc-source"));
+        assert!(glob_match(b"*", b""));
+        assert!(glob_match(b"a?c*", b"abcdef"));
+        assert!(!glob_match(
+            b"/nix/store/*-source",
+            b"/nix/store/abc-source.drv"
+        ));
+        assert!(!glob_match(b"a?c", b"ac"));
+    }
+}
@@ -0,0 +1,321 @@
+use std::convert::TryInto;
+use std::fmt::{self, Debug, Display, Formatter};
+#[cfg(feature = "fs")]
+use std::fs::File;
+use std::io::{self, Error, ErrorKind, Read, Write};
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::str::FromStr;
+
+use self::sha256::Sha256;
+use crate::encoding;
+
+mod sha256;
+
+const STATE_VERSION: u8 = 1;
+const STATE_HEADER_LEN: usize = 1 + 8 * 4 + 8;
+
+#[derive(Clone, Copy, Eq, Hash, PartialEq)]
+pub struct Sha256Hash([u8; 32]);
+
+impl Sha256Hash {
+    pub const fn from_bytes(bytes: [u8; 32]) -> Self {
+        Sha256Hash(bytes)
+    }
+
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    pub fn to_hex(&self) -> String {
+        encoding::to_hex(&self.0)
+    }
+
+    pub fn to_nix_base32(&self) -> String {
+        encoding::to_nix_base32(&self.0)
+    }
+
+    pub fn to_sri(&self) -> String {
+        format!("sha256-{}", encoding::to_base64(&self.0))
+    }
+}
+
+impl Debug for Sha256Hash {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "Sha256Hash({})", self)
+    }
+}
+
+impl Display for Sha256Hash {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "sha256:{}", self.to_nix_base32())
+    }
+}
+
+/// Parses `sha256:<base32|hex>` as well as SRI (`sha256-<base64>`) strings.
+impl FromStr for Sha256Hash {
+    type Err = Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        let invalid = || {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid SHA-256 hash {:?}", s),
+            )
+        };
+
+        let mut bytes = [0; 32];
+        if let Some(encoded) = s.strip_prefix("sha256:") {
+            encoding::from_nix_base32(encoded, &mut bytes)
+                .or_else(|| encoding::from_hex(encoded, &mut bytes))
+                .ok_or_else(invalid)?;
+        } else if let Some(encoded) = s.strip_prefix("sha256-") {
+            let decoded = encoding::from_base64(encoded).ok_or_else(invalid)?;
+            bytes = decoded.as_slice().try_into().map_err(|_| invalid())?;
+        } else {
+            return Err(invalid());
+        }
+
+        Ok(Sha256Hash(bytes))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Sha256Hash {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Sha256Hash {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Name of the SHA-256 implementation compiled in, either `sha2` or `sha2-asm`.
+pub fn backend() -> &'static str {
+    if cfg!(feature = "sha2-asm") {
+        "sha2-asm"
+    } else {
+        "sha2"
+    }
+}
+
+#[cfg(feature = "fs")]
+pub fn hash_path<P: AsRef<Path>>(path: P) -> io::Result<(Sha256Hash, u64)> {
+    let mut hasher = NarHasher::new();
+    crate::ser::to_writer(&mut hasher, path)?;
+    Ok(hasher.finish())
+}
+
+pub fn hash_flat_reader<R: Read>(mut reader: R) -> io::Result<Sha256Hash> {
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 8192];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => return Ok(Sha256Hash(hasher.finalize())),
+            Ok(n) => hasher.update(&buffer[..n]),
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(feature = "fs")]
+pub fn hash_flat_file<P: AsRef<Path>>(path: P) -> io::Result<Sha256Hash> {
+    hash_flat_reader(File::open(path)?)
+}
+
+#[derive(Clone, Debug)]
+pub struct NarHasher {
+    inner: Sha256,
+}
+
+impl NarHasher {
+    pub fn new() -> Self {
+        NarHasher {
+            inner: Sha256::new(),
+        }
+    }
+
+    pub fn resume(state: &HasherState) -> Self {
+        NarHasher {
+            inner: state.inner.clone(),
+        }
+    }
+
+    /// Number of archive bytes hashed so far; a resumed hasher expects input from this offset.
+    #[inline]
+    pub fn size(&self) -> u64 {
+        self.inner.len()
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
+
+    pub fn state(&self) -> HasherState {
+        HasherState {
+            inner: self.inner.clone(),
+        }
+    }
+
+    pub fn finish(self) -> (Sha256Hash, u64) {
+        let size = self.inner.len();
+        (Sha256Hash(self.inner.finalize()), size)
+    }
+}
+
+impl Default for NarHasher {
+    fn default() -> Self {
+        NarHasher::new()
+    }
+}
+
+impl Write for NarHasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Checks a downloaded file, such as a compressed NAR, against the `FileHash` and `FileSize` of
+/// its narinfo while it is being read. Reading fails as soon as the file runs past the expected
+/// size, and at the end of the file if the size or hash is wrong, so corrupt downloads are never
+/// mistaken for complete ones. Once reading has failed, it keeps failing.
+#[derive(Debug)]
+pub struct VerifyingReader<R> {
+    inner: R,
+    hasher: Sha256,
+    expected_hash: Sha256Hash,
+    expected_len: u64,
+    state: Verification,
+}
+
+#[derive(Debug)]
+enum Verification {
+    Pending,
+    Verified,
+    Failed(String),
+}
+
+impl<R: Read> VerifyingReader<R> {
+    pub fn new(reader: R, expected_hash: Sha256Hash, expected_len: u64) -> Self {
+        VerifyingReader {
+            inner: reader,
+            hasher: Sha256::new(),
+            expected_hash,
+            expected_len,
+            state: Verification::Pending,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn mismatch(&self) -> Option<String> {
+        let len = self.hasher.len();
+        if len != self.expected_len {
+            let message = format!(
+                "File size mismatch: expected {} bytes, got {}",
+                self.expected_len, len
+            );
+            return Some(message);
+        }
+
+        let actual = Sha256Hash(self.hasher.clone().finalize());
+        if actual != self.expected_hash {
+            let message = format!(
+                "File hash mismatch: expected {}, got {}",
+                self.expected_hash, actual
+            );
+            return Some(message);
+        }
+
+        None
+    }
+}
+
+impl<R: Read> Read for VerifyingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &self.state {
+            Verification::Pending => {}
+            Verification::Verified => return Ok(0),
+            Verification::Failed(message) => {
+                return Err(Error::new(ErrorKind::InvalidData, message.clone()))
+            }
+        }
+
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+
+        let failure = if n == 0 {
+            self.mismatch()
+        } else if self.hasher.len() > self.expected_len {
+            let message = format!(
+                "File size mismatch: expected {} bytes, got more",
+                self.expected_len
+            );
+            Some(message)
+        } else {
+            return Ok(n);
+        };
+
+        match failure {
+            Some(message) => {
+                self.state = Verification::Failed(message.clone());
+                Err(Error::new(ErrorKind::InvalidData, message))
+            }
+            None => {
+                self.state = Verification::Verified;
+                Ok(0)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct HasherState {
+    inner: Sha256,
+}
+
+impl HasherState {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let pending = self.inner.pending();
+        let mut bytes = Vec::with_capacity(STATE_HEADER_LEN + pending.len());
+        bytes.push(STATE_VERSION);
+        for word in self.inner.state().iter() {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.inner.len().to_le_bytes());
+        bytes.extend_from_slice(pending);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let invalid = || Error::new(ErrorKind::InvalidData, "Invalid hasher state");
+
+        if bytes.len() < STATE_HEADER_LEN || bytes[0] != STATE_VERSION {
+            return Err(invalid());
+        }
+
+        let mut state = [0u32; 8];
+        for (word, chunk) in state.iter_mut().zip(bytes[1..33].chunks_exact(4)) {
+            *word = u32::from_le_bytes(chunk.try_into().unwrap());
+        }
+        let len = u64::from_le_bytes(bytes[33..STATE_HEADER_LEN].try_into().unwrap());
+        let inner =
+            Sha256::from_parts(state, len, &bytes[STATE_HEADER_LEN..]).ok_or_else(invalid)?;
+
+        Ok(HasherState { inner })
+    }
+}
@@ -0,0 +1,162 @@
+use std::convert::TryInto;
+
+const BLOCK_LEN: usize = 64;
+
+const INITIAL_STATE: [u32; 8] = [
+    0x6a09_e667,
+    0xbb67_ae85,
+    0x3c6e_f372,
+    0xa54f_f53a,
+    0x510e_527f,
+    0x9b05_688c,
+    0x1f83_d9ab,
+    0x5be0_cd19,
+];
+
+#[derive(Clone, Debug)]
+pub(crate) struct Sha256 {
+    state: [u32; 8],
+    buffer: [u8; BLOCK_LEN],
+    len: u64,
+}
+
+impl Sha256 {
+    pub fn new() -> Self {
+        Sha256 {
+            state: INITIAL_STATE,
+            buffer: [0; BLOCK_LEN],
+            len: 0,
+        }
+    }
+
+    /// Reconstructs a hasher from the chaining state and the bytes not yet consumed by it.
+    pub fn from_parts(state: [u32; 8], len: u64, pending: &[u8]) -> Option<Self> {
+        if pending.len() != (len % BLOCK_LEN as u64) as usize {
+            return None;
+        }
+
+        let mut buffer = [0; BLOCK_LEN];
+        buffer[..pending.len()].copy_from_slice(pending);
+        Some(Sha256 { state, buffer, len })
+    }
+
+    pub fn state(&self) -> [u32; 8] {
+        self.state
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn pending(&self) -> &[u8] {
+        &self.buffer[..self.buffered()]
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        let buffered = self.buffered();
+        self.len += data.len() as u64;
+
+        if buffered > 0 {
+            let take = (BLOCK_LEN - buffered).min(data.len());
+            self.buffer[buffered..buffered + take].copy_from_slice(&data[..take]);
+            data = &data[take..];
+            if buffered + take < BLOCK_LEN {
+                return;
+            }
+            let block = self.buffer;
+            compress(&mut self.state, &block);
+        }
+
+        let mut blocks = data.chunks_exact(BLOCK_LEN);
+        for block in &mut blocks {
+            compress(&mut self.state, block.try_into().unwrap());
+        }
+
+        let rest = blocks.remainder();
+        self.buffer[..rest.len()].copy_from_slice(rest);
+    }
+
+    pub fn finalize(mut self) -> [u8; 32] {
+        let bit_len = self.len.wrapping_mul(8);
+        let buffered = self.buffered();
+
+        let mut tail = [0u8; BLOCK_LEN * 2];
+        tail[..buffered].copy_from_slice(&self.buffer[..buffered]);
+        tail[buffered] = 0x80;
+        let tail_len = if buffered < BLOCK_LEN - 8 {
+            BLOCK_LEN
+        } else {
+            BLOCK_LEN * 2
+        };
+        tail[tail_len - 8..tail_len].copy_from_slice(&bit_len.to_be_bytes());
+
+        for block in tail[..tail_len].chunks_exact(BLOCK_LEN) {
+            compress(&mut self.state, block.try_into().unwrap());
+        }
+
+        let mut digest = [0; 32];
+        for (chunk, word) in digest.chunks_exact_mut(4).zip(self.state.iter()) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn buffered(&self) -> usize {
+        (self.len % BLOCK_LEN as u64) as usize
+    }
+}
+
+/// Block compression is delegated to the `sha2` crate, which picks up SHA-NI/ARMv8 extensions at
+/// runtime (and the assembly cores with `sha2-asm`). The buffering stays here so that hasher
+/// state remains resumable.
+fn compress(state: &mut [u32; 8], block: &[u8; BLOCK_LEN]) {
+    use sha2::digest::generic_array::GenericArray;
+    sha2::compress256(state, std::slice::from_ref(GenericArray::from_slice(block)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(digest: [u8; 32]) -> String {
+        digest.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn sha256(data: &[u8]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+        hex(hasher.finalize())
+    }
+
+    #[test]
+    fn matches_known_vectors() {
+        assert_eq!(
+            sha256(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        assert_eq!(
+            sha256(&[b'a'; 1_000_000]),
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+        );
+    }
+
+    #[test]
+    fn splits_updates_at_any_boundary() {
+        let data: Vec<u8> = (0..300u32).map(|i| i as u8).collect();
+        let expected = sha256(&data);
+        for split in 0..data.len() {
+            let mut hasher = Sha256::new();
+            hasher.update(&data[..split]);
+            hasher.update(&data[split..]);
+            assert_eq!(hex(hasher.finalize()), expected);
+        }
+    }
+}
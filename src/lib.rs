@@ -1,12 +1,68 @@
 #![forbid(unsafe_code)]
 
+#[doc(inline)]
+pub use self::budget::{MemoryBudget, Reservation};
 #[doc(inline)]
 pub use self::de::Archive;
+#[cfg(feature = "fs")]
+#[doc(inline)]
+pub use self::hash::hash_path;
+#[cfg(feature = "fs")]
 #[doc(inline)]
 pub use self::ser::{to_vec, to_writer};
+#[doc(inline)]
+pub use self::symlink::SymlinkTarget;
+#[doc(inline)]
+pub use self::warning::Warning;
 
 const NIX_VERSION_MAGIC: &[u8] = b"nix-archive-1";
 const PAD_LEN: usize = 8;
 
+pub mod analysis;
+#[cfg(feature = "fs")]
+pub mod blobstore;
+pub mod cache;
+pub mod chunking;
+#[cfg(feature = "fs")]
+pub mod clock;
+pub mod compression;
 pub mod de;
+#[cfg(feature = "diagnostics")]
+pub mod diagnostics;
+#[cfg(feature = "json")]
+pub mod dump;
+#[cfg(feature = "elf")]
+pub mod elf;
+pub mod export;
+pub mod fanout;
+pub mod hash;
+pub mod listing;
+pub mod merkle;
+pub mod narmeta;
+pub mod pipeline;
+pub mod refs;
+pub mod remote;
+pub mod rewrite;
+pub mod sample;
 pub mod ser;
+#[cfg(feature = "experimental-serde")]
+pub mod serde;
+#[cfg(feature = "signing")]
+pub mod signing;
+pub mod split;
+pub mod store_path;
+#[cfg(feature = "fs")]
+pub mod temp;
+pub mod tree;
+#[cfg(feature = "fs")]
+pub mod vfs;
+pub mod wire;
+
+mod budget;
+#[cfg(not(feature = "elf"))]
+mod elf;
+mod encoding;
+#[cfg(any(feature = "fs", feature = "signing"))]
+mod glob;
+mod symlink;
+mod warning;
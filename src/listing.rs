@@ -0,0 +1,309 @@
+use std::collections::BTreeMap;
+#[cfg(feature = "fs")]
+use std::collections::VecDeque;
+#[cfg(feature = "fs")]
+use std::fs::File;
+#[cfg(feature = "fs")]
+use std::io::BufReader;
+use std::io::{self, Error, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+#[cfg(feature = "fs")]
+use std::sync::{mpsc, Arc, Mutex};
+#[cfg(feature = "fs")]
+use std::thread;
+
+#[cfg(feature = "json")]
+use serde::{Deserialize, Serialize};
+
+#[cfg(all(feature = "fs", feature = "xz"))]
+use crate::compression::xz;
+#[cfg(all(feature = "fs", feature = "zstd"))]
+use crate::compression::zstd;
+use crate::de::{Entry, EntryKind};
+#[cfg(feature = "fs")]
+use crate::Archive;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "json", derive(Deserialize, Serialize))]
+pub struct Listing {
+    version: u32,
+    root: Node,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "json", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "json", serde(tag = "type", rename_all = "lowercase"))]
+pub enum Node {
+    Directory {
+        entries: BTreeMap<String, Node>,
+    },
+    Regular {
+        size: u64,
+        #[cfg_attr(feature = "json", serde(default, skip_serializing_if = "is_false"))]
+        executable: bool,
+        #[cfg_attr(
+            feature = "json",
+            serde(default, rename = "narOffset", skip_serializing_if = "Option::is_none")
+        )]
+        nar_offset: Option<u64>,
+    },
+    Symlink {
+        target: String,
+    },
+}
+
+impl Node {
+    /// The listing node of a parsed entry, sized by the contents it holds.
+    pub(crate) fn from_kind(kind: &EntryKind) -> io::Result<Self> {
+        match kind {
+            EntryKind::Directory => Ok(Node::Directory {
+                entries: BTreeMap::new(),
+            }),
+            EntryKind::Regular {
+                executable,
+                data,
+                offset,
+            } => Ok(Node::Regular {
+                size: data.len() as u64,
+                executable: *executable,
+                nar_offset: Some(*offset),
+            }),
+            EntryKind::Symlink { target } => Ok(Node::Symlink {
+                target: target.to_utf8()?.to_owned(),
+            }),
+            EntryKind::Unknown { type_name, .. } => {
+                let message = format!("Cannot list unrecognized node type `{}`", type_name);
+                Err(Error::other(message))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+impl Listing {
+    pub fn new(root: Node) -> Self {
+        Listing { version: 1, root }
+    }
+
+    #[inline]
+    pub fn root(&self) -> &Node {
+        &self.root
+    }
+
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> Option<&Node> {
+        let mut node = &self.root;
+        for component in path.as_ref().components() {
+            let name = match component {
+                Component::Normal(name) => name.to_str()?,
+                Component::CurDir => continue,
+                _ => return None,
+            };
+
+            node = match node {
+                Node::Directory { entries } => entries.get(name)?,
+                _ => return None,
+            };
+        }
+
+        Some(node)
+    }
+
+    #[cfg(feature = "json")]
+    pub fn from_json(json: &str) -> io::Result<Self> {
+        let listing: Listing =
+            serde_json::from_str(json).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        if listing.version != 1 {
+            let message = format!("Unsupported listing version {}", listing.version);
+            return Err(Error::new(ErrorKind::InvalidData, message));
+        }
+        Ok(listing)
+    }
+
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("listing is always representable as JSON")
+    }
+
+    pub fn verify(&self, actual: &Listing) -> io::Result<()> {
+        verify_node(&self.root, &actual.root, Path::new(""))
+    }
+
+    pub(crate) fn from_entries<'a, I>(entries: I) -> io::Result<Self>
+    where
+        I: IntoIterator<Item = io::Result<Entry<'a>>>,
+    {
+        Listing::from_nodes(entries.into_iter().map(|entry| {
+            let entry = entry?;
+            let node = Node::from_kind(&entry.kind)?;
+            Ok((entry.name().to_owned(), node))
+        }))
+    }
+
+    /// Assembles a listing from nodes in archive order, each named by its path in the archive.
+    pub(crate) fn from_nodes<I>(nodes: I) -> io::Result<Self>
+    where
+        I: IntoIterator<Item = io::Result<(PathBuf, Node)>>,
+    {
+        let mut root = None;
+
+        for item in nodes {
+            let (path, node) = item?;
+            let mut components = path.iter();
+            let name = match components.next_back() {
+                Some(name) => name.to_string_lossy().into_owned(),
+                None => {
+                    root = Some(node);
+                    continue;
+                }
+            };
+
+            let mut parent = root.as_mut();
+            for component in components {
+                parent = match parent {
+                    Some(Node::Directory { entries }) => {
+                        entries.get_mut(&*component.to_string_lossy())
+                    }
+                    _ => None,
+                };
+            }
+
+            match parent {
+                Some(Node::Directory { entries }) => {
+                    entries.insert(name, node);
+                }
+                _ => {
+                    let message = format!("Orphaned entry {:?}", path);
+                    return Err(Error::other(message));
+                }
+            }
+        }
+
+        root.map(Listing::new)
+            .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "Archive is empty"))
+    }
+}
+
+/// Lists many archive files on up to `parallelism` threads, e.g. every file of a binary cache.
+/// Files ending in `.nar.xz` or `.nar.zst` are decompressed, within the default
+/// [`DecompressionLimits`](crate::compression::DecompressionLimits), and any other file is read as a plain NAR.
+///
+/// Results are yielded as each file is finished, so not necessarily in the order of `paths`.
+/// Dropping the iterator stops the threads after the files they are working on.
+#[cfg(feature = "fs")]
+pub fn list_many<I>(
+    paths: I,
+    parallelism: usize,
+) -> impl Iterator<Item = (PathBuf, io::Result<Listing>)>
+where
+    I: IntoIterator<Item = PathBuf>,
+{
+    let queue: VecDeque<PathBuf> = paths.into_iter().collect();
+    let threads = parallelism.clamp(1, queue.len().max(1));
+    let queue = Arc::new(Mutex::new(queue));
+    let (sender, receiver) = mpsc::channel();
+
+    for _ in 0..threads {
+        let queue = queue.clone();
+        let sender = sender.clone();
+        thread::spawn(move || loop {
+            let next = queue.lock().unwrap_or_else(|e| e.into_inner()).pop_front();
+            let path = match next {
+                Some(path) => path,
+                None => break,
+            };
+            let listing = list_file(&path);
+            if sender.send((path, listing)).is_err() {
+                break;
+            }
+        });
+    }
+
+    receiver.into_iter()
+}
+
+#[cfg(feature = "fs")]
+fn list_file(path: &Path) -> io::Result<Listing> {
+    let file = BufReader::new(File::open(path)?);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    #[cfg(any(not(feature = "xz"), not(feature = "zstd")))]
+    let unsupported = |format| {
+        let message = format!("Support for {} archives is not enabled", format);
+        Error::new(ErrorKind::Unsupported, message)
+    };
+
+    if name.ends_with(".nar.xz") {
+        #[cfg(feature = "xz")]
+        return Archive::new(xz::decoder_with_limits(file, &Default::default())?).listing();
+        #[cfg(not(feature = "xz"))]
+        return Err(unsupported("xz"));
+    }
+    if name.ends_with(".nar.zst") {
+        #[cfg(feature = "zstd")]
+        return Archive::new(zstd::decoder_with_limits(file, &Default::default())?).listing();
+        #[cfg(not(feature = "zstd"))]
+        return Err(unsupported("zstd"));
+    }
+    Archive::new(file).listing()
+}
+
+fn verify_node(expected: &Node, actual: &Node, path: &Path) -> io::Result<()> {
+    let mismatch = |what: String| {
+        let message = format!("Listing mismatch at {:?}: {}", path, what);
+        Err(Error::new(ErrorKind::InvalidData, message))
+    };
+
+    match (expected, actual) {
+        (Node::Directory { entries: expected }, Node::Directory { entries: actual }) => {
+            if let Some(name) = expected.keys().find(|name| !actual.contains_key(*name)) {
+                return mismatch(format!("missing entry `{}`", name));
+            }
+            if let Some(name) = actual.keys().find(|name| !expected.contains_key(*name)) {
+                return mismatch(format!("unexpected entry `{}`", name));
+            }
+            for (name, node) in expected {
+                verify_node(node, &actual[name], &path.join(name))?;
+            }
+            Ok(())
+        }
+        (
+            Node::Regular {
+                size,
+                executable,
+                nar_offset,
+            },
+            Node::Regular {
+                size: actual_size,
+                executable: actual_executable,
+                nar_offset: actual_offset,
+            },
+        ) => {
+            if size != actual_size {
+                return mismatch(format!("expected size {}, found {}", size, actual_size));
+            }
+            if executable != actual_executable {
+                return mismatch(format!(
+                    "expected executable {}, found {}",
+                    executable, actual_executable
+                ));
+            }
+            if nar_offset.is_some() && nar_offset != actual_offset {
+                return mismatch(format!(
+                    "expected offset {:?}, found {:?}",
+                    nar_offset, actual_offset
+                ));
+            }
+            Ok(())
+        }
+        (Node::Symlink { target }, Node::Symlink { target: actual }) => {
+            if target != actual {
+                return mismatch(format!("expected target {:?}, found {:?}", target, actual));
+            }
+            Ok(())
+        }
+        _ => mismatch("node types differ".to_owned()),
+    }
+}
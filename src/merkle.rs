@@ -0,0 +1,193 @@
+use std::collections::BTreeMap;
+use std::io::{self, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use crate::de::{Entry, EntryKind};
+use crate::hash::{NarHasher, Sha256Hash};
+use crate::{wire, NIX_VERSION_MAGIC};
+
+/// Digests of every directory in an archive, each computed over the directory's canonical NAR
+/// encoding as if it were archived on its own. A directory's digest therefore matches
+/// `hash_path` of that directory, and equal digests imply identical subtrees. Building the tree
+/// hashes each byte once per enclosing directory.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MerkleTree {
+    digests: BTreeMap<PathBuf, Sha256Hash>,
+}
+
+impl MerkleTree {
+    /// Digest of the whole archive, which is recorded even if the root is not a directory.
+    pub fn root(&self) -> Option<&Sha256Hash> {
+        self.digests.get(Path::new(""))
+    }
+
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> Option<&Sha256Hash> {
+        self.digests.get(path.as_ref())
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.digests.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.digests.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Path, &Sha256Hash)> {
+        self.digests
+            .iter()
+            .map(|(path, hash)| (path.as_path(), hash))
+    }
+
+    /// Returns every directory whose contents differ between the two trees. Directories present
+    /// on only one side are reported without their descendants.
+    pub fn diff(&self, other: &MerkleTree) -> Vec<PathBuf> {
+        let mut paths: Vec<&PathBuf> = self.digests.keys().chain(other.digests.keys()).collect();
+        paths.sort();
+        paths.dedup();
+
+        let mut changed: Vec<PathBuf> = Vec::new();
+        let mut one_sided: Vec<&Path> = Vec::new();
+        for path in paths {
+            if one_sided.iter().any(|p| path.starts_with(p)) {
+                continue;
+            }
+
+            match (self.digests.get(path), other.digests.get(path)) {
+                (Some(a), Some(b)) if a == b => {}
+                (Some(_), Some(_)) => changed.push(path.clone()),
+                _ => {
+                    one_sided.push(path);
+                    changed.push(path.clone());
+                }
+            }
+        }
+
+        changed
+    }
+
+    pub(crate) fn from_entries<'a, I>(entries: I) -> io::Result<Self>
+    where
+        I: IntoIterator<Item = io::Result<Entry<'a>>>,
+    {
+        let mut builder = Builder::default();
+        for entry in entries {
+            builder.push(&entry?)?;
+        }
+        builder.finish()
+    }
+}
+
+#[derive(Default)]
+struct Builder {
+    open: Vec<(PathBuf, NarHasher)>,
+    digests: BTreeMap<PathBuf, Sha256Hash>,
+    seen_root: bool,
+}
+
+impl Builder {
+    fn push(&mut self, entry: &Entry) -> io::Result<()> {
+        let name = entry.name();
+        while let Some((dir, _)) = self.open.last() {
+            if name.parent() == Some(dir.as_path()) {
+                break;
+            }
+            self.close()?;
+        }
+
+        match name.file_name() {
+            Some(file_name) => {
+                if self.open.is_empty() {
+                    let message = format!("Orphaned entry {:?}", name);
+                    return Err(Error::other(message));
+                }
+                self.emit(b"entry")?;
+                self.emit(b"(")?;
+                self.emit(b"name")?;
+                self.emit(file_name.to_string_lossy().as_bytes())?;
+                self.emit(b"node")?;
+            }
+            None if self.seen_root => return Err(Error::other("Archive has multiple roots")),
+            None => {}
+        }
+        self.seen_root = true;
+
+        let mut tokens: Vec<&[u8]> = vec![b"(", b"type"];
+        match &entry.kind {
+            EntryKind::Directory => {
+                self.open.push((name.to_owned(), standalone_hasher()?));
+                tokens.push(b"directory");
+                return tokens.iter().try_for_each(|token| self.emit(token));
+            }
+            EntryKind::Regular {
+                executable, data, ..
+            } => {
+                tokens.push(b"regular");
+                if *executable {
+                    tokens.extend_from_slice(&[b"executable", b""]);
+                }
+                tokens.extend_from_slice(&[b"contents", data]);
+            }
+            EntryKind::Symlink { target: link } => {
+                tokens.extend_from_slice(&[b"symlink", b"target", link.as_bytes()]);
+            }
+            EntryKind::Unknown { type_name, .. } => {
+                let message = format!("Cannot hash unrecognized node type `{}`", type_name);
+                return Err(Error::other(message));
+            }
+        }
+        tokens.push(b")");
+
+        if self.open.is_empty() {
+            let mut hasher = standalone_hasher()?;
+            for token in tokens {
+                wire::write_token(&mut hasher, token)?;
+            }
+            self.digests.insert(PathBuf::new(), hasher.finish().0);
+        } else {
+            for token in tokens {
+                self.emit(token)?;
+            }
+            self.emit(b")")?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<MerkleTree> {
+        while !self.open.is_empty() {
+            self.close()?;
+        }
+        if !self.seen_root {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "Archive is empty"));
+        }
+        Ok(MerkleTree {
+            digests: self.digests,
+        })
+    }
+
+    /// Feeds a token to the encodings of every directory that is still open.
+    fn emit(&mut self, token: &[u8]) -> io::Result<()> {
+        for (_, hasher) in &mut self.open {
+            wire::write_token(hasher, token)?;
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.emit(b")")?;
+        let (dir, hasher) = self.open.pop().expect("a directory is open");
+        self.digests.insert(dir, hasher.finish().0);
+        if !self.open.is_empty() {
+            self.emit(b")")?;
+        }
+        Ok(())
+    }
+}
+
+fn standalone_hasher() -> io::Result<NarHasher> {
+    let mut hasher = NarHasher::new();
+    wire::write_token(&mut hasher, NIX_VERSION_MAGIC)?;
+    Ok(hasher)
+}
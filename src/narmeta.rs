@@ -0,0 +1,384 @@
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::io::{self, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+
+use crate::de::EntryKind;
+use crate::hash::{self, NarHasher, Sha256Hash};
+use crate::Archive;
+
+#[cfg(feature = "fs")]
+pub use self::cache::MetaCache;
+
+#[cfg(feature = "fs")]
+mod cache;
+
+const MAGIC: &[u8; 8] = b"narmeta\0";
+const VERSION: u32 = 1;
+
+const KIND_DIRECTORY: u8 = 0;
+const KIND_REGULAR: u8 = 1;
+const KIND_SYMLINK: u8 = 2;
+
+/// An index of a stored NAR, kept next to it as a `.narmeta` sidecar, recording the offset, size
+/// and content hash of every file so it can be listed, read and verified without reparsing.
+#[derive(Clone, Eq, PartialEq)]
+pub struct NarMeta {
+    nar_hash: Sha256Hash,
+    nar_size: u64,
+    entries: Vec<MetaEntry>,
+    /// Index of every entry by path, built when the index is generated or loaded.
+    by_path: HashMap<String, usize>,
+    /// Indices of the direct children of each entry, in archive order.
+    children: Vec<Vec<usize>>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MetaEntry {
+    path: String,
+    kind: MetaKind,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum MetaKind {
+    Directory,
+    Regular {
+        executable: bool,
+        offset: u64,
+        size: u64,
+        hash: Sha256Hash,
+    },
+    Symlink {
+        target: String,
+    },
+}
+
+impl MetaEntry {
+    /// Path within the archive with components joined by `/`, empty for the root.
+    #[inline]
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    #[inline]
+    pub fn kind(&self) -> &MetaKind {
+        &self.kind
+    }
+
+    /// Last component of the path, empty for the root.
+    pub fn file_name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or("")
+    }
+}
+
+impl NarMeta {
+    /// Indexes a NAR in a single pass over `reader`.
+    pub fn generate<R: Read>(reader: R) -> io::Result<Self> {
+        let mut archive = Archive::new(HashingReader {
+            inner: reader,
+            hasher: NarHasher::new(),
+        });
+
+        let mut entries = Vec::new();
+        for entry in archive.entries()? {
+            let entry = entry?;
+            let kind = match &entry.kind {
+                EntryKind::Directory => MetaKind::Directory,
+                EntryKind::Regular {
+                    executable,
+                    data,
+                    offset,
+                } => MetaKind::Regular {
+                    executable: *executable,
+                    offset: *offset,
+                    size: data.len() as u64,
+                    hash: hash::hash_flat_reader(&data[..])?,
+                },
+                EntryKind::Symlink { target } => MetaKind::Symlink {
+                    target: target.to_utf8()?.to_owned(),
+                },
+                EntryKind::Unknown { type_name, .. } => {
+                    let message = format!("Cannot index unrecognized node type `{}`", type_name);
+                    return Err(Error::other(message));
+                }
+            };
+            entries.push(MetaEntry {
+                path: entry.nar_path(),
+                kind,
+            });
+        }
+
+        let (nar_hash, nar_size) = archive.into_inner().hasher.finish();
+        NarMeta::new(nar_hash, nar_size, entries)
+    }
+
+    fn new(nar_hash: Sha256Hash, nar_size: u64, entries: Vec<MetaEntry>) -> io::Result<Self> {
+        let mut by_path = HashMap::with_capacity(entries.len());
+        let mut children = vec![Vec::new(); entries.len()];
+        for (index, entry) in entries.iter().enumerate() {
+            if by_path.insert(entry.path.clone(), index).is_some() {
+                let message = format!("Duplicate narmeta entry {:?}", entry.path);
+                return Err(Error::new(ErrorKind::InvalidData, message));
+            }
+            if entry.path.is_empty() {
+                continue;
+            }
+
+            let parent = entry.path.rsplit_once('/').map_or("", |(parent, _)| parent);
+            match by_path.get(parent) {
+                Some(&parent) if entries[parent].kind == MetaKind::Directory => {
+                    children[parent].push(index)
+                }
+                _ => {
+                    let message = format!("Narmeta entry {:?} has no parent directory", entry.path);
+                    return Err(Error::new(ErrorKind::InvalidData, message));
+                }
+            }
+        }
+
+        Ok(NarMeta {
+            nar_hash,
+            nar_size,
+            entries,
+            by_path,
+            children,
+        })
+    }
+
+    #[inline]
+    pub fn nar_hash(&self) -> &Sha256Hash {
+        &self.nar_hash
+    }
+
+    #[inline]
+    pub fn nar_size(&self) -> u64 {
+        self.nar_size
+    }
+
+    /// Every entry in archive order, which lists parents before their children.
+    #[inline]
+    pub fn entries(&self) -> &[MetaEntry] {
+        &self.entries
+    }
+
+    pub fn get(&self, path: &str) -> Option<&MetaEntry> {
+        self.position(path).map(|index| &self.entries[index])
+    }
+
+    /// Returns the direct children of the directory at `path`.
+    pub fn list(&self, path: &str) -> io::Result<Vec<&MetaEntry>> {
+        let index = match self.position(path) {
+            Some(index) if self.entries[index].kind == MetaKind::Directory => index,
+            Some(_) => return Err(Error::other(format!("Not a directory: {}", path))),
+            None => return Err(not_found(path)),
+        };
+
+        let children = self.children[index].iter();
+        Ok(children.map(|&child| &self.entries[child]).collect())
+    }
+
+    fn position(&self, path: &str) -> Option<usize> {
+        self.by_path.get(path.trim_matches('/')).copied()
+    }
+
+    /// Reads the contents of the file at `path` straight out of the stored NAR.
+    pub fn read_file<R: Read + Seek>(&self, nar: &mut R, path: &str) -> io::Result<Vec<u8>> {
+        let (offset, size) = match self.get(path).map(MetaEntry::kind) {
+            Some(MetaKind::Regular { offset, size, .. }) => (*offset, *size),
+            Some(_) => return Err(Error::other(format!("Not a regular file: {}", path))),
+            None => return Err(not_found(path)),
+        };
+
+        nar.seek(SeekFrom::Start(offset))?;
+        let mut contents = Vec::new();
+        nar.take(size).read_to_end(&mut contents)?;
+        if contents.len() as u64 != size {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "Stored NAR is truncated",
+            ));
+        }
+        Ok(contents)
+    }
+
+    /// Checks a single file in the stored NAR against its recorded hash.
+    pub fn verify_file<R: Read + Seek>(&self, nar: &mut R, path: &str) -> io::Result<()> {
+        let expected = match self.get(path).map(MetaEntry::kind) {
+            Some(MetaKind::Regular { hash, .. }) => hash,
+            Some(_) => return Err(Error::other(format!("Not a regular file: {}", path))),
+            None => return Err(not_found(path)),
+        };
+
+        let actual = hash::hash_flat_reader(&self.read_file(nar, path)?[..])?;
+        if actual != *expected {
+            let message = format!(
+                "Hash mismatch for {}: expected {}, got {}",
+                path, expected, actual
+            );
+            return Err(Error::new(ErrorKind::InvalidData, message));
+        }
+        Ok(())
+    }
+
+    /// Checks the whole stored NAR against the recorded hash and size.
+    pub fn verify<R: Read>(&self, mut nar: R) -> io::Result<()> {
+        let mut hasher = NarHasher::new();
+        io::copy(&mut nar, &mut hasher)?;
+        let (hash, size) = hasher.finish();
+        if size != self.nar_size || hash != self.nar_hash {
+            let message = format!(
+                "NAR mismatch: expected {} ({} bytes), got {} ({} bytes)",
+                self.nar_hash, self.nar_size, hash, size
+            );
+            return Err(Error::new(ErrorKind::InvalidData, message));
+        }
+        Ok(())
+    }
+
+    pub fn to_writer<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&self.nar_size.to_le_bytes())?;
+        writer.write_all(self.nar_hash.as_bytes())?;
+        writer.write_all(&(self.entries.len() as u64).to_le_bytes())?;
+
+        for entry in &self.entries {
+            let kind = match entry.kind {
+                MetaKind::Directory => KIND_DIRECTORY,
+                MetaKind::Regular { .. } => KIND_REGULAR,
+                MetaKind::Symlink { .. } => KIND_SYMLINK,
+            };
+            writer.write_all(&[kind])?;
+            write_str(&mut writer, &entry.path)?;
+
+            match &entry.kind {
+                MetaKind::Directory => {}
+                MetaKind::Regular {
+                    executable,
+                    offset,
+                    size,
+                    hash,
+                } => {
+                    writer.write_all(&[*executable as u8])?;
+                    writer.write_all(&offset.to_le_bytes())?;
+                    writer.write_all(&size.to_le_bytes())?;
+                    writer.write_all(hash.as_bytes())?;
+                }
+                MetaKind::Symlink { target } => write_str(&mut writer, target)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.to_writer(&mut bytes)
+            .expect("writing to a Vec cannot fail");
+        bytes
+    }
+
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "Not a narmeta file"));
+        }
+
+        let version = u32::from_le_bytes(read_array(&mut reader)?);
+        if version != VERSION {
+            let message = format!("Unsupported narmeta version {}", version);
+            return Err(Error::new(ErrorKind::InvalidData, message));
+        }
+
+        let nar_size = read_u64(&mut reader)?;
+        let nar_hash = Sha256Hash::from_bytes(read_array(&mut reader)?);
+        let count = read_u64(&mut reader)?;
+
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let [kind] = read_array(&mut reader)?;
+            let path = read_str(&mut reader)?;
+            let kind = match kind {
+                KIND_DIRECTORY => MetaKind::Directory,
+                KIND_REGULAR => {
+                    let [executable] = read_array(&mut reader)?;
+                    MetaKind::Regular {
+                        executable: executable != 0,
+                        offset: read_u64(&mut reader)?,
+                        size: read_u64(&mut reader)?,
+                        hash: Sha256Hash::from_bytes(read_array(&mut reader)?),
+                    }
+                }
+                KIND_SYMLINK => MetaKind::Symlink {
+                    target: read_str(&mut reader)?,
+                },
+                other => {
+                    let message = format!("Unknown narmeta entry kind {}", other);
+                    return Err(Error::new(ErrorKind::InvalidData, message));
+                }
+            };
+            entries.push(MetaEntry { path, kind });
+        }
+
+        NarMeta::new(nar_hash, nar_size, entries)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        NarMeta::from_reader(bytes)
+    }
+}
+
+impl Debug for NarMeta {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        fmt.debug_struct(stringify!(NarMeta))
+            .field("nar_hash", &self.nar_hash)
+            .field("nar_size", &self.nar_size)
+            .field("entries", &self.entries)
+            .finish()
+    }
+}
+
+struct HashingReader<R> {
+    inner: R,
+    hasher: NarHasher,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.hasher.update(&buf[..len]);
+        Ok(len)
+    }
+}
+
+fn not_found(path: &str) -> Error {
+    Error::new(ErrorKind::NotFound, format!("No entry at {}", path))
+}
+
+fn write_str<W: Write>(writer: &mut W, value: &str) -> io::Result<()> {
+    writer.write_all(&(value.len() as u32).to_le_bytes())?;
+    writer.write_all(value.as_bytes())
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    read_array(reader).map(u64::from_le_bytes)
+}
+
+fn read_str<R: Read>(reader: &mut R) -> io::Result<String> {
+    let len = u32::from_le_bytes(read_array(reader)?);
+    let mut bytes = Vec::new();
+    reader.take(len.into()).read_to_end(&mut bytes)?;
+    if bytes.len() != len as usize {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "Truncated narmeta string",
+        ));
+    }
+    String::from_utf8(bytes).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
@@ -0,0 +1,108 @@
+use std::fs;
+use std::io::{self, Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use super::NarMeta;
+use crate::hash::Sha256Hash;
+use crate::temp::{SameFilesystem, TempProvider};
+
+const EXTENSION: &str = "narmeta";
+
+/// An on-disk cache of [`NarMeta`] indexes keyed by NAR hash, so that archives inspected
+/// repeatedly are only parsed once. Each index is a flat `.narmeta` file, written atomically.
+#[derive(Clone, Debug)]
+pub struct MetaCache {
+    root: PathBuf,
+}
+
+impl MetaCache {
+    pub fn open<P: AsRef<Path>>(root: P) -> io::Result<Self> {
+        let root = root.as_ref().to_owned();
+        fs::create_dir_all(&root)?;
+        Ok(MetaCache { root })
+    }
+
+    #[inline]
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn meta_path(&self, nar_hash: &Sha256Hash) -> PathBuf {
+        let hex = nar_hash.to_hex();
+        self.root
+            .join(&hex[..2])
+            .join(format!("{}.{}", hex, EXTENSION))
+    }
+
+    /// Looks up the index of the archive with `nar_hash`, failing if the cached file is corrupt
+    /// or describes another archive.
+    pub fn get(&self, nar_hash: &Sha256Hash) -> io::Result<Option<NarMeta>> {
+        let path = self.meta_path(nar_hash);
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        let meta = NarMeta::from_bytes(&bytes).map_err(|err| {
+            let message = format!("Corrupt cached index {}: {}", path.display(), err);
+            Error::new(ErrorKind::InvalidData, message)
+        })?;
+        if meta.nar_hash() != nar_hash {
+            let message = format!(
+                "Cached index for {} describes {}",
+                nar_hash,
+                meta.nar_hash()
+            );
+            return Err(Error::new(ErrorKind::InvalidData, message));
+        }
+        Ok(Some(meta))
+    }
+
+    /// Stores `meta` under its NAR hash, replacing any previous entry.
+    pub fn put(&self, meta: &NarMeta) -> io::Result<()> {
+        let dst = self.meta_path(meta.nar_hash());
+        fs::create_dir_all(dst.parent().expect("index paths have a parent"))?;
+
+        let (temp_path, mut file) = SameFilesystem.create_temp_file(&dst)?;
+        let result = file
+            .write_all(&meta.to_bytes())
+            .and_then(|_| file.sync_all())
+            .and_then(|_| fs::rename(&temp_path, &dst));
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result
+    }
+
+    /// Returns the cached index of the archive with `nar_hash`, or indexes the archive returned
+    /// by `open` and caches the result. Corrupt entries are replaced, and an archive that does
+    /// not match `nar_hash` is rejected without being cached.
+    pub fn get_or_index<R, F>(&self, nar_hash: &Sha256Hash, open: F) -> io::Result<NarMeta>
+    where
+        R: Read,
+        F: FnOnce() -> io::Result<R>,
+    {
+        match self.get(nar_hash) {
+            Ok(Some(meta)) => return Ok(meta),
+            Ok(None) => {}
+            Err(err) if err.kind() == ErrorKind::InvalidData => {}
+            Err(err) => return Err(err),
+        }
+
+        let meta = NarMeta::generate(open()?)?;
+        if meta.nar_hash() != nar_hash {
+            let message = format!("Expected NAR hash {}, got {}", nar_hash, meta.nar_hash());
+            return Err(Error::new(ErrorKind::InvalidData, message));
+        }
+        self.put(&meta)?;
+        Ok(meta)
+    }
+
+    pub fn remove(&self, nar_hash: &Sha256Hash) -> io::Result<()> {
+        match fs::remove_file(self.meta_path(nar_hash)) {
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+}
@@ -0,0 +1,358 @@
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Error, Read, Write};
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread;
+
+use crate::fanout::{FanOut, Sink};
+use crate::hash::{NarHasher, Sha256Hash};
+
+const BLOCK_LEN: usize = 64 * 1024;
+const DEFAULT_QUEUE_LEN: usize = 16;
+
+/// How the archive is compressed before it reaches the upload sink.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Compression {
+    None,
+    #[cfg(feature = "xz")]
+    Xz(u32),
+    #[cfg(feature = "zstd")]
+    Zstd(i32),
+}
+
+/// A flag shared with a running [`Pipeline`] that stops every stage when raised.
+#[derive(Clone, Debug, Default)]
+pub struct Cancellation {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Cancellation {
+    pub fn new() -> Self {
+        Cancellation::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// The digests of an uploaded archive, before and after compression, along with the value
+/// returned by the upload sink.
+#[derive(Debug)]
+pub struct Uploaded<T> {
+    pub nar_hash: Sha256Hash,
+    pub nar_size: u64,
+    pub file_hash: Sha256Hash,
+    pub file_size: u64,
+    pub upload: T,
+}
+
+/// Packs, hashes, compresses and uploads an archive on a supervised set of threads. A failure in
+/// any stage cancels the others, and the error that caused it is the one reported.
+#[derive(Clone, Debug)]
+pub struct Pipeline {
+    cancellation: Cancellation,
+    compression: Compression,
+    queue_len: usize,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Pipeline::default()
+    }
+
+    /// Stops the pipeline once `cancellation` is raised, failing [`run`](Pipeline::run).
+    pub fn set_cancellation(&mut self, cancellation: Cancellation) {
+        self.cancellation = cancellation;
+    }
+
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
+    /// Sets how many 64 KiB blocks may wait between two stages (16 by default).
+    pub fn set_queue_len(&mut self, len: usize) {
+        self.queue_len = len.max(1);
+    }
+
+    #[inline]
+    pub fn cancellation(&self) -> &Cancellation {
+        &self.cancellation
+    }
+
+    #[inline]
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    #[inline]
+    pub fn queue_len(&self) -> usize {
+        self.queue_len
+    }
+
+    /// Runs `source` on the calling thread to produce the archive, e.g. with
+    /// [`to_writer`](crate::to_writer), while `upload` reads the compressed archive on its own
+    /// thread. `upload` must read until the end, or the pipeline fails.
+    pub fn run<S, U, T>(&self, source: S, upload: U) -> io::Result<Uploaded<T>>
+    where
+        S: FnOnce(&mut dyn Write) -> io::Result<()>,
+        U: FnOnce(&mut dyn Read) -> io::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let stop = Stop {
+            cancellation: self.cancellation.clone(),
+            failed: Cancellation::new(),
+        };
+
+        let (sender, receiver) = mpsc::sync_channel(self.queue_len);
+        let uploader = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                let mut reader = BlockReader::new(receiver, stop.clone());
+                let result = upload(&mut reader).and_then(|value| {
+                    if reader.read(&mut [0])? != 0 {
+                        let message = "Upload sink returned before reading the whole archive";
+                        return Err(Error::other(message));
+                    }
+                    let (file_hash, file_size) = mem::take(&mut reader.hasher).finish();
+                    Ok((value, file_hash, file_size))
+                });
+                stop.fail_on(result)
+            })
+        };
+
+        let mut fanout = FanOut::new(self.queue_len);
+        let hasher = fanout.add_sink(NarHasher::new());
+        let compressor = compress(&mut fanout, self.compression, BlockWriter::new(sender));
+
+        let mut writer = StoppableWriter {
+            inner: &mut fanout,
+            stop: stop.clone(),
+        };
+        // The stop flag must be raised before the fan-out is dropped, so that the upload stage
+        // sees the queues closing early as a failure rather than the end of the archive.
+        let packed = stop.fail_on(source(&mut writer).and_then(|_| writer.flush()));
+        let packed = match packed {
+            Ok(()) => stop.fail_on(fanout.finish()),
+            Err(err) => {
+                drop(fanout);
+                Err(err)
+            }
+        };
+
+        let hashed = hasher.join();
+        let compressed = stop.fail_on(compressor.join());
+        let uploaded = uploader
+            .join()
+            .unwrap_or_else(|_| Err(Error::other("Upload sink panicked")));
+
+        match (uploaded, compressed, hashed, packed) {
+            (Ok((upload, file_hash, file_size)), Ok(()), Ok(hasher), Ok(())) => {
+                let (nar_hash, nar_size) = hasher.finish();
+                Ok(Uploaded {
+                    nar_hash,
+                    nar_size,
+                    file_hash,
+                    file_size,
+                    upload,
+                })
+            }
+            (uploaded, compressed, hashed, packed) => {
+                let errors = vec![uploaded.err(), compressed.err(), hashed.err(), packed.err()];
+                Err(root_cause(errors.into_iter().flatten()))
+            }
+        }
+    }
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Pipeline {
+            cancellation: Cancellation::new(),
+            compression: Compression::None,
+            queue_len: DEFAULT_QUEUE_LEN,
+        }
+    }
+}
+
+/// Why a stage gave up because of another stage, rather than failing on its own.
+#[derive(Debug)]
+enum Interruption {
+    Cancelled,
+    Stopped,
+}
+
+impl Display for Interruption {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match self {
+            Interruption::Cancelled => fmt.write_str("Pipeline was cancelled"),
+            Interruption::Stopped => fmt.write_str("Pipeline stage stopped early"),
+        }
+    }
+}
+
+impl std::error::Error for Interruption {}
+
+fn is_interruption(err: &Error) -> bool {
+    err.get_ref()
+        .is_some_and(|inner| inner.is::<Interruption>())
+}
+
+/// Picks the first error that is not merely a consequence of another stage stopping, so that a
+/// failed upload is reported as such and not as the packer being unable to write.
+fn root_cause<I: Iterator<Item = Error>>(errors: I) -> Error {
+    let mut first = None;
+    for err in errors {
+        if !is_interruption(&err) {
+            return err;
+        }
+        first.get_or_insert(err);
+    }
+    first.expect("at least one stage failed")
+}
+
+#[derive(Clone, Debug)]
+struct Stop {
+    cancellation: Cancellation,
+    failed: Cancellation,
+}
+
+impl Stop {
+    fn check(&self) -> io::Result<()> {
+        if self.cancellation.is_cancelled() {
+            Err(Error::other(Interruption::Cancelled))
+        } else if self.failed.is_cancelled() {
+            Err(Error::other(Interruption::Stopped))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn fail_on<T>(&self, result: io::Result<T>) -> io::Result<T> {
+        if result.is_err() {
+            self.failed.cancel();
+        }
+        result
+    }
+}
+
+fn compress(fanout: &mut FanOut, compression: Compression, writer: BlockWriter) -> Sink<()> {
+    match compression {
+        Compression::None => fanout.add_sink_with(writer, BlockWriter::close),
+        #[cfg(feature = "xz")]
+        Compression::Xz(level) => {
+            let encoder = crate::compression::xz::encoder(writer, level);
+            fanout.add_sink_with(encoder, |encoder| encoder.finish()?.close())
+        }
+        #[cfg(feature = "zstd")]
+        Compression::Zstd(level) => match crate::compression::zstd::encoder(writer, level) {
+            Ok(encoder) => fanout.add_sink_with(encoder, |encoder| encoder.finish()?.close()),
+            Err(err) => fanout.add_sink_with(io::sink(), move |_| Err(err)),
+        },
+    }
+}
+
+struct StoppableWriter<'a> {
+    inner: &'a mut FanOut,
+    stop: Stop,
+}
+
+impl Write for StoppableWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stop.check()?;
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stop.check()?;
+        self.inner.flush()
+    }
+}
+
+/// Hands the compressed archive over to the upload thread in blocks.
+struct BlockWriter {
+    sender: SyncSender<Vec<u8>>,
+    buffer: Vec<u8>,
+}
+
+impl BlockWriter {
+    fn new(sender: SyncSender<Vec<u8>>) -> Self {
+        BlockWriter {
+            sender,
+            buffer: Vec::with_capacity(BLOCK_LEN),
+        }
+    }
+
+    fn close(mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+impl Write for BlockWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(BLOCK_LEN - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+        if self.buffer.len() == BLOCK_LEN {
+            self.flush()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let block = mem::replace(&mut self.buffer, Vec::with_capacity(BLOCK_LEN));
+        self.sender
+            .send(block)
+            .map_err(|_| Error::other(Interruption::Stopped))
+    }
+}
+
+/// The upload thread's view of the compressed archive, which also hashes it. Reaching the end
+/// fails if another stage has stopped, so a truncated archive is never uploaded as complete.
+struct BlockReader {
+    receiver: Receiver<Vec<u8>>,
+    block: io::Cursor<Vec<u8>>,
+    hasher: NarHasher,
+    stop: Stop,
+}
+
+impl BlockReader {
+    fn new(receiver: Receiver<Vec<u8>>, stop: Stop) -> Self {
+        BlockReader {
+            receiver,
+            block: io::Cursor::new(Vec::new()),
+            hasher: NarHasher::new(),
+            stop,
+        }
+    }
+}
+
+impl Read for BlockReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stop.check()?;
+        loop {
+            let n = self.block.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                self.hasher.update(&buf[..n]);
+                return Ok(n);
+            }
+
+            match self.receiver.recv() {
+                Ok(block) => self.block = io::Cursor::new(block),
+                Err(_) => {
+                    self.stop.check()?;
+                    return Ok(0);
+                }
+            }
+        }
+    }
+}
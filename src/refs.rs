@@ -0,0 +1,139 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, Read};
+
+use crate::de::EntryKind;
+use crate::elf::Elf;
+use crate::store_path::{StorePath, HASH_PART_LEN};
+use crate::Archive;
+
+const BASE32_CHARS: &[u8; 32] = b"0123456789abcdfghijklmnpqrsvwxyz";
+
+/// How much of an archive a [`ReferenceScanner`] looks at.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ScanMode {
+    /// Searches every file name, file and symlink target, like Nix does.
+    Full,
+    /// Only inspects the places picked by the scanner's [`Matcher`]s. This is much faster but can
+    /// miss references, so it is meant for pruning candidates before a full scan.
+    Fast,
+}
+
+/// Places inspected by a [`ScanMode::Fast`] scan.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum Matcher {
+    SymlinkTarget,
+    /// The `#!` line of scripts.
+    Shebang,
+    /// The `PT_INTERP` path of ELF executables.
+    ElfInterpreter,
+}
+
+/// Finds which of a set of candidate store paths are referenced from within a NAR, by looking for
+/// their hash parts.
+#[derive(Clone, Debug)]
+pub struct ReferenceScanner {
+    candidates: HashMap<String, StorePath>,
+    mode: ScanMode,
+    matchers: Vec<Matcher>,
+}
+
+impl ReferenceScanner {
+    pub fn new<I: IntoIterator<Item = StorePath>>(candidates: I) -> Self {
+        let candidates = candidates
+            .into_iter()
+            .map(|path| (path.hash_part().to_owned(), path))
+            .collect();
+        ReferenceScanner {
+            candidates,
+            mode: ScanMode::Full,
+            matchers: vec![
+                Matcher::SymlinkTarget,
+                Matcher::Shebang,
+                Matcher::ElfInterpreter,
+            ],
+        }
+    }
+
+    /// Defaults to [`ScanMode::Full`].
+    pub fn set_mode(&mut self, mode: ScanMode) {
+        self.mode = mode;
+    }
+
+    /// Sets what a fast scan inspects. All matchers are enabled by default.
+    pub fn set_matchers(&mut self, matchers: &[Matcher]) {
+        self.matchers = matchers.to_vec();
+    }
+
+    #[inline]
+    pub fn mode(&self) -> ScanMode {
+        self.mode
+    }
+
+    #[inline]
+    pub fn matchers(&self) -> &[Matcher] {
+        &self.matchers
+    }
+
+    pub fn scan<R: Read>(&self, reader: R) -> io::Result<BTreeSet<StorePath>> {
+        let mut found = BTreeSet::new();
+        let mut archive = Archive::new(reader);
+
+        for entry in archive.entries()? {
+            let entry = entry?;
+            if self.mode == ScanMode::Full {
+                self.search(entry.name().as_os_str().as_encoded_bytes(), &mut found);
+            }
+
+            match &entry.kind {
+                EntryKind::Symlink { target } if self.inspects(Matcher::SymlinkTarget) => {
+                    self.search(target.as_bytes(), &mut found);
+                }
+                EntryKind::Regular { data, .. } if self.mode == ScanMode::Full => {
+                    self.search(data, &mut found);
+                }
+                EntryKind::Regular { data, .. } => {
+                    if self.matchers.contains(&Matcher::Shebang) && data.starts_with(b"#!") {
+                        let line = data.split(|&b| b == b'\n').next().unwrap_or(data);
+                        self.search(line, &mut found);
+                    }
+                    if self.matchers.contains(&Matcher::ElfInterpreter) {
+                        if let Some(interpreter) = Elf::parse(data).and_then(|e| e.interpreter()) {
+                            self.search(interpreter, &mut found);
+                        }
+                    }
+                }
+                _ => {}
+            }
+
+            if found.len() == self.candidates.len() {
+                break;
+            }
+        }
+
+        Ok(found)
+    }
+
+    fn inspects(&self, matcher: Matcher) -> bool {
+        self.mode == ScanMode::Full || self.matchers.contains(&matcher)
+    }
+
+    fn search(&self, data: &[u8], found: &mut BTreeSet<StorePath>) {
+        let mut i = 0;
+        'windows: while i + HASH_PART_LEN <= data.len() {
+            // Checking from the end of the window lets a single invalid byte skip past it.
+            for j in (0..HASH_PART_LEN).rev() {
+                if !BASE32_CHARS.contains(&data[i + j]) {
+                    i += j + 1;
+                    continue 'windows;
+                }
+            }
+
+            let window = std::str::from_utf8(&data[i..i + HASH_PART_LEN]).expect("base32 is ASCII");
+            if let Some(path) = self.candidates.get(window) {
+                found.insert(path.clone());
+            }
+            i += 1;
+        }
+    }
+}
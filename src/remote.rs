@@ -0,0 +1,255 @@
+#[cfg(feature = "fs")]
+use std::fs;
+#[cfg(feature = "fs")]
+use std::io::Write;
+use std::io::{self, Error, ErrorKind, Read, Seek, SeekFrom};
+#[cfg(feature = "fs")]
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+#[cfg(feature = "fs")]
+use std::path::{Component, PathBuf};
+
+#[cfg(feature = "zstd")]
+use crate::compression::zstd::SeekableReader;
+use crate::listing::{Listing, Node};
+#[cfg(feature = "fs")]
+use crate::temp::{InDirectory, TempProvider};
+
+pub use self::prefetch::PrefetchReader;
+
+mod prefetch;
+
+/// A source of byte ranges, such as an HTTP client issuing `Range` requests against a binary
+/// cache. Implementations must return exactly the requested bytes or an error.
+pub trait RangeTransport {
+    fn total_len(&self) -> io::Result<u64>;
+
+    fn read_range(&self, offset: u64, len: u64) -> io::Result<Vec<u8>>;
+}
+
+impl<T: RangeTransport + ?Sized> RangeTransport for &T {
+    fn total_len(&self) -> io::Result<u64> {
+        (**self).total_len()
+    }
+
+    fn read_range(&self, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        (**self).read_range(offset, len)
+    }
+}
+
+/// Adapts a [`RangeTransport`] into a `Read + Seek` stream; every read becomes one range request.
+#[derive(Debug)]
+pub struct RangeReader<T> {
+    transport: T,
+    len: u64,
+    position: u64,
+}
+
+impl<T: RangeTransport> RangeReader<T> {
+    pub fn new(transport: T) -> io::Result<Self> {
+        let len = transport.total_len()?;
+        Ok(RangeReader {
+            transport,
+            len,
+            position: 0,
+        })
+    }
+
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+}
+
+impl<T: RangeTransport> Read for RangeReader<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = (buf.len() as u64).min(self.len.saturating_sub(self.position));
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let data = self.transport.read_range(self.position, len)?;
+        if data.len() as u64 != len {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "Transport returned a short range",
+            ));
+        }
+
+        buf[..data.len()].copy_from_slice(&data);
+        self.position += len;
+        Ok(data.len())
+    }
+}
+
+impl<T: RangeTransport> Seek for RangeReader<T> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+
+        self.position = position.ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                "Seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+#[derive(Debug)]
+enum Source<T> {
+    Uncompressed(T),
+    #[cfg(feature = "zstd")]
+    Seekable(Box<SeekableReader<RangeReader<T>>>),
+}
+
+/// A NAR hosted remotely, from which single files can be read by fetching only the byte ranges
+/// that hold them.
+#[derive(Debug)]
+pub struct RemoteNar<T> {
+    source: Source<T>,
+    listing: Listing,
+}
+
+impl<T: RangeTransport> RemoteNar<T> {
+    pub fn uncompressed(transport: T, listing: Listing) -> Self {
+        RemoteNar {
+            source: Source::Uncompressed(transport),
+            listing,
+        }
+    }
+
+    /// Opens a `nar.zst` written in the zstd seekable format. Only the seek table is fetched.
+    #[cfg(feature = "zstd")]
+    pub fn zstd_seekable(transport: T, listing: Listing) -> io::Result<Self> {
+        let reader = SeekableReader::new(RangeReader::new(transport)?)?;
+        Ok(RemoteNar {
+            source: Source::Seekable(Box::new(reader)),
+            listing,
+        })
+    }
+
+    #[inline]
+    pub fn listing(&self) -> &Listing {
+        &self.listing
+    }
+
+    pub fn read_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<Vec<u8>> {
+        let path = path.as_ref();
+        let (offset, size) = match self.listing.get(path) {
+            Some(Node::Regular {
+                size,
+                nar_offset: Some(offset),
+                ..
+            }) => (*offset, *size),
+            Some(Node::Regular { .. }) => {
+                let message = format!("Listing has no offset for {:?}", path);
+                return Err(Error::new(ErrorKind::InvalidInput, message));
+            }
+            Some(_) => {
+                let message = format!("{:?} is not a regular file", path);
+                return Err(Error::new(ErrorKind::InvalidInput, message));
+            }
+            None => {
+                let message = format!("{:?} not found in listing", path);
+                return Err(Error::new(ErrorKind::NotFound, message));
+            }
+        };
+
+        match &mut self.source {
+            Source::Uncompressed(transport) => {
+                let data = transport.read_range(offset, size)?;
+                if data.len() as u64 != size {
+                    let message = "Transport returned a short range";
+                    return Err(Error::new(ErrorKind::UnexpectedEof, message));
+                }
+                Ok(data)
+            }
+            #[cfg(feature = "zstd")]
+            Source::Seekable(reader) => reader.read_range(offset, size),
+        }
+    }
+}
+
+/// Materializes files of a [`RemoteNar`] into a local directory on first access, so a tree can
+/// be used before the whole archive has been downloaded. This is the caching core a FUSE
+/// front-end would call from its `open` handler.
+#[derive(Debug)]
+#[cfg(feature = "fs")]
+pub struct LazyTree<T> {
+    remote: RemoteNar<T>,
+    root: PathBuf,
+    temp: InDirectory,
+}
+
+#[cfg(feature = "fs")]
+impl<T: RangeTransport> LazyTree<T> {
+    pub fn new<P: AsRef<Path>>(remote: RemoteNar<T>, root: P) -> io::Result<Self> {
+        let root = root.as_ref().to_owned();
+        fs::create_dir_all(&root)?;
+        let temp = InDirectory(root.clone());
+        Ok(LazyTree { remote, root, temp })
+    }
+
+    #[inline]
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn is_materialized<P: AsRef<Path>>(&self, path: P) -> bool {
+        fs::symlink_metadata(self.root.join(path)).is_ok()
+    }
+
+    /// Returns the local path of `path`, fetching it (and creating its parent directories) if
+    /// it has not been materialized yet. Directories are created empty; their children are
+    /// fetched individually as they are accessed.
+    pub fn materialize<P: AsRef<Path>>(&mut self, path: P) -> io::Result<PathBuf> {
+        let path = path.as_ref();
+        if path
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            let message = format!("Invalid path {:?}", path);
+            return Err(Error::new(ErrorKind::InvalidInput, message));
+        }
+
+        let local = self.root.join(path);
+        if fs::symlink_metadata(&local).is_ok() {
+            return Ok(local);
+        }
+
+        let node = self.remote.listing().get(path).cloned().ok_or_else(|| {
+            let message = format!("{:?} not found in listing", path);
+            Error::new(ErrorKind::NotFound, message)
+        })?;
+
+        if let Some(parent) = local.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        match node {
+            Node::Directory { .. } => fs::create_dir_all(&local)?,
+            Node::Symlink { target } => std::os::unix::fs::symlink(target, &local)?,
+            Node::Regular { executable, .. } => {
+                let data = self.remote.read_file(path)?;
+                let (temp_path, mut file) = self.temp.create_temp_file(&local)?;
+                let result = file
+                    .write_all(&data)
+                    .and_then(|_| {
+                        let mode = if executable { 0o555 } else { 0o444 };
+                        fs::set_permissions(&temp_path, fs::Permissions::from_mode(mode))
+                    })
+                    .and_then(|_| fs::rename(&temp_path, &local));
+                if result.is_err() {
+                    let _ = fs::remove_file(&temp_path);
+                }
+                result?;
+            }
+        }
+
+        Ok(local)
+    }
+}
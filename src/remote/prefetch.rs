@@ -0,0 +1,119 @@
+use std::io::{self, BufRead, Error, ErrorKind, Read};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+const DEFAULT_BLOCK_LEN: usize = 64 * 1024;
+const DEFAULT_BLOCKS: usize = 16;
+
+/// Reads ahead of its consumer on a background thread, for sources with a high latency per read
+/// such as HTTP bodies or network filesystems. The parser issues many reads of a few bytes for
+/// tags and length prefixes, which are then served from blocks that have already arrived.
+///
+/// At most `blocks` blocks wait to be consumed, and consumed blocks are handed back to the
+/// background thread for reuse.
+#[derive(Debug)]
+pub struct PrefetchReader {
+    receiver: Receiver<io::Result<Vec<u8>>>,
+    recycle: Sender<Vec<u8>>,
+    block: Vec<u8>,
+    pos: usize,
+    thread: Option<JoinHandle<()>>,
+    failed: bool,
+}
+
+impl PrefetchReader {
+    /// Reads ahead up to 16 blocks of 64 KiB each.
+    pub fn new<R: Read + Send + 'static>(reader: R) -> Self {
+        PrefetchReader::with_capacity(reader, DEFAULT_BLOCK_LEN, DEFAULT_BLOCKS)
+    }
+
+    /// Reads ahead up to `blocks` blocks of `block_len` bytes each.
+    pub fn with_capacity<R>(mut reader: R, block_len: usize, blocks: usize) -> Self
+    where
+        R: Read + Send + 'static,
+    {
+        let block_len = block_len.max(1);
+        let (sender, receiver) = mpsc::sync_channel(blocks.max(1));
+        let (recycle, recycled) = mpsc::channel::<Vec<u8>>();
+
+        let thread = thread::spawn(move || loop {
+            let mut block = recycled.try_recv().unwrap_or_default();
+            block.resize(block_len, 0);
+            let len = match read_retrying(&mut reader, &mut block) {
+                Ok(0) => return,
+                Ok(len) => len,
+                Err(err) => {
+                    let _ = sender.send(Err(err));
+                    return;
+                }
+            };
+            block.truncate(len);
+            if sender.send(Ok(block)).is_err() {
+                return;
+            }
+        });
+
+        PrefetchReader {
+            receiver,
+            recycle,
+            block: Vec::new(),
+            pos: 0,
+            thread: Some(thread),
+            failed: false,
+        }
+    }
+}
+
+fn read_retrying<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        match reader.read(buf) {
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            result => return result,
+        }
+    }
+}
+
+impl BufRead for PrefetchReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.failed {
+            return Err(Error::other("Prefetched reader failed on an earlier read"));
+        }
+
+        if self.pos == self.block.len() && self.thread.is_some() {
+            match self.receiver.recv() {
+                Ok(Ok(block)) => {
+                    let used = std::mem::replace(&mut self.block, block);
+                    let _ = self.recycle.send(used);
+                    self.pos = 0;
+                }
+                Ok(Err(err)) => {
+                    self.failed = true;
+                    return Err(err);
+                }
+                Err(_) => {
+                    let thread = self.thread.take().expect("checked above");
+                    if thread.join().is_err() {
+                        self.failed = true;
+                        return Err(Error::other("Prefetching thread panicked"));
+                    }
+                }
+            }
+        }
+
+        Ok(&self.block[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.block.len());
+    }
+}
+
+impl Read for PrefetchReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
@@ -0,0 +1,209 @@
+use std::borrow::Cow;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+pub use self::filters::{RpathFilter, ShebangFilter};
+pub use self::relocate::{relocate, LengthPolicy, Relocator};
+
+use crate::de::{EntryKind, ExtractSink};
+use crate::{wire, Archive, SymlinkTarget, NIX_VERSION_MAGIC};
+
+mod filters;
+mod relocate;
+
+/// Patches file contents and symlink targets on their way out of an archive, either into another
+/// archive through [`rewrite`] or into an [`ExtractSink`] through [`Filtered`].
+///
+/// Both methods leave their input untouched by default.
+pub trait ContentFilter {
+    fn filter_file<'a>(
+        &mut self,
+        path: &Path,
+        executable: bool,
+        contents: &'a [u8],
+    ) -> io::Result<Cow<'a, [u8]>> {
+        let _ = (path, executable);
+        Ok(Cow::Borrowed(contents))
+    }
+
+    fn filter_symlink<'a>(
+        &mut self,
+        path: &Path,
+        target: &'a SymlinkTarget,
+    ) -> io::Result<Cow<'a, SymlinkTarget>> {
+        let _ = path;
+        Ok(Cow::Borrowed(target))
+    }
+}
+
+impl<F: ContentFilter + ?Sized> ContentFilter for &mut F {
+    fn filter_file<'a>(
+        &mut self,
+        path: &Path,
+        executable: bool,
+        contents: &'a [u8],
+    ) -> io::Result<Cow<'a, [u8]>> {
+        (**self).filter_file(path, executable, contents)
+    }
+
+    fn filter_symlink<'a>(
+        &mut self,
+        path: &Path,
+        target: &'a SymlinkTarget,
+    ) -> io::Result<Cow<'a, SymlinkTarget>> {
+        (**self).filter_symlink(path, target)
+    }
+}
+
+/// Applies `A` and then `B`.
+impl<A: ContentFilter, B: ContentFilter> ContentFilter for (A, B) {
+    fn filter_file<'a>(
+        &mut self,
+        path: &Path,
+        executable: bool,
+        contents: &'a [u8],
+    ) -> io::Result<Cow<'a, [u8]>> {
+        match self.0.filter_file(path, executable, contents)? {
+            Cow::Borrowed(contents) => self.1.filter_file(path, executable, contents),
+            Cow::Owned(contents) => {
+                let filtered = self.1.filter_file(path, executable, &contents)?;
+                Ok(Cow::Owned(filtered.into_owned()))
+            }
+        }
+    }
+
+    fn filter_symlink<'a>(
+        &mut self,
+        path: &Path,
+        target: &'a SymlinkTarget,
+    ) -> io::Result<Cow<'a, SymlinkTarget>> {
+        match self.0.filter_symlink(path, target)? {
+            Cow::Borrowed(target) => self.1.filter_symlink(path, target),
+            Cow::Owned(target) => {
+                let filtered = self.1.filter_symlink(path, &target)?;
+                Ok(Cow::Owned(filtered.into_owned()))
+            }
+        }
+    }
+}
+
+/// An [`ExtractSink`] that passes every file and symlink through a filter before handing it on.
+#[derive(Debug)]
+pub struct Filtered<S, F> {
+    sink: S,
+    filter: F,
+}
+
+impl<S: ExtractSink, F: ContentFilter> Filtered<S, F> {
+    pub fn new(sink: S, filter: F) -> Self {
+        Filtered { sink, filter }
+    }
+
+    pub fn into_inner(self) -> (S, F) {
+        (self.sink, self.filter)
+    }
+}
+
+impl<S: ExtractSink, F: ContentFilter> ExtractSink for Filtered<S, F> {
+    fn create_dir(&mut self, path: &Path) -> io::Result<()> {
+        self.sink.create_dir(path)
+    }
+
+    fn create_file(&mut self, path: &Path, executable: bool, contents: &[u8]) -> io::Result<()> {
+        let contents = self.filter.filter_file(path, executable, contents)?;
+        self.sink.create_file(path, executable, &contents)
+    }
+
+    fn create_symlink(&mut self, path: &Path, target: &SymlinkTarget) -> io::Result<()> {
+        let target = self.filter.filter_symlink(path, target)?;
+        self.sink.create_symlink(path, &target)
+    }
+}
+
+/// Copies the archive read from `reader` to `writer`, passing every file and symlink through
+/// `filter`. Everything else, including unrecognized nodes, is copied unchanged.
+pub fn rewrite<R, W, F>(reader: R, writer: &mut W, filter: &mut F) -> io::Result<()>
+where
+    R: Read,
+    W: Write,
+    F: ContentFilter + ?Sized,
+{
+    let mut archive = Archive::new(reader);
+    wire::write_token(writer, NIX_VERSION_MAGIC)?;
+
+    // Depths of the directories whose nodes are still open.
+    let mut open_dirs: Vec<usize> = Vec::new();
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let depth = entry.depth();
+        while let Some(&open) = open_dirs.last() {
+            if open < depth {
+                break;
+            }
+            open_dirs.pop();
+            close_node(writer, open)?;
+        }
+
+        if let Some(name) = entry.path_components().next_back() {
+            wire::write_token(writer, b"entry")?;
+            wire::write_token(writer, b"(")?;
+            wire::write_token(writer, b"name")?;
+            wire::write_token(writer, name.as_bytes())?;
+            wire::write_token(writer, b"node")?;
+        }
+
+        match &entry.kind {
+            EntryKind::Directory => {
+                wire::write_token(writer, b"(")?;
+                wire::write_token(writer, b"type")?;
+                wire::write_token(writer, b"directory")?;
+                open_dirs.push(depth);
+                continue;
+            }
+            EntryKind::Regular {
+                executable, data, ..
+            } => {
+                let contents = filter.filter_file(entry.name(), *executable, data)?;
+                wire::write_token(writer, b"(")?;
+                wire::write_token(writer, b"type")?;
+                wire::write_token(writer, b"regular")?;
+                if *executable {
+                    wire::write_token(writer, b"executable")?;
+                    wire::write_token(writer, b"")?;
+                }
+                wire::write_token(writer, b"contents")?;
+                wire::write_token(writer, &contents)?;
+            }
+            EntryKind::Symlink { target } => {
+                let target = filter.filter_symlink(entry.name(), target)?;
+                wire::write_token(writer, b"(")?;
+                wire::write_token(writer, b"type")?;
+                wire::write_token(writer, b"symlink")?;
+                wire::write_token(writer, b"target")?;
+                wire::write_token(writer, target.as_bytes())?;
+            }
+            EntryKind::Unknown { .. } => {
+                entry.write_raw_node(writer)?;
+                if depth > 0 {
+                    wire::write_token(writer, b")")?;
+                }
+                continue;
+            }
+        }
+        close_node(writer, depth)?;
+    }
+
+    while let Some(open) = open_dirs.pop() {
+        close_node(writer, open)?;
+    }
+    Ok(())
+}
+
+/// Closes a node, along with the directory entry holding it unless it is the root.
+fn close_node<W: Write + ?Sized>(writer: &mut W, depth: usize) -> io::Result<()> {
+    wire::write_token(writer, b")")?;
+    if depth > 0 {
+        wire::write_token(writer, b")")?;
+    }
+    Ok(())
+}
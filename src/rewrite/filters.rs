@@ -0,0 +1,120 @@
+use std::borrow::Cow;
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+
+use super::ContentFilter;
+use crate::elf::Elf;
+
+/// Replaces the `from` prefix of the interpreter named on the `#!` line of scripts with `to`.
+/// Arguments after the interpreter and the rest of the script are left alone.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ShebangFilter {
+    from: String,
+    to: String,
+}
+
+impl ShebangFilter {
+    pub fn new<S: Into<String>, T: Into<String>>(from: S, to: T) -> Self {
+        ShebangFilter {
+            from: from.into(),
+            to: to.into(),
+        }
+    }
+}
+
+impl ContentFilter for ShebangFilter {
+    fn filter_file<'a>(
+        &mut self,
+        _path: &Path,
+        _executable: bool,
+        contents: &'a [u8],
+    ) -> io::Result<Cow<'a, [u8]>> {
+        let rest = match contents.strip_prefix(b"#!") {
+            Some(rest) => rest,
+            None => return Ok(Cow::Borrowed(contents)),
+        };
+
+        let start = 2 + rest
+            .iter()
+            .take_while(|&&b| b == b' ' || b == b'\t')
+            .count();
+        if !contents[start..].starts_with(self.from.as_bytes()) {
+            return Ok(Cow::Borrowed(contents));
+        }
+
+        let mut patched = Vec::with_capacity(contents.len() + self.to.len());
+        patched.extend_from_slice(&contents[..start]);
+        patched.extend_from_slice(self.to.as_bytes());
+        patched.extend_from_slice(&contents[start + self.from.len()..]);
+        Ok(Cow::Owned(patched))
+    }
+}
+
+/// Replaces the `from` prefix of every `RPATH` and `RUNPATH` entry of ELF files with `to`.
+///
+/// The strings are patched in place and padded with NUL bytes, so this fails on any file where
+/// a patched string would be longer than the original.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RpathFilter {
+    from: String,
+    to: String,
+}
+
+impl RpathFilter {
+    pub fn new<S: Into<String>, T: Into<String>>(from: S, to: T) -> Self {
+        RpathFilter {
+            from: from.into(),
+            to: to.into(),
+        }
+    }
+
+    fn patch(&self, rpath: &[u8]) -> Option<Vec<u8>> {
+        let mut changed = false;
+        let entries: Vec<Vec<u8>> = rpath
+            .split(|&b| b == b':')
+            .map(|entry| match entry.strip_prefix(self.from.as_bytes()) {
+                Some(rest) => {
+                    changed = true;
+                    [self.to.as_bytes(), rest].concat()
+                }
+                None => entry.to_vec(),
+            })
+            .collect();
+        Some(entries.join(&b':')).filter(|_| changed)
+    }
+}
+
+impl ContentFilter for RpathFilter {
+    fn filter_file<'a>(
+        &mut self,
+        path: &Path,
+        _executable: bool,
+        contents: &'a [u8],
+    ) -> io::Result<Cow<'a, [u8]>> {
+        let elf = match Elf::parse(contents) {
+            Some(elf) => elf,
+            None => return Ok(Cow::Borrowed(contents)),
+        };
+
+        let mut patched = Cow::Borrowed(contents);
+        for (offset, rpath) in elf.run_paths() {
+            let replacement = match self.patch(rpath) {
+                Some(replacement) => replacement,
+                None => continue,
+            };
+            if replacement.len() > rpath.len() {
+                let message = format!(
+                    "Rewritten RPATH of {} is {} bytes longer than the original",
+                    path.display(),
+                    replacement.len() - rpath.len()
+                );
+                return Err(Error::new(ErrorKind::InvalidData, message));
+            }
+
+            let target = &mut patched.to_mut()[offset..offset + rpath.len()];
+            target[..replacement.len()].copy_from_slice(&replacement);
+            target[replacement.len()..].fill(0);
+        }
+        Ok(patched)
+    }
+}
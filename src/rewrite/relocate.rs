@@ -0,0 +1,136 @@
+use std::borrow::Cow;
+use std::io::{self, Error, ErrorKind, Read, Write};
+use std::path::Path;
+
+use super::{rewrite, ContentFilter};
+use crate::SymlinkTarget;
+
+/// What [`relocate`] does when the two prefixes differ in length.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LengthPolicy {
+    /// Refuses prefixes of different lengths, so every file keeps its size and offsets embedded
+    /// in binaries stay valid.
+    Strict,
+    /// Pads a shorter destination prefix with leading slashes, e.g. `/opt/app` becomes
+    /// `///////opt/app`, which still names the same directory. Longer prefixes are refused.
+    PadWithSlashes,
+    /// Replaces the prefix as is and lets files change size. Only safe for archives without
+    /// binaries that embed the prefix.
+    Resize,
+}
+
+/// A [`ContentFilter`] replacing every occurrence of one prefix with another in file contents
+/// and symlink targets.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Relocator {
+    from: Vec<u8>,
+    to: Vec<u8>,
+    replaced: u64,
+}
+
+impl Relocator {
+    pub fn new(from: &str, to: &str, policy: LengthPolicy) -> io::Result<Self> {
+        if from.is_empty() {
+            let message = "Cannot relocate an empty prefix";
+            return Err(Error::new(ErrorKind::InvalidInput, message));
+        }
+
+        let to = match policy {
+            _ if to.len() == from.len() => to.to_owned(),
+            LengthPolicy::PadWithSlashes if to.len() < from.len() && to.starts_with('/') => {
+                format!("{}{}", "/".repeat(from.len() - to.len()), to)
+            }
+            LengthPolicy::Resize => to.to_owned(),
+            _ => {
+                let message = format!(
+                    "Cannot relocate {} ({} bytes) to {} ({} bytes) without changing file sizes",
+                    from,
+                    from.len(),
+                    to,
+                    to.len()
+                );
+                return Err(Error::new(ErrorKind::InvalidInput, message));
+            }
+        };
+
+        Ok(Relocator {
+            from: from.as_bytes().to_vec(),
+            to: to.into_bytes(),
+            replaced: 0,
+        })
+    }
+
+    /// The prefix written in place of the original one, including any padding.
+    pub fn replacement(&self) -> &str {
+        std::str::from_utf8(&self.to).expect("replacement is built from a str")
+    }
+
+    /// Number of occurrences replaced so far.
+    #[inline]
+    pub fn replaced(&self) -> u64 {
+        self.replaced
+    }
+
+    fn replace<'a>(&mut self, data: &'a [u8]) -> Cow<'a, [u8]> {
+        let mut rest = data;
+        let mut replaced = Vec::new();
+        while let Some(at) = find(rest, &self.from) {
+            replaced.extend_from_slice(&rest[..at]);
+            replaced.extend_from_slice(&self.to);
+            rest = &rest[at + self.from.len()..];
+            self.replaced += 1;
+        }
+
+        if rest.len() == data.len() {
+            return Cow::Borrowed(data);
+        }
+        replaced.extend_from_slice(rest);
+        Cow::Owned(replaced)
+    }
+}
+
+impl ContentFilter for Relocator {
+    fn filter_file<'a>(
+        &mut self,
+        _path: &Path,
+        _executable: bool,
+        contents: &'a [u8],
+    ) -> io::Result<Cow<'a, [u8]>> {
+        Ok(self.replace(contents))
+    }
+
+    fn filter_symlink<'a>(
+        &mut self,
+        _path: &Path,
+        target: &'a SymlinkTarget,
+    ) -> io::Result<Cow<'a, SymlinkTarget>> {
+        match self.replace(target.as_bytes()) {
+            Cow::Borrowed(_) => Ok(Cow::Borrowed(target)),
+            Cow::Owned(bytes) => Ok(Cow::Owned(SymlinkTarget::from_bytes(bytes))),
+        }
+    }
+}
+
+/// Copies the archive read from `reader` to `writer` with every occurrence of `from` in file
+/// contents and symlink targets replaced by `to`, returning the number of replacements.
+pub fn relocate<R, W>(
+    reader: R,
+    writer: &mut W,
+    from: &str,
+    to: &str,
+    policy: LengthPolicy,
+) -> io::Result<u64>
+where
+    R: Read,
+    W: Write,
+{
+    let mut relocator = Relocator::new(from, to, policy)?;
+    rewrite(reader, writer, &mut relocator)?;
+    Ok(relocator.replaced())
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
@@ -0,0 +1,115 @@
+use std::io::{self, Read, Write};
+
+use crate::de::{Archive, Kind};
+use crate::hash::{self, NarHasher, Sha256Hash};
+
+const DEFAULT_SAMPLE_LEN: usize = 64 * 1024;
+
+/// How much of each regular file [`sample_digests`] hashes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SampleSpec {
+    head_len: usize,
+    tail_len: usize,
+}
+
+impl SampleSpec {
+    pub fn new() -> Self {
+        SampleSpec::default()
+    }
+
+    /// Sets how many leading bytes of each file are hashed (64 KiB by default).
+    pub fn set_head_len(&mut self, len: usize) {
+        self.head_len = len;
+    }
+
+    /// Sets how many trailing bytes of each file are hashed (64 KiB by default).
+    pub fn set_tail_len(&mut self, len: usize) {
+        self.tail_len = len;
+    }
+
+    #[inline]
+    pub fn head_len(&self) -> usize {
+        self.head_len
+    }
+
+    #[inline]
+    pub fn tail_len(&self) -> usize {
+        self.tail_len
+    }
+}
+
+impl Default for SampleSpec {
+    fn default() -> Self {
+        SampleSpec {
+            head_len: DEFAULT_SAMPLE_LEN,
+            tail_len: DEFAULT_SAMPLE_LEN,
+        }
+    }
+}
+
+/// Digests of the start and end of a regular file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FileSample {
+    pub path: String,
+    pub size: u64,
+    pub head: Sha256Hash,
+    pub tail: Sha256Hash,
+}
+
+/// Cheap fingerprints of an archive. Archives with different samples certainly differ, while
+/// equal samples only mean the archives are probably identical and a full hash is still needed
+/// to be sure.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Samples {
+    /// Digest of every path, node type, file size, executable bit and symlink target, in archive
+    /// order.
+    pub structure: Sha256Hash,
+    pub files: Vec<FileSample>,
+}
+
+impl Samples {
+    pub fn probably_identical(&self, other: &Samples) -> bool {
+        self == other
+    }
+}
+
+/// Samples the archive read from `reader`, hashing only the parts of each file chosen by `spec`.
+pub fn sample_digests<R: Read>(reader: R, spec: SampleSpec) -> io::Result<Samples> {
+    let mut archive = Archive::new(reader);
+    let mut structure = NarHasher::new();
+    let mut files = Vec::new();
+
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let path = entry.nar_path();
+        structure.write_all(path.as_bytes())?;
+        structure.write_all(b"\0")?;
+
+        match entry.kind() {
+            Kind::Dir => structure.write_all(b"directory")?,
+            Kind::File { executable, size } => {
+                write!(structure, "regular {} {}", executable, size)?;
+                let data = entry.data().unwrap_or_default();
+                let head = &data[..data.len().min(spec.head_len)];
+                let tail = &data[data.len().saturating_sub(spec.tail_len)..];
+                files.push(FileSample {
+                    path,
+                    size,
+                    head: hash::hash_flat_reader(head)?,
+                    tail: hash::hash_flat_reader(tail)?,
+                });
+            }
+            Kind::Symlink { target } => {
+                structure.write_all(b"symlink ")?;
+                structure.write_all(target.as_bytes())?;
+            }
+            Kind::Unknown { type_name } => write!(structure, "unknown {}", type_name)?,
+        }
+        structure.write_all(b"\n")?;
+    }
+
+    Ok(Samples {
+        structure: structure.finish().0,
+        files,
+    })
+}
@@ -0,0 +1,177 @@
+use std::future::Future;
+use std::io::{self, Error, ErrorKind};
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::pack::{check_symlink_target, is_special, relative_path};
+use super::PackOptions;
+use crate::{wire, Warning, NIX_VERSION_MAGIC, PAD_LEN};
+
+/// Packs `path` into `writer` without blocking the runtime, producing exactly the bytes that
+/// [`to_writer`](super::to_writer) would.
+pub async fn to_async_writer<W, P>(writer: &mut W, path: P) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin + Send,
+    P: AsRef<Path>,
+{
+    PackOptions::new()
+        .to_async_writer(writer, path)
+        .await
+        .map(|_| ())
+}
+
+impl PackOptions {
+    /// Like [`to_writer`](PackOptions::to_writer), but for an [`AsyncWrite`]. Hard-linked files
+    /// are read once per link rather than cached.
+    pub async fn to_async_writer<W, P>(&self, writer: &mut W, path: P) -> io::Result<Vec<Warning>>
+    where
+        W: AsyncWrite + Unpin + Send,
+        P: AsRef<Path>,
+    {
+        let target = path.as_ref();
+        if tokio::fs::symlink_metadata(target).await.is_err() {
+            return Err(Error::new(ErrorKind::NotFound, "Path not found"));
+        }
+
+        let mut packing = AsyncPacking {
+            writer,
+            options: self,
+            root: target.to_owned(),
+            path: target.to_owned(),
+            warnings: Vec::new(),
+        };
+        packing.write_token(NIX_VERSION_MAGIC).await?;
+        packing.encode_entry(target.to_owned()).await?;
+        packing.writer.flush().await?;
+        Ok(packing.warnings)
+    }
+}
+
+/// The state of a `to_async_writer` call, naming the path being packed when the writer stops
+/// accepting bytes.
+struct AsyncPacking<'a, W> {
+    writer: &'a mut W,
+    options: &'a PackOptions,
+    root: PathBuf,
+    path: PathBuf,
+    warnings: Vec<Warning>,
+}
+
+impl<W: AsyncWrite + Unpin + Send> AsyncPacking<'_, W> {
+    fn encode_entry(
+        &mut self,
+        path: PathBuf,
+    ) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send + '_>> {
+        Box::pin(async move {
+            let metadata = tokio::fs::symlink_metadata(&path).await?;
+            self.path = path.clone();
+
+            self.write_token(b"(").await?;
+            self.write_token(b"type").await?;
+
+            if metadata.file_type().is_dir() {
+                self.write_token(b"directory").await?;
+
+                let mut entries = Vec::new();
+                let mut read_dir = tokio::fs::read_dir(&path).await?;
+                while let Some(entry) = read_dir.next_entry().await? {
+                    entries.push(entry);
+                }
+                entries.sort_by_key(|x| x.path());
+
+                for entry in entries {
+                    if self.options.lenient() && is_special(&entry.file_type().await?) {
+                        let path = entry.path();
+                        self.warnings.push(Warning::SpecialFileSkipped { path });
+                        continue;
+                    }
+
+                    self.write_token(b"entry").await?;
+                    self.write_token(b"(").await?;
+                    self.write_token(b"name").await?;
+                    let name = entry.file_name();
+                    self.write_token(name.to_string_lossy().as_bytes()).await?;
+                    self.write_token(b"node").await?;
+                    self.encode_entry(entry.path()).await?;
+                    self.path = path.clone();
+                    self.write_token(b")").await?;
+                }
+            } else if metadata.file_type().is_file() {
+                self.write_token(b"regular").await?;
+
+                let relative = relative_path(&self.root, &path);
+                if self
+                    .options
+                    .executable_detection()
+                    .is_executable(&relative, metadata.mode())
+                {
+                    self.write_token(b"executable").await?;
+                    self.write_token(b"").await?;
+                }
+
+                self.write_token(b"contents").await?;
+                let file = tokio::fs::File::open(&path).await?;
+                self.write_token_from_file(file, metadata.len()).await?;
+            } else if metadata.file_type().is_symlink() {
+                self.write_token(b"symlink").await?;
+                self.write_token(b"target").await?;
+                let target = tokio::fs::read_link(&path)
+                    .await?
+                    .into_os_string()
+                    .into_vec();
+                let target = check_symlink_target(&path, target, self.options)?;
+                self.write_token(&target).await?;
+            } else {
+                return Err(Error::new(ErrorKind::InvalidData, "Unrecognized file type"));
+            }
+
+            self.write_token(b")").await
+        })
+    }
+
+    async fn write_token(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let len = bytes.len() as u64;
+        self.write_all(&len.to_le_bytes()).await?;
+        self.write_all(bytes).await?;
+        self.write_all(&[0; PAD_LEN][..wire::pad_len(len)]).await
+    }
+
+    async fn write_token_from_file(&mut self, file: tokio::fs::File, len: u64) -> io::Result<()> {
+        self.write_all(&len.to_le_bytes()).await?;
+
+        let mut reader = file.take(len);
+        let mut buffer = vec![0; self.options.copy_buffer_len()];
+        let mut copied = 0;
+        loop {
+            let read = reader.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            self.write_all(&buffer[..read]).await?;
+            copied += read as u64;
+        }
+        if copied != len {
+            let message = format!("Source ended after {} of {} bytes", copied, len);
+            return Err(Error::new(ErrorKind::UnexpectedEof, message));
+        }
+
+        self.write_all(&[0; PAD_LEN][..wire::pad_len(len)]).await
+    }
+
+    async fn write_all(&mut self, bytes: &[u8]) -> io::Result<()> {
+        match self.writer.write_all(bytes).await {
+            Err(ref e) if e.kind() == ErrorKind::WriteZero => {
+                let message = format!(
+                    "Writer accepted no more bytes while packing {}",
+                    self.path.display()
+                );
+                Err(Error::new(ErrorKind::WriteZero, message))
+            }
+            result => result,
+        }
+    }
+}
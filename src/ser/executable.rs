@@ -0,0 +1,94 @@
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
+
+use crate::glob::glob_match;
+
+type Callback = Arc<dyn Fn(&str, u32) -> bool + Send + Sync>;
+
+/// Decides which regular files are packed as executable. By default a file is executable if any
+/// of its execute bits is set, as Nix does.
+///
+/// Globs are matched against paths relative to the root being packed, with components joined by
+/// `/`; a `*` also matches across `/`. Globs forcing files to be plain take precedence over those
+/// forcing them to be executable, and both over the callback and the mode bits.
+#[derive(Clone, Default)]
+pub struct ExecutableDetection {
+    owner_only: bool,
+    executable_globs: Vec<String>,
+    plain_globs: Vec<String>,
+    callback: Option<Callback>,
+}
+
+impl ExecutableDetection {
+    pub fn new() -> Self {
+        ExecutableDetection::default()
+    }
+
+    /// Only considers the owner's execute bit, ignoring noisy group and other bits.
+    pub fn set_owner_only(&mut self, owner_only: bool) {
+        self.owner_only = owner_only;
+    }
+
+    pub fn add_executable_glob<S: Into<String>>(&mut self, glob: S) {
+        self.executable_globs.push(glob.into());
+    }
+
+    pub fn add_plain_glob<S: Into<String>>(&mut self, glob: S) {
+        self.plain_globs.push(glob.into());
+    }
+
+    /// Decides files that match no glob with `callback`, given their relative path and mode,
+    /// instead of their execute bits.
+    pub fn set_callback<F>(&mut self, callback: F)
+    where
+        F: Fn(&str, u32) -> bool + Send + Sync + 'static,
+    {
+        self.callback = Some(Arc::new(callback));
+    }
+
+    #[inline]
+    pub fn owner_only(&self) -> bool {
+        self.owner_only
+    }
+
+    #[inline]
+    pub fn executable_globs(&self) -> &[String] {
+        &self.executable_globs
+    }
+
+    #[inline]
+    pub fn plain_globs(&self) -> &[String] {
+        &self.plain_globs
+    }
+
+    pub fn is_executable(&self, path: &str, mode: u32) -> bool {
+        let matches = |globs: &[String]| {
+            globs
+                .iter()
+                .any(|glob| glob_match(glob.as_bytes(), path.as_bytes()))
+        };
+
+        if matches(&self.plain_globs) {
+            false
+        } else if matches(&self.executable_globs) {
+            true
+        } else if let Some(callback) = &self.callback {
+            callback(path, mode)
+        } else if self.owner_only {
+            mode & 0o100 != 0
+        } else {
+            mode & 0o111 != 0
+        }
+    }
+}
+
+impl Debug for ExecutableDetection {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        fmt.debug_struct(stringify!(ExecutableDetection))
+            .field("owner_only", &self.owner_only)
+            .field("executable_globs", &self.executable_globs)
+            .field("plain_globs", &self.plain_globs)
+            .field("callback", &self.callback.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
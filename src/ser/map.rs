@@ -0,0 +1,72 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, Error, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+
+use crate::de::validate_entry_name;
+use crate::tree::{NarTree, Node};
+
+/// Packs files held in memory into a canonical archive whose root is a directory, creating any
+/// parent directories implied by the paths.
+///
+/// Paths must be relative and made of plain UTF-8 names. Every path in `executable` must also be
+/// a key of `map`.
+pub fn from_map(
+    map: &HashMap<PathBuf, Vec<u8>>,
+    executable: &HashSet<PathBuf>,
+) -> io::Result<Vec<u8>> {
+    if let Some(path) = executable.iter().find(|path| !map.contains_key(*path)) {
+        return Err(invalid(path, "marked executable but has no contents"));
+    }
+
+    let mut root = BTreeMap::new();
+    for (path, contents) in map {
+        let names = names(path)?;
+        let (name, parents) = names
+            .split_last()
+            .ok_or_else(|| invalid(path, "empty path"))?;
+
+        let mut entries = &mut root;
+        for parent in parents {
+            let node = entries
+                .entry((*parent).to_owned())
+                .or_insert_with(|| Node::Directory {
+                    entries: BTreeMap::new(),
+                });
+            entries = node
+                .entries_mut()
+                .ok_or_else(|| invalid(path, "parent is a regular file"))?;
+        }
+
+        let node = Node::Regular {
+            executable: executable.contains(path),
+            contents: contents.clone(),
+        };
+        match entries.insert((*name).to_owned(), node) {
+            Some(Node::Directory { .. }) => return Err(invalid(path, "also used as a directory")),
+            Some(_) => return Err(invalid(path, "duplicate path")),
+            None => {}
+        }
+    }
+
+    Ok(NarTree::new(Node::Directory { entries: root }).to_vec())
+}
+
+fn names(path: &Path) -> io::Result<Vec<&str>> {
+    path.components()
+        .map(|component| match component {
+            Component::Normal(name) => {
+                let name = name
+                    .to_str()
+                    .ok_or_else(|| invalid(path, "name is not valid UTF-8"))?;
+                validate_entry_name(name).map_err(|problem| invalid(path, &problem))?;
+                Ok(name)
+            }
+            _ => Err(invalid(path, "path must be relative and normalized")),
+        })
+        .collect()
+}
+
+fn invalid(path: &Path, problem: &str) -> Error {
+    let message = format!("Invalid path {:?}: {}", path, problem);
+    Error::new(ErrorKind::InvalidInput, message)
+}
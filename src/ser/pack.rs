@@ -0,0 +1,438 @@
+use std::collections::HashMap;
+use std::fs::{self, File, FileType, Metadata};
+use std::io::{self, Error, ErrorKind, Write};
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use super::ExecutableDetection;
+use crate::tree::target_problem;
+use crate::{wire, Warning, NIX_VERSION_MAGIC};
+
+const HARD_LINK_CACHE_LEN: u64 = 64 * 1024 * 1024;
+
+pub fn to_vec<P: AsRef<Path>>(path: P) -> io::Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    to_writer(&mut buffer, path)?;
+    Ok(buffer)
+}
+
+pub fn to_writer<W, P>(writer: &mut W, path: P) -> io::Result<()>
+where
+    W: Write,
+    P: AsRef<Path>,
+{
+    PackOptions::new().to_writer(writer, path).map(drop)
+}
+
+/// Like `to_writer`, but leaves sockets, FIFOs and device nodes below the root out of the archive
+/// instead of failing, returning a warning for each one skipped.
+pub fn to_writer_lenient<W, P>(writer: &mut W, path: P) -> io::Result<Vec<Warning>>
+where
+    W: Write,
+    P: AsRef<Path>,
+{
+    let mut options = PackOptions::new();
+    options.set_lenient(true);
+    options.to_writer(writer, path)
+}
+
+/// Packing configuration that can be built once and applied to any number of paths, including
+/// concurrently from several threads.
+#[derive(Clone, Debug)]
+pub struct PackOptions {
+    lenient: bool,
+    hard_link_cache_len: u64,
+    copy_buffer_len: usize,
+    executable_detection: ExecutableDetection,
+    max_symlink_target_len: Option<usize>,
+    strict_utf8_symlinks: bool,
+}
+
+impl PackOptions {
+    pub fn new() -> Self {
+        PackOptions::default()
+    }
+
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
+    }
+
+    #[inline]
+    pub fn lenient(&self) -> bool {
+        self.lenient
+    }
+
+    /// Caps how many bytes of hard-linked file contents are kept in memory (64 MiB by default).
+    pub fn set_hard_link_cache_len(&mut self, len: u64) {
+        self.hard_link_cache_len = len;
+    }
+
+    /// Sets the size of the buffer file contents are streamed through (64 KiB by default).
+    pub fn set_copy_buffer_len(&mut self, len: usize) {
+        self.copy_buffer_len = len.max(1);
+    }
+
+    #[inline]
+    pub fn copy_buffer_len(&self) -> usize {
+        self.copy_buffer_len
+    }
+
+    pub fn set_executable_detection(&mut self, detection: ExecutableDetection) {
+        self.executable_detection = detection;
+    }
+
+    /// Rejects symlinks whose targets are longer than `len` bytes, e.g. 4095 to match Linux.
+    pub fn set_max_symlink_target_len(&mut self, len: Option<usize>) {
+        self.max_symlink_target_len = len;
+    }
+
+    #[inline]
+    pub fn max_symlink_target_len(&self) -> Option<usize> {
+        self.max_symlink_target_len
+    }
+
+    /// Rejects symlink targets that are not valid UTF-8, rather than packing their raw bytes.
+    pub fn set_strict_utf8_symlinks(&mut self, strict: bool) {
+        self.strict_utf8_symlinks = strict;
+    }
+
+    #[inline]
+    pub fn strict_utf8_symlinks(&self) -> bool {
+        self.strict_utf8_symlinks
+    }
+
+    #[inline]
+    pub fn executable_detection(&self) -> &ExecutableDetection {
+        &self.executable_detection
+    }
+
+    pub fn to_vec<P: AsRef<Path>>(&self, path: P) -> io::Result<(Vec<u8>, Vec<Warning>)> {
+        let mut buffer = Vec::new();
+        let warnings = self.to_writer(&mut buffer, path)?;
+        Ok((buffer, warnings))
+    }
+
+    pub fn to_writer<W, P>(&self, writer: &mut W, path: P) -> io::Result<Vec<Warning>>
+    where
+        W: Write,
+        P: AsRef<Path>,
+    {
+        let target = path.as_ref();
+        if fs::symlink_metadata(target).is_err() {
+            return Err(Error::new(ErrorKind::NotFound, "Path not found"));
+        }
+
+        let mut writer = Packing {
+            inner: writer,
+            root: target.to_owned(),
+            path: target.to_owned(),
+        };
+        write_padded(&mut writer, NIX_VERSION_MAGIC)?;
+        let mut links = HardLinks::new(self.hard_link_cache_len);
+        let mut warnings = Vec::new();
+        let skipped = if self.lenient {
+            Some(&mut warnings)
+        } else {
+            None
+        };
+        encode_entry(&mut writer, target, self, &mut links, skipped)?;
+        writer.flush()?;
+        Ok(warnings)
+    }
+
+    /// Like `to_writer`, but also waits for the archive to reach the disk before returning.
+    pub fn to_file<P: AsRef<Path>>(&self, file: &mut File, path: P) -> io::Result<Vec<Warning>> {
+        let warnings = self.to_writer(file, path)?;
+        file.sync_all()?;
+        Ok(warnings)
+    }
+}
+
+impl Default for PackOptions {
+    fn default() -> Self {
+        PackOptions {
+            lenient: false,
+            hard_link_cache_len: HARD_LINK_CACHE_LEN,
+            copy_buffer_len: wire::DEFAULT_COPY_BUFFER_LEN,
+            executable_detection: ExecutableDetection::default(),
+            max_symlink_target_len: None,
+            strict_utf8_symlinks: false,
+        }
+    }
+}
+
+pub fn archive_len<P: AsRef<Path>>(path: P) -> io::Result<u64> {
+    PackOptions::new().archive_len(path)
+}
+
+impl PackOptions {
+    /// Computes the exact length of the archive `to_writer` would produce, without reading any
+    /// file contents.
+    pub fn archive_len<P: AsRef<Path>>(&self, path: P) -> io::Result<u64> {
+        let target = path.as_ref();
+        if fs::symlink_metadata(target).is_err() {
+            return Err(Error::new(ErrorKind::NotFound, "Path not found"));
+        }
+
+        Ok(wire::header_len() + node_len(target, target, self)?)
+    }
+}
+
+fn node_len(root: &Path, path: &Path, options: &PackOptions) -> io::Result<u64> {
+    let metadata = fs::symlink_metadata(path)?;
+
+    if metadata.file_type().is_dir() {
+        let mut entries_len = 0;
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            if options.lenient && is_special(&entry.file_type()?) {
+                continue;
+            }
+
+            let name_len = entry.file_name().to_string_lossy().len() as u64;
+            let len = node_len(root, &entry.path(), options)?;
+            entries_len += wire::directory_entry_len(name_len, len);
+        }
+        Ok(wire::directory_node_len(entries_len))
+    } else if metadata.file_type().is_file() {
+        let relative = relative_path(root, path);
+        let executable = options
+            .executable_detection
+            .is_executable(&relative, metadata.mode());
+        Ok(wire::regular_node_len(metadata.len(), executable))
+    } else if metadata.file_type().is_symlink() {
+        let target = symlink_target(path, options)?;
+        Ok(wire::symlink_node_len(target.len() as u64))
+    } else {
+        Err(Error::new(ErrorKind::InvalidData, "Unrecognized file type"))
+    }
+}
+
+fn encode_entry<W: Write>(
+    writer: &mut Packing<W>,
+    path: &Path,
+    options: &PackOptions,
+    links: &mut HardLinks,
+    mut warnings: Option<&mut Vec<Warning>>,
+) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    writer.path = path.to_owned();
+
+    write_padded(writer, b"(")?;
+    write_padded(writer, b"type")?;
+
+    if metadata.file_type().is_dir() {
+        write_padded(writer, b"directory")?;
+
+        let mut entries: Vec<_> = fs::read_dir(path)?.collect::<Result<_, _>>()?;
+        entries.sort_by_key(|x| x.path());
+
+        for entry in entries {
+            if let Some(warnings) = warnings.as_deref_mut() {
+                if is_special(&entry.file_type()?) {
+                    let path = entry.path();
+                    warnings.push(Warning::SpecialFileSkipped { path });
+                    continue;
+                }
+            }
+
+            write_padded(writer, b"entry")?;
+            write_padded(writer, b"(")?;
+            write_padded(writer, b"name")?;
+            write_padded(writer, entry.file_name().to_string_lossy().as_bytes())?;
+            write_padded(writer, b"node")?;
+            let child = entry.path();
+            encode_entry(writer, &child, options, links, warnings.as_deref_mut())?;
+            writer.path = path.to_owned();
+            write_padded(writer, b")")?;
+        }
+    } else if metadata.file_type().is_file() {
+        write_padded(writer, b"regular")?;
+
+        let relative = relative_path(&writer.root, path);
+        if options
+            .executable_detection
+            .is_executable(&relative, metadata.mode())
+        {
+            write_padded(writer, b"executable")?;
+            write_padded(writer, b"")?;
+        }
+
+        write_padded(writer, b"contents")?;
+        match links.contents(path, &metadata)? {
+            Some(contents) => write_padded(writer, &contents)?,
+            None => {
+                let mut file = File::open(path)?;
+                let (len, buffer_len) = (metadata.len(), options.copy_buffer_len);
+                wire::write_token_from_reader(writer, &mut file, len, buffer_len)?;
+            }
+        }
+    } else if metadata.file_type().is_symlink() {
+        write_padded(writer, b"symlink")?;
+        write_padded(writer, b"target")?;
+        let target = symlink_target(path, options)?;
+        write_padded(writer, &target)?;
+    } else {
+        return Err(Error::new(ErrorKind::InvalidData, "Unrecognized file type"));
+    }
+
+    write_padded(writer, b")")?;
+
+    Ok(())
+}
+
+/// Reads the target of the symlink at `path`, rejecting targets that Nix or strict parsers would
+/// refuse to unpack.
+fn symlink_target(path: &Path, options: &PackOptions) -> io::Result<Vec<u8>> {
+    let target = fs::read_link(path)?.into_os_string().into_vec();
+    check_symlink_target(path, target, options)
+}
+
+pub(super) fn check_symlink_target(
+    path: &Path,
+    target: Vec<u8>,
+    options: &PackOptions,
+) -> io::Result<Vec<u8>> {
+    let invalid = |problem: &dyn std::fmt::Display| {
+        let message = format!("Cannot pack symlink {}: {}", path.display(), problem);
+        Error::new(ErrorKind::InvalidData, message)
+    };
+
+    if options.strict_utf8_symlinks && std::str::from_utf8(&target).is_err() {
+        return Err(invalid(&"symlink target is not valid UTF-8"));
+    }
+    if let Some(problem) = target_problem(&target) {
+        return Err(invalid(&problem));
+    }
+    let max_len = options.max_symlink_target_len;
+    if let Some(max) = max_len.filter(|&max| target.len() > max) {
+        return Err(invalid(&format_args!(
+            "symlink target exceeds {} bytes",
+            max
+        )));
+    }
+    Ok(target)
+}
+
+/// The path of `path` below `root` with components joined by `/`, empty for the root itself.
+pub(super) fn relative_path(root: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    let names: Vec<_> = relative.iter().map(|name| name.to_string_lossy()).collect();
+    names.join("/")
+}
+
+/// Wraps the output of `to_writer` to name the path being packed when the writer stops
+/// accepting bytes, rather than leaving a truncated archive that looks complete.
+struct Packing<W> {
+    inner: W,
+    root: PathBuf,
+    path: PathBuf,
+}
+
+impl<W> Packing<W> {
+    fn short_write(&self) -> Error {
+        let message = format!(
+            "Writer accepted no more bytes while packing {}",
+            self.path.display()
+        );
+        Error::new(ErrorKind::WriteZero, message)
+    }
+}
+
+impl<W: Write> Write for Packing<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.inner.write(buf) {
+            Ok(0) if !buf.is_empty() => Err(self.short_write()),
+            Err(ref e) if e.kind() == ErrorKind::WriteZero => Err(self.short_write()),
+            result => result,
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub(super) fn is_special(file_type: &FileType) -> bool {
+    !file_type.is_dir() && !file_type.is_file() && !file_type.is_symlink()
+}
+
+/// Caches the contents of hard-linked files so that each inode is read from disk only once, up
+/// to a total of `budget` bytes.
+#[derive(Debug)]
+struct HardLinks {
+    cache: HashMap<(u64, u64), Rc<[u8]>>,
+    cached_len: u64,
+    budget: u64,
+}
+
+impl HardLinks {
+    fn new(budget: u64) -> Self {
+        HardLinks {
+            cache: HashMap::new(),
+            cached_len: 0,
+            budget,
+        }
+    }
+
+    fn contents(&mut self, path: &Path, metadata: &Metadata) -> io::Result<Option<Rc<[u8]>>> {
+        if metadata.nlink() < 2 {
+            return Ok(None);
+        }
+
+        let key = (metadata.dev(), metadata.ino());
+        if let Some(contents) = self.cache.get(&key) {
+            return Ok(Some(contents.clone()));
+        }
+
+        if self.cached_len + metadata.len() > self.budget {
+            return Ok(None);
+        }
+
+        let contents: Rc<[u8]> = fs::read(path)?.into();
+        self.cached_len += contents.len() as u64;
+        self.cache.insert(key, contents.clone());
+        Ok(Some(contents))
+    }
+}
+
+fn write_padded<W: Write>(writer: &mut W, bytes: &[u8]) -> io::Result<()> {
+    wire::write_token(writer, bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_hard_linked_files_once_within_budget() {
+        let dir = tempfile::tempdir().unwrap();
+        let (first, second) = (dir.path().join("first"), dir.path().join("second"));
+        fs::write(&first, "shared").unwrap();
+        fs::hard_link(&first, &second).unwrap();
+
+        let mut links = HardLinks::new(1024);
+        let a = links
+            .contents(&first, &fs::metadata(&first).unwrap())
+            .unwrap();
+        let b = links
+            .contents(&second, &fs::metadata(&second).unwrap())
+            .unwrap();
+        assert!(Rc::ptr_eq(&a.unwrap(), &b.unwrap()));
+
+        let mut tiny = HardLinks::new(3);
+        assert!(tiny
+            .contents(&first, &fs::metadata(&first).unwrap())
+            .unwrap()
+            .is_none());
+
+        let single = dir.path().join("single");
+        fs::write(&single, "alone").unwrap();
+        assert!(links
+            .contents(&single, &fs::metadata(&single).unwrap())
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,586 @@
+//! Experimental mapping between Serde data structures and NAR archives.
+//!
+//! Structs and maps are stored as directories, with each field or key becoming a directory entry.
+//! Byte buffers and strings are stored as regular files, as are numbers, booleans, characters and
+//! unit enum variants, which are written out in their textual form. `None` values are omitted.
+//! Sequences, tuples, unit values, non-unit enum variants and symlinks have no faithful NAR
+//! representation and are rejected with an error.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+use ::serde::de::value::StringDeserializer;
+use ::serde::de::{self, DeserializeOwned, IntoDeserializer, MapAccess, Visitor};
+use ::serde::ser::{self, Serialize};
+
+use crate::de::{validate_entry_name, Archive, EntryKind};
+use crate::{wire, NIX_VERSION_MAGIC};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Unsupported(&'static str),
+    Message(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(fmt, "{}", err),
+            Error::Unsupported(kind) => write!(fmt, "{} cannot be represented in a NAR", kind),
+            Error::Message(msg) => fmt.write_str(msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error::Message(msg.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Error::Message(msg.to_string())
+    }
+}
+
+pub fn to_vec<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    to_writer(&mut buffer, value)?;
+    Ok(buffer)
+}
+
+pub fn to_writer<W, T>(writer: &mut W, value: &T) -> Result<()>
+where
+    W: Write,
+    T: Serialize + ?Sized,
+{
+    let node = value
+        .serialize(NodeSerializer)?
+        .ok_or(Error::Unsupported("a top-level `None`"))?;
+
+    wire::write_token(writer, NIX_VERSION_MAGIC)?;
+    encode_node(writer, &node)?;
+    Ok(())
+}
+
+pub fn from_slice<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    from_reader(bytes)
+}
+
+pub fn from_reader<R: Read, T: DeserializeOwned>(reader: R) -> Result<T> {
+    let mut archive = Archive::new(reader);
+    let mut root = None;
+
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let path = entry.name().to_owned();
+        let node = match entry.kind {
+            EntryKind::Directory => Node::Directory(BTreeMap::new()),
+            EntryKind::Regular { data, .. } => Node::Regular(data),
+            EntryKind::Symlink { .. } => return Err(Error::Unsupported("a symlink")),
+            EntryKind::Unknown { .. } => return Err(Error::Unsupported("an unknown node type")),
+        };
+
+        let mut components = path.iter();
+        let name = match components.next_back() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => {
+                root = Some(node);
+                continue;
+            }
+        };
+
+        let mut parent = root.as_mut();
+        for component in components {
+            parent = match parent {
+                Some(Node::Directory(entries)) => entries.get_mut(&*component.to_string_lossy()),
+                _ => None,
+            };
+        }
+
+        match parent {
+            Some(Node::Directory(entries)) => {
+                entries.insert(name, node);
+            }
+            _ => return Err(Error::Message(format!("Orphaned entry {:?}", path))),
+        }
+    }
+
+    let root = root.ok_or_else(|| Error::Message("Archive is empty".into()))?;
+    T::deserialize(NodeDeserializer(root))
+}
+
+#[derive(Debug)]
+enum Node {
+    Directory(BTreeMap<String, Node>),
+    Regular(Vec<u8>),
+}
+
+fn encode_node<W: Write>(writer: &mut W, node: &Node) -> io::Result<()> {
+    wire::write_token(writer, b"(")?;
+    wire::write_token(writer, b"type")?;
+
+    match node {
+        Node::Directory(entries) => {
+            wire::write_token(writer, b"directory")?;
+            for (name, child) in entries {
+                wire::write_token(writer, b"entry")?;
+                wire::write_token(writer, b"(")?;
+                wire::write_token(writer, b"name")?;
+                wire::write_token(writer, name.as_bytes())?;
+                wire::write_token(writer, b"node")?;
+                encode_node(writer, child)?;
+                wire::write_token(writer, b")")?;
+            }
+        }
+        Node::Regular(data) => {
+            wire::write_token(writer, b"regular")?;
+            wire::write_token(writer, b"contents")?;
+            wire::write_token(writer, data)?;
+        }
+    }
+
+    wire::write_token(writer, b")")
+}
+
+fn validate_name(name: &str) -> Result<()> {
+    validate_entry_name(name).map_err(|message| Error::Message(message.into_owned()))
+}
+
+struct NodeSerializer;
+
+impl NodeSerializer {
+    fn text<T: ToString>(value: T) -> Result<Option<Node>> {
+        Ok(Some(Node::Regular(value.to_string().into_bytes())))
+    }
+}
+
+impl ser::Serializer for NodeSerializer {
+    type Ok = Option<Node>;
+    type Error = Error;
+
+    type SerializeSeq = ser::Impossible<Self::Ok, Error>;
+    type SerializeTuple = ser::Impossible<Self::Ok, Error>;
+    type SerializeTupleStruct = ser::Impossible<Self::Ok, Error>;
+    type SerializeTupleVariant = ser::Impossible<Self::Ok, Error>;
+    type SerializeMap = DirectorySerializer;
+    type SerializeStruct = DirectorySerializer;
+    type SerializeStructVariant = ser::Impossible<Self::Ok, Error>;
+
+    fn serialize_bool(self, v: bool) -> Result<Self::Ok> {
+        Self::text(v)
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Self::Ok> {
+        Self::text(v)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Self::Ok> {
+        Self::text(v)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Self::Ok> {
+        Self::text(v)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Self::Ok> {
+        Self::text(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Self::Ok> {
+        Self::text(v)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Self::Ok> {
+        Self::text(v)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Self::Ok> {
+        Self::text(v)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Self::Ok> {
+        Self::text(v)
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Self::Ok> {
+        Self::text(v)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Self::Ok> {
+        Self::text(v)
+    }
+
+    fn serialize_char(self, v: char) -> Result<Self::Ok> {
+        Self::text(v)
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Self::Ok> {
+        Ok(Some(Node::Regular(v.as_bytes().to_vec())))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok> {
+        Ok(Some(Node::Regular(v.to_vec())))
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok> {
+        Ok(None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Self::Ok> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok> {
+        Err(Error::Unsupported("a unit value"))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok> {
+        Err(Error::Unsupported("a unit struct"))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Self::Ok> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Self::Ok> {
+        Err(Error::Unsupported("a newtype variant"))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        Err(Error::Unsupported("a sequence"))
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        Err(Error::Unsupported("a tuple"))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        Err(Error::Unsupported("a tuple struct"))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        Err(Error::Unsupported("a tuple variant"))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        Ok(DirectorySerializer::default())
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        Ok(DirectorySerializer::default())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        Err(Error::Unsupported("a struct variant"))
+    }
+}
+
+#[derive(Default)]
+struct DirectorySerializer {
+    entries: BTreeMap<String, Node>,
+    next_key: Option<String>,
+}
+
+impl DirectorySerializer {
+    fn insert<T: Serialize + ?Sized>(&mut self, name: String, value: &T) -> Result<()> {
+        validate_name(&name)?;
+        if let Some(node) = value.serialize(NodeSerializer)? {
+            if self.entries.insert(name.clone(), node).is_some() {
+                return Err(Error::Message(format!("Duplicate entry name {:?}", name)));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for DirectorySerializer {
+    type Ok = Option<Node>;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        let name = match key.serialize(NodeSerializer)? {
+            Some(Node::Regular(bytes)) => {
+                String::from_utf8(bytes).map_err(|_| Error::Unsupported("a non-UTF-8 map key"))?
+            }
+            _ => return Err(Error::Unsupported("a map key that is not a string")),
+        };
+        self.next_key = Some(name);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let name = self
+            .next_key
+            .take()
+            .ok_or_else(|| Error::Message("Map value serialized before its key".into()))?;
+        self.insert(name, value)
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        Ok(Some(Node::Directory(self.entries)))
+    }
+}
+
+impl ser::SerializeStruct for DirectorySerializer {
+    type Ok = Option<Node>;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.insert(key.to_owned(), value)
+    }
+
+    fn end(self) -> Result<Self::Ok> {
+        Ok(Some(Node::Directory(self.entries)))
+    }
+}
+
+struct NodeDeserializer(Node);
+
+impl NodeDeserializer {
+    fn into_bytes(self) -> Result<Vec<u8>> {
+        match self.0 {
+            Node::Regular(data) => Ok(data),
+            Node::Directory(_) => Err(Error::Message("Expected a file, found a directory".into())),
+        }
+    }
+
+    fn into_string(self) -> Result<String> {
+        String::from_utf8(self.into_bytes()?)
+            .map_err(|e| Error::Message(format!("File contents are not UTF-8: {}", e)))
+    }
+
+    fn parse<T>(self) -> Result<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let text = self.into_string()?;
+        text.parse()
+            .map_err(|e| Error::Message(format!("Failed to parse {:?}: {}", text, e)))
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+                visitor.$visit(self.parse()?)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for NodeDeserializer {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.0 {
+            Node::Directory(entries) => visitor.visit_map(DirectoryAccess {
+                entries: entries.into_iter(),
+                value: None,
+            }),
+            Node::Regular(data) => match String::from_utf8(data) {
+                Ok(text) => visitor.visit_string(text),
+                Err(err) => visitor.visit_byte_buf(err.into_bytes()),
+            },
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_string(self.into_string()?)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_string(self.into_string()?)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_byte_buf(self.into_bytes()?)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_byte_buf(self.into_bytes()?)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(Error::Unsupported("a unit value"))
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _visitor: V,
+    ) -> Result<V::Value> {
+        Err(Error::Unsupported("a unit struct"))
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(Error::Unsupported("a sequence"))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, _visitor: V) -> Result<V::Value> {
+        Err(Error::Unsupported("a tuple"))
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        _visitor: V,
+    ) -> Result<V::Value> {
+        Err(Error::Unsupported("a tuple struct"))
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.0 {
+            Node::Directory(entries) => visitor.visit_map(DirectoryAccess {
+                entries: entries.into_iter(),
+                value: None,
+            }),
+            Node::Regular(_) => Err(Error::Message("Expected a directory, found a file".into())),
+        }
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        let variant: StringDeserializer<Error> = self.into_string()?.into_deserializer();
+        visitor.visit_enum(variant)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+}
+
+struct DirectoryAccess {
+    entries: std::collections::btree_map::IntoIter<String, Node>,
+    value: Option<Node>,
+}
+
+impl<'de> MapAccess<'de> for DirectoryAccess {
+    type Error = Error;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        match self.entries.next() {
+            Some((name, node)) => {
+                self.value = Some(node);
+                let name: StringDeserializer<Error> = name.into_deserializer();
+                seed.deserialize(name).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        let node = self
+            .value
+            .take()
+            .ok_or_else(|| Error::Message("Map value requested before its key".into()))?;
+        seed.deserialize(NodeDeserializer(node))
+    }
+}
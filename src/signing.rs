@@ -0,0 +1,344 @@
+//! Detached Ed25519 signatures over the fingerprint of a store path, as used by Nix binary caches.
+
+use std::convert::TryInto;
+use std::fmt::{self, Debug, Display, Formatter};
+#[cfg(feature = "fs")]
+use std::fs::{self, OpenOptions};
+#[cfg(feature = "fs")]
+use std::io::Write;
+use std::io::{self, Error, ErrorKind};
+#[cfg(feature = "fs")]
+use std::os::unix::fs::OpenOptionsExt;
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::str::FromStr;
+
+use ed25519_dalek::{Signer, SigningKey, Verifier, VerifyingKey};
+use rand_core::OsRng;
+
+pub use self::policy::TrustPolicy;
+
+use crate::encoding;
+use crate::hash::Sha256Hash;
+use crate::store_path::StoreDir;
+
+mod policy;
+
+pub const SIGNATURE_LEN: usize = 64;
+
+/// Computes the string Nix signs for a store path:
+/// `1;<store path>;<nar hash>;<nar size>;<comma-separated references>`.
+pub fn fingerprint<I, S>(
+    store_dir: &StoreDir,
+    store_path: &str,
+    nar_hash: &Sha256Hash,
+    nar_size: u64,
+    references: I,
+) -> io::Result<String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    store_dir.parse_path(store_path)?;
+
+    let mut references = references
+        .into_iter()
+        .map(|r| {
+            store_dir
+                .parse_path(r.as_ref())
+                .map(|_| r.as_ref().to_owned())
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    references.sort();
+    references.dedup();
+
+    Ok(format!(
+        "1;{};{};{};{}",
+        store_path,
+        nar_hash,
+        nar_size,
+        references.join(",")
+    ))
+}
+
+/// The metadata of a store path that its signatures cover.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PathInfo {
+    store_path: String,
+    nar_hash: Sha256Hash,
+    nar_size: u64,
+    references: Vec<String>,
+}
+
+impl PathInfo {
+    pub fn new<S, I, R>(store_path: S, nar_hash: Sha256Hash, nar_size: u64, references: I) -> Self
+    where
+        S: Into<String>,
+        I: IntoIterator<Item = R>,
+        R: Into<String>,
+    {
+        PathInfo {
+            store_path: store_path.into(),
+            nar_hash,
+            nar_size,
+            references: references.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    #[inline]
+    pub fn store_path(&self) -> &str {
+        &self.store_path
+    }
+
+    #[inline]
+    pub fn nar_hash(&self) -> &Sha256Hash {
+        &self.nar_hash
+    }
+
+    #[inline]
+    pub fn nar_size(&self) -> u64 {
+        self.nar_size
+    }
+
+    #[inline]
+    pub fn references(&self) -> &[String] {
+        &self.references
+    }
+
+    /// Computes the string Nix signs for this path, as by [`fingerprint`].
+    pub fn fingerprint(&self, store_dir: &StoreDir) -> io::Result<String> {
+        fingerprint(
+            store_dir,
+            &self.store_path,
+            &self.nar_hash,
+            self.nar_size,
+            &self.references,
+        )
+    }
+}
+
+/// Signs `fingerprint` through a caller-supplied function, e.g. one backed by a KMS or HSM.
+pub fn sign_with<F>(key_name: &str, fingerprint: &str, sign: F) -> io::Result<Signature>
+where
+    F: FnOnce(&[u8]) -> io::Result<[u8; SIGNATURE_LEN]>,
+{
+    let bytes = sign(fingerprint.as_bytes())?;
+    Ok(Signature::new(key_name, bytes))
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Signature {
+    key_name: String,
+    bytes: [u8; SIGNATURE_LEN],
+}
+
+impl Signature {
+    pub fn new<S: Into<String>>(key_name: S, bytes: [u8; SIGNATURE_LEN]) -> Self {
+        Signature {
+            key_name: key_name.into(),
+            bytes,
+        }
+    }
+
+    #[inline]
+    pub fn key_name(&self) -> &str {
+        &self.key_name
+    }
+
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8; SIGNATURE_LEN] {
+        &self.bytes
+    }
+}
+
+impl Display for Signature {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.key_name, encoding::to_base64(&self.bytes))
+    }
+}
+
+impl FromStr for Signature {
+    type Err = Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        let (key_name, bytes) = split_named(s, "signature")?;
+        let bytes = bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| invalid("signature", s))?;
+        Ok(Signature::new(key_name, bytes))
+    }
+}
+
+#[derive(Clone)]
+pub struct Keypair {
+    name: String,
+    key: SigningKey,
+}
+
+impl Keypair {
+    pub fn generate<S: Into<String>>(name: S) -> io::Result<Self> {
+        let name = name.into();
+        check_key_name(&name)?;
+        Ok(Keypair {
+            name,
+            key: SigningKey::generate(&mut OsRng),
+        })
+    }
+
+    pub fn from_seed<S: Into<String>>(name: S, seed: &[u8; 32]) -> io::Result<Self> {
+        let name = name.into();
+        check_key_name(&name)?;
+        Ok(Keypair {
+            name,
+            key: SigningKey::from_bytes(seed),
+        })
+    }
+
+    /// Parses the contents of a Nix `secret-key-file`: `<name>:<base64 of seed and public key>`.
+    pub fn from_secret_key(s: &str) -> io::Result<Self> {
+        let s = s.trim();
+        let (name, bytes) = split_named(s, "secret key")?;
+        let bytes: [u8; 64] = bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| invalid("secret key", "<redacted>"))?;
+        let key = SigningKey::from_keypair_bytes(&bytes).map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidData,
+                "Secret key does not match its public half",
+            )
+        })?;
+        Ok(Keypair {
+            name: name.to_owned(),
+            key,
+        })
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn read_secret_key_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Keypair::from_secret_key(&fs::read_to_string(path)?)
+    }
+
+    pub fn to_secret_key(&self) -> String {
+        let bytes = self.key.to_keypair_bytes();
+        format!("{}:{}", self.name, encoding::to_base64(&bytes))
+    }
+
+    /// Writes the secret key to a new file readable only by its owner.
+    #[cfg(feature = "fs")]
+    pub fn write_secret_key_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(path)?;
+        file.write_all(self.to_secret_key().as_bytes())?;
+        file.sync_all()
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey {
+            name: self.name.clone(),
+            key: self.key.verifying_key(),
+        }
+    }
+
+    pub fn sign(&self, fingerprint: &str) -> Signature {
+        let bytes = self.key.sign(fingerprint.as_bytes()).to_bytes();
+        Signature::new(self.name.clone(), bytes)
+    }
+}
+
+impl Debug for Keypair {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Keypair")
+            .field("name", &self.name)
+            .field("public_key", &self.public_key().to_string())
+            .finish()
+    }
+}
+
+fn check_key_name(name: &str) -> io::Result<()> {
+    if name.is_empty() || name.contains(':') || name.contains(char::is_whitespace) {
+        let message = format!("Invalid key name {:?}", name);
+        return Err(Error::new(ErrorKind::InvalidInput, message));
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PublicKey {
+    name: String,
+    key: VerifyingKey,
+}
+
+impl PublicKey {
+    pub fn from_bytes<S: Into<String>>(name: S, bytes: &[u8; 32]) -> io::Result<Self> {
+        let key = VerifyingKey::from_bytes(bytes)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "Invalid Ed25519 public key"))?;
+        Ok(PublicKey {
+            name: name.into(),
+            key,
+        })
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        self.key.as_bytes()
+    }
+
+    /// Returns `true` if `signature` was made by this key over `fingerprint`. Signatures by a key
+    /// with a different name never verify.
+    pub fn verify(&self, fingerprint: &str, signature: &Signature) -> bool {
+        if signature.key_name != self.name {
+            return false;
+        }
+
+        let signature = ed25519_dalek::Signature::from_bytes(&signature.bytes);
+        self.key.verify(fingerprint.as_bytes(), &signature).is_ok()
+    }
+}
+
+impl Display for PublicKey {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.name, encoding::to_base64(self.as_bytes()))
+    }
+}
+
+impl FromStr for PublicKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        let (name, bytes) = split_named(s.trim(), "public key")?;
+        let bytes = bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| invalid("public key", s))?;
+        PublicKey::from_bytes(name, bytes)
+    }
+}
+
+fn split_named<'a>(s: &'a str, what: &str) -> io::Result<(&'a str, Vec<u8>)> {
+    match s.find(':') {
+        Some(i) if i > 0 => {
+            let bytes = encoding::from_base64(&s[i + 1..]).ok_or_else(|| invalid(what, s))?;
+            Ok((&s[..i], bytes))
+        }
+        _ => Err(invalid(what, s)),
+    }
+}
+
+fn invalid(what: &str, s: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("Invalid {} {:?}", what, s))
+}
@@ -0,0 +1,118 @@
+use std::collections::HashSet;
+use std::io::{self, Error, ErrorKind};
+
+use super::{PathInfo, PublicKey, Signature};
+use crate::glob::glob_match;
+use crate::store_path::StoreDir;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TrustPolicy {
+    keys: Vec<PublicKey>,
+    required: Vec<String>,
+    store_dir: StoreDir,
+    threshold: usize,
+    unsigned_allowed: Vec<String>,
+}
+
+impl TrustPolicy {
+    /// Trusts any single valid signature made by one of `keys`.
+    pub fn new<I: IntoIterator<Item = PublicKey>>(keys: I) -> Self {
+        TrustPolicy {
+            keys: keys.into_iter().collect(),
+            required: Vec::new(),
+            store_dir: StoreDir::default(),
+            threshold: 1,
+            unsigned_allowed: Vec::new(),
+        }
+    }
+
+    /// Requires valid signatures from at least `threshold` distinct trusted keys. A threshold of 0
+    /// is raised to 1, since only [`with_unsigned_allowed`](TrustPolicy::with_unsigned_allowed)
+    /// may exempt paths from signatures.
+    pub fn with_threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold.max(1);
+        self
+    }
+
+    /// Sets the store directory that checked paths must be in, `/nix/store` by default.
+    pub fn with_store_dir(mut self, store_dir: StoreDir) -> Self {
+        self.store_dir = store_dir;
+        self
+    }
+
+    /// Requires a valid signature from the trusted key named `name`, in addition to the threshold.
+    pub fn with_required_signer<S: Into<String>>(mut self, name: S) -> Self {
+        self.required.push(name.into());
+        self
+    }
+
+    /// Accepts store paths matching `pattern` without any signature. `*` matches any run of
+    /// characters and `?` matches a single character.
+    pub fn with_unsigned_allowed<S: Into<String>>(mut self, pattern: S) -> Self {
+        self.unsigned_allowed.push(pattern.into());
+        self
+    }
+
+    #[inline]
+    pub fn keys(&self) -> &[PublicKey] {
+        &self.keys
+    }
+
+    #[inline]
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    pub fn allows_unsigned(&self, store_path: &str) -> bool {
+        self.unsigned_allowed
+            .iter()
+            .any(|pattern| glob_match(pattern.as_bytes(), store_path.as_bytes()))
+    }
+
+    /// Checks `signatures` against the fingerprint of `info`. Keys trusted under several names
+    /// count once towards the threshold. Paths outside of the store directory are rejected, even
+    /// where allowed unsigned.
+    pub fn check(&self, info: &PathInfo, signatures: &[Signature]) -> io::Result<()> {
+        let store_path = info.store_path();
+        self.store_dir.parse_path(store_path)?;
+        if self.allows_unsigned(store_path) {
+            return Ok(());
+        }
+
+        let fingerprint = info.fingerprint(&self.store_dir)?;
+        let valid: Vec<&PublicKey> = self
+            .keys
+            .iter()
+            .filter(|key| signatures.iter().any(|sig| key.verify(&fingerprint, sig)))
+            .collect();
+        let signers: HashSet<&[u8; 32]> = valid.iter().map(|key| key.as_bytes()).collect();
+
+        let signed_by = |name: &String| valid.iter().any(|key| key.name() == name);
+        if let Some(missing) = self.required.iter().find(|name| !signed_by(name)) {
+            let message = format!(
+                "Path {} is not trusted: missing signature from required key {:?}",
+                store_path, missing
+            );
+            return Err(Error::new(ErrorKind::PermissionDenied, message));
+        }
+
+        if signers.len() < self.threshold {
+            let message = format!(
+                "Path {} is not trusted: {} of {} required signatures are valid",
+                store_path,
+                signers.len(),
+                self.threshold
+            );
+            return Err(Error::new(ErrorKind::PermissionDenied, message));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for TrustPolicy {
+    /// Trusts no keys, so that only paths allowed unsigned pass.
+    fn default() -> Self {
+        TrustPolicy::new(Vec::new())
+    }
+}
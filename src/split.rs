@@ -0,0 +1,247 @@
+use std::io::{self, Error, ErrorKind, Read, Write};
+
+use crate::hash::{self, Sha256Hash};
+use crate::{wire, PAD_LEN};
+
+#[cfg(feature = "fs")]
+pub use self::checkpoint::{upload_resumable, PartSink, UploadCheckpoint, UploadSummary};
+
+#[cfg(feature = "fs")]
+mod checkpoint;
+
+/// One piece of a split archive. Parts are cut between tokens wherever possible, so a part only
+/// begins inside a token when that token alone is larger than the part size limit.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Part {
+    index: u64,
+    offset: u64,
+    hash: Sha256Hash,
+    data: Vec<u8>,
+}
+
+impl Part {
+    #[inline]
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    /// Offset of the first byte of this part within the whole archive.
+    #[inline]
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// SHA-256 of the part's bytes, recorded when the part was cut.
+    #[inline]
+    pub fn hash(&self) -> &Sha256Hash {
+        &self.hash
+    }
+
+    #[inline]
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// Reassembles a part received from elsewhere, such as an object store.
+    pub fn from_parts(index: u64, offset: u64, hash: Sha256Hash, data: Vec<u8>) -> Self {
+        Part {
+            index,
+            offset,
+            hash,
+            data,
+        }
+    }
+
+    pub fn verify(&self) -> io::Result<()> {
+        let actual = hash::hash_flat_reader(&self.data[..])?;
+        if actual != self.hash {
+            let message = format!(
+                "Part {} is corrupt: expected {}, got {}",
+                self.index, self.hash, actual
+            );
+            return Err(Error::new(ErrorKind::InvalidData, message));
+        }
+        Ok(())
+    }
+}
+
+pub fn split_nar<R: Read>(reader: R, max_part_size: u64) -> io::Result<Splitter<R>> {
+    Splitter::new(reader, max_part_size)
+}
+
+#[derive(Debug)]
+pub struct Splitter<R> {
+    reader: R,
+    max_part_size: usize,
+    index: u64,
+    offset: u64,
+    /// Length prefix of a token that did not fit into the previous part.
+    header: Option<[u8; PAD_LEN]>,
+    /// Bytes of the current token that have not been emitted yet.
+    remaining: u64,
+    done: bool,
+}
+
+impl<R: Read> Splitter<R> {
+    pub fn new(reader: R, max_part_size: u64) -> io::Result<Self> {
+        if max_part_size < PAD_LEN as u64 {
+            let message = format!("Part size must be at least {} bytes", PAD_LEN);
+            return Err(Error::new(ErrorKind::InvalidInput, message));
+        }
+
+        Ok(Splitter {
+            reader,
+            max_part_size: max_part_size.min(usize::MAX as u64) as usize,
+            index: 0,
+            offset: 0,
+            header: None,
+            remaining: 0,
+            done: false,
+        })
+    }
+
+    fn next_part(&mut self) -> io::Result<Option<Part>> {
+        let mut data = Vec::new();
+
+        while data.len() < self.max_part_size {
+            if self.remaining > 0 {
+                let space = (self.max_part_size - data.len()) as u64;
+                let take = self.remaining.min(space);
+                let read = (&mut self.reader).take(take).read_to_end(&mut data)?;
+                if read as u64 != take {
+                    return Err(Error::new(ErrorKind::UnexpectedEof, "Truncated token"));
+                }
+                self.remaining -= take;
+                continue;
+            }
+
+            let header = match self.header.take() {
+                Some(header) => header,
+                None => match wire::read_len_prefix(&mut self.reader)? {
+                    Some(len) => len.to_le_bytes(),
+                    None => {
+                        self.done = true;
+                        break;
+                    }
+                },
+            };
+
+            let len = u64::from_le_bytes(header);
+            let token_len = wire::untrusted_encoded_len(len)?;
+            let part_len = (data.len() as u64).checked_add(token_len).ok_or_else(|| {
+                let message = format!("Token length {} is out of range", len);
+                Error::new(ErrorKind::InvalidData, message)
+            })?;
+            if !data.is_empty() && part_len > self.max_part_size as u64 {
+                self.header = Some(header);
+                break;
+            }
+
+            data.extend_from_slice(&header);
+            self.remaining = token_len - PAD_LEN as u64;
+        }
+
+        if data.is_empty() {
+            return Ok(None);
+        }
+
+        let part = Part {
+            index: self.index,
+            offset: self.offset,
+            hash: hash::hash_flat_reader(&data[..])?,
+            data,
+        };
+        self.index += 1;
+        self.offset += part.data.len() as u64;
+        Ok(Some(part))
+    }
+}
+
+impl<R: Read> Iterator for Splitter<R> {
+    type Item = io::Result<Part>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done && self.header.is_none() && self.remaining == 0 {
+            return None;
+        }
+
+        match self.next_part() {
+            Ok(part) => part.map(Ok),
+            Err(err) => {
+                self.done = true;
+                self.header = None;
+                self.remaining = 0;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// Writes verified parts back into a single archive. Parts must arrive in order; after an
+/// interruption, a new joiner can pick up from `next_index` and `offset` of the previous one.
+#[derive(Debug)]
+pub struct Joiner<W> {
+    writer: W,
+    next_index: u64,
+    offset: u64,
+}
+
+impl<W: Write> Joiner<W> {
+    pub fn new(writer: W) -> Self {
+        Joiner::resume(writer, 0, 0)
+    }
+
+    pub fn resume(writer: W, next_index: u64, offset: u64) -> Self {
+        Joiner {
+            writer,
+            next_index,
+            offset,
+        }
+    }
+
+    #[inline]
+    pub fn next_index(&self) -> u64 {
+        self.next_index
+    }
+
+    #[inline]
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn push(&mut self, part: &Part) -> io::Result<()> {
+        if part.index != self.next_index || part.offset != self.offset {
+            let message = format!(
+                "Expected part {} at offset {}, got part {} at offset {}",
+                self.next_index, self.offset, part.index, part.offset
+            );
+            return Err(Error::new(ErrorKind::InvalidInput, message));
+        }
+
+        part.verify()?;
+        self.writer.write_all(&part.data)?;
+        self.next_index += 1;
+        self.offset += part.data.len() as u64;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+pub fn join_parts<I, W>(parts: I, writer: W) -> io::Result<W>
+where
+    I: IntoIterator<Item = Part>,
+    W: Write,
+{
+    let mut joiner = Joiner::new(writer);
+    for part in parts {
+        joiner.push(&part)?;
+    }
+    Ok(joiner.into_inner())
+}
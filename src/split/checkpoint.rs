@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use super::{split_nar, Part};
+use crate::hash::Sha256Hash;
+
+const HEADER: &str = "narsplit-checkpoint 1";
+
+/// Destination of a multipart upload, such as an object store client.
+pub trait PartSink {
+    fn upload_part(&mut self, part: &Part) -> io::Result<()>;
+}
+
+impl<F: FnMut(&Part) -> io::Result<()>> PartSink for F {
+    fn upload_part(&mut self, part: &Part) -> io::Result<()> {
+        self(part)
+    }
+}
+
+/// Records which parts of a multipart upload have completed, persisted after every part so an
+/// interrupted upload can be resumed from where it stopped.
+///
+/// The file is append-only: a line torn by a crash is ignored on the next load, and at worst
+/// causes that one part to be uploaded again.
+#[derive(Debug)]
+pub struct UploadCheckpoint {
+    path: PathBuf,
+    part_size: u64,
+    file: File,
+    uploaded: BTreeMap<u64, (u64, Sha256Hash)>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct UploadSummary {
+    pub uploaded: u64,
+    pub skipped: u64,
+}
+
+impl UploadCheckpoint {
+    /// Opens the checkpoint at `path`, creating it if it does not exist yet.
+    pub fn open<P: AsRef<Path>>(path: P, part_size: u64) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let mut uploaded = BTreeMap::new();
+
+        let file = match File::open(&path) {
+            Ok(file) => {
+                let mut lines = BufReader::new(file).lines();
+                let header = lines.next().transpose()?.unwrap_or_default();
+                let expected = format!("{} {}", HEADER, part_size);
+                if header != expected {
+                    let message = format!(
+                        "Checkpoint {} was not written for {} byte parts",
+                        path.display(),
+                        part_size
+                    );
+                    return Err(Error::new(ErrorKind::InvalidData, message));
+                }
+
+                for line in lines {
+                    if let Some((index, offset, hash)) = parse_line(&line?) {
+                        uploaded.insert(index, (offset, hash));
+                    }
+                }
+                OpenOptions::new().append(true).open(&path)?
+            }
+            Err(ref err) if err.kind() == ErrorKind::NotFound => {
+                let mut file = OpenOptions::new()
+                    .append(true)
+                    .create_new(true)
+                    .open(&path)?;
+                writeln!(file, "{} {}", HEADER, part_size)?;
+                file.sync_data()?;
+                file
+            }
+            Err(err) => return Err(err),
+        };
+
+        Ok(UploadCheckpoint {
+            path,
+            part_size,
+            file,
+            uploaded,
+        })
+    }
+
+    #[inline]
+    pub fn part_size(&self) -> u64 {
+        self.part_size
+    }
+
+    #[inline]
+    pub fn uploaded_parts(&self) -> usize {
+        self.uploaded.len()
+    }
+
+    /// Whether `part` was already uploaded. Fails if a part with the same index but different
+    /// contents was recorded, which means the source changed since the checkpoint was written.
+    pub fn is_uploaded(&self, part: &Part) -> io::Result<bool> {
+        match self.uploaded.get(&part.index()) {
+            Some((offset, hash)) if *offset == part.offset() && hash == part.hash() => Ok(true),
+            Some(_) => {
+                let message = format!("Checkpoint does not match part {}", part.index());
+                Err(Error::new(ErrorKind::InvalidData, message))
+            }
+            None => Ok(false),
+        }
+    }
+
+    pub fn record(&mut self, part: &Part) -> io::Result<()> {
+        let line = format!(
+            "{} {} {}\n",
+            part.index(),
+            part.offset(),
+            part.hash().to_hex()
+        );
+        self.file.write_all(line.as_bytes())?;
+        self.file.sync_data()?;
+        self.uploaded
+            .insert(part.index(), (part.offset(), *part.hash()));
+        Ok(())
+    }
+
+    /// Deletes the checkpoint once the upload has been completed.
+    pub fn remove(self) -> io::Result<()> {
+        fs::remove_file(&self.path)
+    }
+}
+
+/// Splits the archive read from `reader` and uploads every part not yet recorded in
+/// `checkpoint`, recording each part as soon as `sink` accepts it.
+pub fn upload_resumable<R, S>(
+    reader: R,
+    checkpoint: &mut UploadCheckpoint,
+    sink: &mut S,
+) -> io::Result<UploadSummary>
+where
+    R: Read,
+    S: PartSink + ?Sized,
+{
+    let mut summary = UploadSummary::default();
+    for part in split_nar(reader, checkpoint.part_size())? {
+        let part = part?;
+        if checkpoint.is_uploaded(&part)? {
+            summary.skipped += 1;
+            continue;
+        }
+
+        sink.upload_part(&part)?;
+        checkpoint.record(&part)?;
+        summary.uploaded += 1;
+    }
+    Ok(summary)
+}
+
+fn parse_line(line: &str) -> Option<(u64, u64, Sha256Hash)> {
+    let mut fields = line.split(' ');
+    let index = fields.next()?.parse().ok()?;
+    let offset = fields.next()?.parse().ok()?;
+    let hash = format!("sha256:{}", fields.next()?).parse().ok()?;
+    if fields.next().is_some() {
+        return None;
+    }
+    Some((index, offset, hash))
+}
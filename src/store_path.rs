@@ -0,0 +1,232 @@
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Error, ErrorKind};
+use std::path::Path;
+use std::str::FromStr;
+
+pub const DEFAULT_STORE_DIR: &str = "/nix/store";
+pub const HASH_PART_LEN: usize = 32;
+/// The longest name Nix accepts for a store path, leaving room for the hash part within the
+/// 255 bytes allowed for a file name.
+pub const MAX_NAME_LEN: usize = 211;
+
+const NIX_BASE32_CHARS: &str = "0123456789abcdfghijklmnpqrsvwxyz";
+
+/// Checks that `name` is acceptable as the name part of a store path: at most
+/// [`MAX_NAME_LEN`] ASCII letters, digits or any of `+-._?=`, and not `.`, `..` or starting
+/// with `.-` or `..-`, which Nix reserves.
+pub fn validate_name(name: &str) -> io::Result<()> {
+    let invalid = |problem: String| {
+        let message = format!("Invalid store path name {:?}: {}", name, problem);
+        Err(Error::new(ErrorKind::InvalidInput, message))
+    };
+
+    if name.is_empty() {
+        return invalid("name is empty".to_owned());
+    }
+    if name.len() > MAX_NAME_LEN {
+        let problem = format!("{} bytes exceeds the limit of {}", name.len(), MAX_NAME_LEN);
+        return invalid(problem);
+    }
+    if name == "." || name == ".." || name.starts_with(".-") || name.starts_with("..-") {
+        return invalid("names starting with `.` or `..` followed by `-` are reserved".to_owned());
+    }
+
+    let allowed = |c: char| c.is_ascii_alphanumeric() || "+-._?=".contains(c);
+    match name.char_indices().find(|&(_, c)| !allowed(c)) {
+        Some((i, c)) => invalid(format!("character {:?} at byte {} is not allowed", c, i)),
+        None => Ok(()),
+    }
+}
+
+/// The directory holding store paths, `/nix/store` unless the store has been relocated.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct StoreDir(String);
+
+impl StoreDir {
+    pub fn new<S: Into<String>>(dir: S) -> io::Result<Self> {
+        let mut dir = dir.into();
+        while dir.len() > 1 && dir.ends_with('/') {
+            dir.pop();
+        }
+
+        if !dir.starts_with('/') || dir == "/" || dir.contains('\0') {
+            let message = format!("Invalid store directory {:?}", dir);
+            return Err(Error::new(ErrorKind::InvalidInput, message));
+        }
+
+        Ok(StoreDir(dir))
+    }
+
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    #[inline]
+    pub fn as_path(&self) -> &Path {
+        Path::new(&self.0)
+    }
+
+    /// Returns the base name of `path` if it is directly inside this store directory.
+    pub fn base_name<'a>(&self, path: &'a str) -> Option<&'a str> {
+        let base = path.strip_prefix(self.0.as_str())?.strip_prefix('/')?;
+        if base.is_empty() || base.contains('/') {
+            None
+        } else {
+            Some(base)
+        }
+    }
+
+    pub fn parse_path(&self, path: &str) -> io::Result<StorePath> {
+        match self.base_name(path) {
+            Some(base) => StorePath::from_base_name(base),
+            None => {
+                let message = format!("Path {:?} is not in store {}", path, self.0);
+                Err(Error::new(ErrorKind::InvalidInput, message))
+            }
+        }
+    }
+
+    pub fn display_path(&self, path: &StorePath) -> String {
+        format!("{}/{}", self.0, path)
+    }
+}
+
+impl Default for StoreDir {
+    fn default() -> Self {
+        StoreDir(DEFAULT_STORE_DIR.to_owned())
+    }
+}
+
+impl AsRef<Path> for StoreDir {
+    fn as_ref(&self) -> &Path {
+        self.as_path()
+    }
+}
+
+impl AsRef<str> for StoreDir {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for StoreDir {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for StoreDir {
+    type Err = Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        StoreDir::new(s)
+    }
+}
+
+/// A store path independent of its store directory: `<hash part>-<name>`.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct StorePath {
+    hash_part: String,
+    name: String,
+}
+
+impl StorePath {
+    pub fn from_base_name(base: &str) -> io::Result<Self> {
+        let invalid = || {
+            let message = format!("Invalid store path name {:?}", base);
+            Error::new(ErrorKind::InvalidInput, message)
+        };
+
+        if base.len() < HASH_PART_LEN + 2 || base.as_bytes()[HASH_PART_LEN] != b'-' {
+            return Err(invalid());
+        }
+
+        let (hash_part, name) = (&base[..HASH_PART_LEN], &base[HASH_PART_LEN + 1..]);
+        if !hash_part.chars().all(|c| NIX_BASE32_CHARS.contains(c)) {
+            return Err(invalid());
+        }
+        validate_name(name)?;
+
+        Ok(StorePath {
+            hash_part: hash_part.to_owned(),
+            name: name.to_owned(),
+        })
+    }
+
+    #[inline]
+    pub fn hash_part(&self) -> &str {
+        &self.hash_part
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Display for StorePath {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.hash_part, self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELLO: &str = "syd87l2rxw8cbsxmxl853h0r6pdwhw0q-hello-2.12";
+
+    #[test]
+    fn parses_paths_in_store_dir() {
+        let store = StoreDir::default();
+        let path = store.parse_path(&format!("/nix/store/{}", HELLO)).unwrap();
+        assert_eq!(path.hash_part(), "syd87l2rxw8cbsxmxl853h0r6pdwhw0q");
+        assert_eq!(path.name(), "hello-2.12");
+        assert_eq!(store.display_path(&path), format!("/nix/store/{}", HELLO));
+
+        let gnu = StoreDir::new("/gnu/store/").unwrap();
+        assert_eq!(gnu.as_str(), "/gnu/store");
+        assert!(gnu.parse_path(&format!("/nix/store/{}", HELLO)).is_err());
+        assert!(store
+            .parse_path(&format!("/nix/store/{}/bin", HELLO))
+            .is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_store_dirs_and_names() {
+        assert!(StoreDir::new("nix/store").is_err());
+        assert!(StoreDir::new("/").is_err());
+        assert!(StorePath::from_base_name("short-name").is_err());
+        assert!(StorePath::from_base_name("eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee-hello").is_err());
+        assert!(StorePath::from_base_name("syd87l2rxw8cbsxmxl853h0r6pdwhw0q-").is_err());
+        assert!(StorePath::from_base_name("syd87l2rxw8cbsxmxl853h0r6pdwhw0q-a b").is_err());
+    }
+
+    #[test]
+    fn validates_names_like_nix() {
+        for name in &[
+            "hello-2.12",
+            "a",
+            "source",
+            "x+y=z?_",
+            ".dotfile",
+            "...",
+            &"a".repeat(211),
+        ] {
+            assert!(validate_name(name).is_ok(), "{:?}", name);
+        }
+
+        let problem = |name: &str| validate_name(name).unwrap_err().to_string();
+        assert!(problem("").contains("empty"));
+        assert!(problem(&"a".repeat(212)).contains("212 bytes"));
+        assert!(problem("..").contains("reserved"));
+        assert!(problem(".-foo").contains("reserved"));
+        assert!(problem("..-foo").contains("reserved"));
+        assert_eq!(
+            problem("hello world"),
+            r#"Invalid store path name "hello world": character ' ' at byte 5 is not allowed"#
+        );
+        assert!(problem("caf\u{e9}").contains("'é' at byte 3"));
+    }
+}
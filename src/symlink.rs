@@ -0,0 +1,168 @@
+use std::ffi::OsString;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Error, ErrorKind};
+#[cfg(unix)]
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct SymlinkTarget(PathBuf);
+
+impl SymlinkTarget {
+    pub fn new<P: Into<PathBuf>>(target: P) -> Self {
+        SymlinkTarget(target.into())
+    }
+
+    /// Creates a target from its raw bytes, which need not be valid UTF-8. Platforms other than
+    /// Unix cannot represent arbitrary bytes in paths, so invalid UTF-8 is replaced there.
+    pub fn from_bytes<B: Into<Vec<u8>>>(target: B) -> Self {
+        #[cfg(unix)]
+        let target = OsString::from_vec(target.into());
+        #[cfg(not(unix))]
+        let target = OsString::from(String::from_utf8_lossy(&target.into()).into_owned());
+        SymlinkTarget(target.into())
+    }
+
+    /// The bytes of the target, exactly as passed to [`from_bytes`](SymlinkTarget::from_bytes)
+    /// on Unix.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_os_str().as_encoded_bytes()
+    }
+
+    /// The target as UTF-8, for formats that can only hold text. Fails with
+    /// [`ErrorKind::InvalidData`] rather than replacing invalid bytes.
+    pub fn to_utf8(&self) -> io::Result<&str> {
+        self.0.to_str().ok_or_else(|| {
+            let message = format!("Symlink target {:?} is not UTF-8", self.0);
+            Error::new(ErrorKind::InvalidData, message)
+        })
+    }
+
+    #[inline]
+    pub fn as_path(&self) -> &Path {
+        &self.0
+    }
+
+    #[inline]
+    pub fn into_path_buf(self) -> PathBuf {
+        self.0
+    }
+
+    #[inline]
+    pub fn is_absolute(&self) -> bool {
+        self.0.has_root()
+    }
+
+    /// Returns `true` if following this link from a directory `depth` levels below the archive
+    /// root would resolve to a location outside of the archive.
+    pub fn escapes_root(&self, depth: usize) -> bool {
+        if self.is_absolute() {
+            return true;
+        }
+
+        let mut level = depth;
+        for component in self.0.components() {
+            match component {
+                Component::ParentDir if level == 0 => return true,
+                Component::ParentDir => level -= 1,
+                Component::Normal(_) => level += 1,
+                Component::CurDir => {}
+                Component::Prefix(_) | Component::RootDir => return true,
+            }
+        }
+
+        false
+    }
+
+    pub fn points_into_store<P: AsRef<Path>>(&self, store_dir: P) -> bool {
+        let store_dir = store_dir.as_ref();
+        if !self.is_absolute() || !store_dir.has_root() {
+            return false;
+        }
+
+        let mut normalized = PathBuf::new();
+        for component in self.0.components() {
+            match component {
+                Component::ParentDir => {
+                    normalized.pop();
+                }
+                Component::CurDir => {}
+                other => normalized.push(other),
+            }
+        }
+
+        normalized
+            .strip_prefix(store_dir)
+            .map(|rest| rest.components().next().is_some())
+            .unwrap_or(false)
+    }
+}
+
+impl AsRef<Path> for SymlinkTarget {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Display for SymlinkTarget {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.0.display())
+    }
+}
+
+impl From<PathBuf> for SymlinkTarget {
+    fn from(target: PathBuf) -> Self {
+        SymlinkTarget(target)
+    }
+}
+
+impl From<&Path> for SymlinkTarget {
+    fn from(target: &Path) -> Self {
+        SymlinkTarget(target.to_owned())
+    }
+}
+
+impl From<String> for SymlinkTarget {
+    fn from(target: String) -> Self {
+        SymlinkTarget(target.into())
+    }
+}
+
+impl From<&str> for SymlinkTarget {
+    fn from(target: &str) -> Self {
+        SymlinkTarget(target.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_absolute_targets() {
+        assert!(SymlinkTarget::new("/nix/store/foo").is_absolute());
+        assert!(!SymlinkTarget::new("../foo").is_absolute());
+    }
+
+    #[test]
+    fn detects_root_escapes() {
+        assert!(!SymlinkTarget::new("foo/bar").escapes_root(0));
+        assert!(!SymlinkTarget::new("./foo").escapes_root(0));
+        assert!(SymlinkTarget::new("../foo").escapes_root(0));
+        assert!(!SymlinkTarget::new("../foo").escapes_root(1));
+        assert!(SymlinkTarget::new("foo/../../bar").escapes_root(0));
+        assert!(!SymlinkTarget::new("foo/../bar").escapes_root(0));
+        assert!(SymlinkTarget::new("/etc/passwd").escapes_root(4));
+    }
+
+    #[test]
+    fn detects_store_targets() {
+        let target = SymlinkTarget::new("/nix/store/abc-hello/bin/hello");
+        assert!(target.points_into_store("/nix/store"));
+        assert!(!target.points_into_store("/gnu/store"));
+        assert!(!SymlinkTarget::new("/nix/store").points_into_store("/nix/store"));
+        assert!(!SymlinkTarget::new("/nix/store/../etc").points_into_store("/nix/store"));
+        assert!(!SymlinkTarget::new("nix/store/abc").points_into_store("/nix/store"));
+    }
+}
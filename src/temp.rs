@@ -0,0 +1,282 @@
+use std::collections::hash_map::RandomState;
+use std::ffi::OsStr;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::fs::{self, File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MAX_ATTEMPTS: u32 = 64;
+const MARKER: &str = ".tmp-";
+
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+pub trait TempProvider: Debug + Send + Sync {
+    /// Returns the directory in which temporary files standing in for `dst` should be created.
+    fn temp_root(&self, dst: &Path) -> io::Result<PathBuf>;
+
+    fn create_temp_dir(&self, dst: &Path) -> io::Result<PathBuf> {
+        let root = self.temp_root(dst)?;
+        create_unique(&root, dst, |path| fs::create_dir(path))
+    }
+
+    fn create_temp_file(&self, dst: &Path) -> io::Result<(PathBuf, File)> {
+        let root = self.temp_root(dst)?;
+        let mut file = None;
+        let path = create_unique(&root, dst, |path| {
+            file = Some(
+                OpenOptions::new()
+                    .write(true)
+                    .read(true)
+                    .create_new(true)
+                    .open(path)?,
+            );
+            Ok(())
+        })?;
+        Ok((path, file.expect("file is created on success")))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SameFilesystem;
+
+impl TempProvider for SameFilesystem {
+    fn temp_root(&self, dst: &Path) -> io::Result<PathBuf> {
+        match dst.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => Ok(parent.to_owned()),
+            _ => Ok(PathBuf::from(".")),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InDirectory(pub PathBuf);
+
+impl TempProvider for InDirectory {
+    fn temp_root(&self, _dst: &Path) -> io::Result<PathBuf> {
+        Ok(self.0.clone())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SystemTemp;
+
+impl TempProvider for SystemTemp {
+    fn temp_root(&self, _dst: &Path) -> io::Result<PathBuf> {
+        Ok(std::env::temp_dir())
+    }
+}
+
+/// The name of a temporary file or directory standing in for a destination, of the form
+/// `.<destination name>.tmp-<pid>-<16 hex digits>`. Cleanup tools can use [`TempName::parse`] to
+/// recognize leftovers of crashed runs, or call [`purge_stale`] directly.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct TempName {
+    stem: String,
+    pid: u32,
+    suffix: u64,
+}
+
+impl TempName {
+    /// Names a temporary path for `dst` created by this process, with a fresh random suffix.
+    pub fn for_destination(dst: &Path) -> Self {
+        let stem = dst
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "libnar".to_owned());
+
+        TempName {
+            stem,
+            pid: std::process::id(),
+            suffix: random_suffix(),
+        }
+    }
+
+    /// Returns `None` if `name` was not produced by a [`TempProvider`].
+    pub fn parse(name: &OsStr) -> Option<Self> {
+        let name = name.to_str()?.strip_prefix('.')?;
+        let marker = name.rfind(MARKER)?;
+        let (pid, suffix) = name[marker + MARKER.len()..].split_once('-')?;
+        let is_hex = |s: &str| s.len() == 16 && s.bytes().all(|b| b.is_ascii_hexdigit());
+        if marker == 0 || !is_hex(suffix) || !pid.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+
+        Some(TempName {
+            stem: name[..marker].to_owned(),
+            pid: pid.parse().ok()?,
+            suffix: u64::from_str_radix(suffix, 16).ok()?,
+        })
+    }
+
+    /// The file name of the destination the temporary path stands in for.
+    #[inline]
+    pub fn stem(&self) -> &str {
+        &self.stem
+    }
+
+    /// The ID of the process that created the temporary path.
+    #[inline]
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    #[inline]
+    pub fn suffix(&self) -> u64 {
+        self.suffix
+    }
+}
+
+impl Display for TempName {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            ".{}{}{}-{:016x}",
+            self.stem, MARKER, self.pid, self.suffix
+        )
+    }
+}
+
+/// Removes every temporary file or directory directly inside `dir` that was last modified more
+/// than `older_than` ago, returning the paths removed.
+pub fn purge_stale(dir: &Path, older_than: Duration) -> io::Result<Vec<PathBuf>> {
+    let cutoff = SystemTime::now()
+        .checked_sub(older_than)
+        .unwrap_or(UNIX_EPOCH);
+    let mut purged = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if TempName::parse(&entry.file_name()).is_none() {
+            continue;
+        }
+
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(ref err) if err.kind() == ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+        if metadata.modified()? > cutoff {
+            continue;
+        }
+
+        let path = entry.path();
+        let result = if metadata.is_dir() {
+            fs::remove_dir_all(&path)
+        } else {
+            fs::remove_file(&path)
+        };
+        match result {
+            Ok(()) => purged.push(path),
+            Err(ref err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(purged)
+}
+
+fn random_suffix() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos());
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.write_u128(nanos);
+    hasher.finish()
+}
+
+fn create_unique<F>(root: &Path, dst: &Path, mut create: F) -> io::Result<PathBuf>
+where
+    F: FnMut(&Path) -> io::Result<()>,
+{
+    for _ in 0..MAX_ATTEMPTS {
+        let path = root.join(TempName::for_destination(dst).to_string());
+        match create(&path) {
+            Ok(()) => return Ok(path),
+            Err(ref err) if err.kind() == ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+
+    let message = format!(
+        "Failed to create a unique temporary path in {}",
+        root.display()
+    );
+    Err(Error::new(ErrorKind::AlreadyExists, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn creates_temp_dirs_next_to_destination() {
+        let dir = tempfile::tempdir().unwrap();
+        let dst = dir.path().join("out");
+
+        let first = SameFilesystem.create_temp_dir(&dst).unwrap();
+        let second = SameFilesystem.create_temp_dir(&dst).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(first.parent(), Some(dir.path()));
+        assert!(first.is_dir() && second.is_dir());
+    }
+
+    #[test]
+    fn creates_temp_files_in_configured_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = InDirectory(dir.path().to_owned());
+
+        let (path, _file) = provider
+            .create_temp_file(Path::new("/elsewhere/out"))
+            .unwrap();
+        assert_eq!(path.parent(), Some(dir.path()));
+        assert!(path.is_file());
+    }
+
+    #[test]
+    fn names_are_recognized_by_the_naming_scheme() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = SameFilesystem
+            .create_temp_dir(&dir.path().join("out.tmp-1-x"))
+            .unwrap();
+        let name = TempName::parse(path.file_name().unwrap()).unwrap();
+        assert_eq!(name.stem(), "out.tmp-1-x");
+        assert_eq!(name.pid(), std::process::id());
+        assert_eq!(
+            name.to_string(),
+            path.file_name().unwrap().to_str().unwrap()
+        );
+
+        for other in &[
+            "out",
+            ".out.tmp-1-2",
+            ".tmp-1-0123456789abcdef",
+            ".out.tmp-x-0123456789abcdef",
+        ] {
+            assert_eq!(TempName::parse(OsStr::new(other)), None);
+        }
+    }
+
+    #[test]
+    fn purges_only_stale_temp_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let dst = dir.path().join("out");
+        let (file, _) = SameFilesystem.create_temp_file(&dst).unwrap();
+        let temp_dir = SameFilesystem.create_temp_dir(&dst).unwrap();
+        fs::write(temp_dir.join("partial"), "data").unwrap();
+        fs::write(&dst, "keep").unwrap();
+
+        let hour = Duration::from_secs(3600);
+        assert!(purge_stale(dir.path(), hour).unwrap().is_empty());
+
+        let mut purged = purge_stale(dir.path(), Duration::from_secs(0)).unwrap();
+        purged.sort();
+        let mut expected = vec![file, temp_dir];
+        expected.sort();
+        assert_eq!(purged, expected);
+        assert!(dst.exists());
+    }
+}
@@ -0,0 +1,310 @@
+use std::collections::BTreeMap;
+use std::io::{self, Error, ErrorKind, Read, Write};
+
+pub use self::builder::TreeBuilder;
+pub use self::compare::{compare, CompareOptions};
+#[cfg(feature = "fs")]
+pub use self::layer::{Layer, WHITEOUT_PREFIX};
+pub use self::walk::{Cursor, Walk, WalkWithPaths};
+
+use crate::de::EntryKind;
+use crate::{wire, Archive, NIX_VERSION_MAGIC};
+
+mod builder;
+mod compare;
+#[cfg(feature = "fs")]
+mod layer;
+#[cfg(feature = "macros")]
+mod macros;
+mod walk;
+
+/// A whole archive held in memory, which can be inspected and edited freely before being
+/// written back out as a canonical NAR.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NarTree {
+    root: Node,
+}
+
+/// Directory entries are kept sorted by the bytes of their names, which is the order NAR
+/// requires.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Node {
+    Directory { entries: BTreeMap<String, Node> },
+    Regular { executable: bool, contents: Vec<u8> },
+    Symlink { target: String },
+}
+
+impl Node {
+    #[inline]
+    pub fn is_dir(&self) -> bool {
+        matches!(self, Node::Directory { .. })
+    }
+
+    /// The entries of a directory, or `None` for any other kind of node.
+    pub fn entries(&self) -> Option<&BTreeMap<String, Node>> {
+        match self {
+            Node::Directory { entries } => Some(entries),
+            _ => None,
+        }
+    }
+
+    pub fn entries_mut(&mut self) -> Option<&mut BTreeMap<String, Node>> {
+        match self {
+            Node::Directory { entries } => Some(entries),
+            _ => None,
+        }
+    }
+}
+
+impl NarTree {
+    pub fn new(root: Node) -> Self {
+        NarTree { root }
+    }
+
+    /// Starts building a tree whose root is a directory.
+    pub fn builder() -> TreeBuilder {
+        TreeBuilder::new()
+    }
+
+    pub fn from_reader<R: Read>(reader: R) -> io::Result<Self> {
+        let mut archive = Archive::new(reader);
+        let mut root = None;
+
+        for entry in archive.entries()? {
+            let entry = entry?;
+            let node = match &entry.kind {
+                EntryKind::Directory => Node::Directory {
+                    entries: BTreeMap::new(),
+                },
+                EntryKind::Regular {
+                    executable, data, ..
+                } => Node::Regular {
+                    executable: *executable,
+                    contents: data.clone(),
+                },
+                EntryKind::Symlink { target } => match target.as_path().to_str() {
+                    Some(target) => Node::Symlink {
+                        target: target.to_owned(),
+                    },
+                    None => {
+                        let message = format!("Symlink target of {:?} is not UTF-8", entry.name());
+                        return Err(Error::new(ErrorKind::InvalidData, message));
+                    }
+                },
+                EntryKind::Unknown { type_name, .. } => {
+                    let message = format!("Cannot load unrecognized node type `{}`", type_name);
+                    return Err(Error::other(message));
+                }
+            };
+
+            let mut components = entry.path_components();
+            let name = match components.next_back() {
+                Some(name) => name.to_owned(),
+                None => {
+                    root = Some(node);
+                    continue;
+                }
+            };
+
+            let parent = root
+                .as_mut()
+                .and_then(|root| lookup_mut(root, components))
+                .and_then(Node::entries_mut);
+            match parent {
+                Some(entries) => {
+                    entries.insert(name, node);
+                }
+                None => {
+                    let message = format!("Orphaned entry {:?}", entry.name());
+                    return Err(Error::other(message));
+                }
+            }
+        }
+
+        root.map(NarTree::new)
+            .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "Archive is empty"))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        NarTree::from_reader(bytes)
+    }
+
+    #[inline]
+    pub fn root(&self) -> &Node {
+        &self.root
+    }
+
+    #[inline]
+    pub fn root_mut(&mut self) -> &mut Node {
+        &mut self.root
+    }
+
+    /// Looks up a node by its `/`-separated path, where the empty path names the root.
+    pub fn get(&self, path: &str) -> Option<&Node> {
+        let mut node = &self.root;
+        for name in split_path(path) {
+            node = node.entries()?.get(name)?;
+        }
+        Some(node)
+    }
+
+    pub fn get_mut(&mut self, path: &str) -> Option<&mut Node> {
+        lookup_mut(&mut self.root, split_path(path))
+    }
+
+    /// Visits every node depth-first, parents before their children and siblings in name order,
+    /// which is the order the streaming parser emits entries in.
+    pub fn walk(&self) -> Walk<'_> {
+        Walk::new(&self.root)
+    }
+
+    /// Like [`walk`](NarTree::walk), but also yields the path of every node.
+    pub fn walk_with_paths(&self) -> WalkWithPaths<'_> {
+        WalkWithPaths::new(&self.root)
+    }
+
+    /// Creates a cursor positioned before the root, which visits nodes in the same order as
+    /// [`walk`](NarTree::walk) while the tree is modified between steps.
+    pub fn cursor(&self) -> Cursor {
+        Cursor::new()
+    }
+
+    /// The exact length of the archive [`to_writer`](NarTree::to_writer) produces.
+    pub fn encoded_len(&self) -> u64 {
+        wire::header_len() + node_len(&self.root)
+    }
+
+    /// Writes the canonical archive straight from the buffers held by the tree, so file contents
+    /// are never copied on the way to `writer`.
+    pub fn to_writer<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        let mut keep = |_: &str, _: &[u8]| Ok(None::<(u64, io::Empty)>);
+        wire::write_token(writer, NIX_VERSION_MAGIC)?;
+        write_node(writer, &self.root, &mut String::new(), &mut keep)
+    }
+
+    /// Like [`to_writer`](NarTree::to_writer), but lets `resolve` substitute the contents of
+    /// regular files as they are written, e.g. fetching the blob named by the stored contents
+    /// from a blob store. `resolve` is given the path and stored contents of each file and
+    /// returns either `None` to keep them, or the length of the actual contents and a reader from
+    /// which exactly that many bytes are read.
+    pub fn to_writer_resolving<W, F, R>(&self, writer: &mut W, mut resolve: F) -> io::Result<()>
+    where
+        W: Write + ?Sized,
+        F: FnMut(&str, &[u8]) -> io::Result<Option<(u64, R)>>,
+        R: Read,
+    {
+        wire::write_token(writer, NIX_VERSION_MAGIC)?;
+        write_node(writer, &self.root, &mut String::new(), &mut resolve)
+    }
+
+    /// Like [`to_writer`](NarTree::to_writer), into a buffer allocated once at the final size.
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.encoded_len() as usize);
+        self.to_writer(&mut bytes)
+            .expect("writing to a Vec cannot fail");
+        bytes
+    }
+}
+
+pub(crate) fn target_problem<T: AsRef<[u8]> + ?Sized>(target: &T) -> Option<&'static str> {
+    let target = target.as_ref();
+    if target.is_empty() {
+        Some("empty symlink target")
+    } else if target.contains(&0) {
+        Some("symlink target contains NUL")
+    } else {
+        None
+    }
+}
+
+fn split_path(path: &str) -> impl DoubleEndedIterator<Item = &str> {
+    path.split('/').filter(|name| !name.is_empty())
+}
+
+fn lookup_mut<'a, 'b, I>(mut node: &'a mut Node, path: I) -> Option<&'a mut Node>
+where
+    I: IntoIterator<Item = &'b str>,
+{
+    for name in path {
+        node = node.entries_mut()?.get_mut(name)?;
+    }
+    Some(node)
+}
+
+fn node_len(node: &Node) -> u64 {
+    match node {
+        Node::Directory { entries } => {
+            let entries_len = entries
+                .iter()
+                .map(|(name, node)| wire::directory_entry_len(name.len() as u64, node_len(node)))
+                .sum();
+            wire::directory_node_len(entries_len)
+        }
+        Node::Regular {
+            executable,
+            contents,
+        } => wire::regular_node_len(contents.len() as u64, *executable),
+        Node::Symlink { target } => wire::symlink_node_len(target.len() as u64),
+    }
+}
+
+fn write_node<W, F, R>(
+    writer: &mut W,
+    node: &Node,
+    path: &mut String,
+    resolve: &mut F,
+) -> io::Result<()>
+where
+    W: Write + ?Sized,
+    F: FnMut(&str, &[u8]) -> io::Result<Option<(u64, R)>>,
+    R: Read,
+{
+    wire::write_token(writer, b"(")?;
+    wire::write_token(writer, b"type")?;
+
+    match node {
+        Node::Directory { entries } => {
+            wire::write_token(writer, b"directory")?;
+            for (name, node) in entries {
+                wire::write_token(writer, b"entry")?;
+                wire::write_token(writer, b"(")?;
+                wire::write_token(writer, b"name")?;
+                wire::write_token(writer, name.as_bytes())?;
+                wire::write_token(writer, b"node")?;
+                let parent_len = path.len();
+                if parent_len > 0 {
+                    path.push('/');
+                }
+                path.push_str(name);
+                write_node(writer, node, path, resolve)?;
+                path.truncate(parent_len);
+                wire::write_token(writer, b")")?;
+            }
+        }
+        Node::Regular {
+            executable,
+            contents,
+        } => {
+            wire::write_token(writer, b"regular")?;
+            if *executable {
+                wire::write_token(writer, b"executable")?;
+                wire::write_token(writer, b"")?;
+            }
+            wire::write_token(writer, b"contents")?;
+            match resolve(path, contents)? {
+                Some((len, mut reader)) => {
+                    let buffer_len = wire::DEFAULT_COPY_BUFFER_LEN;
+                    wire::write_token_from_reader(writer, &mut reader, len, buffer_len)?
+                }
+                None => wire::write_token(writer, contents)?,
+            }
+        }
+        Node::Symlink { target } => {
+            wire::write_token(writer, b"symlink")?;
+            wire::write_token(writer, b"target")?;
+            wire::write_token(writer, target.as_bytes())?;
+        }
+    }
+
+    wire::write_token(writer, b")")
+}
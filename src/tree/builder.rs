@@ -0,0 +1,102 @@
+use std::collections::BTreeMap;
+use std::io::{self, Error, ErrorKind};
+
+use super::{target_problem, NarTree, Node};
+use crate::de::validate_entry_name;
+
+/// Fluent construction of a [`NarTree`] rooted at a directory, created by [`NarTree::builder`].
+///
+/// Names and targets are checked by [`build`](TreeBuilder::build), which reports the first
+/// problem found.
+#[derive(Clone, Debug, Default)]
+pub struct TreeBuilder {
+    path: String,
+    entries: BTreeMap<String, Node>,
+    error: Option<String>,
+}
+
+impl TreeBuilder {
+    pub(super) fn new() -> Self {
+        TreeBuilder::default()
+    }
+
+    pub fn file<S, C>(self, name: S, contents: C, executable: bool) -> Self
+    where
+        S: Into<String>,
+        C: Into<Vec<u8>>,
+    {
+        let node = Node::Regular {
+            executable,
+            contents: contents.into(),
+        };
+        self.insert(name.into(), node)
+    }
+
+    pub fn symlink<S: Into<String>, T: Into<String>>(self, name: S, target: T) -> Self {
+        let target = target.into();
+        let name = name.into();
+        match target_problem(&target) {
+            Some(problem) => {
+                let path = self.child_path(&name);
+                self.fail(path, problem)
+            }
+            None => self.insert(name, Node::Symlink { target }),
+        }
+    }
+
+    /// Adds a directory whose contents are filled in by `build`.
+    pub fn dir<S, F>(mut self, name: S, build: F) -> Self
+    where
+        S: Into<String>,
+        F: FnOnce(TreeBuilder) -> TreeBuilder,
+    {
+        let name = name.into();
+        let child = build(TreeBuilder {
+            path: self.child_path(&name),
+            ..TreeBuilder::default()
+        });
+
+        if let Some(error) = child.error {
+            self.error.get_or_insert(error);
+            return self;
+        }
+        let entries = child.entries;
+        self.insert(name, Node::Directory { entries })
+    }
+
+    pub fn build(self) -> io::Result<NarTree> {
+        match self.error {
+            Some(message) => Err(Error::new(ErrorKind::InvalidInput, message)),
+            None => Ok(NarTree::new(Node::Directory {
+                entries: self.entries,
+            })),
+        }
+    }
+
+    fn insert(mut self, name: String, node: Node) -> Self {
+        let path = self.child_path(&name);
+        if let Err(problem) = validate_entry_name(&name) {
+            return self.fail(path, &problem);
+        }
+        if self.entries.contains_key(&name) {
+            return self.fail(path, "duplicate entry");
+        }
+
+        self.entries.insert(name, node);
+        self
+    }
+
+    fn fail(mut self, path: String, problem: &str) -> Self {
+        let message = format!("Invalid tree at {:?}: {}", path, problem);
+        self.error.get_or_insert(message);
+        self
+    }
+
+    fn child_path(&self, name: &str) -> String {
+        if self.path.is_empty() {
+            name.to_owned()
+        } else {
+            format!("{}/{}", self.path, name)
+        }
+    }
+}
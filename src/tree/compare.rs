@@ -0,0 +1,173 @@
+use std::collections::BTreeSet;
+use std::io::{self, Read};
+
+use super::{split_path, walk::join, NarTree, Node};
+
+/// Symlink chains longer than this are left unresolved, matching the limit used by Linux.
+const MAX_SYMLINK_HOPS: usize = 40;
+
+/// Relaxations applied by [`compare`], for artifacts produced on platforms that cannot represent
+/// every property of an archive faithfully.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CompareOptions {
+    ignore_executable: bool,
+    resolve_symlinks: bool,
+}
+
+impl CompareOptions {
+    pub fn new() -> Self {
+        CompareOptions::default()
+    }
+
+    /// Treats regular files as equal regardless of their executable bits.
+    pub fn set_ignore_executable(&mut self, ignore: bool) {
+        self.ignore_executable = ignore;
+    }
+
+    /// Compares what relative symlinks point to within the archive instead of their targets, so
+    /// a symlink matches a copy of the node it refers to. Absolute, dangling and cyclic symlinks
+    /// are compared as symlinks.
+    pub fn set_resolve_symlinks(&mut self, resolve: bool) {
+        self.resolve_symlinks = resolve;
+    }
+
+    #[inline]
+    pub fn ignore_executable(&self) -> bool {
+        self.ignore_executable
+    }
+
+    #[inline]
+    pub fn resolve_symlinks(&self) -> bool {
+        self.resolve_symlinks
+    }
+}
+
+/// Compares the structure and contents of two archives, returning the paths at which they
+/// differ in walk order. Entries present on only one side are reported without their
+/// descendants, and an empty result means the archives are equal under `options`.
+pub fn compare<A: Read, B: Read>(a: A, b: B, options: CompareOptions) -> io::Result<Vec<String>> {
+    let a = NarTree::from_reader(a)?;
+    let b = NarTree::from_reader(b)?;
+    Ok(a.compare(&b, options))
+}
+
+impl NarTree {
+    /// Like [`compare`], but for trees that are already in memory.
+    pub fn compare(&self, other: &NarTree, options: CompareOptions) -> Vec<String> {
+        let mut comparer = Comparer {
+            a: self,
+            b: other,
+            options,
+            visiting: Vec::new(),
+            differences: Vec::new(),
+        };
+        comparer.compare("", String::new(), String::new());
+        comparer.differences
+    }
+}
+
+struct Comparer<'a> {
+    a: &'a NarTree,
+    b: &'a NarTree,
+    options: CompareOptions,
+    /// Resolved directory pairs currently being compared, to stop at symlink cycles.
+    visiting: Vec<(String, String)>,
+    differences: Vec<String>,
+}
+
+impl<'a> Comparer<'a> {
+    fn compare(&mut self, path: &str, a_path: String, b_path: String) {
+        let (a_path, a) = self.lookup(self.a, a_path);
+        let (b_path, b) = self.lookup(self.b, b_path);
+
+        let equal = match (a, b) {
+            (Node::Directory { entries: a }, Node::Directory { entries: b }) => {
+                let pair = (a_path, b_path);
+                if self.visiting.contains(&pair) {
+                    return;
+                }
+                self.visiting.push(pair);
+
+                let names: BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+                for name in names {
+                    let child = join(path, name);
+                    if !a.contains_key(name) || !b.contains_key(name) {
+                        self.differences.push(child);
+                        continue;
+                    }
+                    let (a_path, b_path) = self.visiting.last().expect("pushed above");
+                    let (a_path, b_path) = (join(a_path, name), join(b_path, name));
+                    self.compare(&child, a_path, b_path);
+                }
+
+                self.visiting.pop();
+                return;
+            }
+            (
+                Node::Regular {
+                    executable,
+                    contents,
+                },
+                Node::Regular {
+                    executable: other_executable,
+                    contents: other_contents,
+                },
+            ) => {
+                contents == other_contents
+                    && (self.options.ignore_executable || executable == other_executable)
+            }
+            (Node::Symlink { target }, Node::Symlink { target: other }) => target == other,
+            _ => false,
+        };
+
+        if !equal {
+            self.differences.push(path.to_owned());
+        }
+    }
+
+    fn lookup(&self, tree: &'a NarTree, path: String) -> (String, &'a Node) {
+        let node = tree.get(&path).expect("compared paths always exist");
+        if !self.options.resolve_symlinks || !matches!(node, Node::Symlink { .. }) {
+            return (path, node);
+        }
+        resolve(tree, &path).unwrap_or((path, node))
+    }
+}
+
+/// Follows every relative symlink along `path`, returning the canonical path and the node it
+/// leads to, or `None` if that cannot be done without leaving the archive.
+fn resolve<'a>(tree: &'a NarTree, path: &str) -> Option<(String, &'a Node)> {
+    let mut pending: Vec<String> = split_path(path).rev().map(ToOwned::to_owned).collect();
+    let mut resolved: Vec<String> = Vec::new();
+    let mut node = tree.root();
+    let mut hops = 0;
+
+    while let Some(name) = pending.pop() {
+        match name.as_str() {
+            "." => continue,
+            ".." => {
+                resolved.pop()?;
+                node = tree.get(&resolved.join("/"))?;
+                continue;
+            }
+            _ => {}
+        }
+
+        let child = node.entries()?.get(&name)?;
+        match child {
+            Node::Symlink { target } => {
+                hops += 1;
+                if hops > MAX_SYMLINK_HOPS || target.starts_with('/') {
+                    return None;
+                }
+                pending.extend(split_path(target).rev().map(ToOwned::to_owned));
+            }
+            _ => {
+                resolved.push(name);
+                node = child;
+            }
+        }
+    }
+
+    Some((resolved.join("/"), node))
+}
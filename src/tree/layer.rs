@@ -0,0 +1,96 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Error, ErrorKind, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use super::{CompareOptions, NarTree, Node};
+
+/// Prefix marking a removed entry in an OCI image layer.
+pub const WHITEOUT_PREFIX: &str = ".wh.";
+
+/// The paths written by [`NarTree::unpack_layer`], in walk order.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Layer {
+    /// Entries that were added or changed, written out with all of their descendants.
+    pub changed: Vec<String>,
+    /// Entries that were removed, each represented by a whiteout file.
+    pub removed: Vec<String>,
+}
+
+impl NarTree {
+    /// Unpacks the changes from `base` to this tree below `dst` as an overlay/OCI image layer:
+    /// added and changed entries are written out, and each removed entry becomes an empty
+    /// `.wh.<name>` file next to where it was. Stacking the layer on top of `base` yields this
+    /// tree.
+    ///
+    /// Both roots must be directories, and no entry of this tree may itself be named like a
+    /// whiteout.
+    pub fn unpack_layer<P: AsRef<Path>>(&self, base: &NarTree, dst: P) -> io::Result<Layer> {
+        if !self.root().is_dir() || !base.root().is_dir() {
+            let message = "Image layers can only be made from archives of directories";
+            return Err(Error::new(ErrorKind::InvalidInput, message));
+        }
+        let reserved = self.walk_with_paths().find(|(path, _)| {
+            let name = path.rsplit('/').next().unwrap_or_default();
+            name.starts_with(WHITEOUT_PREFIX)
+        });
+        if let Some((path, _)) = reserved {
+            let message = format!("Entry {:?} would be read as a whiteout", path);
+            return Err(Error::new(ErrorKind::InvalidData, message));
+        }
+
+        let dst = dst.as_ref();
+        fs::create_dir_all(dst)?;
+
+        let mut layer = Layer::default();
+        for path in base.compare(self, CompareOptions::new()) {
+            let (parent, name) = match path.rsplit_once('/') {
+                Some((parent, name)) => (dst.join(parent), name),
+                None => (dst.to_owned(), path.as_str()),
+            };
+            fs::create_dir_all(&parent)?;
+
+            match self.get(&path) {
+                Some(node) => {
+                    write_node(&parent.join(name), node)?;
+                    layer.changed.push(path);
+                }
+                None => {
+                    let whiteout = parent.join(format!("{}{}", WHITEOUT_PREFIX, name));
+                    OpenOptions::new()
+                        .create_new(true)
+                        .write(true)
+                        .open(whiteout)?;
+                    layer.removed.push(path);
+                }
+            }
+        }
+
+        Ok(layer)
+    }
+}
+
+fn write_node(path: &Path, node: &Node) -> io::Result<()> {
+    match node {
+        Node::Directory { entries } => {
+            fs::create_dir(path)?;
+            for (name, child) in entries {
+                write_node(&path.join(name), child)?;
+            }
+        }
+        Node::Regular {
+            executable,
+            contents,
+        } => {
+            let mode = if *executable { 0o555 } else { 0o444 };
+            let mut file = OpenOptions::new()
+                .create_new(true)
+                .write(true)
+                .mode(mode)
+                .open(path)?;
+            file.write_all(contents)?;
+        }
+        Node::Symlink { target } => std::os::unix::fs::symlink(target, path)?,
+    }
+    Ok(())
+}
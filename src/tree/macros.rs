@@ -0,0 +1,55 @@
+/// Declares a [`NarTree`](crate::tree::NarTree) rooted at a directory, mainly for test fixtures.
+///
+/// Each entry maps a name to `file(contents)`, `exec(contents)`, `symlink(target)` or a braced
+/// list of nested entries. Panics if the tree is invalid, e.g. because a name is repeated.
+///
+/// ```
+/// let tree = libnar::nar_tree! {
+///     "bin" => {
+///         "hello" => exec(b"#!/bin/sh\necho hello\n"),
+///         "readme" => file(b"Says hello"),
+///     },
+///     "lib" => symlink("bin"),
+/// };
+/// assert!(tree.get("bin/hello").is_some());
+/// ```
+#[macro_export]
+macro_rules! nar_tree {
+    ($($entries:tt)*) => {
+        $crate::__nar_tree_entries!($crate::tree::NarTree::builder(); $($entries)*)
+            .build()
+            .expect("`nar_tree!` declared an invalid tree")
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __nar_tree_entries {
+    ($builder:expr;) => {
+        $builder
+    };
+    ($builder:expr; $name:expr => { $($entries:tt)* } $(, $($rest:tt)*)?) => {
+        $crate::__nar_tree_entries!(
+            $builder.dir($name, |dir| $crate::__nar_tree_entries!(dir; $($entries)*));
+            $($($rest)*)?
+        )
+    };
+    ($builder:expr; $name:expr => file($contents:expr) $(, $($rest:tt)*)?) => {
+        $crate::__nar_tree_entries!(
+            $builder.file($name, &$contents[..], false);
+            $($($rest)*)?
+        )
+    };
+    ($builder:expr; $name:expr => exec($contents:expr) $(, $($rest:tt)*)?) => {
+        $crate::__nar_tree_entries!(
+            $builder.file($name, &$contents[..], true);
+            $($($rest)*)?
+        )
+    };
+    ($builder:expr; $name:expr => symlink($target:expr) $(, $($rest:tt)*)?) => {
+        $crate::__nar_tree_entries!(
+            $builder.symlink($name, $target);
+            $($($rest)*)?
+        )
+    };
+}
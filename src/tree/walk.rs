@@ -0,0 +1,158 @@
+use std::ops::Bound;
+
+use super::{NarTree, Node};
+
+/// Depth-first iterator over the nodes of a [`NarTree`], created by [`NarTree::walk`].
+#[derive(Clone, Debug)]
+pub struct Walk<'a> {
+    inner: WalkWithPaths<'a>,
+}
+
+impl<'a> Walk<'a> {
+    pub(super) fn new(root: &'a Node) -> Self {
+        Walk {
+            inner: WalkWithPaths::new(root),
+        }
+    }
+
+    /// Visits children before their parents instead, e.g. to delete a tree bottom-up.
+    pub fn post_order(self) -> Self {
+        Walk {
+            inner: self.inner.post_order(),
+        }
+    }
+}
+
+impl<'a> Iterator for Walk<'a> {
+    type Item = &'a Node;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(_, node)| node)
+    }
+}
+
+/// Depth-first iterator over the nodes of a [`NarTree`] and their `/`-separated paths, created by
+/// [`NarTree::walk_with_paths`]. The root has the empty path.
+#[derive(Clone, Debug)]
+pub struct WalkWithPaths<'a> {
+    /// Nodes still to be visited, along with whether their children have been queued already.
+    stack: Vec<(String, &'a Node, bool)>,
+    post_order: bool,
+}
+
+impl<'a> WalkWithPaths<'a> {
+    pub(super) fn new(root: &'a Node) -> Self {
+        WalkWithPaths {
+            stack: vec![(String::new(), root, false)],
+            post_order: false,
+        }
+    }
+
+    /// Visits children before their parents instead, e.g. to delete a tree bottom-up.
+    pub fn post_order(mut self) -> Self {
+        self.post_order = true;
+        self
+    }
+
+    fn push_children(&mut self, path: &str, node: &'a Node) {
+        if let Some(entries) = node.entries() {
+            for (name, child) in entries.iter().rev() {
+                self.stack.push((join(path, name), child, false));
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for WalkWithPaths<'a> {
+    type Item = (String, &'a Node);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (path, node, expanded) = self.stack.pop()?;
+            if !self.post_order {
+                self.push_children(&path, node);
+                return Some((path, node));
+            }
+
+            if expanded || !node.is_dir() {
+                return Some((path, node));
+            }
+            self.stack.push((path.clone(), node, true));
+            self.push_children(&path, node);
+        }
+    }
+}
+
+/// A position in a [`NarTree`] that is tracked by path rather than by reference, so the tree can
+/// be modified between steps.
+///
+/// Steps follow the order of [`NarTree::walk`] as it applies to the tree at the time of each
+/// step: entries added after the current path are visited, and removing the current node simply
+/// continues with its next sibling.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Cursor {
+    position: Option<Vec<String>>,
+    done: bool,
+}
+
+impl Cursor {
+    pub(super) fn new() -> Self {
+        Cursor::default()
+    }
+
+    /// The path of the node the cursor is on, or `None` before the first step and after the
+    /// last one.
+    pub fn path(&self) -> Option<String> {
+        self.position.as_ref().map(|names| names.join("/"))
+    }
+
+    /// Moves to the next node of `tree` and returns its path.
+    pub fn advance(&mut self, tree: &NarTree) -> Option<String> {
+        if self.done {
+            return None;
+        }
+
+        let names = match &mut self.position {
+            Some(names) => names,
+            None => {
+                self.position = Some(Vec::new());
+                return self.path();
+            }
+        };
+
+        let first_child = tree
+            .get(&names.join("/"))
+            .and_then(Node::entries)
+            .and_then(|entries| entries.keys().next());
+        if let Some(name) = first_child {
+            names.push(name.clone());
+            return self.path();
+        }
+
+        while let Some(name) = names.pop() {
+            let sibling = tree
+                .get(&names.join("/"))
+                .and_then(Node::entries)
+                .and_then(|entries| {
+                    let after = (Bound::Excluded(name.as_str()), Bound::Unbounded);
+                    entries.range::<str, _>(after).next()
+                });
+            if let Some((sibling, _)) = sibling {
+                names.push(sibling.clone());
+                return self.path();
+            }
+        }
+
+        self.position = None;
+        self.done = true;
+        None
+    }
+}
+
+pub(super) fn join(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}/{}", parent, name)
+    }
+}
@@ -0,0 +1,217 @@
+use std::ffi::{OsStr, OsString};
+use std::fmt::Debug;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+
+use filetime::FileTime;
+
+pub use self::memory::MemoryFilesystem;
+
+mod memory;
+
+/// The filesystem operations performed while unpacking entries, so that extraction policies
+/// can be exercised against something other than the real disk.
+///
+/// Paths are never resolved through symlinks: `metadata` describes a symlink itself and
+/// `set_times` applies to the symlink rather than to its target.
+pub trait Filesystem: Debug + Send + Sync {
+    fn metadata(&self, path: &Path) -> io::Result<FileMetadata>;
+
+    fn create_dir(&self, path: &Path) -> io::Result<()>;
+
+    fn read_file(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// The names of the entries of a directory, sorted.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<OsString>>;
+
+    /// Creates a read-only file, failing if anything already exists at `path`.
+    fn create_file(&self, path: &Path, executable: bool, contents: &[u8]) -> io::Result<()>;
+
+    fn create_symlink(&self, path: &Path, target: &Path) -> io::Result<()>;
+
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    /// Removes an empty directory.
+    fn remove_dir(&self, path: &Path) -> io::Result<()>;
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    fn set_times(&self, path: &Path, accessed: FileTime, modified: FileTime) -> io::Result<()>;
+
+    fn xattrs(&self, path: &Path) -> io::Result<Vec<OsString>>;
+
+    fn remove_xattr(&self, path: &Path, name: &OsStr) -> io::Result<()>;
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum FileKind {
+    Directory,
+    File,
+    Symlink,
+    /// Sockets, FIFOs and device nodes.
+    Other,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FileMetadata {
+    kind: FileKind,
+    executable: bool,
+    accessed: FileTime,
+    modified: FileTime,
+    created: Option<FileTime>,
+}
+
+impl FileMetadata {
+    pub fn new(kind: FileKind, accessed: FileTime, modified: FileTime) -> Self {
+        FileMetadata {
+            kind,
+            executable: false,
+            accessed,
+            modified,
+            created: None,
+        }
+    }
+
+    pub fn with_executable(mut self, executable: bool) -> Self {
+        self.executable = executable;
+        self
+    }
+
+    pub fn with_created(mut self, created: FileTime) -> Self {
+        self.created = Some(created);
+        self
+    }
+
+    #[inline]
+    pub fn kind(&self) -> FileKind {
+        self.kind
+    }
+
+    #[inline]
+    pub fn is_dir(&self) -> bool {
+        self.kind == FileKind::Directory
+    }
+
+    #[inline]
+    pub fn is_symlink(&self) -> bool {
+        self.kind == FileKind::Symlink
+    }
+
+    /// Whether a regular file has any executable bit set.
+    #[inline]
+    pub fn executable(&self) -> bool {
+        self.executable
+    }
+
+    #[inline]
+    pub fn accessed(&self) -> FileTime {
+        self.accessed
+    }
+
+    #[inline]
+    pub fn modified(&self) -> FileTime {
+        self.modified
+    }
+
+    /// The creation time, on platforms and filesystems that record one.
+    #[inline]
+    pub fn created(&self) -> Option<FileTime> {
+        self.created
+    }
+}
+
+/// The host filesystem, used unless another one is configured.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct RealFilesystem;
+
+impl Filesystem for RealFilesystem {
+    fn metadata(&self, path: &Path) -> io::Result<FileMetadata> {
+        let metadata = fs::symlink_metadata(path)?;
+        let file_type = metadata.file_type();
+        let kind = if file_type.is_dir() {
+            FileKind::Directory
+        } else if file_type.is_file() {
+            FileKind::File
+        } else if file_type.is_symlink() {
+            FileKind::Symlink
+        } else {
+            FileKind::Other
+        };
+
+        Ok(FileMetadata {
+            kind,
+            executable: kind == FileKind::File && metadata.permissions().mode() & 0o111 != 0,
+            accessed: FileTime::from_last_access_time(&metadata),
+            modified: FileTime::from_last_modification_time(&metadata),
+            created: FileTime::from_creation_time(&metadata),
+        })
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir(path)
+    }
+
+    fn read_file(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<OsString>> {
+        let mut names = fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<io::Result<Vec<_>>>()?;
+        names.sort();
+        Ok(names)
+    }
+
+    fn create_file(&self, path: &Path, executable: bool, contents: &[u8]) -> io::Result<()> {
+        let mode = if executable { 0o555 } else { 0o444 };
+        let mut file = OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .mode(mode)
+            .open(path)?;
+        file.write_all(contents)
+    }
+
+    fn create_symlink(&self, path: &Path, target: &Path) -> io::Result<()> {
+        std::os::unix::fs::symlink(target, path)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir_all(path)
+    }
+
+    fn set_times(&self, path: &Path, accessed: FileTime, modified: FileTime) -> io::Result<()> {
+        filetime::set_symlink_file_times(path, accessed, modified)
+    }
+
+    #[cfg(all(unix, feature = "xattr"))]
+    fn xattrs(&self, path: &Path) -> io::Result<Vec<OsString>> {
+        Ok(xattr::list(path)?.collect())
+    }
+
+    #[cfg(not(all(unix, feature = "xattr")))]
+    fn xattrs(&self, _path: &Path) -> io::Result<Vec<OsString>> {
+        Ok(Vec::new())
+    }
+
+    #[cfg(all(unix, feature = "xattr"))]
+    fn remove_xattr(&self, path: &Path, name: &OsStr) -> io::Result<()> {
+        xattr::remove(path, name)
+    }
+
+    #[cfg(not(all(unix, feature = "xattr")))]
+    fn remove_xattr(&self, _path: &Path, _name: &OsStr) -> io::Result<()> {
+        Ok(())
+    }
+}
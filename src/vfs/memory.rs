@@ -0,0 +1,236 @@
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::io::{self, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use filetime::FileTime;
+
+use super::{FileKind, FileMetadata, Filesystem};
+
+/// A filesystem held entirely in memory, for testing extraction without touching the disk.
+///
+/// Clones share the same contents, so a clone can be handed to an archive and inspected once
+/// unpacking is done. Paths whose parent is empty or `/` can always be created; any other path
+/// needs its parent to exist as a directory. New nodes are stamped with the time they were
+/// created.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryFilesystem {
+    nodes: Arc<Mutex<BTreeMap<PathBuf, MemoryNode>>>,
+}
+
+#[derive(Clone, Debug)]
+struct MemoryNode {
+    contents: Contents,
+    metadata: FileMetadata,
+    xattrs: BTreeMap<OsString, Vec<u8>>,
+}
+
+#[derive(Clone, Debug)]
+enum Contents {
+    Directory,
+    File { executable: bool, data: Vec<u8> },
+    Symlink { target: PathBuf },
+}
+
+impl MemoryFilesystem {
+    pub fn new() -> Self {
+        MemoryFilesystem::default()
+    }
+
+    /// Every path in the filesystem, sorted.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.lock().keys().cloned().collect()
+    }
+
+    /// The contents and executable bit of the file at `path`.
+    pub fn file(&self, path: &Path) -> Option<(Vec<u8>, bool)> {
+        match &self.lock().get(path)?.contents {
+            Contents::File { executable, data } => Some((data.clone(), *executable)),
+            _ => None,
+        }
+    }
+
+    pub fn read_link(&self, path: &Path) -> Option<PathBuf> {
+        match &self.lock().get(path)?.contents {
+            Contents::Symlink { target } => Some(target.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn set_xattr(&self, path: &Path, name: &OsStr, value: &[u8]) -> io::Result<()> {
+        let mut nodes = self.lock();
+        let node = nodes.get_mut(path).ok_or_else(|| not_found(path))?;
+        node.xattrs.insert(name.to_owned(), value.to_owned());
+        Ok(())
+    }
+
+    fn insert(&self, path: &Path, contents: Contents, metadata: FileMetadata) -> io::Result<()> {
+        let mut nodes = self.lock();
+        if nodes.contains_key(path) {
+            let message = format!("{} already exists", path.display());
+            return Err(Error::new(ErrorKind::AlreadyExists, message));
+        }
+
+        let parent = path.parent().filter(|p| !p.as_os_str().is_empty());
+        if let Some(parent) = parent.filter(|p| p.parent().is_some()) {
+            match nodes.get(parent) {
+                Some(node) if node.metadata.is_dir() => {}
+                Some(_) => {
+                    let message = format!("{} is not a directory", parent.display());
+                    return Err(Error::new(ErrorKind::NotADirectory, message));
+                }
+                None => return Err(not_found(parent)),
+            }
+        }
+
+        let node = MemoryNode {
+            contents,
+            metadata,
+            xattrs: BTreeMap::new(),
+        };
+        nodes.insert(path.to_owned(), node);
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path, recursive: bool, expect_dir: bool) -> io::Result<()> {
+        let mut nodes = self.lock();
+        let is_dir = match nodes.get(path) {
+            Some(node) => node.metadata.is_dir(),
+            None => return Err(not_found(path)),
+        };
+        if is_dir != expect_dir {
+            let (kind, message) = if is_dir {
+                (ErrorKind::IsADirectory, "is a directory")
+            } else {
+                (ErrorKind::NotADirectory, "is not a directory")
+            };
+            return Err(Error::new(kind, format!("{} {}", path.display(), message)));
+        }
+
+        let descendants: Vec<PathBuf> = nodes
+            .keys()
+            .filter(|p| p.as_path() != path && p.starts_with(path))
+            .cloned()
+            .collect();
+        if !descendants.is_empty() && !recursive {
+            let message = format!("{} is not empty", path.display());
+            return Err(Error::new(ErrorKind::DirectoryNotEmpty, message));
+        }
+
+        for descendant in descendants {
+            nodes.remove(&descendant);
+        }
+        nodes.remove(path);
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<PathBuf, MemoryNode>> {
+        self.nodes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Filesystem for MemoryFilesystem {
+    fn metadata(&self, path: &Path) -> io::Result<FileMetadata> {
+        let nodes = self.lock();
+        let node = nodes.get(path).ok_or_else(|| not_found(path))?;
+        Ok(node.metadata)
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        self.insert(path, Contents::Directory, new_metadata(FileKind::Directory))
+    }
+
+    fn read_file(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match self.file(path) {
+            Some((data, _)) => Ok(data),
+            None => {
+                let message = format!("{} is not a file", path.display());
+                Err(Error::new(ErrorKind::NotFound, message))
+            }
+        }
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<OsString>> {
+        let nodes = self.lock();
+        match nodes.get(path) {
+            Some(node) if node.metadata.is_dir() => {}
+            Some(_) => {
+                let message = format!("{} is not a directory", path.display());
+                return Err(Error::new(ErrorKind::NotADirectory, message));
+            }
+            None => return Err(not_found(path)),
+        }
+
+        let names = nodes
+            .keys()
+            .filter(|p| p.parent() == Some(path))
+            .filter_map(|p| p.file_name().map(OsStr::to_owned))
+            .collect();
+        Ok(names)
+    }
+
+    fn create_file(&self, path: &Path, executable: bool, contents: &[u8]) -> io::Result<()> {
+        let contents = Contents::File {
+            executable,
+            data: contents.to_owned(),
+        };
+        let metadata = new_metadata(FileKind::File).with_executable(executable);
+        self.insert(path, contents, metadata)
+    }
+
+    fn create_symlink(&self, path: &Path, target: &Path) -> io::Result<()> {
+        let contents = Contents::Symlink {
+            target: target.to_owned(),
+        };
+        self.insert(path, contents, new_metadata(FileKind::Symlink))
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.remove(path, false, false)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        self.remove(path, false, true)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.remove(path, true, true)
+    }
+
+    fn set_times(&self, path: &Path, accessed: FileTime, modified: FileTime) -> io::Result<()> {
+        let mut nodes = self.lock();
+        let node = nodes.get_mut(path).ok_or_else(|| not_found(path))?;
+        node.metadata.accessed = accessed;
+        node.metadata.modified = modified;
+        Ok(())
+    }
+
+    fn xattrs(&self, path: &Path) -> io::Result<Vec<OsString>> {
+        let nodes = self.lock();
+        let node = nodes.get(path).ok_or_else(|| not_found(path))?;
+        Ok(node.xattrs.keys().cloned().collect())
+    }
+
+    fn remove_xattr(&self, path: &Path, name: &OsStr) -> io::Result<()> {
+        let mut nodes = self.lock();
+        let node = nodes.get_mut(path).ok_or_else(|| not_found(path))?;
+        match node.xattrs.remove(name) {
+            Some(_) => Ok(()),
+            None => {
+                let message = format!("No attribute {:?} on {}", name, path.display());
+                Err(Error::new(ErrorKind::NotFound, message))
+            }
+        }
+    }
+}
+
+fn new_metadata(kind: FileKind) -> FileMetadata {
+    let now = FileTime::now();
+    FileMetadata::new(kind, now, now).with_created(now)
+}
+
+fn not_found(path: &Path) -> Error {
+    let message = format!("{} does not exist", path.display());
+    Error::new(ErrorKind::NotFound, message)
+}
@@ -0,0 +1,50 @@
+use std::ffi::OsString;
+use std::fmt::{self, Display, Formatter};
+use std::path::PathBuf;
+
+/// A policy violation that was tolerated rather than treated as an error.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Warning {
+    /// An extended attribute was removed from an unpacked path.
+    XattrDropped { path: PathBuf, name: OsString },
+    /// A socket, FIFO or device node was left out of an archive being packed.
+    SpecialFileSkipped { path: PathBuf },
+    /// An entry's name differs only in case from a sibling unpacked before it.
+    CaseCollision { path: PathBuf, unpacked_as: PathBuf },
+    /// A directory entry of a lenient archive did not sort after its predecessor.
+    NonCanonicalOrder { path: PathBuf },
+    /// A symlink could not be replaced by a copy of its target and was left out.
+    SymlinkSkipped { path: PathBuf, target: PathBuf },
+}
+
+impl Display for Warning {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        match self {
+            Warning::XattrDropped { path, name } => write!(
+                fmt,
+                "Dropped extended attribute {:?} from {}",
+                name,
+                path.display()
+            ),
+            Warning::SpecialFileSkipped { path } => {
+                write!(fmt, "Skipped special file {}", path.display())
+            }
+            Warning::CaseCollision { path, unpacked_as } => write!(
+                fmt,
+                "Entry {} collides with a sibling differing only in case, unpacked as {}",
+                path.display(),
+                unpacked_as.display()
+            ),
+            Warning::NonCanonicalOrder { path } => {
+                write!(fmt, "Entry {} is out of canonical order", path.display())
+            }
+            Warning::SymlinkSkipped { path, target } => write!(
+                fmt,
+                "Left out symlink {} because its target {} could not be copied",
+                path.display(),
+                target.display()
+            ),
+        }
+    }
+}
@@ -0,0 +1,345 @@
+use std::io::{self, Error, ErrorKind, Read, Write};
+
+use crate::{NIX_VERSION_MAGIC, PAD_LEN};
+
+pub use self::validator::{FramingError, Validator};
+
+mod validator;
+
+/// Size of the buffer file contents are streamed through unless configured otherwise.
+pub(crate) const DEFAULT_COPY_BUFFER_LEN: usize = 64 * 1024;
+
+/// Upper bound on tokens that are never file contents, such as tags, symlink targets and store
+/// paths, for readers without configurable limits.
+pub(crate) const MAX_METADATA_TOKEN_LEN: u64 = 64 * 1024;
+
+pub const fn pad_len(len: u64) -> usize {
+    (PAD_LEN - (len % PAD_LEN as u64) as usize) % PAD_LEN
+}
+
+pub const fn encoded_len(payload_len: u64) -> u64 {
+    PAD_LEN as u64 + payload_len + pad_len(payload_len) as u64
+}
+
+/// Like [`encoded_len`], but returns `None` instead of overflowing on a length read from untrusted
+/// input.
+pub const fn checked_encoded_len(payload_len: u64) -> Option<u64> {
+    payload_len.checked_add((PAD_LEN + pad_len(payload_len)) as u64)
+}
+
+/// Encoded length of a token whose length prefix was read from untrusted input.
+pub(crate) fn untrusted_encoded_len(payload_len: u64) -> io::Result<u64> {
+    checked_encoded_len(payload_len).ok_or_else(|| {
+        let message = format!("Token length {} is out of range", payload_len);
+        Error::new(ErrorKind::InvalidData, message)
+    })
+}
+
+pub const fn header_len() -> u64 {
+    encoded_len(NIX_VERSION_MAGIC.len() as u64)
+}
+
+pub const fn regular_node_len(size: u64, executable: bool) -> u64 {
+    let executable_len = if executable {
+        tag_len(b"executable") + tag_len(b"")
+    } else {
+        0
+    };
+
+    tag_len(b"(")
+        + tag_len(b"type")
+        + tag_len(b"regular")
+        + executable_len
+        + tag_len(b"contents")
+        + encoded_len(size)
+        + tag_len(b")")
+}
+
+pub const fn symlink_node_len(target_len: u64) -> u64 {
+    tag_len(b"(")
+        + tag_len(b"type")
+        + tag_len(b"symlink")
+        + tag_len(b"target")
+        + encoded_len(target_len)
+        + tag_len(b")")
+}
+
+pub const fn directory_node_len(entries_len: u64) -> u64 {
+    tag_len(b"(") + tag_len(b"type") + tag_len(b"directory") + entries_len + tag_len(b")")
+}
+
+pub const fn directory_entry_len(name_len: u64, node_len: u64) -> u64 {
+    tag_len(b"entry")
+        + tag_len(b"(")
+        + tag_len(b"name")
+        + encoded_len(name_len)
+        + tag_len(b"node")
+        + node_len
+        + tag_len(b")")
+}
+
+const fn tag_len(tag: &[u8]) -> u64 {
+    encoded_len(tag.len() as u64)
+}
+
+/// Reads a token of at most `max_len` bytes. Longer tokens fail with [`ErrorKind::InvalidData`]
+/// before anything is allocated for them, and the buffer only grows as bytes actually arrive.
+pub fn read_token<R: Read + ?Sized>(reader: &mut R, max_len: u64) -> io::Result<Vec<u8>> {
+    let mut len_buffer = [0u8; PAD_LEN];
+    reader.read_exact(&mut len_buffer[..])?;
+    let len = u64::from_le_bytes(len_buffer);
+    if len > max_len {
+        let message = format!("Token of {} bytes exceeds the limit of {}", len, max_len);
+        return Err(Error::new(ErrorKind::InvalidData, message));
+    }
+
+    let mut data_buffer = Vec::new();
+    (&mut *reader).take(len).read_to_end(&mut data_buffer)?;
+    if (data_buffer.len() as u64) < len {
+        let message = format!(
+            "Archive ended {} bytes into a {}-byte token",
+            data_buffer.len(),
+            len
+        );
+        return Err(Error::new(ErrorKind::UnexpectedEof, message));
+    }
+    read_padding(reader, len)?;
+
+    Ok(data_buffer)
+}
+
+pub fn write_token<W: Write + ?Sized>(writer: &mut W, bytes: &[u8]) -> io::Result<()> {
+    let len = bytes.len() as u64;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(bytes)?;
+    write_padding(writer, len)
+}
+
+pub(crate) fn write_token_from_reader<W, R>(
+    writer: &mut W,
+    reader: &mut R,
+    len: u64,
+    buffer_len: usize,
+) -> io::Result<()>
+where
+    W: Write + ?Sized,
+    R: Read + ?Sized,
+{
+    writer.write_all(&len.to_le_bytes())?;
+    let copied = copy_buffered(&mut reader.take(len), writer, buffer_len)?;
+    if copied != len {
+        let message = format!("Source ended after {} of {} bytes", copied, len);
+        return Err(Error::new(ErrorKind::UnexpectedEof, message));
+    }
+    write_padding(writer, len)
+}
+
+/// Like `io::copy`, but through a buffer of `buffer_len` bytes instead of its fixed 8 KiB.
+pub(crate) fn copy_buffered<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    buffer_len: usize,
+) -> io::Result<u64>
+where
+    R: Read + ?Sized,
+    W: Write + ?Sized,
+{
+    let mut buffer = vec![0; buffer_len.max(1)];
+    let mut copied = 0;
+    loop {
+        let len = match reader.read(&mut buffer) {
+            Ok(0) => return Ok(copied),
+            Ok(len) => len,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&buffer[..len])?;
+        copied += len as u64;
+    }
+}
+
+/// Reads the length prefix of the next token, or returns `None` on a clean end of input.
+pub(crate) fn read_len_prefix<R: Read + ?Sized>(reader: &mut R) -> io::Result<Option<u64>> {
+    let mut prefix = [0u8; PAD_LEN];
+    let mut filled = 0;
+    while filled < PAD_LEN {
+        match reader.read(&mut prefix[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(Error::new(ErrorKind::UnexpectedEof, "Truncated token")),
+            Ok(n) => filled += n,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(Some(u64::from_le_bytes(prefix)))
+}
+
+pub(crate) fn read_padding<R: Read + ?Sized>(reader: &mut R, len: u64) -> io::Result<()> {
+    let mut buffer = [0u8; PAD_LEN];
+    let padding = &mut buffer[..pad_len(len)];
+    reader.read_exact(padding)?;
+    if !padding.iter().all(|b| *b == 0) {
+        return Err(Error::other("Bad archive padding"));
+    }
+
+    Ok(())
+}
+
+pub(crate) fn write_padding<W: Write + ?Sized>(writer: &mut W, len: u64) -> io::Result<()> {
+    let buf = [0u8; PAD_LEN];
+    writer.write_all(&buf[..pad_len(len)])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem::size_of;
+
+    use super::*;
+
+    #[test]
+    fn computes_padding_length() {
+        assert_eq!(pad_len(0), 0);
+        assert_eq!(pad_len(1), 7);
+        assert_eq!(pad_len(8), 0);
+        assert_eq!(pad_len(13), 3);
+    }
+
+    #[test]
+    fn computes_encoded_length() {
+        assert_eq!(encoded_len(0), 8);
+        assert_eq!(encoded_len(1), 16);
+        assert_eq!(encoded_len(8), 16);
+        assert_eq!(encoded_len(13), 24);
+        assert_eq!(header_len(), 24);
+    }
+
+    #[test]
+    fn checks_encoded_length_for_overflow() {
+        assert_eq!(checked_encoded_len(13), Some(24));
+        assert_eq!(checked_encoded_len(u64::MAX - 15), Some(u64::MAX - 7));
+        assert_eq!(checked_encoded_len(u64::MAX - 2), None);
+        assert_eq!(checked_encoded_len(u64::MAX), None);
+    }
+
+    #[test]
+    fn node_lengths_match_encoding() {
+        let mut buffer = Vec::new();
+        for token in &[
+            &b"("[..],
+            b"type",
+            b"regular",
+            b"executable",
+            b"",
+            b"contents",
+        ] {
+            write_token(&mut buffer, token).unwrap();
+        }
+        write_token(&mut buffer, b"hello world").unwrap();
+        write_token(&mut buffer, b")").unwrap();
+        assert_eq!(regular_node_len(11, true), buffer.len() as u64);
+
+        let mut buffer = Vec::new();
+        for token in &[&b"("[..], b"type", b"symlink", b"target", b"./foo", b")"] {
+            write_token(&mut buffer, token).unwrap();
+        }
+        assert_eq!(symlink_node_len(5), buffer.len() as u64);
+
+        let mut buffer = Vec::new();
+        let tokens: &[&[u8]] = &[
+            b"(",
+            b"type",
+            b"directory",
+            b"entry",
+            b"(",
+            b"name",
+            b"foo",
+            b"node",
+            b"(",
+            b"type",
+            b"symlink",
+            b"target",
+            b"./foo",
+            b")",
+            b")",
+            b")",
+        ];
+        for token in tokens {
+            write_token(&mut buffer, token).unwrap();
+        }
+        let entry_len = directory_entry_len(3, symlink_node_len(5));
+        assert_eq!(directory_node_len(entry_len), buffer.len() as u64);
+    }
+
+    #[test]
+    fn writes_multiple_of_eight_exactly() {
+        let mut buffer = Vec::new();
+        let length = 16u64;
+        let data = vec![1u8; length as usize];
+        write_token(&mut buffer, &data[..]).unwrap();
+
+        let written_data_len = size_of::<u64>() as u64 + length;
+        assert_eq!(buffer.len() as u64, written_data_len);
+
+        let header_bytes = length.to_le_bytes();
+        assert_eq!(&buffer[..size_of::<u64>()], header_bytes);
+
+        let data_bytes = [1u8; 16];
+        assert_eq!(&buffer[size_of::<u64>()..], data_bytes);
+    }
+
+    #[test]
+    fn pads_non_multiple_of_eight() {
+        let mut buffer = Vec::new();
+        let length = 5u64;
+        let data = vec![1u8; length as usize];
+        write_token(&mut buffer, &data[..]).unwrap();
+
+        let written_data_len = size_of::<u64>() as u64 + length + 3;
+        assert_eq!(buffer.len() as u64, written_data_len);
+
+        let header_bytes = length.to_le_bytes();
+        assert_eq!(&buffer[..size_of::<u64>()], header_bytes);
+
+        let data_bytes = [1u8; 5];
+        assert_eq!(&buffer[size_of::<u64>()..size_of::<u64>() + 5], data_bytes);
+
+        let padding_bytes = [0u8; 3];
+        assert_eq!(&buffer[size_of::<u64>() + 5..], padding_bytes);
+    }
+
+    #[test]
+    fn reads_written_token() {
+        let mut buffer = Vec::new();
+        write_token(&mut buffer, b"nix-archive-1").unwrap();
+        write_token(&mut buffer, b"").unwrap();
+
+        let mut reader = &buffer[..];
+        assert_eq!(read_token(&mut reader, 13).unwrap(), b"nix-archive-1");
+        assert_eq!(read_token(&mut reader, 0).unwrap(), b"");
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn rejects_nonzero_padding() {
+        let mut buffer = Vec::new();
+        write_token(&mut buffer, b"(").unwrap();
+        *buffer.last_mut().unwrap() = 1;
+
+        let err = read_token(&mut &buffer[..], 1).unwrap_err();
+        assert_eq!(err.to_string(), "Bad archive padding");
+    }
+
+    #[test]
+    fn rejects_oversized_and_truncated_tokens() {
+        let buffer = u64::MAX.to_le_bytes();
+        let err = read_token(&mut &buffer[..], 1024).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let mut buffer = Vec::new();
+        write_token(&mut buffer, b"nix-archive-1").unwrap();
+        let err = read_token(&mut &buffer[..], 12).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let err = read_token(&mut &buffer[..12], 13).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+}
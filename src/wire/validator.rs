@@ -0,0 +1,349 @@
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Error, ErrorKind, Read};
+
+use crate::PAD_LEN;
+
+/// Checks the framing of a stream of length-prefixed tokens without interpreting them, e.g. NAR
+/// data embedded in a larger daemon protocol message.
+///
+/// Every token is a little-endian `u64` length, that many payload bytes and then zero bytes up
+/// to the next multiple of the alignment. Input can be fed in chunks of any size, and errors
+/// report the offset of the offending byte and the token it belongs to.
+#[derive(Clone, Debug)]
+pub struct Validator {
+    alignment: u64,
+    max_token_len: Option<u64>,
+    state: State,
+    offset: u64,
+    tokens: u64,
+    token_offset: u64,
+    error: Option<FramingError>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum State {
+    Prefix { bytes: [u8; 8], filled: usize },
+    Payload { remaining: u64 },
+    Padding { remaining: u64 },
+}
+
+impl Validator {
+    /// Creates a validator for tokens padded to multiples of 8 bytes, as in NAR.
+    pub fn new() -> Self {
+        Validator::with_alignment(PAD_LEN as u64)
+    }
+
+    /// Creates a validator for tokens padded to a multiple of `alignment` bytes, where an
+    /// alignment of 1 means no padding at all.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `alignment` is zero.
+    pub fn with_alignment(alignment: u64) -> Self {
+        assert!(alignment > 0, "alignment must not be zero");
+        Validator {
+            alignment,
+            max_token_len: None,
+            state: State::Prefix {
+                bytes: [0; 8],
+                filled: 0,
+            },
+            offset: 0,
+            tokens: 0,
+            token_offset: 0,
+            error: None,
+        }
+    }
+
+    /// Rejects tokens longer than `len`, which catches a stream that has lost its framing long
+    /// before the bogus length would be exhausted.
+    pub fn set_max_token_len(&mut self, len: u64) {
+        self.max_token_len = Some(len);
+    }
+
+    #[inline]
+    pub fn alignment(&self) -> u64 {
+        self.alignment
+    }
+
+    #[inline]
+    pub fn max_token_len(&self) -> Option<u64> {
+        self.max_token_len
+    }
+
+    /// The number of complete tokens seen so far.
+    #[inline]
+    pub fn tokens(&self) -> u64 {
+        self.tokens
+    }
+
+    /// The number of bytes fed so far.
+    #[inline]
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Whether the input so far ends on a token boundary.
+    pub fn is_at_boundary(&self) -> bool {
+        matches!(self.state, State::Prefix { filled: 0, .. })
+    }
+
+    /// Checks the next chunk of the stream. Once an error has been reported, it is returned
+    /// again for any further input.
+    pub fn feed(&mut self, mut input: &[u8]) -> Result<(), FramingError> {
+        if let Some(error) = &self.error {
+            return Err(error.clone());
+        }
+
+        while !input.is_empty() {
+            let consumed = match &mut self.state {
+                State::Prefix { bytes, filled } => {
+                    let n = input.len().min(bytes.len() - *filled);
+                    bytes[*filled..*filled + n].copy_from_slice(&input[..n]);
+                    *filled += n;
+                    if *filled == bytes.len() {
+                        let len = u64::from_le_bytes(*bytes);
+                        if let Some(max) = self.max_token_len.filter(|max| len > *max) {
+                            let message = format!("Token length {} exceeds limit of {}", len, max);
+                            return Err(self.fail(message, self.token_offset));
+                        }
+                        self.state = State::Payload { remaining: len };
+                        self.finish_payload(len);
+                    }
+                    n
+                }
+                State::Payload { remaining } => {
+                    let n = (input.len() as u64).min(*remaining);
+                    *remaining -= n;
+                    if *remaining == 0 {
+                        let len = self.offset + n - self.token_offset - 8;
+                        self.finish_payload(len);
+                    }
+                    n as usize
+                }
+                State::Padding { remaining } => {
+                    let n = (input.len() as u64).min(*remaining) as usize;
+                    if let Some(i) = input[..n].iter().position(|&b| b != 0) {
+                        let message = format!("Non-zero padding byte {:#04x}", input[i]);
+                        return Err(self.fail(message, self.offset + i as u64));
+                    }
+                    *remaining -= n as u64;
+                    if *remaining == 0 {
+                        self.finish_token(self.offset + n as u64);
+                    }
+                    n
+                }
+            };
+
+            self.offset += consumed as u64;
+            input = &input[consumed..];
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the stream ended on a token boundary.
+    pub fn finish(&mut self) -> Result<(), FramingError> {
+        if let Some(error) = &self.error {
+            return Err(error.clone());
+        }
+
+        let missing = match self.state {
+            State::Prefix { filled: 0, .. } => return Ok(()),
+            State::Prefix { filled, .. } => format!("{} length prefix bytes", 8 - filled),
+            State::Payload { remaining } => format!("{} payload bytes", remaining),
+            State::Padding { remaining } => format!("{} padding bytes", remaining),
+        };
+        let message = format!("Stream ends in the middle of a token, missing {}", missing);
+        let mut error = self.fail(message, self.offset);
+        error.truncated = true;
+        self.error = Some(error.clone());
+        Err(error)
+    }
+
+    /// Feeds everything `reader` yields and checks that it ends on a token boundary, returning
+    /// the number of tokens.
+    pub fn validate<R: Read>(mut self, mut reader: R) -> io::Result<u64> {
+        let mut buffer = [0; 8192];
+        loop {
+            match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => self.feed(&buffer[..n])?,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        self.finish()?;
+        Ok(self.tokens)
+    }
+
+    fn finish_payload(&mut self, len: u64) {
+        if let State::Payload { remaining: 0 } = self.state {
+            let padding = (self.alignment - len % self.alignment) % self.alignment;
+            self.state = State::Padding { remaining: padding };
+            if padding == 0 {
+                let end = self.token_offset + 8 + len;
+                self.finish_token(end);
+            }
+        }
+    }
+
+    fn finish_token(&mut self, end: u64) {
+        self.tokens += 1;
+        self.token_offset = end;
+        self.state = State::Prefix {
+            bytes: [0; 8],
+            filled: 0,
+        };
+    }
+
+    fn fail(&mut self, message: String, offset: u64) -> FramingError {
+        let error = FramingError {
+            message,
+            offset,
+            token: self.tokens,
+            token_offset: self.token_offset,
+            truncated: false,
+        };
+        self.error = Some(error.clone());
+        error
+    }
+}
+
+impl Default for Validator {
+    fn default() -> Self {
+        Validator::new()
+    }
+}
+
+/// A framing violation found by a [`Validator`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FramingError {
+    message: String,
+    offset: u64,
+    token: u64,
+    token_offset: u64,
+    truncated: bool,
+}
+
+impl FramingError {
+    /// Byte offset of the offending byte, or of the end of the stream if it was truncated.
+    #[inline]
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Index of the token containing the offending byte.
+    #[inline]
+    pub fn token(&self) -> u64 {
+        self.token
+    }
+
+    /// Byte offset of the length prefix of that token.
+    #[inline]
+    pub fn token_offset(&self) -> u64 {
+        self.token_offset
+    }
+
+    #[inline]
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+}
+
+impl Display for FramingError {
+    fn fmt(&self, fmt: &mut Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "{} at offset {} (token {} at offset {})",
+            self.message, self.offset, self.token, self.token_offset
+        )
+    }
+}
+
+impl std::error::Error for FramingError {}
+
+impl From<FramingError> for Error {
+    fn from(err: FramingError) -> Self {
+        let kind = if err.truncated {
+            ErrorKind::UnexpectedEof
+        } else {
+            ErrorKind::InvalidData
+        };
+        Error::new(kind, err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wire::write_token;
+
+    fn tokens(tokens: &[&[u8]]) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        for token in tokens {
+            write_token(&mut buffer, token).unwrap();
+        }
+        buffer
+    }
+
+    #[test]
+    fn accepts_well_formed_streams_in_any_chunking() {
+        let stream = tokens(&[b"nix-archive-1", b"", b"(", b"exactly8"]);
+        assert_eq!(Validator::new().validate(&stream[..]).unwrap(), 4);
+
+        for chunk_len in 1..stream.len() {
+            let mut validator = Validator::new();
+            for chunk in stream.chunks(chunk_len) {
+                validator.feed(chunk).unwrap();
+            }
+            validator.finish().unwrap();
+            assert_eq!(validator.tokens(), 4);
+            assert!(validator.is_at_boundary());
+        }
+    }
+
+    #[test]
+    fn pinpoints_bad_padding() {
+        let mut stream = tokens(&[b"type", b"regular"]);
+        stream[31] = 0xff;
+
+        let mut validator = Validator::new();
+        let error = validator.feed(&stream).unwrap_err();
+        assert_eq!(error.offset(), 31);
+        assert_eq!(error.token(), 1);
+        assert_eq!(error.token_offset(), 16);
+        assert_eq!(
+            error.to_string(),
+            "Non-zero padding byte 0xff at offset 31 (token 1 at offset 16)"
+        );
+        assert_eq!(validator.feed(b"").unwrap_err(), error);
+        assert_eq!(validator.finish().unwrap_err(), error);
+    }
+
+    #[test]
+    fn reports_truncation_and_oversized_tokens() {
+        let stream = tokens(&[b"contents"]);
+        let error = Validator::new().validate(&stream[..12]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+        assert!(error.to_string().contains("missing 4 payload bytes"));
+
+        let mut validator = Validator::new();
+        validator.set_max_token_len(4);
+        let error = validator.feed(&stream).unwrap_err();
+        assert_eq!(error.offset(), 0);
+        assert!(!error.is_truncated());
+    }
+
+    #[test]
+    fn supports_other_alignments() {
+        let mut stream = 3u64.to_le_bytes().to_vec();
+        stream.extend_from_slice(b"abc\0");
+        assert_eq!(
+            Validator::with_alignment(4).validate(&stream[..]).unwrap(),
+            1
+        );
+        assert!(Validator::with_alignment(1).validate(&stream[..]).is_err());
+        assert!(Validator::new().validate(&stream[..]).is_err());
+    }
+}
@@ -0,0 +1,75 @@
+use libnar::analysis::max_symlink_chain;
+use libnar::tree::NarTree;
+
+#[test]
+fn measures_symlink_chains_and_loops() {
+    let nar = NarTree::builder()
+        .dir("bin", |d| {
+            d.file("tool", "", true)
+                .symlink("a", "tool")
+                .symlink("b", "a")
+                .symlink("c", "../lib/b")
+        })
+        .dir("lib", |d| d.symlink("b", "../bin/b"))
+        .symlink("self", "self")
+        .symlink("grow", "grow/x")
+        .symlink("ping", "pong")
+        .symlink("pong", "ping")
+        .symlink("etc", "/etc/passwd")
+        .symlink("dangling", "missing")
+        .build()
+        .unwrap()
+        .to_vec();
+
+    let chains = max_symlink_chain(&nar[..]).unwrap();
+    let lens: Vec<_> = chains
+        .chains()
+        .iter()
+        .map(|(path, len)| (path.as_str(), *len))
+        .collect();
+    assert_eq!(
+        lens,
+        [
+            ("bin/a", 1),
+            ("bin/b", 2),
+            ("bin/c", 4),
+            ("dangling", 1),
+            ("etc", 1),
+            ("lib/b", 3),
+        ]
+    );
+    assert_eq!(chains.loops(), ["grow", "ping", "pong", "self"]);
+    assert_eq!(chains.longest(), Some(("bin/c", 4)));
+
+    let err = chains.check(10).unwrap_err();
+    assert_eq!(err.to_string(), "Symlink \"grow\" never resolves");
+
+    let nar = NarTree::builder()
+        .symlink("a", "b")
+        .symlink("b", "c")
+        .file("c", "", false)
+        .build()
+        .unwrap()
+        .to_vec();
+    let chains = max_symlink_chain(&nar[..]).unwrap();
+    assert!(chains.check(2).is_ok());
+    assert!(chains.check(1).is_err());
+}
+
+/// An archive holding a symlink whose target is not valid UTF-8.
+fn non_utf8_symlink_nar() -> Vec<u8> {
+    let mut nar = NarTree::builder()
+        .symlink("link", "bad?")
+        .build()
+        .unwrap()
+        .to_vec();
+    let at = nar.windows(4).position(|token| token == b"bad?").unwrap();
+    nar[at + 3] = 0xff;
+    nar
+}
+
+#[test]
+fn rejects_non_utf8_symlink_targets() {
+    let err = max_symlink_chain(&non_utf8_symlink_nar()[..]).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
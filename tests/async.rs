@@ -0,0 +1,159 @@
+#![cfg(feature = "tokio")]
+
+use libnar::de::{AsyncArchive, ParseError, ParseLimits, UnpackOptions};
+use libnar::tree::NarTree;
+use libnar::{Archive, MemoryBudget};
+
+fn sample() -> Vec<u8> {
+    NarTree::builder()
+        .file("bin", b"#!/bin/sh\n".to_vec(), true)
+        .dir("share", |dir| {
+            dir.file("a.txt", b"hello".to_vec(), false)
+                .dir("empty", |dir| dir)
+                .file("b.txt", vec![7; 10_000], false)
+        })
+        .symlink("link", "share/a.txt")
+        .build()
+        .unwrap()
+        .to_vec()
+}
+
+#[tokio::test]
+async fn reads_entries_from_async_reader() {
+    let nar = sample();
+    let mut archive = AsyncArchive::new(&nar[..]);
+    let mut entries = archive.entries().unwrap();
+    let mut items = Vec::new();
+    while let Some(entry) = entries.next().await {
+        let entry = entry.unwrap();
+        items.push((entry.name().to_owned(), format!("{:?}", entry.kind())));
+    }
+    assert!(entries.next().await.is_none());
+
+    let expected: Vec<_> = Archive::new(&nar[..])
+        .entries()
+        .unwrap()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            (entry.name().to_owned(), format!("{:?}", entry.kind()))
+        })
+        .collect();
+    assert_eq!(items, expected);
+    assert!(archive.take_warnings().is_empty());
+}
+
+#[tokio::test]
+async fn reports_the_same_errors_as_the_blocking_parser() {
+    let nar = sample();
+    let mut archive = AsyncArchive::new(&nar[..nar.len() - 20]);
+    let mut entries = archive.entries().unwrap();
+    let mut last = None;
+    while let Some(entry) = entries.next().await {
+        last = Some(entry);
+    }
+    assert!(last.unwrap().is_err());
+
+    let limits = ParseLimits::unlimited().with_max_dir_entries(Some(2));
+    let mut options = UnpackOptions::default();
+    options.set_parse_limits(limits);
+    let mut archive = AsyncArchive::with_options(&nar[..], options);
+    let mut entries = archive.entries().unwrap();
+    let err = loop {
+        match entries.next().await.unwrap() {
+            Ok(_) => continue,
+            Err(err) => break err,
+        }
+    };
+    let err = err.get_ref().unwrap().downcast_ref::<ParseError>().unwrap();
+    assert!(err.to_string().contains("more than 2 entries"));
+    assert!(entries.next().await.is_none());
+}
+
+#[tokio::test]
+async fn holds_file_contents_against_the_memory_budget() {
+    let nar = sample();
+    let budget = MemoryBudget::new(20_000);
+    let mut options = UnpackOptions::default();
+    options.set_memory_budget(budget.clone());
+    let mut archive = AsyncArchive::with_options(&nar[..], options);
+    let mut entries = archive.entries().unwrap();
+
+    let mut held = Vec::new();
+    while let Some(entry) = entries.next().await {
+        let entry = entry.unwrap();
+        if entry.name().ends_with("b.txt") {
+            assert!(budget.used() >= 10_000);
+        }
+        held.push(entry);
+    }
+    drop(held);
+    assert_eq!(budget.used(), 0);
+}
+
+#[tokio::test]
+async fn lists_seekable_reader_without_buffering_contents() {
+    let nar = sample();
+    let expected = Archive::new(&nar[..]).listing().unwrap();
+
+    // The budget is smaller than `share/b.txt`, so its contents must be seeked past.
+    let mut options = UnpackOptions::default();
+    options.set_memory_budget(MemoryBudget::new(1_000));
+    let reader = std::io::Cursor::new(nar.clone());
+    let mut archive = AsyncArchive::with_options(reader, options);
+    assert_eq!(archive.listing().await.unwrap(), expected);
+    assert_eq!(archive.into_inner().position(), nar.len() as u64);
+
+    let mut archive = AsyncArchive::new(std::io::Cursor::new(&nar[..nar.len() - 20]));
+    assert!(archive.listing().await.is_err());
+}
+
+#[cfg(feature = "fs")]
+#[tokio::test]
+async fn unpacks_from_async_reader() {
+    let nar = sample();
+    let dir = tempfile::tempdir().unwrap();
+    let dst = dir.path().join("out");
+
+    AsyncArchive::new(&nar[..]).unpack(&dst).await.unwrap();
+    assert_eq!(libnar::ser::to_vec(&dst).unwrap(), nar);
+    let mtime = std::fs::symlink_metadata(dst.join("share/a.txt"))
+        .unwrap()
+        .modified()
+        .unwrap();
+    assert_eq!(mtime, std::time::UNIX_EPOCH);
+
+    // Like the blocking unpacker, an existing empty directory is unpacked into.
+    let existing = dir.path().join("existing");
+    std::fs::create_dir(&existing).unwrap();
+    AsyncArchive::new(&nar[..]).unpack(&existing).await.unwrap();
+    assert_eq!(libnar::ser::to_vec(&existing).unwrap(), nar);
+}
+
+#[cfg(feature = "fs")]
+#[tokio::test]
+async fn packs_into_async_writer() {
+    let nar = sample();
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("src");
+    AsyncArchive::new(&nar[..]).unpack(&src).await.unwrap();
+    std::fs::write(src.join("share/b.txt"), vec![9; 100_000]).unwrap();
+    let expected = libnar::to_vec(&src).unwrap();
+
+    let mut out = Vec::new();
+    libnar::ser::to_async_writer(&mut out, &src).await.unwrap();
+    assert_eq!(out, expected);
+
+    let mut options = libnar::ser::PackOptions::new();
+    options.set_copy_buffer_len(7);
+    let mut out = Vec::new();
+    let warnings = options.to_async_writer(&mut out, &src).await.unwrap();
+    assert!(warnings.is_empty());
+    assert_eq!(out, expected);
+
+    let mut buffer = [0u8; 64];
+    let mut short = std::io::Cursor::new(&mut buffer[..]);
+    let err = libnar::ser::to_async_writer(&mut short, &src)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::WriteZero);
+}
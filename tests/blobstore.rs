@@ -0,0 +1,83 @@
+#![cfg(feature = "fs")]
+
+use std::fs;
+use std::time::Duration;
+
+use libnar::blobstore::BlobStore;
+use libnar::hash::hash_flat_reader;
+
+#[test]
+fn stores_and_retrieves_blobs_by_hash() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = BlobStore::open(dir.path()).unwrap();
+
+    let hash = store.put(b"chunk of data").unwrap();
+    assert_eq!(hash, hash_flat_reader(&b"chunk of data"[..]).unwrap());
+    assert!(store.contains(&hash));
+    assert_eq!(store.put(b"chunk of data").unwrap(), hash);
+    assert_eq!(store.get(&hash).unwrap(), b"chunk of data");
+
+    let mut out = Vec::new();
+    assert_eq!(store.copy_to(&hash, &mut out).unwrap(), 13);
+    assert_eq!(out, b"chunk of data");
+
+    let leftovers = fs::read_dir(dir.path().join("tmp")).unwrap().count();
+    assert_eq!(leftovers, 0);
+
+    store.remove(&hash).unwrap();
+    let err = store.get(&hash).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+}
+
+#[test]
+fn stores_whole_nars_under_their_nar_hash() {
+    let src = tempfile::tempdir().unwrap();
+    fs::write(src.path().join("file"), "contents").unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let store = BlobStore::open(dir.path()).unwrap();
+    let (hash, size) = store.put_path(src.path()).unwrap();
+    assert_eq!((hash, size), libnar::hash_path(src.path()).unwrap());
+    assert_eq!(
+        store.get(&hash).unwrap(),
+        libnar::to_vec(src.path()).unwrap()
+    );
+}
+
+#[test]
+fn detects_corrupted_blobs() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = BlobStore::open(dir.path()).unwrap();
+    let hash = store.put(b"original").unwrap();
+
+    fs::write(store.blob_path(&hash), "tampered").unwrap();
+    assert!(store.verify(&hash).is_err());
+    assert!(store.get(&hash).is_err());
+    assert!(store.copy_to(&hash, &mut Vec::new()).is_err());
+}
+
+#[test]
+fn collects_blobs_unreachable_from_roots() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = BlobStore::open(dir.path()).unwrap();
+    let kept = store.put(b"kept").unwrap();
+    let dropped = store.put(b"dropped").unwrap();
+    store.pin_root("closure-1", vec![kept]).unwrap();
+
+    let report = store.collect_garbage(Duration::from_secs(3600)).unwrap();
+    assert!(report.removed.is_empty());
+    assert_eq!(report.live, 2);
+
+    let report = store.collect_garbage(Duration::from_secs(0)).unwrap();
+    assert_eq!(report.removed, vec![dropped]);
+    assert_eq!(report.freed_bytes, 7);
+    assert!(store.contains(&kept) && !store.contains(&dropped));
+
+    assert_eq!(store.roots().unwrap(), vec!["closure-1".to_owned()]);
+    assert_eq!(store.root_hashes("closure-1").unwrap(), vec![kept]);
+    store.unpin_root("closure-1").unwrap();
+    store.collect_garbage(Duration::from_secs(0)).unwrap();
+    assert!(store.list().unwrap().is_empty());
+
+    assert!(store.pin_root("../escape", vec![kept]).is_err());
+}
@@ -0,0 +1,78 @@
+#![cfg(feature = "fs")]
+
+use std::fs;
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use libnar::chunking::{ChunkPipeline, ChunkerParams, ProcessedChunk};
+use libnar::{Archive, MemoryBudget};
+
+fn example_nar() -> Vec<u8> {
+    let dir = tempfile::tempdir().unwrap();
+    let data: Vec<u8> = (0..200_000u32).map(|i| (i * 7 + i / 251) as u8).collect();
+    fs::write(dir.path().join("big"), &data).unwrap();
+    fs::write(dir.path().join("small"), "small").unwrap();
+    libnar::to_vec(dir.path()).unwrap()
+}
+
+#[test]
+fn reservations_are_released_on_drop() {
+    let budget = MemoryBudget::new(100);
+    let first = budget.try_reserve(60).unwrap();
+    assert_eq!(budget.used(), 60);
+
+    let err = budget.clone().try_reserve(50).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::OutOfMemory);
+    assert!(budget.reserve(101).is_err());
+
+    drop(first);
+    assert_eq!(budget.used(), 0);
+    assert_eq!(budget.try_reserve(100).unwrap().len(), 100);
+    assert_eq!(MemoryBudget::unlimited().limit(), None);
+}
+
+#[test]
+fn entries_hold_their_contents_against_the_budget() {
+    let nar = example_nar();
+    let budget = MemoryBudget::new(300_000);
+    let mut archive = Archive::new(&nar[..]);
+    archive.set_memory_budget(budget.clone());
+
+    let mut entries = archive.entries().unwrap();
+    let _root = entries.next().unwrap().unwrap();
+    let big = entries.next().unwrap().unwrap();
+    assert!(budget.used() >= 200_000);
+    drop(big);
+    assert!(budget.used() < 200_000);
+    drop(entries);
+
+    let mut archive = Archive::new(&nar[..]);
+    archive.set_memory_budget(MemoryBudget::new(100_000));
+    let err = archive.unpack(tempfile::tempdir().unwrap().path().join("out"));
+    assert_eq!(err.unwrap_err().kind(), ErrorKind::OutOfMemory);
+}
+
+#[test]
+fn pipeline_stays_within_budget() {
+    let nar = example_nar();
+    let params = ChunkerParams::new(1024, 4096, 8192);
+    let budget = MemoryBudget::new(4 * 8192);
+    let peak = AtomicU64::new(0);
+
+    let mut pipeline = ChunkPipeline::new(params);
+    pipeline.set_threads(4);
+    pipeline.set_max_in_flight(64);
+    pipeline.set_memory_budget(budget.clone());
+    let sink = |_: &ProcessedChunk| {
+        peak.fetch_max(budget.used(), Ordering::SeqCst);
+        Ok(())
+    };
+    let manifest = pipeline.run(&nar[..], &sink).unwrap();
+    assert_eq!(manifest.nar_size(), nar.len() as u64);
+    assert!(peak.load(Ordering::SeqCst) <= 4 * 8192);
+    assert_eq!(budget.used(), 0);
+
+    pipeline.set_memory_budget(MemoryBudget::new(8192));
+    let err = pipeline.run(&nar[..], &sink).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::OutOfMemory);
+}
@@ -0,0 +1,195 @@
+#![cfg(feature = "fs")]
+
+use std::fs;
+use std::io;
+
+use libnar::blobstore::BlobStore;
+use libnar::chunking::{self, ChunkPipeline, ChunkerParams, ProcessedChunk};
+
+fn example_nar(seed: u8) -> Vec<u8> {
+    let dir = tempfile::tempdir().unwrap();
+    let data: Vec<u8> = (0..200_000u32).map(|i| (i * 7 + i / 251) as u8).collect();
+    fs::write(dir.path().join("big"), &data).unwrap();
+    fs::write(dir.path().join("small"), [seed; 16]).unwrap();
+    libnar::to_vec(dir.path()).unwrap()
+}
+
+#[test]
+fn round_trips_nar_through_blob_store() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = BlobStore::open(dir.path()).unwrap();
+    let params = ChunkerParams::new(1024, 8192, 32768);
+
+    let nar = example_nar(1);
+    let manifest = chunking::chunk_and_store(&nar[..], &store, params).unwrap();
+    assert!(manifest.chunks().len() > 1);
+    assert_eq!(manifest.nar_size(), nar.len() as u64);
+
+    let mut out = Vec::new();
+    chunking::reassemble(&manifest, &store, &mut out).unwrap();
+    assert_eq!(out, nar);
+
+    let other = chunking::chunk_and_store(&example_nar(2)[..], &store, params).unwrap();
+    let shared = other
+        .chunk_hashes()
+        .filter(|h| manifest.chunk_hashes().any(|m| m == *h))
+        .count();
+    assert!(shared > 0);
+}
+
+#[test]
+fn rejects_reassembly_with_missing_or_corrupt_chunks() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = BlobStore::open(dir.path()).unwrap();
+    let params = ChunkerParams::new(1024, 8192, 32768);
+    let manifest = chunking::chunk_and_store(&example_nar(1)[..], &store, params).unwrap();
+
+    let first = manifest.chunks()[0].hash;
+    store.remove(&first).unwrap();
+    assert!(chunking::reassemble(&manifest, &store, &mut Vec::new()).is_err());
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn round_trips_manifest_json() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = BlobStore::open(dir.path()).unwrap();
+    let manifest =
+        chunking::chunk_and_store(&example_nar(1)[..], &store, ChunkerParams::default()).unwrap();
+
+    let json = manifest.to_json();
+    assert!(json.contains("\"narHash\":\"sha256:"));
+    assert_eq!(chunking::Manifest::from_json(&json).unwrap(), manifest);
+}
+
+fn many_small_files_nar() -> Vec<u8> {
+    let dir = tempfile::tempdir().unwrap();
+    for i in 0..200u32 {
+        fs::write(dir.path().join(format!("f{:03}", i)), i.to_le_bytes()).unwrap();
+    }
+    let data: Vec<u8> = (0..100_000u32).map(|i| (i * 13 + i / 97) as u8).collect();
+    fs::write(dir.path().join("large"), &data).unwrap();
+    libnar::to_vec(dir.path()).unwrap()
+}
+
+fn entry_tag() -> Vec<u8> {
+    let mut tag = 5u64.to_le_bytes().to_vec();
+    tag.extend_from_slice(b"entry\0\0\0");
+    tag
+}
+
+#[test]
+fn aligned_chunks_group_small_entries_and_cut_before_entries() {
+    let nar = many_small_files_nar();
+    let params = ChunkerParams::new(1024, 4096, 16384);
+
+    let chunks: Vec<Vec<u8>> = chunking::EntryAlignedChunker::new(&nar[..], params)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(chunks.concat(), nar);
+    assert!(chunks.len() < 200 / 4);
+    assert!(chunks.iter().all(|c| c.len() <= params.max_size()));
+
+    // Every chunk holding only small files begins at a directory entry.
+    let large_at = nar.windows(5).position(|w| w == b"large").unwrap();
+    let mut offset = 0;
+    for chunk in &chunks {
+        if offset > 0 && offset + chunk.len() < large_at {
+            assert!(chunk.starts_with(&entry_tag()));
+        }
+        offset += chunk.len();
+    }
+}
+
+#[test]
+fn aligned_chunks_reject_out_of_range_token_lengths() {
+    let params = ChunkerParams::new(1024, 4096, 16384);
+    for len in [u64::MAX - 2, u64::MAX] {
+        let nar = [&len.to_le_bytes()[..], &[0; 16]].concat();
+        let err = chunking::EntryAlignedChunker::new(&nar[..], params)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
+
+#[test]
+fn round_trips_aligned_chunks_through_blob_store() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = BlobStore::open(dir.path()).unwrap();
+    let params = ChunkerParams::new(1024, 4096, 16384);
+
+    let nar = many_small_files_nar();
+    let manifest = chunking::chunk_and_store_aligned(&nar[..], &store, params).unwrap();
+    assert_eq!(manifest.nar_size(), nar.len() as u64);
+
+    let mut out = Vec::new();
+    chunking::reassemble(&manifest, &store, &mut out).unwrap();
+    assert_eq!(out, nar);
+}
+
+#[test]
+fn pipeline_matches_sequential_chunking() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = BlobStore::open(dir.path()).unwrap();
+    let params = ChunkerParams::new(1024, 8192, 32768);
+    let nar = example_nar(3);
+
+    let mut pipeline = ChunkPipeline::new(params);
+    pipeline.set_threads(3);
+    pipeline.set_max_in_flight(2);
+    let manifest = pipeline.run(&nar[..], &store).unwrap();
+
+    let other = tempfile::tempdir().unwrap();
+    let expected =
+        chunking::chunk_and_store(&nar[..], &BlobStore::open(other.path()).unwrap(), params);
+    assert_eq!(manifest, expected.unwrap());
+
+    let mut out = Vec::new();
+    chunking::reassemble(&manifest, &store, &mut out).unwrap();
+    assert_eq!(out, nar);
+}
+
+#[test]
+fn pipeline_reports_sink_failures() {
+    let params = ChunkerParams::new(1024, 8192, 32768);
+    let nar = example_nar(4);
+
+    let sink = |chunk: &ProcessedChunk| {
+        if chunk.index() == 2 {
+            Err(io::Error::other("upload failed"))
+        } else {
+            Ok(())
+        }
+    };
+    let err = ChunkPipeline::new(params).run(&nar[..], &sink).unwrap_err();
+    assert_eq!(err.to_string(), "upload failed");
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn pipeline_compresses_chunks() {
+    use std::io::Read;
+    use std::sync::Mutex;
+
+    let params = ChunkerParams::new(1024, 8192, 32768);
+    let nar = example_nar(5);
+
+    let mut pipeline = ChunkPipeline::new(params);
+    pipeline.set_zstd_level(Some(3));
+    let chunks = Mutex::new(Vec::new());
+    let sink = |chunk: &ProcessedChunk| {
+        let mut data = Vec::new();
+        libnar::compression::zstd::decoder(chunk.compressed().unwrap())?.read_to_end(&mut data)?;
+        assert_eq!(data, chunk.data());
+        chunks.lock().unwrap().push((chunk.index(), data));
+        Ok(())
+    };
+    let manifest = pipeline.run(&nar[..], &sink).unwrap();
+
+    let mut chunks = chunks.into_inner().unwrap();
+    assert_eq!(chunks.len(), manifest.chunks().len());
+    chunks.sort();
+    let data: Vec<u8> = chunks.into_iter().flat_map(|(_, data)| data).collect();
+    assert_eq!(data, nar);
+}
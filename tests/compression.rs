@@ -0,0 +1,112 @@
+#![cfg(all(feature = "fs", feature = "zstd"))]
+
+use std::fs;
+use std::io::{Cursor, Read, Write};
+
+use libnar::compression::zstd::{
+    self, DictionaryTrainer, SeekableReader, SeekableWriter, DEFAULT_LEVEL,
+};
+use libnar::compression::DecompressionLimits;
+
+fn small_nar(i: usize) -> Vec<u8> {
+    let dir = tempfile::tempdir().unwrap();
+    let script = format!(
+        "#!/nix/store/5ppl5kfq7gm2mz5zqbrc2wfbr7lrd9ks-bash-5.2/bin/bash\n\
+         export PATH=/nix/store/3gwv0x0i2kdgx6wrrnbx9q0pahwy5jdd-coreutils-9.3/bin\n\
+         exec /nix/store/l1lqx6xxw6n6yi4pzzq2pfcdcl1ac1i9-hello-2.12/bin/hello --greeting={} \"$@\"\n",
+        i
+    );
+    fs::write(dir.path().join("run"), script).unwrap();
+    libnar::to_vec(dir.path()).unwrap()
+}
+
+#[test]
+fn trains_dictionary_that_improves_small_nar_compression() {
+    let mut trainer = DictionaryTrainer::new();
+    for i in 0..200 {
+        trainer.add_nar(&small_nar(i)[..]).unwrap();
+    }
+    assert_eq!(trainer.sample_count(), 200);
+    let dict = trainer.train(4096).unwrap();
+
+    let nar = small_nar(1000);
+    let with_dict = dict.compress(&nar, DEFAULT_LEVEL).unwrap();
+    let mut plain = zstd::encoder(Vec::new(), DEFAULT_LEVEL).unwrap();
+    plain.write_all(&nar).unwrap();
+    let plain = plain.finish().unwrap();
+    assert!(with_dict.len() < plain.len());
+
+    assert_eq!(dict.decompress(&with_dict).unwrap(), nar);
+    let mut decoded = Vec::new();
+    zstd::decoder(&plain[..])
+        .unwrap()
+        .read_to_end(&mut decoded)
+        .unwrap();
+    assert_eq!(decoded, nar);
+}
+
+#[test]
+fn refuses_to_train_without_samples() {
+    assert!(DictionaryTrainer::new().train(4096).is_err());
+}
+
+#[test]
+fn reads_single_files_from_seekable_zstd_archive() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("share")).unwrap();
+    let big: Vec<u8> = (0..300_000u32).map(|i| (i % 253) as u8).collect();
+    fs::write(dir.path().join("share").join("big"), &big).unwrap();
+    fs::write(dir.path().join("small"), "hello").unwrap();
+
+    let nar = libnar::to_vec(dir.path()).unwrap();
+    let listing = libnar::Archive::new(&nar[..]).listing().unwrap();
+
+    let mut writer = SeekableWriter::with_frame_len(Vec::new(), DEFAULT_LEVEL, 16 * 1024);
+    writer.write_all(&nar).unwrap();
+    let compressed = writer.finish().unwrap();
+
+    let mut whole = Vec::new();
+    zstd::decoder(&compressed[..])
+        .unwrap()
+        .read_to_end(&mut whole)
+        .unwrap();
+    assert_eq!(whole, nar);
+
+    let mut reader = SeekableReader::new(Cursor::new(&compressed)).unwrap();
+    assert_eq!(reader.decompressed_len(), nar.len() as u64);
+    assert_eq!(reader.read_file(&listing, "small").unwrap(), b"hello");
+    assert_eq!(reader.read_file(&listing, "share/big").unwrap(), big);
+    assert!(reader.read_file(&listing, "share").is_err());
+    assert!(reader.read_range(nar.len() as u64 - 1, 2).is_err());
+}
+
+#[test]
+fn rejects_streams_without_seek_table() {
+    let mut plain = zstd::encoder(Vec::new(), DEFAULT_LEVEL).unwrap();
+    plain.write_all(b"not seekable").unwrap();
+    let plain = plain.finish().unwrap();
+    assert!(SeekableReader::new(Cursor::new(plain)).is_err());
+}
+
+#[test]
+fn stops_decompression_bombs() {
+    let bomb = zstd::compress(&vec![0; 8 * 1024 * 1024], DEFAULT_LEVEL).unwrap();
+
+    let mut limits = DecompressionLimits::new();
+    let mut decoder = zstd::decoder_with_limits(&bomb[..], &limits).unwrap();
+    let err = std::io::copy(&mut decoder, &mut std::io::sink()).unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("Decompression ratio exceeds 1024:1"));
+
+    limits.set_max_ratio(None);
+    limits.set_max_len(Some(1024 * 1024));
+    let mut decoder = zstd::decoder_with_limits(&bomb[..], &limits).unwrap();
+    let err = std::io::copy(&mut decoder, &mut std::io::sink()).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+    limits.set_max_len(None);
+    let mut decoder = zstd::decoder_with_limits(&bomb[..], &limits).unwrap();
+    let len = std::io::copy(&mut decoder, &mut std::io::sink()).unwrap();
+    assert_eq!(len, 8 * 1024 * 1024);
+}
@@ -0,0 +1,1089 @@
+#![cfg(feature = "fs")]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use libnar::de::{
+    BlackHole, CaseCollision, DynArchive, Event, FileEntry, Kind, ParseError, ParseLimits,
+    PathLimits, SymlinkPolicy, UnpackError, UnpackOptions,
+};
+use libnar::tree::NarTree;
+use libnar::{wire, Archive, SymlinkTarget, Warning};
+
+fn encode(tokens: &[&[u8]]) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    for token in tokens {
+        wire::write_token(&mut buffer, token).unwrap();
+    }
+    buffer
+}
+
+const UNKNOWN_NODE: &[&[u8]] = &[
+    b"(", b"type", b"socket", b"mode", b"0600", b"options", b"(", b")", b")",
+];
+
+fn archive_with_unknown_node() -> Vec<u8> {
+    let mut tokens: Vec<&[u8]> = vec![
+        b"nix-archive-1",
+        b"(",
+        b"type",
+        b"directory",
+        b"entry",
+        b"(",
+        b"name",
+        b"sock",
+        b"node",
+    ];
+    tokens.extend_from_slice(UNKNOWN_NODE);
+    tokens.extend_from_slice(&[b")", b")"]);
+    encode(&tokens)
+}
+
+#[test]
+fn rejects_unknown_node_types_by_default() {
+    let bytes = archive_with_unknown_node();
+    let mut archive = Archive::new(&bytes[..]);
+    let result: Result<Vec<_>, _> = archive.entries().unwrap().collect();
+    assert_eq!(result.unwrap_err().to_string(), "Unrecognized file type");
+}
+
+#[test]
+fn preserves_unknown_node_types_when_lenient() {
+    let bytes = archive_with_unknown_node();
+    let mut archive = Archive::new(&bytes[..]);
+    archive.set_lenient(true);
+
+    let entries: Vec<_> = archive
+        .entries()
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(entries.len(), 2);
+
+    let unknown = &entries[1];
+    assert!(unknown.is_unknown());
+    assert_eq!(unknown.name(), Path::new("sock"));
+
+    let mut reemitted = Vec::new();
+    unknown.write_raw_node(&mut reemitted).unwrap();
+    assert_eq!(reemitted, encode(UNKNOWN_NODE));
+    assert!(entries[0].write_raw_node(&mut Vec::new()).is_err());
+}
+
+#[test]
+fn exposes_entry_kinds_and_payloads() {
+    let bytes = encode(&[
+        b"nix-archive-1",
+        b"(",
+        b"type",
+        b"directory",
+        b"entry",
+        b"(",
+        b"name",
+        b"hello",
+        b"node",
+        b"(",
+        b"type",
+        b"regular",
+        b"executable",
+        b"",
+        b"contents",
+        b"#!/bin/sh\n",
+        b")",
+        b")",
+        b"entry",
+        b"(",
+        b"name",
+        b"link",
+        b"node",
+        b"(",
+        b"type",
+        b"symlink",
+        b"target",
+        b"hello",
+        b")",
+        b")",
+        b")",
+    ]);
+
+    let mut archive = Archive::new(&bytes[..]);
+    let entries: Vec<_> = archive
+        .entries()
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+
+    let target = SymlinkTarget::new("hello");
+    let kinds: Vec<_> = entries.iter().map(|entry| entry.kind()).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            Kind::Dir,
+            Kind::File {
+                executable: true,
+                size: 10
+            },
+            Kind::Symlink { target: &target },
+        ]
+    );
+
+    assert_eq!(entries[0].data(), None);
+    assert_eq!(entries[1].data(), Some(&b"#!/bin/sh\n"[..]));
+    assert_eq!(entries[1].symlink_target(), None);
+    assert_eq!(entries[2].symlink_target(), Some(Path::new("hello")));
+}
+
+#[test]
+fn refuses_to_replace_directory_with_symlink_by_default() {
+    let src = tempfile::tempdir().unwrap();
+    std::os::unix::fs::symlink("target", src.path().join("link")).unwrap();
+    let bytes = libnar::to_vec(src.path()).unwrap();
+
+    let dst = tempfile::tempdir().unwrap();
+    fs::create_dir(dst.path().join("link")).unwrap();
+    fs::write(dst.path().join("link").join("file"), "keep me").unwrap();
+
+    let err = Archive::new(&bytes[..]).unpack(dst.path()).unwrap_err();
+    let inner = err.get_ref().and_then(|e| e.downcast_ref::<UnpackError>());
+    assert!(
+        matches!(inner, Some(UnpackError::WouldReplaceDirectory(path)) if path.ends_with("link")),
+        "{:?}",
+        err
+    );
+    assert!(dst.path().join("link").join("file").exists());
+
+    let mut archive = Archive::new(&bytes[..]);
+    archive.set_replace_directories(true);
+    archive.unpack(dst.path()).unwrap();
+    let target = fs::read_link(dst.path().join("link")).unwrap();
+    assert_eq!(target, Path::new("target"));
+}
+
+#[test]
+fn refuses_to_unpack_through_earlier_symlink() {
+    let outside = tempfile::tempdir().unwrap();
+    let outside_path = outside.path().to_str().unwrap().to_owned();
+    let bytes = encode(&[
+        b"nix-archive-1",
+        b"(",
+        b"type",
+        b"directory",
+        b"entry",
+        b"(",
+        b"name",
+        b"a",
+        b"node",
+        b"(",
+        b"type",
+        b"symlink",
+        b"target",
+        outside_path.as_bytes(),
+        b")",
+        b")",
+        b"entry",
+        b"(",
+        b"name",
+        b"a",
+        b"node",
+        b"(",
+        b"type",
+        b"directory",
+        b"entry",
+        b"(",
+        b"name",
+        b"pwned",
+        b"node",
+        b"(",
+        b"type",
+        b"regular",
+        b"contents",
+        b"gotcha",
+        b")",
+        b")",
+        b")",
+        b")",
+        b")",
+    ]);
+
+    // The parser rejects the second `a` before the unpacker could follow the symlink.
+    let dst = tempfile::tempdir().unwrap();
+    let err = Archive::new(&bytes[..]).unpack(dst.path()).unwrap_err();
+    assert_eq!(err.to_string(), "Duplicate entry name");
+    assert!(err.get_ref().unwrap().is::<ParseError>());
+    assert!(!outside.path().join("pwned").exists());
+}
+
+#[test]
+fn rejects_names_containing_separators() {
+    let bytes = encode(&[
+        b"nix-archive-1",
+        b"(",
+        b"type",
+        b"directory",
+        b"entry",
+        b"(",
+        b"name",
+        b"a/b",
+        b"node",
+        b"(",
+        b"type",
+        b"regular",
+        b"contents",
+        b"",
+        b")",
+        b")",
+        b")",
+    ]);
+
+    let mut archive = Archive::new(&bytes[..]);
+    let result: Result<Vec<_>, _> = archive.entries().unwrap().collect();
+    assert_eq!(result.unwrap_err().to_string(), "Invalid name \"a/b\"");
+}
+
+#[test]
+fn records_and_rolls_back_created_paths() {
+    let src = tempfile::tempdir().unwrap();
+    fs::create_dir(src.path().join("bin")).unwrap();
+    fs::write(src.path().join("bin").join("hello"), "hello").unwrap();
+    fs::write(src.path().join("zzz"), "last").unwrap();
+    let bytes = libnar::to_vec(src.path()).unwrap();
+
+    let dst = tempfile::tempdir().unwrap();
+    let mut log = libnar::de::UnpackLog::new();
+    Archive::new(&bytes[..])
+        .unpack_with_log(dst.path().join("out"), &mut log)
+        .unwrap();
+
+    let out = dst.path().join("out");
+    let expected = [
+        out.clone(),
+        out.join("bin"),
+        out.join("bin").join("hello"),
+        out.join("zzz"),
+    ];
+    assert_eq!(log.created(), expected);
+
+    log.rollback().unwrap();
+    assert!(log.created().is_empty());
+    assert!(!out.exists());
+}
+
+#[test]
+fn rolls_back_on_error_when_enabled() {
+    let src = tempfile::tempdir().unwrap();
+    fs::write(src.path().join("a"), "a").unwrap();
+    std::os::unix::fs::symlink("elsewhere", src.path().join("b")).unwrap();
+    let bytes = libnar::to_vec(src.path()).unwrap();
+
+    let dst = tempfile::tempdir().unwrap();
+    fs::create_dir(dst.path().join("b")).unwrap();
+
+    let mut archive = Archive::new(&bytes[..]);
+    archive.set_rollback_on_error(true);
+    assert!(archive.unpack(dst.path()).is_err());
+    assert!(!dst.path().join("a").exists());
+    assert!(dst.path().join("b").is_dir());
+}
+
+#[test]
+fn extracts_into_black_hole() {
+    let src = tempfile::tempdir().unwrap();
+    fs::create_dir(src.path().join("bin")).unwrap();
+    fs::write(src.path().join("bin").join("hello"), "hello").unwrap();
+    fs::write(src.path().join("README"), "lorem ipsum").unwrap();
+    std::os::unix::fs::symlink("bin/hello", src.path().join("link")).unwrap();
+    let bytes = libnar::to_vec(src.path()).unwrap();
+
+    let mut sink = BlackHole::new();
+    Archive::new(&bytes[..]).extract_to(&mut sink).unwrap();
+    assert_eq!(sink.directories(), 2);
+    assert_eq!(sink.files(), 2);
+    assert_eq!(sink.symlinks(), 1);
+    assert_eq!(sink.bytes(), 16);
+
+    let truncated = &bytes[..bytes.len() - 16];
+    let err = Archive::new(truncated).extract_to(&mut BlackHole::new());
+    assert!(err.is_err());
+}
+
+#[test]
+fn reads_boxed_readers_through_one_archive_type() {
+    let src = tempfile::tempdir().unwrap();
+    fs::write(src.path().join("hello"), "hello").unwrap();
+    let bytes = libnar::to_vec(src.path()).unwrap();
+    let file = tempfile::NamedTempFile::new().unwrap();
+    fs::write(file.path(), &bytes).unwrap();
+
+    let mut options = UnpackOptions::new();
+    options.set_lenient(true);
+    let archives: Vec<DynArchive> = vec![
+        Archive::new_dyn(Box::new(&bytes[..])),
+        Archive::new_dyn(Box::new(fs::File::open(file.path()).unwrap())),
+        options.archive_dyn(Box::new(std::io::Cursor::new(bytes.clone()))),
+    ];
+
+    for mut archive in archives {
+        let names: Vec<PathBuf> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().name().to_owned())
+            .collect();
+        assert_eq!(names, vec![PathBuf::new(), PathBuf::from("hello")]);
+    }
+}
+
+fn archive_with_case_collision() -> Vec<u8> {
+    let file = |name: &'static [u8], contents: &'static [u8]| -> Vec<&'static [u8]> {
+        vec![
+            b"entry",
+            b"(",
+            b"name",
+            name,
+            b"node",
+            b"(",
+            b"type",
+            b"regular",
+            b"contents",
+            contents,
+            b")",
+            b")",
+        ]
+    };
+
+    let mut tokens: Vec<&[u8]> = vec![b"nix-archive-1", b"(", b"type", b"directory"];
+    tokens.extend(file(b"README", b"upper"));
+    tokens.extend(file(b"readme", b"lower"));
+    tokens.push(b")");
+    encode(&tokens)
+}
+
+#[test]
+fn detects_case_collisions_when_requested() {
+    let bytes = archive_with_case_collision();
+    let dst = tempfile::tempdir().unwrap();
+
+    let mut archive = Archive::new(&bytes[..]);
+    archive.set_case_collision(CaseCollision::Error);
+    let err = archive.unpack(dst.path()).unwrap_err();
+    let inner = err.get_ref().and_then(|e| e.downcast_ref::<UnpackError>());
+    assert!(
+        matches!(inner, Some(UnpackError::CaseCollision(path)) if path.ends_with("readme")),
+        "{:?}",
+        err
+    );
+
+    let dst = tempfile::tempdir().unwrap();
+    let mut archive = Archive::new(&bytes[..]);
+    archive.set_case_collision(CaseCollision::CaseHack);
+    archive.unpack(dst.path()).unwrap();
+    assert_eq!(fs::read(dst.path().join("README")).unwrap(), b"upper");
+    let hacked = dst.path().join("readme~nix~case~hack~1");
+    assert_eq!(fs::read(hacked).unwrap(), b"lower");
+}
+
+#[test]
+fn rejects_destinations_exceeding_path_limits() {
+    let src = tempfile::tempdir().unwrap();
+    let name = "x".repeat(100);
+    fs::write(src.path().join(&name), "data").unwrap();
+    let bytes = libnar::to_vec(src.path()).unwrap();
+
+    let dst = tempfile::tempdir().unwrap();
+    let limit = dst.path().as_os_str().len() + 50;
+    let mut archive = Archive::new(&bytes[..]);
+    archive.set_path_limits(PathLimits::unix().with_max_path_len(Some(limit)));
+
+    let err = archive.unpack(dst.path()).unwrap_err();
+    let inner = err.get_ref().and_then(|e| e.downcast_ref::<UnpackError>());
+    assert!(
+        matches!(inner, Some(UnpackError::PathTooLong { path, .. }) if path.ends_with(&name)),
+        "{:?}",
+        err
+    );
+    assert!(!dst.path().join(&name).exists());
+}
+
+#[test]
+fn unpacks_atomically_via_temp_provider() {
+    let src = tempfile::tempdir().unwrap();
+    fs::write(src.path().join("a"), "a").unwrap();
+    std::os::unix::fs::symlink("elsewhere", src.path().join("b")).unwrap();
+    let bytes = libnar::to_vec(src.path()).unwrap();
+
+    let dst = tempfile::tempdir().unwrap();
+    let staging = tempfile::tempdir_in(dst.path()).unwrap();
+    let mut archive = Archive::new(&bytes[..]);
+    archive.set_temp_provider(libnar::temp::InDirectory(staging.path().to_owned()));
+    archive.unpack_atomic(dst.path().join("out")).unwrap();
+
+    assert_eq!(fs::read(dst.path().join("out").join("a")).unwrap(), b"a");
+    assert_eq!(fs::read_dir(staging.path()).unwrap().count(), 0);
+
+    let err = Archive::new(&bytes[..])
+        .unpack_atomic(dst.path().join("out"))
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+}
+
+#[test]
+fn leaves_nothing_behind_when_atomic_unpack_fails() {
+    let src = tempfile::tempdir().unwrap();
+    fs::write(src.path().join("a"), "a").unwrap();
+    let bytes = libnar::to_vec(src.path()).unwrap();
+    let truncated = &bytes[..bytes.len() - 16];
+
+    let dst = tempfile::tempdir().unwrap();
+    assert!(Archive::new(truncated)
+        .unpack_atomic(dst.path().join("out"))
+        .is_err());
+    assert_eq!(fs::read_dir(dst.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn verifies_unpacked_tree_against_archive() {
+    let src = tempfile::tempdir().unwrap();
+    fs::create_dir(src.path().join("dir")).unwrap();
+    for i in 0..32 {
+        fs::write(src.path().join("dir").join(i.to_string()), i.to_string()).unwrap();
+    }
+    std::os::unix::fs::symlink("dir", src.path().join("link")).unwrap();
+    let bytes = libnar::to_vec(src.path()).unwrap();
+
+    let dst = tempfile::tempdir().unwrap();
+    let out = dst.path().join("out");
+    Archive::new(&bytes[..]).unpack(&out).unwrap();
+
+    let mut archive = Archive::new(&bytes[..]);
+    archive.set_verify_threads(3);
+    archive.verify_tree(&out).unwrap();
+
+    fs::write(out.join("dir").join("7"), "x").unwrap();
+    let err = Archive::new(&bytes[..]).verify_tree(&out).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+    fs::write(out.join("dir").join("7"), "7").unwrap();
+    fs::write(out.join("extra"), "").unwrap();
+    let err = Archive::new(&bytes[..]).verify_tree(&out).unwrap_err();
+    assert!(err.to_string().contains("unexpected entry"));
+}
+
+#[test]
+fn streams_root_file_into_writer() {
+    let src = tempfile::tempdir().unwrap();
+    let file = src.path().join("file");
+    fs::write(&file, "lorem ipsum").unwrap();
+    let permissions = std::os::unix::fs::PermissionsExt::from_mode(0o755);
+    fs::set_permissions(&file, permissions).unwrap();
+    let bytes = libnar::to_vec(&file).unwrap();
+
+    let mut contents = Vec::new();
+    let info = Archive::new(&bytes[..])
+        .unpack_root_file(&mut contents)
+        .unwrap();
+    assert_eq!(contents, b"lorem ipsum");
+    assert_eq!(info.size(), 11);
+    assert!(info.executable());
+
+    assert!(Archive::new(&bytes[..bytes.len() - 20])
+        .unpack_root_file(std::io::sink())
+        .is_err());
+
+    let bytes = libnar::to_vec(src.path()).unwrap();
+    let err = Archive::new(&bytes[..])
+        .unpack_root_file(std::io::sink())
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn peeks_root_kind_without_consuming_archive() {
+    use libnar::de::RootKind;
+
+    let src = tempfile::tempdir().unwrap();
+    let file = src.path().join("file");
+    fs::write(&file, "lorem ipsum").unwrap();
+    std::os::unix::fs::symlink("file", src.path().join("link")).unwrap();
+
+    let bytes = libnar::to_vec(&file).unwrap();
+    let mut archive = Archive::new(&bytes[..]);
+    let expected = RootKind::File {
+        executable: false,
+        size: 11,
+    };
+    assert_eq!(archive.root_kind().unwrap(), expected);
+    assert_eq!(archive.root_kind().unwrap(), expected);
+    let mut contents = Vec::new();
+    archive.unpack_root_file(&mut contents).unwrap();
+    assert_eq!(contents, b"lorem ipsum");
+
+    let bytes = libnar::to_vec(src.path().join("link")).unwrap();
+    let target = SymlinkTarget::from("file".to_owned());
+    assert_eq!(
+        Archive::new(&bytes[..]).root_kind().unwrap(),
+        RootKind::Symlink { target }
+    );
+
+    let bytes = libnar::to_vec(src.path()).unwrap();
+    let mut archive = Archive::new(&bytes[..]);
+    assert_eq!(archive.root_kind().unwrap(), RootKind::Directory);
+    assert_eq!(
+        archive.listing().unwrap(),
+        Archive::new(&bytes[..]).listing().unwrap()
+    );
+}
+
+#[test]
+fn reports_location_of_malformed_tokens() {
+    let bytes = encode(&[b"nix-archive-1", b"(", b"type", b"fifo"]);
+    let err = Archive::new(&bytes[..]).listing().unwrap_err();
+    assert_eq!(err.to_string(), "Unrecognized file type");
+
+    let err = err
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<libnar::de::ParseError>())
+        .unwrap();
+    assert_eq!(err.path(), Path::new(""));
+    assert_eq!(err.offset(), 56);
+    assert_eq!(err.expected(), &["regular", "symlink", "directory"]);
+    assert_eq!(err.found(), b"fifo");
+}
+
+#[test]
+fn aggregates_entry_failures_when_continuing_on_error() {
+    let src = tempfile::tempdir().unwrap();
+    fs::write(src.path().join("a"), "a").unwrap();
+    std::os::unix::fs::symlink("elsewhere", src.path().join("b")).unwrap();
+    std::os::unix::fs::symlink("elsewhere", src.path().join("c")).unwrap();
+    fs::write(src.path().join("d"), "d").unwrap();
+    let bytes = libnar::to_vec(src.path()).unwrap();
+
+    let dst = tempfile::tempdir().unwrap();
+    fs::create_dir(dst.path().join("b")).unwrap();
+    fs::create_dir(dst.path().join("c")).unwrap();
+
+    let mut archive = Archive::new(&bytes[..]);
+    archive.set_continue_on_error(true);
+    let err = archive.unpack(dst.path()).unwrap_err();
+    assert!(dst.path().join("a").is_file());
+    assert!(dst.path().join("d").is_file());
+
+    let report = err
+        .into_inner()
+        .unwrap()
+        .downcast::<libnar::de::UnpackFailures>()
+        .unwrap();
+    let paths: Vec<_> = report.failures().iter().map(|(p, _)| p.clone()).collect();
+    assert_eq!(paths, [Path::new("b"), Path::new("c")]);
+    assert!(report
+        .to_string()
+        .starts_with("2 entries failed to unpack, first b: "));
+}
+
+#[test]
+fn collects_warnings_for_tolerated_violations() {
+    use libnar::Warning;
+
+    let mut tokens: Vec<&[u8]> = vec![b"nix-archive-1", b"(", b"type", b"directory"];
+    for name in &[&b"b"[..], b"a"] {
+        tokens.extend_from_slice(&[b"entry", b"(", b"name", name, b"node"]);
+        tokens.extend_from_slice(&[b"(", b"type", b"directory", b")", b")"]);
+    }
+    tokens.push(b")");
+    let bytes = encode(&tokens);
+
+    let err = Archive::new(&bytes[..]).listing().unwrap_err();
+    assert_eq!(err.to_string(), "Entry is out of canonical order");
+
+    let mut archive = Archive::new(&bytes[..]);
+    archive.set_lenient(true);
+    archive.listing().unwrap();
+    let path = Path::new("a").to_owned();
+    assert_eq!(
+        archive.take_warnings(),
+        [Warning::NonCanonicalOrder { path }]
+    );
+    assert!(archive.take_warnings().is_empty());
+
+    let mut tokens: Vec<&[u8]> = vec![b"nix-archive-1", b"(", b"type", b"directory"];
+    for _ in 0..2 {
+        tokens.extend_from_slice(&[b"entry", b"(", b"name", b"a", b"node"]);
+        tokens.extend_from_slice(&[b"(", b"type", b"directory", b")", b")"]);
+    }
+    tokens.push(b")");
+    let bytes = encode(&tokens);
+    let mut archive = Archive::new(&bytes[..]);
+    archive.set_lenient(true);
+    let err = archive.listing().unwrap_err();
+    assert_eq!(err.to_string(), "Duplicate entry name");
+
+    let dst = tempfile::tempdir().unwrap();
+    let bytes = archive_with_case_collision();
+    let mut archive = Archive::new(&bytes[..]);
+    archive.set_case_collision(CaseCollision::CaseHack);
+    archive.unpack(dst.path().join("out")).unwrap();
+    let warnings = archive.take_warnings();
+    assert_eq!(warnings.len(), 1);
+    assert!(matches!(warnings[0], Warning::CaseCollision { .. }));
+}
+
+#[test]
+fn applies_shared_options_to_many_archives() {
+    let src = tempfile::tempdir().unwrap();
+    fs::write(src.path().join("a"), "a").unwrap();
+    let bytes = libnar::to_vec(src.path()).unwrap();
+
+    let mut options = libnar::de::UnpackOptions::new();
+    options.set_case_collision(CaseCollision::Error);
+    let dst = tempfile::tempdir().unwrap();
+    for i in 0..3 {
+        let out = dst.path().join(i.to_string());
+        options.unpack(&bytes[..], &out).unwrap();
+        options.verify_tree(&bytes[..], &out).unwrap();
+    }
+
+    let archive = options.archive(&bytes[..]);
+    assert_eq!(archive.options().case_collision(), CaseCollision::Error);
+}
+
+#[test]
+fn exposes_validated_path_components() {
+    let src = tempfile::tempdir().unwrap();
+    fs::create_dir_all(src.path().join("share").join("doc")).unwrap();
+    fs::write(src.path().join("share").join("doc").join("README"), "").unwrap();
+    let bytes = libnar::to_vec(src.path()).unwrap();
+
+    let mut archive = Archive::new(&bytes[..]);
+    let paths: Vec<_> = archive
+        .entries()
+        .unwrap()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            let components: Vec<_> = entry.path_components().map(str::to_owned).collect();
+            (components, entry.nar_path())
+        })
+        .collect();
+
+    let expected: Vec<(Vec<String>, String)> = vec![
+        (vec![], "".into()),
+        (vec!["share".into()], "share".into()),
+        (vec!["share".into(), "doc".into()], "share/doc".into()),
+        (
+            vec!["share".into(), "doc".into(), "README".into()],
+            "share/doc/README".into(),
+        ),
+    ];
+    assert_eq!(paths, expected);
+}
+
+#[test]
+fn splits_archive_file_into_independent_slices() {
+    let src = tempfile::tempdir().unwrap();
+    for dir in &["a", "b/c", "d"] {
+        fs::create_dir_all(src.path().join(dir)).unwrap();
+        for i in 0..4 {
+            let data = vec![i as u8; 1000 * (i + 1)];
+            fs::write(src.path().join(dir).join(format!("f{}", i)), data).unwrap();
+        }
+    }
+    std::os::unix::fs::symlink("a/f0", src.path().join("link")).unwrap();
+
+    let work = tempfile::tempdir().unwrap();
+    let nar_path = work.path().join("archive.nar");
+    fs::write(&nar_path, libnar::to_vec(src.path()).unwrap()).unwrap();
+
+    let archive = Archive::new(fs::File::open(&nar_path).unwrap());
+    let slices = archive.try_split(3).unwrap();
+    assert_eq!(slices.len(), 3);
+    assert!(archive.try_split(0).is_err());
+
+    let dst = work.path().join("out");
+    std::thread::scope(|scope| {
+        for slice in &slices {
+            let dst = &dst;
+            scope.spawn(move || slice.unpack_in(dst).unwrap());
+        }
+    });
+
+    let total: usize = slices.iter().map(|s| s.entries().len()).sum();
+    assert_eq!(total, 1 + 4 + 12 + 1);
+
+    let expected = work.path().join("expected");
+    Archive::new(fs::File::open(&nar_path).unwrap())
+        .unpack(&expected)
+        .unwrap();
+    assert_eq!(
+        libnar::to_vec(&dst).unwrap(),
+        libnar::to_vec(&expected).unwrap()
+    );
+}
+
+#[test]
+fn splits_archive_file_by_priority() {
+    let src = tempfile::tempdir().unwrap();
+    for dir in &["bin", "share/doc", "lib"] {
+        fs::create_dir_all(src.path().join(dir)).unwrap();
+        for i in 0..3 {
+            let data = vec![i as u8; 1000 * (i + 1)];
+            fs::write(src.path().join(dir).join(format!("f{}", i)), data).unwrap();
+        }
+    }
+
+    let work = tempfile::tempdir().unwrap();
+    let nar_path = work.path().join("archive.nar");
+    fs::write(&nar_path, libnar::to_vec(src.path()).unwrap()).unwrap();
+
+    let priority = |path: &str| if path.starts_with("bin/") { 1 } else { 0 };
+    let archive = Archive::new(fs::File::open(&nar_path).unwrap());
+    let slices = archive.try_split_by_priority(2, priority).unwrap();
+    assert_eq!(slices.len(), 2);
+    assert!(archive.try_split_by_priority(0, priority).is_err());
+
+    for slice in &slices {
+        let priorities: Vec<_> = slice.entries().iter().map(|e| priority(e.path())).collect();
+        assert!(priorities.windows(2).all(|w| w[0] >= w[1]));
+        assert_eq!(priorities[0], 1);
+    }
+
+    let dst = work.path().join("out");
+    std::thread::scope(|scope| {
+        for slice in &slices {
+            let dst = &dst;
+            scope.spawn(move || slice.unpack_in(dst).unwrap());
+        }
+    });
+
+    let expected = work.path().join("expected");
+    Archive::new(fs::File::open(&nar_path).unwrap())
+        .unpack(&expected)
+        .unwrap();
+    assert_eq!(
+        libnar::to_vec(&dst).unwrap(),
+        libnar::to_vec(&expected).unwrap()
+    );
+}
+
+#[test]
+fn collects_archive_into_map() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("bin")).unwrap();
+    fs::create_dir_all(dir.path().join("empty")).unwrap();
+    fs::write(dir.path().join("bin/tool"), "tool").unwrap();
+    std::os::unix::fs::symlink("bin/tool", dir.path().join("tool")).unwrap();
+    let nar = libnar::to_vec(dir.path()).unwrap();
+
+    let map = libnar::de::to_map(&nar[..]).unwrap();
+    let expected = vec![
+        (PathBuf::new(), FileEntry::Directory),
+        (PathBuf::from("bin"), FileEntry::Directory),
+        (
+            PathBuf::from("bin/tool"),
+            FileEntry::Regular {
+                executable: false,
+                contents: b"tool".to_vec(),
+            },
+        ),
+        (PathBuf::from("empty"), FileEntry::Directory),
+        (
+            PathBuf::from("tool"),
+            FileEntry::Symlink {
+                target: SymlinkTarget::new("bin/tool"),
+            },
+        ),
+    ];
+    assert_eq!(map.into_iter().collect::<Vec<_>>(), expected);
+
+    assert!(libnar::de::to_map(&archive_with_unknown_node()[..]).is_err());
+}
+
+#[test]
+fn yields_entries_with_depth() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("a/b")).unwrap();
+    fs::write(dir.path().join("a/b/c"), "").unwrap();
+    fs::write(dir.path().join("d"), "").unwrap();
+    let nar = libnar::to_vec(dir.path()).unwrap();
+
+    let mut archive = Archive::new(&nar[..]);
+    let depths: Vec<(usize, String)> = archive
+        .entries_with_depth()
+        .unwrap()
+        .map(|entry| entry.map(|(depth, entry)| (depth, entry.nar_path())))
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let expected = vec![
+        (0, String::new()),
+        (1, "a".to_owned()),
+        (2, "a/b".to_owned()),
+        (3, "a/b/c".to_owned()),
+        (1, "d".to_owned()),
+    ];
+    assert_eq!(depths, expected);
+}
+
+#[test]
+fn reports_directories_as_they_close() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("a/b")).unwrap();
+    fs::create_dir_all(dir.path().join("e")).unwrap();
+    fs::write(dir.path().join("a/b/c"), "").unwrap();
+    fs::write(dir.path().join("d"), "").unwrap();
+    let nar = libnar::to_vec(dir.path()).unwrap();
+
+    let mut archive = Archive::new(&nar[..]);
+    let events: Vec<String> = archive
+        .events()
+        .unwrap()
+        .map(|event| match event.unwrap() {
+            Event::Entry(entry) => format!("entry {}", entry.nar_path()),
+            Event::DirectoryClosed(path) => format!("close {}", path.display()),
+        })
+        .collect();
+    let expected = [
+        "entry ",
+        "entry a",
+        "entry a/b",
+        "entry a/b/c",
+        "close a/b",
+        "close a",
+        "entry d",
+        "entry e",
+        "close e",
+        "close ",
+    ];
+    assert_eq!(events, expected);
+}
+
+#[test]
+fn copies_symlink_targets_when_requested() {
+    let nar = NarTree::builder()
+        .symlink("a-link", "bin/hello")
+        .dir("bin", |d| d.file("hello", "hello", true))
+        .symlink("chain", "a-link")
+        .symlink("lib", "./bin")
+        .dir("share", |d| {
+            d.dir("doc", |d| d.symlink("hello", "../../bin/hello"))
+        })
+        .build()
+        .unwrap()
+        .to_vec();
+
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("out");
+    let mut archive = Archive::new(&nar[..]);
+    archive.set_symlink_policy(SymlinkPolicy::Copy);
+    archive.unpack(&out).unwrap();
+
+    for path in &["a-link", "chain", "lib/hello", "share/doc/hello"] {
+        let path = out.join(path);
+        assert!(!fs::symlink_metadata(&path)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read(&path).unwrap(), b"hello");
+        let mode =
+            std::os::unix::fs::PermissionsExt::mode(&fs::metadata(&path).unwrap().permissions());
+        assert_eq!(mode & 0o111, 0o111, "{:?}", path);
+    }
+    assert!(fs::symlink_metadata(out.join("lib")).unwrap().is_dir());
+}
+
+#[test]
+fn rejects_or_skips_symlinks_that_cannot_be_copied() {
+    let nar = NarTree::builder()
+        .symlink("absolute", "/etc/passwd")
+        .symlink("dangling", "missing")
+        .dir("dir", |d| d.symlink("self", "."))
+        .symlink("escape", "../outside")
+        .file("file", "", false)
+        .build()
+        .unwrap()
+        .to_vec();
+
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("outside"), "secret").unwrap();
+    let mut archive = Archive::new(&nar[..]);
+    archive.set_symlink_policy(SymlinkPolicy::Copy);
+    let error = archive.unpack(dir.path().join("strict")).unwrap_err();
+    assert!(
+        error.to_string().contains("outside the unpacked tree"),
+        "{}",
+        error
+    );
+
+    let out = dir.path().join("lenient");
+    let mut archive = Archive::new(&nar[..]);
+    archive.set_symlink_policy(SymlinkPolicy::CopyOrSkip);
+    archive.unpack(&out).unwrap();
+
+    let mut skipped: Vec<PathBuf> = archive
+        .take_warnings()
+        .into_iter()
+        .map(|warning| match warning {
+            Warning::SymlinkSkipped { path, .. } => path.strip_prefix(&out).unwrap().to_owned(),
+            other => panic!("unexpected warning {:?}", other),
+        })
+        .collect();
+    skipped.sort();
+    let expected: Vec<PathBuf> = ["absolute", "dangling", "dir/self", "escape"]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+    assert_eq!(skipped, expected);
+    assert!(out.join("file").exists());
+    assert!(!out.join("escape").exists());
+}
+
+#[test]
+fn enforces_parse_limits() {
+    let nar = NarTree::builder()
+        .dir("wide", |d| {
+            d.file("a", "", false)
+                .file("b", "", false)
+                .file("c", "", false)
+        })
+        .dir("deep", |d| d.dir("nested", |d| d.file("file", "", false)))
+        .build()
+        .unwrap()
+        .to_vec();
+
+    let count = |limits: ParseLimits| {
+        let mut options = UnpackOptions::new();
+        options.set_parse_limits(limits);
+        let mut archive = options.archive(&nar[..]);
+        let entries = archive.entries()?;
+        entries
+            .collect::<std::io::Result<Vec<_>>>()
+            .map(|e| e.len())
+    };
+
+    assert_eq!(count(ParseLimits::unlimited()).unwrap(), 8);
+    assert_eq!(
+        count(ParseLimits::unlimited().with_max_dir_entries(Some(3))).unwrap(),
+        8
+    );
+    assert_eq!(
+        count(ParseLimits::unlimited().with_max_path_len(Some(16))).unwrap(),
+        8
+    );
+
+    let err = count(ParseLimits::unlimited().with_max_dir_entries(Some(2))).unwrap_err();
+    assert_eq!(err.to_string(), "Directory has more than 2 entries");
+    let err = err.get_ref().unwrap().downcast_ref::<ParseError>().unwrap();
+    assert_eq!(err.path(), Path::new("wide"));
+
+    let err = count(ParseLimits::unlimited().with_max_path_len(Some(15))).unwrap_err();
+    assert_eq!(err.to_string(), "Entry path exceeds 15 bytes");
+    let err = err.get_ref().unwrap().downcast_ref::<ParseError>().unwrap();
+    assert_eq!(err.found(), b"file");
+}
+
+#[test]
+fn bounds_entry_readers_to_their_contents() {
+    use std::io::Read;
+
+    let nar = NarTree::builder()
+        .file("a", "first", false)
+        .file("b", "second", false)
+        .build()
+        .unwrap()
+        .to_vec();
+
+    let mut archive = Archive::new(&nar[..]);
+    let mut entries = archive.entries().unwrap();
+    let root = entries.next().unwrap().unwrap();
+    assert!(root.take_reader().is_err());
+
+    let a = entries.next().unwrap().unwrap();
+    let mut contents = Vec::new();
+    a.take_reader().unwrap().read_to_end(&mut contents).unwrap();
+    assert_eq!(contents, b"first");
+
+    let b = entries.next().unwrap().unwrap();
+    let mut reader = b.take_reader().unwrap();
+    assert_eq!(reader.limit(), 6);
+    let mut buf = vec![0; 64];
+    assert_eq!(reader.read(&mut buf).unwrap(), 6);
+    assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    assert!(entries.next().is_none());
+}
+
+#[test]
+fn continues_after_entry_readers_are_dropped_early() {
+    use std::io::Read;
+
+    let nar = NarTree::builder()
+        .dir("bin", |d| d.file("big", vec![7; 10_000], true))
+        .file("next", "after", false)
+        .build()
+        .unwrap()
+        .to_vec();
+
+    let mut archive = Archive::new(&nar[..]);
+    let mut names = Vec::new();
+    for entry in archive.entries().unwrap() {
+        let entry = entry.unwrap();
+        if let Ok(mut reader) = entry.take_reader() {
+            let mut byte = [0];
+            reader.read_exact(&mut byte).unwrap();
+        }
+        names.push(entry.nar_path());
+    }
+    assert_eq!(names, ["", "bin", "bin/big", "next"]);
+}
+
+/// Interrupts every other read, and fails with `WouldBlock` once `block_at` bytes were read.
+struct Flaky<'a> {
+    data: &'a [u8],
+    interrupt: bool,
+    block_at: Option<usize>,
+    read: usize,
+}
+
+impl std::io::Read for Flaky<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        use std::io::{Error, ErrorKind};
+
+        self.interrupt = !self.interrupt;
+        if self.interrupt {
+            return Err(Error::new(ErrorKind::Interrupted, "interrupted"));
+        }
+        if self.block_at.is_some_and(|at| self.read >= at) {
+            return Err(Error::new(ErrorKind::WouldBlock, "would block"));
+        }
+        let len = buf.len().min(3);
+        let len = self.data.read(&mut buf[..len])?;
+        self.read += len;
+        Ok(len)
+    }
+}
+
+#[test]
+fn retries_interrupted_reads_and_reports_would_block() {
+    let nar = NarTree::builder()
+        .dir("bin", |d| d.file("tool", "tool", true))
+        .build()
+        .unwrap()
+        .to_vec();
+
+    let reader = Flaky {
+        data: &nar,
+        interrupt: false,
+        block_at: None,
+        read: 0,
+    };
+    let listing = Archive::new(reader).listing().unwrap();
+    assert_eq!(listing, Archive::new(&nar[..]).listing().unwrap());
+
+    let reader = Flaky {
+        data: &nar,
+        interrupt: false,
+        block_at: Some(30),
+        read: 0,
+    };
+    let err = Archive::new(reader).listing().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock);
+    assert_eq!(
+        err.to_string(),
+        "Reader would block at offset 30; archives must be read from blocking readers"
+    );
+}
@@ -0,0 +1,45 @@
+#![cfg(feature = "diagnostics")]
+
+use libnar::de::ParseError;
+use libnar::{wire, Archive};
+
+fn encode(tokens: &[&[u8]]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for token in tokens {
+        wire::write_token(&mut bytes, token).unwrap();
+    }
+    bytes
+}
+
+#[test]
+fn renders_parse_errors_with_context() {
+    let bytes = encode(&[
+        b"nix-archive-1",
+        b"(",
+        b"type",
+        b"directory",
+        b"entry",
+        b"(",
+        b"name",
+        b"bin",
+        b"node",
+        b"(",
+        b"type",
+        b"regular",
+        b"contents",
+        b"hi",
+        b"]",
+    ]);
+
+    let err = Archive::new(&bytes[..]).listing().unwrap_err();
+    let parse_error = err.get_ref().unwrap().downcast_ref::<ParseError>().unwrap();
+    assert_eq!(parse_error.offset(), bytes.len() as u64 - 16);
+
+    let report = libnar::diagnostics::render(&err);
+    assert!(report.starts_with("error: Missing regular close tag\n"));
+    assert!(report.contains("--> <root> > bin"));
+    assert!(report.contains(&format!("at byte {} ", parse_error.offset())));
+    assert!(report.contains("expected: \")\""));
+    assert!(report.contains("found: \"]\""));
+    assert!(report.contains("01 00 00 00 00 00 00 00 5d 00 00 00 00 00 00 00  |........]......."));
+}
@@ -0,0 +1,183 @@
+#![cfg(all(feature = "fs", feature = "json"))]
+
+use libnar::dump::{json_to_nar, nar_to_json, ContentEncoding};
+use libnar::tree::NarTree;
+use serde_json::{json, Value};
+
+fn example_nar() -> Vec<u8> {
+    NarTree::builder()
+        .dir("bin", |d| d.file("hello", "hello", true))
+        .dir("empty", |d| d)
+        .file("data", vec![0, 255], false)
+        .symlink("link", "bin/hello")
+        .build()
+        .unwrap()
+        .to_vec()
+}
+
+fn dump(nar: &[u8], contents: ContentEncoding) -> Value {
+    let mut output = Vec::new();
+    nar_to_json(nar, &mut output, contents).unwrap();
+    serde_json::from_slice(&output).unwrap()
+}
+
+#[test]
+fn dumps_structure_and_contents() {
+    let nar = example_nar();
+    let dumped = dump(&nar, ContentEncoding::Base64);
+
+    let hello = &dumped["entries"]["bin"]["entries"]["hello"];
+    let offset = hello["narOffset"].as_u64().unwrap() as usize;
+    assert_eq!(&nar[offset..offset + 5], b"hello");
+
+    let data_offset = dumped["entries"]["data"]["narOffset"].clone();
+    let expected = json!({
+        "type": "directory",
+        "entries": {
+            "bin": {
+                "type": "directory",
+                "entries": {
+                    "hello": {
+                        "type": "regular",
+                        "size": 5,
+                        "narOffset": offset,
+                        "executable": true,
+                        "contents": "aGVsbG8=",
+                    },
+                },
+            },
+            "data": {
+                "type": "regular",
+                "size": 2,
+                "narOffset": data_offset,
+                "contents": "AP8=",
+            },
+            "empty": { "type": "directory", "entries": {} },
+            "link": { "type": "symlink", "target": "bin/hello" },
+        },
+    });
+    assert_eq!(dumped, expected);
+}
+
+#[test]
+fn hashes_or_skips_contents() {
+    let nar = example_nar();
+
+    let hashed = dump(&nar, ContentEncoding::Hash);
+    let hello = &hashed["entries"]["bin"]["entries"]["hello"];
+    assert_eq!(
+        hello["hash"],
+        "sha256-LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ="
+    );
+    assert!(hello.get("contents").is_none());
+
+    let skipped = dump(&nar, ContentEncoding::Skip);
+    let hello = &skipped["entries"]["bin"]["entries"]["hello"];
+    assert!(hello.get("hash").is_none() && hello.get("contents").is_none());
+    assert_eq!(hello["size"], 5);
+}
+
+#[test]
+fn dumps_single_file_archives() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("file"), "").unwrap();
+    let nar = libnar::to_vec(dir.path().join("file")).unwrap();
+    let dumped = dump(&nar, ContentEncoding::Base64);
+    assert_eq!(dumped["type"], "regular");
+    assert_eq!(dumped["contents"], "");
+}
+
+#[test]
+fn reconstructs_archives_from_json() {
+    let nar = example_nar();
+    let mut json = Vec::new();
+    nar_to_json(&nar[..], &mut json, ContentEncoding::Base64).unwrap();
+
+    let mut rebuilt = Vec::new();
+    json_to_nar(&json[..], &mut rebuilt).unwrap();
+    assert_eq!(rebuilt, nar);
+
+    let authored = r#"{"type":"directory","entries":{
+        "link":{"type":"symlink","target":"bin/hello"},
+        "empty":{"type":"directory","entries":{}},
+        "data":{"type":"regular","narOffset":0,"contents":"AP8="},
+        "bin":{"type":"directory","entries":{
+            "hello":{"type":"regular","size":5,"executable":true,"contents":"aGVsbG8=",
+                "hash":"sha256-LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ="}
+        }}
+    }}"#;
+    let mut rebuilt = Vec::new();
+    json_to_nar(authored.as_bytes(), &mut rebuilt).unwrap();
+    assert_eq!(rebuilt, nar);
+}
+
+#[test]
+fn validates_json_before_reconstructing() {
+    let error =
+        |json: Value| json_to_nar(json.to_string().as_bytes(), &mut Vec::new()).unwrap_err();
+
+    let mut skipped = Vec::new();
+    nar_to_json(&example_nar()[..], &mut skipped, ContentEncoding::Skip).unwrap();
+    let missing = json_to_nar(&skipped[..], &mut Vec::new()).unwrap_err();
+    assert_eq!(
+        missing.to_string(),
+        r#"Invalid node at "bin/hello": missing `contents`"#
+    );
+
+    let file = |extra: Value| {
+        let mut node = json!({"type": "regular", "contents": "aGVsbG8="});
+        node.as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        json!({"type": "directory", "entries": {"file": node}})
+    };
+    assert!(error(file(json!({"size": 4})))
+        .to_string()
+        .contains("`size`"));
+    assert!(error(file(json!({"hash": "sha256-AAAA"})))
+        .to_string()
+        .contains("`hash`"));
+    assert!(error(file(json!({"executeable": true})))
+        .to_string()
+        .contains("unexpected"));
+    assert!(error(file(json!({"contents": "!"})))
+        .to_string()
+        .contains("base64"));
+
+    for name in ["..", "~"] {
+        let bad_name =
+            json!({"type": "directory", "entries": {name: {"type": "symlink", "target": "x"}}});
+        let message = format!("Invalid node at {:?}: Invalid name `{}`", name, name);
+        assert_eq!(error(bad_name).to_string(), message);
+    }
+    assert!(error(json!({"type": "fifo"}))
+        .to_string()
+        .contains("unknown node type"));
+    assert!(error(json!({"type": "symlink", "target": ""}))
+        .to_string()
+        .contains("empty"));
+    assert!(json_to_nar(&b"not json"[..], &mut Vec::new()).is_err());
+}
+
+/// An archive holding a symlink whose target is not valid UTF-8.
+fn non_utf8_symlink_nar() -> Vec<u8> {
+    let mut nar = NarTree::builder()
+        .symlink("link", "bad?")
+        .build()
+        .unwrap()
+        .to_vec();
+    let at = nar.windows(4).position(|token| token == b"bad?").unwrap();
+    nar[at + 3] = 0xff;
+    nar
+}
+
+#[test]
+fn rejects_non_utf8_symlink_targets() {
+    let err = nar_to_json(
+        &non_utf8_symlink_nar()[..],
+        &mut Vec::new(),
+        ContentEncoding::Skip,
+    )
+    .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,83 @@
+#![cfg(all(feature = "fs", feature = "elf"))]
+
+use std::fs;
+
+use libnar::elf::ElfReport;
+
+const BASE: u64 = 0x40_0000;
+
+/// Builds a 64-bit little-endian ELF image with `PT_INTERP`, `PT_LOAD` and `PT_DYNAMIC` segments.
+fn elf_image(interpreter: &str, needed: &[&str]) -> Vec<u8> {
+    let mut image = vec![0; 64 + 3 * 56];
+    image[..6].copy_from_slice(b"\x7fELF\x02\x01");
+    image[0x20..0x28].copy_from_slice(&64u64.to_le_bytes());
+    image[0x36..0x38].copy_from_slice(&56u16.to_le_bytes());
+    image[0x38..0x3a].copy_from_slice(&3u16.to_le_bytes());
+
+    let interp_at = image.len() as u64;
+    image.extend_from_slice(interpreter.as_bytes());
+    image.push(0);
+
+    let strtab_at = image.len() as u64;
+    let mut names = Vec::new();
+    image.push(0);
+    for name in needed {
+        names.push(image.len() as u64 - strtab_at);
+        image.extend_from_slice(name.as_bytes());
+        image.push(0);
+    }
+
+    let dynamic_at = image.len() as u64;
+    for name in &names {
+        image.extend_from_slice(&1u64.to_le_bytes());
+        image.extend_from_slice(&name.to_le_bytes());
+    }
+    image.extend_from_slice(&5u64.to_le_bytes());
+    image.extend_from_slice(&(BASE + strtab_at).to_le_bytes());
+    image.extend_from_slice(&[0; 16]);
+    let dynamic_len = image.len() as u64 - dynamic_at;
+
+    let headers = [
+        (3u32, interp_at, interpreter.len() as u64 + 1),
+        (1, 0, image.len() as u64),
+        (2, dynamic_at, dynamic_len),
+    ];
+    for (i, (kind, offset, len)) in headers.iter().enumerate() {
+        let at = 64 + i * 56;
+        image[at..at + 4].copy_from_slice(&kind.to_le_bytes());
+        image[at + 8..at + 16].copy_from_slice(&offset.to_le_bytes());
+        image[at + 16..at + 24].copy_from_slice(&(BASE + offset).to_le_bytes());
+        image[at + 32..at + 40].copy_from_slice(&len.to_le_bytes());
+    }
+    image
+}
+
+#[test]
+fn reports_interpreter_and_needed_libraries() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("bin")).unwrap();
+    let interpreter = "/nix/store/0c7c96gikmzv87i7lv3vq5s1cmfjd6zf-glibc-2.39/lib/ld.so";
+    let image = elf_image(interpreter, &["libz.so.1", "libc.so.6"]);
+    fs::write(dir.path().join("bin/hello"), image).unwrap();
+    fs::write(dir.path().join("README"), "not a binary").unwrap();
+    fs::write(dir.path().join("truncated"), b"\x7fELF\x02\x01").unwrap();
+    let nar = libnar::to_vec(dir.path()).unwrap();
+
+    let report = ElfReport::generate(&nar[..]).unwrap();
+    assert_eq!(report.files().len(), 3);
+
+    let hello = report.get("bin/hello").unwrap();
+    assert!(hello.is_elf());
+    assert_eq!(hello.interpreter(), Some(interpreter));
+    assert_eq!(hello.needed(), ["libz.so.1", "libc.so.6"]);
+
+    let readme = report.get("README").unwrap();
+    assert!(!readme.is_elf());
+    assert_eq!(readme.interpreter(), None);
+    assert!(readme.needed().is_empty());
+    assert!(!report.get("truncated").unwrap().is_elf());
+
+    let json = report.to_json();
+    assert!(json.contains(r#"{"path":"README","isElf":false}"#));
+    assert_eq!(ElfReport::from_json(&json).unwrap(), report);
+}
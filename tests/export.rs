@@ -0,0 +1,147 @@
+#![cfg(feature = "fs")]
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io::Read;
+
+use libnar::export::{self, ImportStream};
+use libnar::store_path::StoreDir;
+use libnar::wire;
+
+const APP: &str = "00000000000000000000000000000app-app";
+const LIB: &str = "0000000000000000000000000000lib0-lib";
+const LIBC: &str = "000000000000000000000000000libc0-libc";
+
+fn graph(store: &str) -> HashMap<String, Vec<String>> {
+    let path = |base: &str| format!("{}/{}", store, base);
+    let mut graph = HashMap::new();
+    graph.insert(path(APP), vec![path(LIB), path(LIBC)]);
+    graph.insert(path(LIB), vec![path(LIBC), path(LIB)]);
+    graph.insert(path(LIBC), vec![]);
+    graph
+}
+
+#[test]
+fn orders_closure_topologically() {
+    let store = StoreDir::default();
+    let graph = graph("/nix/store");
+    let app = format!("/nix/store/{}", APP);
+    let order = export::closure(&store, [&app, &app], |p| Ok(graph[p].clone())).unwrap();
+
+    let expected: Vec<_> = [LIBC, LIB, APP]
+        .iter()
+        .map(|b| format!("/nix/store/{}", b))
+        .collect();
+    assert_eq!(order, expected);
+}
+
+#[test]
+fn rejects_reference_cycles() {
+    let store = StoreDir::default();
+    let (a, b) = (format!("/nix/store/{}", APP), format!("/nix/store/{}", LIB));
+    let result = export::closure(&store, [&a], |p| {
+        Ok(vec![if p == a { b.clone() } else { a.clone() }])
+    });
+    assert!(result.is_err());
+}
+
+#[test]
+fn writes_nix_export_stream() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = StoreDir::new(dir.path().to_str().unwrap()).unwrap();
+    for base in &[APP, LIB, LIBC] {
+        fs::write(dir.path().join(base), base.as_bytes()).unwrap();
+    }
+
+    let graph = graph(store.as_str());
+    let app = format!("{}/{}", store, APP);
+    let mut stream = Vec::new();
+    let paths =
+        export::export_closure(&mut stream, &store, [&app], |p| Ok(graph[p].clone())).unwrap();
+    assert_eq!(paths.len(), 3);
+
+    let mut reader = &stream[..];
+    let read_u64 = |reader: &mut &[u8]| {
+        let mut buf = [0; 8];
+        reader.read_exact(&mut buf).unwrap();
+        u64::from_le_bytes(buf)
+    };
+
+    for path in &paths {
+        assert_eq!(read_u64(&mut reader), 1);
+        let nar = libnar::to_vec(path).unwrap();
+        assert_eq!(&reader[..nar.len()], &nar[..]);
+        reader = &reader[nar.len()..];
+        assert_eq!(read_u64(&mut reader), 0x4558_494e);
+        assert_eq!(
+            wire::read_token(&mut reader, 4096).unwrap(),
+            path.as_bytes()
+        );
+        let refs = read_u64(&mut reader);
+        assert_eq!(
+            refs as usize,
+            graph[path].iter().collect::<BTreeSet<_>>().len()
+        );
+        for _ in 0..refs {
+            wire::read_token(&mut reader, 4096).unwrap();
+        }
+        assert_eq!(wire::read_token(&mut reader, 4096).unwrap(), b"");
+        assert_eq!(read_u64(&mut reader), 0);
+    }
+    assert_eq!(read_u64(&mut reader), 0);
+    assert!(reader.is_empty());
+}
+
+#[test]
+fn imports_each_path_with_streaming_reader() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = StoreDir::new(dir.path().to_str().unwrap()).unwrap();
+    for base in &[APP, LIB, LIBC] {
+        fs::write(dir.path().join(base), format!("({})", base)).unwrap();
+    }
+
+    let graph = graph(store.as_str());
+    let app = format!("{}/{}", store, APP);
+    let mut stream = Vec::new();
+    let exported =
+        export::export_closure(&mut stream, &store, [&app], |p| Ok(graph[p].clone())).unwrap();
+
+    let mut import = ImportStream::new(&stream[..]);
+    import.set_store_dir(store.clone());
+    let mut seen = Vec::new();
+    let count = import
+        .for_each(|path, refs, nar| {
+            let expected: BTreeSet<_> = graph[path].iter().cloned().collect();
+            assert_eq!(refs.iter().cloned().collect::<BTreeSet<_>>(), expected);
+            if path.ends_with("lib") {
+                // Leave the NAR partly unread; the stream must still resynchronize.
+                nar.read_exact(&mut [0; 8])?;
+            } else {
+                let mut bytes = Vec::new();
+                nar.read_to_end(&mut bytes)?;
+                assert_eq!(bytes, libnar::to_vec(path).unwrap());
+            }
+            seen.push(path.to_owned());
+            Ok(())
+        })
+        .unwrap();
+
+    assert_eq!(count, 3);
+    assert_eq!(seen, exported);
+}
+
+#[test]
+fn rejects_truncated_import_stream() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = StoreDir::new(dir.path().to_str().unwrap()).unwrap();
+    fs::write(dir.path().join(LIBC), "libc").unwrap();
+
+    let libc = format!("{}/{}", store, LIBC);
+    let mut stream = Vec::new();
+    export::export_closure(&mut stream, &store, [&libc], |_| Ok(vec![])).unwrap();
+    stream.truncate(stream.len() - 24);
+
+    let mut import = ImportStream::new(&stream[..]);
+    import.set_store_dir(store);
+    assert!(import.for_each(|_, _, _| Ok(())).is_err());
+}
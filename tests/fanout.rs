@@ -0,0 +1,91 @@
+#![cfg(feature = "fs")]
+
+use std::fs;
+use std::io::{self, Write};
+
+use libnar::fanout::FanOut;
+use libnar::hash::NarHasher;
+
+#[test]
+fn duplicates_stream_into_every_sink() {
+    let data: Vec<u8> = (0..300_000u32).map(|i| (i % 241) as u8).collect();
+
+    let mut fanout = FanOut::new(2);
+    let copy = fanout.add_sink(Vec::new());
+    let hasher = fanout.add_sink(NarHasher::new());
+    let len = fanout.add_sink_with(Vec::new(), |v| Ok(v.len()));
+    fanout.write_all(&data).unwrap();
+    fanout.finish().unwrap();
+
+    assert_eq!(copy.join().unwrap(), data);
+    assert_eq!(hasher.join().unwrap().size(), data.len() as u64);
+    assert_eq!(len.join().unwrap(), data.len());
+}
+
+#[derive(Debug)]
+struct Failing;
+
+impl Write for Failing {
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+        Err(io::Error::other("disk full"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn reports_failing_sink() {
+    let mut fanout = FanOut::new(1);
+    let failing = fanout.add_sink(Failing);
+    let _healthy = fanout.add_sink(Vec::new());
+
+    let written = (0..64).try_for_each(|_| fanout.write_all(&[0; 64 * 1024]));
+    let finished = written.and_then(|_| fanout.finish());
+    assert!(finished.is_err());
+    assert_eq!(failing.join().unwrap_err().to_string(), "disk full");
+}
+
+#[cfg(all(feature = "zstd", feature = "xz"))]
+#[test]
+fn packs_hash_and_two_compressions_in_one_pass() {
+    use std::io::Read;
+
+    use libnar::compression::{xz, zstd};
+
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("file"), "lorem ipsum ".repeat(1000)).unwrap();
+
+    let artifacts = libnar::fanout::pack_artifacts(dir.path(), Vec::new(), Vec::new()).unwrap();
+    let nar = libnar::to_vec(dir.path()).unwrap();
+    assert_eq!(
+        (artifacts.nar_hash, artifacts.nar_size),
+        libnar::hash_path(dir.path()).unwrap()
+    );
+
+    let mut unzstd = Vec::new();
+    zstd::decoder(&artifacts.zstd[..])
+        .unwrap()
+        .read_to_end(&mut unzstd)
+        .unwrap();
+    assert_eq!(unzstd, nar);
+
+    let mut unxz = Vec::new();
+    xz::decoder(&artifacts.xz[..])
+        .read_to_end(&mut unxz)
+        .unwrap();
+    assert_eq!(unxz, nar);
+}
+
+#[test]
+fn packs_into_fanout() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("file"), "contents").unwrap();
+
+    let mut fanout = FanOut::new(4);
+    let copy = fanout.add_sink(Vec::new());
+    libnar::to_writer(&mut fanout, dir.path()).unwrap();
+    fanout.finish().unwrap();
+    assert_eq!(copy.join().unwrap(), libnar::to_vec(dir.path()).unwrap());
+}
@@ -0,0 +1,125 @@
+#![cfg(feature = "fs")]
+
+use std::fs;
+use std::io::{self, Read, Write};
+
+use libnar::hash::{
+    hash_flat_file, hash_flat_reader, HasherState, NarHasher, Sha256Hash, VerifyingReader,
+};
+
+#[test]
+fn resumes_hashing_from_saved_state() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("file"), vec![7u8; 10_000]).unwrap();
+    fs::write(dir.path().join("other"), "lorem ipsum").unwrap();
+    let bytes = libnar::to_vec(dir.path()).unwrap();
+
+    let mut whole = NarHasher::new();
+    whole.write_all(&bytes).unwrap();
+    let expected = whole.finish();
+    assert_eq!(expected.1, bytes.len() as u64);
+
+    let mut first = NarHasher::new();
+    first.write_all(&bytes[..4099]).unwrap();
+    let saved = first.state().to_bytes();
+
+    let state = HasherState::from_bytes(&saved).unwrap();
+    let mut resumed = NarHasher::resume(&state);
+    let offset = resumed.size() as usize;
+    resumed.write_all(&bytes[offset..]).unwrap();
+    assert_eq!(resumed.finish(), expected);
+}
+
+#[test]
+fn rejects_corrupt_hasher_state() {
+    let mut hasher = NarHasher::new();
+    hasher.update(b"abc");
+    let mut saved = hasher.state().to_bytes();
+
+    saved.pop();
+    assert!(HasherState::from_bytes(&saved).is_err());
+    assert!(HasherState::from_bytes(&[]).is_err());
+}
+
+#[test]
+fn hashes_flat_files_in_nix_formats() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("abc");
+    fs::write(&path, "abc").unwrap();
+
+    let hash = hash_flat_file(&path).unwrap();
+    assert_eq!(hash, hash_flat_reader(&b"abc"[..]).unwrap());
+    assert_eq!(
+        hash.to_hex(),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    assert_eq!(
+        hash.to_string(),
+        "sha256:1b8m03r63zqhnjf7l5wnldhh7c134ap5vpj0850ymkq1iyzicy5s"
+    );
+    assert_eq!(
+        hash.to_sri(),
+        "sha256-ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0="
+    );
+
+    for text in [
+        hash.to_string(),
+        hash.to_sri(),
+        format!("sha256:{}", hash.to_hex()),
+    ] {
+        assert_eq!(text.parse::<Sha256Hash>().unwrap(), hash);
+    }
+    assert!("md5:abc".parse::<Sha256Hash>().is_err());
+    assert!("sha256:abc".parse::<Sha256Hash>().is_err());
+}
+
+#[test]
+fn hashes_path_without_producing_archive() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("bin")).unwrap();
+    fs::write(dir.path().join("bin").join("hello"), "hello").unwrap();
+    std::os::unix::fs::symlink("bin/hello", dir.path().join("link")).unwrap();
+
+    let bytes = libnar::to_vec(dir.path()).unwrap();
+    let (hash, size) = libnar::hash_path(dir.path()).unwrap();
+    assert_eq!(hash, hash_flat_reader(&bytes[..]).unwrap());
+    assert_eq!(size, bytes.len() as u64);
+    assert_eq!(size, libnar::ser::archive_len(dir.path()).unwrap());
+}
+
+#[test]
+fn verifies_downloads_while_streaming() {
+    let data = vec![7u8; 100_000];
+    let hash = hash_flat_reader(&data[..]).unwrap();
+    let len = data.len() as u64;
+
+    let mut out = Vec::new();
+    VerifyingReader::new(&data[..], hash, len)
+        .read_to_end(&mut out)
+        .unwrap();
+    assert_eq!(out, data);
+
+    let mut corrupt = data.clone();
+    corrupt[50_000] = 8;
+    let mut reader = VerifyingReader::new(&corrupt[..], hash, len);
+    let err = io::copy(&mut reader, &mut io::sink()).unwrap_err();
+    assert!(err.to_string().starts_with("File hash mismatch"));
+    assert!(reader.read(&mut [0; 16]).is_err());
+
+    let mut reader = VerifyingReader::new(&data[..], hash, 1000);
+    let mut buf = [0; 4096];
+    let err = reader.read(&mut buf).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "File size mismatch: expected 1000 bytes, got more"
+    );
+
+    let err = io::copy(
+        &mut VerifyingReader::new(&data[..99_999], hash, len),
+        &mut io::sink(),
+    );
+    assert_eq!(
+        err.unwrap_err().to_string(),
+        "File size mismatch: expected 100000 bytes, got 99999"
+    );
+}
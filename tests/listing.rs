@@ -0,0 +1,181 @@
+#![cfg(feature = "fs")]
+
+use std::collections::BTreeMap;
+use std::fs;
+
+use libnar::listing::{Listing, Node};
+use libnar::tree::NarTree;
+use libnar::Archive;
+
+fn example_tree() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("bin")).unwrap();
+    fs::write(dir.path().join("bin").join("hello"), "hello").unwrap();
+    std::os::unix::fs::symlink("bin/hello", dir.path().join("link")).unwrap();
+    dir
+}
+
+#[test]
+fn lists_single_file_with_offset() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("file"), "lorem ipsum").unwrap();
+    let bytes = libnar::to_vec(dir.path().join("file")).unwrap();
+
+    let listing = Archive::new(&bytes[..]).listing().unwrap();
+    let expected = Node::Regular {
+        size: 11,
+        executable: false,
+        nar_offset: Some(96),
+    };
+    assert_eq!(listing.root(), &expected);
+    assert_eq!(&bytes[96..107], b"lorem ipsum");
+}
+
+#[test]
+fn lists_directory_tree() {
+    let dir = example_tree();
+    let bytes = libnar::to_vec(dir.path()).unwrap();
+    let listing = Archive::new(&bytes[..]).listing().unwrap();
+
+    match listing.get("bin/hello") {
+        Some(Node::Regular {
+            size: 5,
+            executable: false,
+            nar_offset: Some(offset),
+        }) => {
+            let offset = *offset as usize;
+            assert_eq!(&bytes[offset..offset + 5], b"hello");
+        }
+        other => panic!("unexpected node {:?}", other),
+    }
+
+    let expected = Node::Symlink {
+        target: "bin/hello".to_owned(),
+    };
+    assert_eq!(listing.get("link"), Some(&expected));
+    assert_eq!(listing.get("missing"), None);
+}
+
+#[test]
+fn verifies_archive_against_listing() {
+    let dir = example_tree();
+    let bytes = libnar::to_vec(dir.path()).unwrap();
+    let listing = Archive::new(&bytes[..]).listing().unwrap();
+    Archive::new(&bytes[..]).verify_listing(&listing).unwrap();
+
+    fs::write(dir.path().join("bin").join("hello"), "tampered").unwrap();
+    let tampered = libnar::to_vec(dir.path()).unwrap();
+    let err = Archive::new(&tampered[..])
+        .verify_listing(&listing)
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Listing mismatch at \"bin/hello\": expected size 5, found 8"
+    );
+
+    let truncated = &bytes[..bytes.len() - 8];
+    assert!(Archive::new(truncated).verify_listing(&listing).is_err());
+}
+
+#[test]
+fn ignores_offsets_missing_from_listing() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("file"), "abc").unwrap();
+    let bytes = libnar::to_vec(dir.path()).unwrap();
+
+    let mut entries = BTreeMap::new();
+    entries.insert(
+        "file".to_owned(),
+        Node::Regular {
+            size: 3,
+            executable: false,
+            nar_offset: None,
+        },
+    );
+    let listing = Listing::new(Node::Directory { entries });
+    Archive::new(&bytes[..]).verify_listing(&listing).unwrap();
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn round_trips_nix_listing_json() {
+    let json = r#"{"version":1,"root":{"type":"directory","entries":{"bin":{"type":"directory","entries":{"hello":{"type":"regular","size":5,"executable":true,"narOffset":400}}},"link":{"type":"symlink","target":"bin/hello"}}}}"#;
+    let listing = Listing::from_json(json).unwrap();
+
+    let expected = Node::Regular {
+        size: 5,
+        executable: true,
+        nar_offset: Some(400),
+    };
+    assert_eq!(listing.get("bin/hello"), Some(&expected));
+    assert_eq!(listing.to_json(), json);
+
+    let unsupported = json.replace(r#""version":1"#, r#""version":2"#);
+    assert!(Listing::from_json(&unsupported).is_err());
+}
+
+#[test]
+fn lists_many_archive_files_concurrently() {
+    let cache = tempfile::tempdir().unwrap();
+    let nar = libnar::to_vec(example_tree().path()).unwrap();
+    let mut paths = Vec::new();
+    for i in 0..5 {
+        let path = cache.path().join(format!("{}.nar", i));
+        fs::write(&path, &nar).unwrap();
+        paths.push(path);
+    }
+    let broken = cache.path().join("broken.nar");
+    fs::write(&broken, &nar[..nar.len() / 2]).unwrap();
+    paths.push(broken.clone());
+
+    let expected = Archive::new(&nar[..]).listing().unwrap();
+    let mut results: Vec<_> = libnar::listing::list_many(paths.clone(), 3).collect();
+    results.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(results.len(), 6);
+    for (path, listing) in results {
+        if path == broken {
+            assert!(listing.is_err());
+        } else {
+            assert_eq!(listing.unwrap(), expected);
+        }
+    }
+
+    assert_eq!(libnar::listing::list_many(Vec::new(), 0).count(), 0);
+}
+
+#[cfg(feature = "xz")]
+#[test]
+fn lists_xz_compressed_archive_files() {
+    use std::io::Write;
+
+    let cache = tempfile::tempdir().unwrap();
+    let nar = libnar::to_vec(example_tree().path()).unwrap();
+    let path = cache.path().join("example.nar.xz");
+    let mut encoder = libnar::compression::xz::encoder(fs::File::create(&path).unwrap(), 6);
+    encoder.write_all(&nar).unwrap();
+    encoder.finish().unwrap();
+
+    let results: Vec<_> = libnar::listing::list_many(vec![path], 1).collect();
+    let expected = Archive::new(&nar[..]).listing().unwrap();
+    assert_eq!(results[0].1.as_ref().unwrap(), &expected);
+}
+
+/// An archive holding a symlink whose target is not valid UTF-8.
+fn non_utf8_symlink_nar() -> Vec<u8> {
+    let mut nar = NarTree::builder()
+        .symlink("link", "bad?")
+        .build()
+        .unwrap()
+        .to_vec();
+    let at = nar.windows(4).position(|token| token == b"bad?").unwrap();
+    nar[at + 3] = 0xff;
+    nar
+}
+
+#[test]
+fn rejects_non_utf8_symlink_targets() {
+    let err = Archive::new(&non_utf8_symlink_nar()[..])
+        .listing()
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,50 @@
+#![cfg(feature = "macros")]
+
+use libnar::nar_tree;
+use libnar::tree::{NarTree, Node};
+
+#[test]
+fn declares_nested_trees() {
+    let tree = nar_tree! {
+        "bin" => {
+            "hello" => exec(b"#!/bin/sh\n"),
+            "readme" => file(b"hi"),
+        },
+        "empty" => {},
+        "lib" => symlink("bin"),
+    };
+
+    let expected = NarTree::builder()
+        .dir("bin", |d| {
+            d.file("hello", "#!/bin/sh\n", true)
+                .file("readme", "hi", false)
+        })
+        .dir("empty", |d| d)
+        .symlink("lib", "bin")
+        .build()
+        .unwrap();
+    assert_eq!(tree, expected);
+    assert_eq!(
+        nar_tree! {}.root(),
+        &Node::Directory {
+            entries: Default::default()
+        }
+    );
+}
+
+#[test]
+fn accepts_owned_contents() {
+    let contents = String::from("owned");
+    let tree = nar_tree! { "data" => file(contents) };
+    let expected = Node::Regular {
+        executable: false,
+        contents: b"owned".to_vec(),
+    };
+    assert_eq!(tree.get("data"), Some(&expected));
+}
+
+#[test]
+#[should_panic(expected = "invalid tree")]
+fn panics_on_invalid_trees() {
+    let _ = nar_tree! { "a" => file(b""), "a" => symlink("b") };
+}
@@ -0,0 +1,72 @@
+#![cfg(feature = "fs")]
+
+use std::fs;
+use std::path::PathBuf;
+
+use libnar::hash::hash_path;
+use libnar::Archive;
+
+#[test]
+fn directory_digests_match_standalone_nar_hashes() {
+    let src = tempfile::tempdir().unwrap();
+    fs::create_dir_all(src.path().join("a").join("b")).unwrap();
+    fs::write(src.path().join("a").join("b").join("file"), "contents").unwrap();
+    fs::write(src.path().join("a").join("other"), "other").unwrap();
+    std::os::unix::fs::symlink("a", src.path().join("link")).unwrap();
+    let bytes = libnar::to_vec(src.path()).unwrap();
+
+    let tree = Archive::new(&bytes[..]).merkle_tree().unwrap();
+    assert_eq!(tree.len(), 3);
+    assert_eq!(tree.root(), Some(&hash_path(src.path()).unwrap().0));
+    assert_eq!(
+        tree.get("a"),
+        Some(&hash_path(src.path().join("a")).unwrap().0)
+    );
+    assert_eq!(
+        tree.get("a/b"),
+        Some(&hash_path(src.path().join("a").join("b")).unwrap().0)
+    );
+}
+
+#[test]
+fn records_root_digest_of_single_file_archive() {
+    let src = tempfile::tempdir().unwrap();
+    let file = src.path().join("file");
+    fs::write(&file, "contents").unwrap();
+    let bytes = libnar::to_vec(&file).unwrap();
+
+    let tree = Archive::new(&bytes[..]).merkle_tree().unwrap();
+    assert_eq!(tree.root(), Some(&hash_path(&file).unwrap().0));
+}
+
+#[test]
+fn diff_reports_outermost_changed_directories() {
+    let src = tempfile::tempdir().unwrap();
+    for dir in &["x/deep", "y/deep"] {
+        fs::create_dir_all(src.path().join(dir)).unwrap();
+        fs::write(src.path().join(dir).join("file"), "same").unwrap();
+    }
+    let before = Archive::new(&libnar::to_vec(src.path()).unwrap()[..])
+        .merkle_tree()
+        .unwrap();
+
+    fs::write(src.path().join("y/deep/file"), "changed").unwrap();
+    let after = Archive::new(&libnar::to_vec(src.path()).unwrap()[..])
+        .merkle_tree()
+        .unwrap();
+
+    assert_eq!(before.get("x"), after.get("x"));
+    assert_eq!(
+        before.diff(&after),
+        vec![PathBuf::new(), PathBuf::from("y"), PathBuf::from("y/deep")]
+    );
+
+    fs::remove_dir_all(src.path().join("y")).unwrap();
+    let removed = Archive::new(&libnar::to_vec(src.path()).unwrap()[..])
+        .merkle_tree()
+        .unwrap();
+    assert_eq!(
+        before.diff(&removed),
+        vec![PathBuf::new(), PathBuf::from("y")]
+    );
+}
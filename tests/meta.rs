@@ -0,0 +1,40 @@
+use libnar::de::{to_meta, EntryType};
+use libnar::tree::NarTree;
+
+fn example_nar() -> Vec<u8> {
+    NarTree::builder()
+        .dir("bin", |d| d.file("tool", "tool", true))
+        .symlink("link", "bin/tool")
+        .build()
+        .unwrap()
+        .to_vec()
+}
+
+#[test]
+fn snapshots_entry_metadata() {
+    let nar = example_nar();
+    let metas = to_meta(&nar[..]).unwrap();
+    let paths: Vec<_> = metas.iter().map(|m| m.path.as_str()).collect();
+    assert_eq!(paths, ["", "bin", "bin/tool", "link"]);
+    assert_eq!(metas[0].kind, EntryType::Directory);
+    assert_eq!(metas[3].kind, EntryType::Symlink);
+
+    let tool = &metas[2];
+    assert_eq!(tool.kind, EntryType::Regular);
+    assert_eq!(tool.size, 4);
+    assert!(tool.executable);
+    let offset = tool.offset.unwrap() as usize;
+    assert_eq!(&nar[offset..offset + 4], b"tool");
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn serializes_entry_metadata() {
+    let metas = to_meta(&example_nar()[..]).unwrap();
+    let json = serde_json::to_string(&metas[2]).unwrap();
+    assert!(json.contains("\"kind\":\"regular\""));
+    assert_eq!(
+        serde_json::from_str::<libnar::de::EntryMeta>(&json).unwrap(),
+        metas[2]
+    );
+}
@@ -0,0 +1,193 @@
+#![cfg(feature = "fs")]
+
+use std::fs;
+use std::io::Cursor;
+use std::os::unix::fs::PermissionsExt;
+
+use libnar::narmeta::{MetaKind, NarMeta};
+use libnar::tree::NarTree;
+
+fn example_nar() -> Vec<u8> {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("bin")).unwrap();
+    fs::write(
+        dir.path().join("bin").join("hello"),
+        "#!/bin/sh\necho hello\n",
+    )
+    .unwrap();
+    fs::set_permissions(
+        dir.path().join("bin").join("hello"),
+        fs::Permissions::from_mode(0o755),
+    )
+    .unwrap();
+    fs::write(dir.path().join("README"), "lorem ipsum").unwrap();
+    std::os::unix::fs::symlink("bin/hello", dir.path().join("link")).unwrap();
+    libnar::to_vec(dir.path()).unwrap()
+}
+
+#[test]
+fn indexes_archive_in_one_pass() {
+    let nar = example_nar();
+    let meta = NarMeta::generate(&nar[..]).unwrap();
+
+    let (hash, size) = {
+        let mut hasher = libnar::hash::NarHasher::new();
+        hasher.update(&nar);
+        hasher.finish()
+    };
+    assert_eq!(meta.nar_hash(), &hash);
+    assert_eq!(meta.nar_size(), size);
+
+    let paths: Vec<_> = meta.entries().iter().map(|e| e.path()).collect();
+    assert_eq!(paths, ["", "README", "bin", "bin/hello", "link"]);
+    assert!(matches!(
+        meta.get("bin/hello").unwrap().kind(),
+        MetaKind::Regular {
+            executable: true,
+            size: 21,
+            ..
+        }
+    ));
+
+    let names: Vec<_> = meta
+        .list("/")
+        .unwrap()
+        .iter()
+        .map(|e| e.file_name())
+        .collect();
+    assert_eq!(names, ["README", "bin", "link"]);
+    assert!(meta.list("README").is_err());
+}
+
+#[test]
+fn reads_and_verifies_files_without_reparsing() {
+    let nar = example_nar();
+    let meta = NarMeta::generate(&nar[..]).unwrap();
+    let meta = NarMeta::from_bytes(&meta.to_bytes()).unwrap();
+
+    let mut stored = Cursor::new(nar.clone());
+    assert_eq!(
+        meta.read_file(&mut stored, "README").unwrap(),
+        b"lorem ipsum"
+    );
+    meta.verify_file(&mut stored, "bin/hello").unwrap();
+    meta.verify(&nar[..]).unwrap();
+
+    let mut corrupted = nar.clone();
+    let position = corrupted.windows(5).position(|w| w == b"lorem").unwrap();
+    corrupted[position] = b'L';
+    assert!(meta
+        .verify_file(&mut Cursor::new(&corrupted), "README")
+        .is_err());
+    assert!(meta.verify(&corrupted[..]).is_err());
+}
+
+#[test]
+fn rejects_foreign_sidecars() {
+    assert!(NarMeta::from_bytes(b"not a sidecar").is_err());
+    let bytes = NarMeta::generate(&example_nar()[..]).unwrap().to_bytes();
+    assert!(NarMeta::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+}
+
+#[test]
+fn lists_children_apart_from_similarly_named_siblings() {
+    let nar = NarTree::builder()
+        .dir("a", |d| {
+            d.file("c", "", false).dir("d", |d| d.file("e", "", false))
+        })
+        .file("a-b", "", false)
+        .build()
+        .unwrap()
+        .to_vec();
+    let meta = NarMeta::from_bytes(&NarMeta::generate(&nar[..]).unwrap().to_bytes()).unwrap();
+
+    let paths = |dir: &str| -> Vec<String> {
+        let entries = meta.list(dir).unwrap();
+        entries.iter().map(|e| e.path().to_owned()).collect()
+    };
+    assert_eq!(paths(""), ["a", "a-b"]);
+    assert_eq!(paths("/a/"), ["a/c", "a/d"]);
+    assert_eq!(paths("a/d"), ["a/d/e"]);
+    assert_eq!(meta.get("a/d/e").unwrap().file_name(), "e");
+    assert!(meta.get("a/e").is_none());
+}
+
+#[test]
+fn rejects_inconsistent_sidecars() {
+    let nar = NarTree::builder()
+        .file("a", "", false)
+        .dir("x", |d| d.file("y", "", false))
+        .build()
+        .unwrap()
+        .to_vec();
+    let bytes = NarMeta::generate(&nar[..]).unwrap().to_bytes();
+    let replace = |from: &[u8], to: &[u8]| {
+        let mut bytes = bytes.clone();
+        let at = bytes.windows(from.len()).rposition(|w| w == from).unwrap();
+        bytes[at..at + to.len()].copy_from_slice(to);
+        NarMeta::from_bytes(&bytes).unwrap_err()
+    };
+
+    // Rename the directory `x`, whose path is stored after its kind and length.
+    let duplicate = replace(b"\x00\x01\x00\x00\x00x", b"\x00\x01\x00\x00\x00a");
+    assert_eq!(duplicate.to_string(), r#"Duplicate narmeta entry "a""#);
+    let orphan = replace(b"x/y", b"z/y");
+    assert_eq!(
+        orphan.to_string(),
+        r#"Narmeta entry "z/y" has no parent directory"#
+    );
+}
+
+#[test]
+fn caches_indexes_by_nar_hash() {
+    use libnar::narmeta::MetaCache;
+
+    let dir = tempfile::tempdir().unwrap();
+    let cache = MetaCache::open(dir.path()).unwrap();
+    let nar = example_nar();
+    let expected = NarMeta::generate(&nar[..]).unwrap();
+    let hash = *expected.nar_hash();
+    assert!(cache.get(&hash).unwrap().is_none());
+
+    let meta = cache.get_or_index(&hash, || Ok(&nar[..])).unwrap();
+    assert_eq!(meta, expected);
+    assert_eq!(cache.get(&hash).unwrap(), Some(expected.clone()));
+    let cached = cache
+        .get_or_index(&hash, || -> std::io::Result<&[u8]> {
+            panic!("archive reparsed")
+        })
+        .unwrap();
+    assert_eq!(cached, expected);
+
+    fs::write(cache.meta_path(&hash), b"garbage").unwrap();
+    assert!(cache.get(&hash).is_err());
+    assert_eq!(
+        cache.get_or_index(&hash, || Ok(&nar[..])).unwrap(),
+        expected
+    );
+
+    let other = libnar::hash::hash_flat_reader(&b"other"[..]).unwrap();
+    assert!(cache.get_or_index(&other, || Ok(&nar[..])).is_err());
+    assert!(cache.get(&other).unwrap().is_none());
+
+    cache.remove(&hash).unwrap();
+    assert!(cache.get(&hash).unwrap().is_none());
+}
+
+/// An archive holding a symlink whose target is not valid UTF-8.
+fn non_utf8_symlink_nar() -> Vec<u8> {
+    let mut nar = NarTree::builder()
+        .symlink("link", "bad?")
+        .build()
+        .unwrap()
+        .to_vec();
+    let at = nar.windows(4).position(|token| token == b"bad?").unwrap();
+    nar[at + 3] = 0xff;
+    nar
+}
+
+#[test]
+fn rejects_non_utf8_symlink_targets() {
+    let err = NarMeta::generate(&non_utf8_symlink_nar()[..]).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,86 @@
+#![cfg(feature = "fs")]
+
+use std::fs;
+use std::sync::Arc;
+use std::thread;
+
+use libnar::de::UnpackOptions;
+use libnar::ser::PackOptions;
+
+fn assert_shareable<T: Clone + Send + Sync + 'static>() {}
+
+#[test]
+fn options_are_shareable_across_threads() {
+    assert_shareable::<UnpackOptions>();
+    assert_shareable::<PackOptions>();
+}
+
+#[test]
+fn unpacks_concurrently_with_one_global_configuration() {
+    let src = tempfile::tempdir().unwrap();
+    fs::create_dir(src.path().join("bin")).unwrap();
+    fs::write(src.path().join("bin").join("hello"), "hello").unwrap();
+
+    let pack = PackOptions::new();
+    let (bytes, _) = pack.to_vec(src.path()).unwrap();
+
+    let mut unpack = UnpackOptions::new();
+    unpack.set_rollback_on_error(true);
+    let unpack = Arc::new(unpack);
+
+    let dst = tempfile::tempdir().unwrap();
+    thread::scope(|scope| {
+        for i in 0..8 {
+            let unpack = unpack.clone();
+            let (pack, bytes, out) = (&pack, &bytes, dst.path().join(i.to_string()));
+            scope.spawn(move || {
+                unpack.unpack(&bytes[..], &out).unwrap();
+                assert_eq!(pack.to_vec(&out).unwrap().0, *bytes);
+            });
+        }
+    });
+}
+
+/// Records the largest single write it receives.
+#[derive(Default)]
+struct LargestWrite {
+    bytes: Vec<u8>,
+    largest: usize,
+}
+
+impl std::io::Write for LargestWrite {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.largest = self.largest.max(buf.len());
+        self.bytes.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn streams_contents_through_configured_buffers() {
+    let src = tempfile::tempdir().unwrap();
+    let file = src.path().join("file");
+    fs::write(&file, vec![7; 100_000]).unwrap();
+
+    let mut pack = PackOptions::new();
+    pack.set_copy_buffer_len(1000);
+    let mut packed = LargestWrite::default();
+    pack.to_writer(&mut packed, &file).unwrap();
+    assert_eq!(packed.largest, 1000);
+    assert_eq!(packed.bytes, libnar::to_vec(&file).unwrap());
+
+    let mut unpack = UnpackOptions::new();
+    unpack.set_copy_buffer_len(512);
+    let mut contents = LargestWrite::default();
+    let info = unpack
+        .archive(&packed.bytes[..])
+        .unpack_root_file(&mut contents)
+        .unwrap();
+    assert_eq!(info.size(), 100_000);
+    assert_eq!(contents.largest, 512);
+    assert_eq!(contents.bytes, vec![7; 100_000]);
+}
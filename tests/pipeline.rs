@@ -0,0 +1,120 @@
+use std::io;
+
+use libnar::hash;
+use libnar::pipeline::{Cancellation, Pipeline};
+use libnar::tree::NarTree;
+
+fn example_nar() -> Vec<u8> {
+    NarTree::builder()
+        .file("big", vec![42; 300 * 1024], false)
+        .dir("bin", |d| d.file("hello", "hello", true))
+        .build()
+        .unwrap()
+        .to_vec()
+}
+
+#[test]
+fn uploads_archive_with_digests() {
+    let nar = example_nar();
+    let uploaded = Pipeline::new()
+        .run(
+            |w| w.write_all(&nar),
+            |r| {
+                let mut body = Vec::new();
+                r.read_to_end(&mut body)?;
+                Ok(body)
+            },
+        )
+        .unwrap();
+
+    assert_eq!(uploaded.upload, nar);
+    assert_eq!(uploaded.nar_size, nar.len() as u64);
+    assert_eq!(uploaded.nar_hash, hash::hash_flat_reader(&nar[..]).unwrap());
+    assert_eq!(uploaded.file_hash, uploaded.nar_hash);
+    assert_eq!(uploaded.file_size, uploaded.nar_size);
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn compresses_before_uploading() {
+    use libnar::pipeline::Compression;
+    use std::io::Read;
+
+    let nar = example_nar();
+    let mut pipeline = Pipeline::new();
+    pipeline.set_compression(Compression::Zstd(3));
+    let uploaded = pipeline
+        .run(
+            |w| w.write_all(&nar),
+            |r| {
+                let mut body = Vec::new();
+                r.read_to_end(&mut body)?;
+                Ok(body)
+            },
+        )
+        .unwrap();
+
+    assert!(uploaded.file_size < uploaded.nar_size);
+    let hash = hash::hash_flat_reader(&uploaded.upload[..]).unwrap();
+    assert_eq!(uploaded.file_hash, hash);
+    let mut decoded = Vec::new();
+    libnar::compression::zstd::decoder(&uploaded.upload[..])
+        .unwrap()
+        .read_to_end(&mut decoded)
+        .unwrap();
+    assert_eq!(decoded, nar);
+}
+
+#[test]
+fn reports_the_failing_stage() {
+    let nar = example_nar();
+    let err = Pipeline::new()
+        .run(
+            |w| w.write_all(&nar),
+            |r| {
+                r.read_exact(&mut [0; 1024])?;
+                Err::<(), _>(io::Error::other("Bucket is full"))
+            },
+        )
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Bucket is full");
+
+    let err = Pipeline::new()
+        .run(
+            |w| {
+                w.write_all(&nar[..1000])?;
+                Err(io::Error::other("Disk went away"))
+            },
+            |r| io::copy(r, &mut io::sink()),
+        )
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Disk went away");
+
+    let err = Pipeline::new()
+        .run(|w| w.write_all(&nar), |r| r.read(&mut [0; 16]))
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Upload sink returned before reading the whole archive"
+    );
+}
+
+#[test]
+fn stops_when_cancelled() {
+    let nar = example_nar();
+    let cancellation = Cancellation::new();
+    let mut pipeline = Pipeline::new();
+    pipeline.set_cancellation(cancellation.clone());
+
+    let err = pipeline
+        .run(
+            |w| {
+                w.write_all(&nar[..1000])?;
+                cancellation.cancel();
+                w.write_all(&nar[1000..])
+            },
+            |r| io::copy(r, &mut io::sink()),
+        )
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Pipeline was cancelled");
+}
@@ -0,0 +1,86 @@
+#![cfg(feature = "fs")]
+
+use std::fs;
+use std::os::unix::fs::symlink;
+
+use libnar::refs::{Matcher, ReferenceScanner, ScanMode};
+use libnar::store_path::StorePath;
+
+const GLIBC: &str = "0c7c96gikmzv87i7lv3vq5s1cmfjd6zf-glibc-2.39";
+const BASH: &str = "1fs3h3fsxq5c4wdqfihg9b2i9l9gh4ks-bash-5.2";
+const PYTHON: &str = "2g8ml8jnhx8f2ixz1c3mg7qdkm4yldzl-python3-3.12";
+const ZLIB: &str = "3ybgsyvvr7yzd8d0vf0kz0yk6zbhdw7a-zlib-1.3";
+
+fn path(base: &str) -> StorePath {
+    StorePath::from_base_name(base).unwrap()
+}
+
+/// A 64-bit little-endian ELF image consisting of a header and a single `PT_INTERP` segment.
+fn elf_with_interpreter(interpreter: &str) -> Vec<u8> {
+    let mut elf = vec![0; 120];
+    elf[..6].copy_from_slice(b"\x7fELF\x02\x01");
+    elf[0x20..0x28].copy_from_slice(&64u64.to_le_bytes());
+    elf[0x36..0x38].copy_from_slice(&56u16.to_le_bytes());
+    elf[0x38..0x3a].copy_from_slice(&1u16.to_le_bytes());
+    elf[64..68].copy_from_slice(&3u32.to_le_bytes());
+    elf[72..80].copy_from_slice(&120u64.to_le_bytes());
+    let len = interpreter.len() as u64 + 1;
+    elf[96..104].copy_from_slice(&len.to_le_bytes());
+    elf.extend_from_slice(interpreter.as_bytes());
+    elf.push(0);
+    elf
+}
+
+fn example_nar() -> Vec<u8> {
+    let dir = tempfile::tempdir().unwrap();
+    let bin = dir.path().join("bin");
+    fs::create_dir(&bin).unwrap();
+    let interpreter = format!("/nix/store/{}/lib/ld-linux-x86-64.so.2", GLIBC);
+    fs::write(bin.join("hello"), elf_with_interpreter(&interpreter)).unwrap();
+    let script = format!(
+        "#!/nix/store/{}/bin/bash\nexec /nix/store/{}/bin/python3\n",
+        BASH, PYTHON
+    );
+    fs::write(bin.join("run"), script).unwrap();
+    symlink(
+        format!("/nix/store/{}/lib/libz.so", ZLIB),
+        dir.path().join("libz.so"),
+    )
+    .unwrap();
+    libnar::to_vec(dir.path()).unwrap()
+}
+
+fn scanner() -> ReferenceScanner {
+    let unrelated = "4q0jh1c1y1aanx9xmcd7cq1c9r4fpj1v-unrelated";
+    ReferenceScanner::new(
+        [GLIBC, BASH, PYTHON, ZLIB, unrelated]
+            .iter()
+            .map(|b| path(b)),
+    )
+}
+
+#[test]
+fn full_scan_finds_references_anywhere() {
+    let found = scanner().scan(&example_nar()[..]).unwrap();
+    let expected = [GLIBC, BASH, PYTHON, ZLIB]
+        .iter()
+        .map(|b| path(b))
+        .collect();
+    assert_eq!(found, expected);
+}
+
+#[test]
+fn fast_scan_only_inspects_interpreters_and_symlinks() {
+    let mut scanner = scanner();
+    scanner.set_mode(ScanMode::Fast);
+    let found = scanner.scan(&example_nar()[..]).unwrap();
+    let expected = [GLIBC, BASH, ZLIB].iter().map(|b| path(b)).collect();
+    assert_eq!(found, expected);
+
+    scanner.set_matchers(&[Matcher::ElfInterpreter]);
+    let found = scanner.scan(&example_nar()[..]).unwrap();
+    assert_eq!(found, std::iter::once(path(GLIBC)).collect());
+
+    scanner.set_matchers(&[]);
+    assert!(scanner.scan(&example_nar()[..]).unwrap().is_empty());
+}
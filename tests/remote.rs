@@ -0,0 +1,140 @@
+#![cfg(feature = "fs")]
+
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+
+use libnar::remote::{LazyTree, PrefetchReader, RangeTransport, RemoteNar};
+use libnar::Archive;
+
+struct CountingTransport {
+    data: Vec<u8>,
+    fetched: RefCell<u64>,
+}
+
+impl CountingTransport {
+    fn new(data: Vec<u8>) -> Self {
+        CountingTransport {
+            data,
+            fetched: RefCell::new(0),
+        }
+    }
+}
+
+impl RangeTransport for CountingTransport {
+    fn total_len(&self) -> io::Result<u64> {
+        Ok(self.data.len() as u64)
+    }
+
+    fn read_range(&self, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        *self.fetched.borrow_mut() += len;
+        let (start, end) = (offset as usize, (offset + len) as usize);
+        Ok(self.data[start..end].to_vec())
+    }
+}
+
+fn example_nar() -> Vec<u8> {
+    let dir = tempfile::tempdir().unwrap();
+    let big: Vec<u8> = (0..500_000u32).map(|i| (i * 31 % 251) as u8).collect();
+    fs::write(dir.path().join("big"), big).unwrap();
+    fs::write(dir.path().join("small"), "hello").unwrap();
+    libnar::to_vec(dir.path()).unwrap()
+}
+
+#[test]
+fn fetches_only_file_range_from_uncompressed_nar() {
+    let nar = example_nar();
+    let listing = Archive::new(&nar[..]).listing().unwrap();
+    let transport = CountingTransport::new(nar);
+
+    let mut remote = RemoteNar::uncompressed(&transport, listing);
+    assert_eq!(remote.read_file("small").unwrap(), b"hello");
+    assert_eq!(*transport.fetched.borrow(), 5);
+    assert!(remote.read_file("missing").is_err());
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn fetches_only_needed_frames_from_seekable_zstd_nar() {
+    use std::io::Write;
+
+    use libnar::compression::zstd::SeekableWriter;
+
+    let nar = example_nar();
+    let listing = Archive::new(&nar[..]).listing().unwrap();
+    let mut writer = SeekableWriter::with_frame_len(Vec::new(), 3, 16 * 1024);
+    writer.write_all(&nar).unwrap();
+    let compressed = writer.finish().unwrap();
+    let total = compressed.len() as u64;
+
+    let transport = CountingTransport::new(compressed);
+    let mut remote = RemoteNar::zstd_seekable(&transport, listing).unwrap();
+    assert_eq!(remote.read_file("small").unwrap(), b"hello");
+    assert!(*transport.fetched.borrow() < total / 4);
+}
+
+#[test]
+fn materializes_files_lazily_on_access() {
+    let nar = example_nar();
+    let listing = Archive::new(&nar[..]).listing().unwrap();
+    let transport = CountingTransport::new(nar);
+    let remote = RemoteNar::uncompressed(&transport, listing);
+
+    let cache = tempfile::tempdir().unwrap();
+    let mut tree = LazyTree::new(remote, cache.path().join("tree")).unwrap();
+    assert!(!tree.is_materialized("small"));
+    assert_eq!(*transport.fetched.borrow(), 0);
+
+    let local = tree.materialize("small").unwrap();
+    assert_eq!(fs::read(&local).unwrap(), b"hello");
+    assert_eq!(*transport.fetched.borrow(), 5);
+
+    tree.materialize("small").unwrap();
+    assert_eq!(*transport.fetched.borrow(), 5);
+    assert!(!tree.is_materialized("big"));
+    assert!(tree.materialize("../escape").is_err());
+}
+
+/// Returns at most a few bytes per read, failing at `fail_at` if set.
+struct Trickle {
+    data: io::Cursor<Vec<u8>>,
+    fail_at: Option<u64>,
+}
+
+impl io::Read for Trickle {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.fail_at.is_some_and(|at| self.data.position() >= at) {
+            return Err(io::Error::other("connection reset"));
+        }
+        let len = buf.len().min(7);
+        self.data.read(&mut buf[..len])
+    }
+}
+
+#[test]
+fn prefetches_archives_from_slow_readers() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("file"), vec![b'x'; 10_000]).unwrap();
+    let nar = libnar::to_vec(dir.path()).unwrap();
+
+    let reader = PrefetchReader::with_capacity(
+        Trickle {
+            data: io::Cursor::new(nar.clone()),
+            fail_at: None,
+        },
+        5,
+        2,
+    );
+    let listing = Archive::new(reader).listing().unwrap();
+    assert_eq!(listing, Archive::new(&nar[..]).listing().unwrap());
+
+    let mut reader = PrefetchReader::new(Trickle {
+        data: io::Cursor::new(nar.clone()),
+        fail_at: Some(100),
+    });
+    let mut contents = Vec::new();
+    let err = io::Read::read_to_end(&mut reader, &mut contents).unwrap_err();
+    assert_eq!(err.to_string(), "connection reset");
+    assert_eq!(contents, &nar[..105]);
+    assert!(io::Read::read(&mut reader, &mut [0; 8]).is_err());
+}
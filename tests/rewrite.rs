@@ -0,0 +1,200 @@
+#![cfg(feature = "fs")]
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::{symlink, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use libnar::de::ExtractSink;
+use libnar::rewrite::{
+    self, ContentFilter, Filtered, LengthPolicy, Relocator, RpathFilter, ShebangFilter,
+};
+use libnar::{Archive, SymlinkTarget};
+
+const OLD: &str = "/nix/store/1fs3h3fsxq5c4wdqfihg9b2i9l9gh4ks-bash-5.2";
+const NEW: &str = "/opt/app/bash";
+const BASE: u64 = 0x40_0000;
+
+/// Builds a 64-bit little-endian ELF image whose dynamic section only holds `DT_RUNPATH`.
+fn elf_with_runpath(runpath: &str) -> Vec<u8> {
+    let mut image = vec![0; 64 + 2 * 56];
+    image[..6].copy_from_slice(b"\x7fELF\x02\x01");
+    image[0x20..0x28].copy_from_slice(&64u64.to_le_bytes());
+    image[0x36..0x38].copy_from_slice(&56u16.to_le_bytes());
+    image[0x38..0x3a].copy_from_slice(&2u16.to_le_bytes());
+
+    let strtab_at = image.len() as u64;
+    image.push(0);
+    image.extend_from_slice(runpath.as_bytes());
+    image.push(0);
+
+    let dynamic_at = image.len() as u64;
+    for (tag, value) in [(29u64, 1u64), (5, BASE + strtab_at), (0, 0)].iter() {
+        image.extend_from_slice(&tag.to_le_bytes());
+        image.extend_from_slice(&value.to_le_bytes());
+    }
+
+    let headers = [(1u32, 0u64, image.len() as u64), (2, dynamic_at, 48)];
+    for (i, (kind, offset, len)) in headers.iter().enumerate() {
+        let at = 64 + i * 56;
+        image[at..at + 4].copy_from_slice(&kind.to_le_bytes());
+        image[at + 8..at + 16].copy_from_slice(&offset.to_le_bytes());
+        image[at + 16..at + 24].copy_from_slice(&(BASE + offset).to_le_bytes());
+        image[at + 32..at + 40].copy_from_slice(&len.to_le_bytes());
+    }
+    image
+}
+
+fn example_tree() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("bin")).unwrap();
+    fs::create_dir_all(dir.path().join("share/doc")).unwrap();
+    let script = dir.path().join("bin/run");
+    fs::write(&script, format!("#! {}/bin/bash -e\necho {}\n", OLD, OLD)).unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    let runpath = format!("{}/lib:/usr/lib", OLD);
+    fs::write(dir.path().join("bin/tool"), elf_with_runpath(&runpath)).unwrap();
+    fs::write(dir.path().join("share/doc/README"), "docs").unwrap();
+    symlink("../bin/run", dir.path().join("share/run")).unwrap();
+    dir
+}
+
+struct Collect(Vec<(PathBuf, Vec<u8>)>);
+
+impl ExtractSink for Collect {
+    fn create_dir(&mut self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn create_file(&mut self, path: &Path, _executable: bool, contents: &[u8]) -> io::Result<()> {
+        self.0.push((path.to_owned(), contents.to_vec()));
+        Ok(())
+    }
+
+    fn create_symlink(&mut self, _path: &Path, _target: &SymlinkTarget) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct Identity;
+
+impl ContentFilter for Identity {}
+
+#[test]
+fn identity_rewrite_reproduces_archive() {
+    let nar = libnar::to_vec(example_tree().path()).unwrap();
+    let mut out = Vec::new();
+    rewrite::rewrite(&nar[..], &mut out, &mut Identity).unwrap();
+    assert_eq!(out, nar);
+
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("file"), "single").unwrap();
+    let nar = libnar::to_vec(dir.path().join("file")).unwrap();
+    let mut out = Vec::new();
+    rewrite::rewrite(&nar[..], &mut out, &mut Identity).unwrap();
+    assert_eq!(out, nar);
+}
+
+#[test]
+fn patches_shebangs_and_runpaths() {
+    let nar = libnar::to_vec(example_tree().path()).unwrap();
+    let mut filter = (ShebangFilter::new(OLD, NEW), RpathFilter::new(OLD, NEW));
+    let mut out = Vec::new();
+    rewrite::rewrite(&nar[..], &mut out, &mut filter).unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let dst = dir.path().join("out");
+    Archive::new(&out[..]).unpack(&dst).unwrap();
+
+    let script = fs::read_to_string(dst.join("bin/run")).unwrap();
+    assert_eq!(script, format!("#! {}/bin/bash -e\necho {}\n", NEW, OLD));
+    assert_ne!(fs::metadata(dst.join("bin/run")).unwrap().mode() & 0o111, 0);
+
+    let tool = fs::read(dst.join("bin/tool")).unwrap();
+    let original = elf_with_runpath(&format!("{}/lib:/usr/lib", OLD));
+    assert_eq!(tool.len(), original.len());
+    let expected = format!("{}/lib:/usr/lib\0", NEW);
+    assert!(tool
+        .windows(expected.len())
+        .any(|w| w == expected.as_bytes()));
+    assert_eq!(
+        fs::read_link(dst.join("share/run")).unwrap(),
+        Path::new("../bin/run")
+    );
+}
+
+#[test]
+fn rejects_runpaths_that_would_grow() {
+    let nar = libnar::to_vec(example_tree().path()).unwrap();
+    let longer = format!("{}/much/longer/than/before", OLD);
+    let mut filter = RpathFilter::new(OLD, longer);
+    let err = rewrite::rewrite(&nar[..], &mut Vec::new(), &mut filter).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn filters_files_during_extraction() {
+    let nar = libnar::to_vec(example_tree().path()).unwrap();
+    let mut sink = Filtered::new(Collect(Vec::new()), ShebangFilter::new(OLD, NEW));
+    Archive::new(&nar[..]).extract_to(&mut sink).unwrap();
+
+    let (Collect(files), _) = sink.into_inner();
+    let run = files
+        .iter()
+        .find(|(p, _)| p == Path::new("bin/run"))
+        .unwrap();
+    assert!(run.1.starts_with(format!("#! {}/bin/bash", NEW).as_bytes()));
+    let readme = files
+        .iter()
+        .find(|(p, _)| p == Path::new("share/doc/README"));
+    assert_eq!(readme.unwrap().1, b"docs");
+}
+
+#[test]
+fn relocates_prefix_in_contents_and_symlinks() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(
+        dir.path().join("config"),
+        format!("a={}/bin b={}/lib", OLD, OLD),
+    )
+    .unwrap();
+    symlink(format!("{}/bin/bash", OLD), dir.path().join("sh")).unwrap();
+    let nar = libnar::to_vec(dir.path()).unwrap();
+
+    let mut out = Vec::new();
+    let padded = "/opt/app/bash-5.2";
+    let count = rewrite::relocate(
+        &nar[..],
+        &mut out,
+        OLD,
+        padded,
+        LengthPolicy::PadWithSlashes,
+    );
+    assert_eq!(count.unwrap(), 3);
+    assert_eq!(out.len(), nar.len());
+
+    let tmp = tempfile::tempdir().unwrap();
+    let dst = tmp.path().join("out");
+    Archive::new(&out[..]).unpack(&dst).unwrap();
+    let prefix = format!("{}{}", "/".repeat(OLD.len() - padded.len()), padded);
+    let config = fs::read_to_string(dst.join("config")).unwrap();
+    assert_eq!(config, format!("a={}/bin b={}/lib", prefix, prefix));
+    let target = fs::read_link(dst.join("sh")).unwrap();
+    assert_eq!(target, Path::new(&format!("{}/bin/bash", prefix)));
+
+    let mut out = Vec::new();
+    rewrite::relocate(&nar[..], &mut out, OLD, NEW, LengthPolicy::Resize).unwrap();
+    assert!(out.len() < nar.len());
+}
+
+#[test]
+fn strict_relocation_requires_equal_lengths() {
+    let err = Relocator::new(OLD, NEW, LengthPolicy::Strict).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    let longer = format!("{}-extra", OLD);
+    assert!(Relocator::new(OLD, &longer, LengthPolicy::PadWithSlashes).is_err());
+
+    let same = "/nix/store/xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx-bash-5.2";
+    let relocator = Relocator::new(OLD, same, LengthPolicy::Strict).unwrap();
+    assert_eq!(relocator.replacement(), same);
+}
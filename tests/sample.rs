@@ -0,0 +1,38 @@
+use libnar::sample::{sample_digests, SampleSpec};
+use libnar::tree::NarTree;
+
+fn archive(middle: u8, executable: bool) -> Vec<u8> {
+    let mut big = vec![b'a'; 300];
+    big[150] = middle;
+    NarTree::builder()
+        .dir("bin", |d| d.file("tool", big, executable))
+        .file("small", "tiny", false)
+        .symlink("link", "bin/tool")
+        .build()
+        .unwrap()
+        .to_vec()
+}
+
+#[test]
+fn samples_heads_and_tails_of_files() {
+    let mut spec = SampleSpec::new();
+    spec.set_head_len(100);
+    spec.set_tail_len(100);
+
+    let original = sample_digests(&archive(b'a', false)[..], spec).unwrap();
+    let paths: Vec<&str> = original.files.iter().map(|f| f.path.as_str()).collect();
+    assert_eq!(paths, vec!["bin/tool", "small"]);
+    assert_eq!(original.files[0].size, 300);
+    assert_eq!(original.files[1].head, original.files[1].tail);
+
+    let middle_changed = sample_digests(&archive(b'b', false)[..], spec).unwrap();
+    assert!(original.probably_identical(&middle_changed));
+
+    let chmodded = sample_digests(&archive(b'a', true)[..], spec).unwrap();
+    assert_ne!(original.structure, chmodded.structure);
+    assert_eq!(original.files, chmodded.files);
+
+    let full = sample_digests(&archive(b'b', false)[..], SampleSpec::new()).unwrap();
+    assert!(!original.probably_identical(&full));
+    assert_ne!(original.files[0].head, full.files[0].head);
+}
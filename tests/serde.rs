@@ -0,0 +1,142 @@
+#![cfg(feature = "experimental-serde")]
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Formatter};
+
+use libnar::serde::{from_slice, to_vec, Error};
+use serde::de::{Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Level {
+    Debug,
+    Info,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+struct Server {
+    host: String,
+    port: u16,
+    tls: bool,
+    level: Level,
+    motd: Option<String>,
+    #[serde(serialize_with = "as_bytes", deserialize_with = "from_bytes")]
+    cert: Vec<u8>,
+    env: BTreeMap<String, String>,
+}
+
+fn as_bytes<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_bytes(bytes)
+}
+
+fn from_bytes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    struct BytesVisitor;
+
+    impl<'de> Visitor<'de> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, fmt: &mut Formatter) -> fmt::Result {
+            fmt.write_str("a byte buffer")
+        }
+
+        fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+            Ok(v)
+        }
+    }
+
+    deserializer.deserialize_byte_buf(BytesVisitor)
+}
+
+fn example_server() -> Server {
+    let mut env = BTreeMap::new();
+    env.insert("PATH".to_owned(), "/bin".to_owned());
+    env.insert("HOME".to_owned(), "/root".to_owned());
+
+    Server {
+        host: "localhost".to_owned(),
+        port: 8080,
+        tls: true,
+        level: Level::Info,
+        motd: None,
+        cert: vec![0xde, 0xad, 0xbe, 0xef],
+        env,
+    }
+}
+
+#[test]
+fn round_trips_struct_through_nar() {
+    let server = example_server();
+    let bytes = to_vec(&server).unwrap();
+    let decoded: Server = from_slice(&bytes).unwrap();
+    assert_eq!(decoded, server);
+
+    let server = Server {
+        motd: Some("hello".to_owned()),
+        level: Level::Debug,
+        ..example_server()
+    };
+    let bytes = to_vec(&server).unwrap();
+    let decoded: Server = from_slice(&bytes).unwrap();
+    assert_eq!(decoded, server);
+}
+
+#[test]
+fn maps_fields_onto_directory_entries() {
+    let bytes = to_vec(&example_server()).unwrap();
+    let mut archive = libnar::Archive::new(&bytes[..]);
+    let entries: Vec<_> = archive
+        .entries()
+        .unwrap()
+        .map(|entry| {
+            let entry = entry.unwrap();
+            (entry.name().to_string_lossy().into_owned(), entry.is_dir())
+        })
+        .collect();
+
+    let expected = vec![
+        ("", true),
+        ("cert", false),
+        ("env", true),
+        ("env/HOME", false),
+        ("env/PATH", false),
+        ("host", false),
+        ("level", false),
+        ("port", false),
+        ("tls", false),
+    ];
+    let expected: Vec<_> = expected
+        .into_iter()
+        .map(|(name, is_dir)| (name.to_owned(), is_dir))
+        .collect();
+    assert_eq!(entries, expected);
+}
+
+#[test]
+fn rejects_unsupported_types() {
+    #[derive(Serialize)]
+    struct WithSeq {
+        items: Vec<u32>,
+    }
+
+    let err = to_vec(&WithSeq { items: vec![1, 2] }).unwrap_err();
+    assert!(matches!(err, Error::Unsupported(_)), "{:?}", err);
+
+    let err = to_vec(&()).unwrap_err();
+    assert!(matches!(err, Error::Unsupported(_)), "{:?}", err);
+}
+
+#[test]
+fn rejects_invalid_entry_names() {
+    let mut map = BTreeMap::new();
+    map.insert("../escape", "oops");
+    assert!(to_vec(&map).is_err());
+
+    // Names the parser rejects are never written, even without a `/`.
+    for name in &["", ".", "..", "~"] {
+        let mut map = BTreeMap::new();
+        map.insert(*name, "oops");
+        let err = to_vec(&map).unwrap_err();
+        assert!(err.to_string().contains("name"), "{}", err);
+    }
+}
@@ -1,6 +1,10 @@
+#![cfg(feature = "fs")]
+
+use std::collections::{HashMap, HashSet};
 use std::fs::{self, File, OpenOptions};
 use std::io::Write;
 use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
 
 #[test]
 fn serializes_regular_file() {
@@ -12,32 +16,22 @@ fn serializes_regular_file() {
         .chain(
             13u64
                 .to_le_bytes()
-                .into_iter()
+                .iter()
                 .chain(b"nix-archive-1")
                 .chain(&[0u8; 3]),
         )
-        .chain(1u64.to_le_bytes().into_iter().chain(b"(").chain(&[0u8; 7]))
-        .chain(
-            4u64.to_le_bytes()
-                .into_iter()
-                .chain(b"type")
-                .chain(&[0u8; 4]),
-        )
-        .chain(
-            7u64.to_le_bytes()
-                .into_iter()
-                .chain(b"regular")
-                .chain(&[0u8; 1]),
-        )
-        .chain(8u64.to_le_bytes().into_iter().chain(b"contents"))
+        .chain(1u64.to_le_bytes().iter().chain(b"(").chain(&[0u8; 7]))
+        .chain(4u64.to_le_bytes().iter().chain(b"type").chain(&[0u8; 4]))
+        .chain(7u64.to_le_bytes().iter().chain(b"regular").chain(&[0u8; 1]))
+        .chain(8u64.to_le_bytes().iter().chain(b"contents"))
         .chain(
             27u8.to_le_bytes()
-                .into_iter()
+                .iter()
                 .chain(&[0u8; 7])
                 .chain("lorem ipsum dolor sic amet\n".as_bytes())
                 .chain(&[0u8; 5]),
         )
-        .chain(1u64.to_le_bytes().into_iter().chain(b")").chain(&[0u8; 7]))
+        .chain(1u64.to_le_bytes().iter().chain(b")").chain(&[0u8; 7]))
         .copied()
         .collect();
 
@@ -51,6 +45,7 @@ fn serializes_executable_file() {
     let mut file = OpenOptions::new()
         .write(true)
         .create(true)
+        .truncate(true)
         .mode(0o777)
         .open(dir.path().join("script.sh"))
         .unwrap();
@@ -61,40 +56,30 @@ fn serializes_executable_file() {
         .chain(
             13u64
                 .to_le_bytes()
-                .into_iter()
+                .iter()
                 .chain(b"nix-archive-1")
                 .chain(&[0u8; 3]),
         )
-        .chain(1u64.to_le_bytes().into_iter().chain(b"(").chain(&[0u8; 7]))
-        .chain(
-            4u64.to_le_bytes()
-                .into_iter()
-                .chain(b"type")
-                .chain(&[0u8; 4]),
-        )
-        .chain(
-            7u64.to_le_bytes()
-                .into_iter()
-                .chain(b"regular")
-                .chain(&[0u8; 1]),
-        )
+        .chain(1u64.to_le_bytes().iter().chain(b"(").chain(&[0u8; 7]))
+        .chain(4u64.to_le_bytes().iter().chain(b"type").chain(&[0u8; 4]))
+        .chain(7u64.to_le_bytes().iter().chain(b"regular").chain(&[0u8; 1]))
         .chain(
             10u8.to_le_bytes()
-                .into_iter()
+                .iter()
                 .chain(&[0u8; 7])
                 .chain(b"executable")
                 .chain(&[0u8; 5]),
         )
-        .chain(0u8.to_le_bytes().into_iter().chain(b"").chain(&[0u8; 8]))
-        .chain(8u64.to_le_bytes().into_iter().chain(b"contents"))
+        .chain(0u8.to_le_bytes().iter().chain(b"").chain(&[0u8; 8]))
+        .chain(8u64.to_le_bytes().iter().chain(b"contents"))
         .chain(
             35u8.to_le_bytes()
-                .into_iter()
+                .iter()
                 .chain(&[0u8; 7])
                 .chain("#!/bin/sh\nset -euo pipefail\nexit 0\n".as_bytes())
                 .chain(&[0u8; 5]),
         )
-        .chain(1u64.to_le_bytes().into_iter().chain(b")").chain(&[0u8; 7]))
+        .chain(1u64.to_le_bytes().iter().chain(b")").chain(&[0u8; 7]))
         .copied()
         .collect();
 
@@ -111,36 +96,16 @@ fn serializes_symlink() {
         .chain(
             13u64
                 .to_le_bytes()
-                .into_iter()
+                .iter()
                 .chain(b"nix-archive-1")
                 .chain(&[0u8; 3]),
         )
-        .chain(1u64.to_le_bytes().into_iter().chain(b"(").chain(&[0u8; 7]))
-        .chain(
-            4u64.to_le_bytes()
-                .into_iter()
-                .chain(b"type")
-                .chain(&[0u8; 4]),
-        )
-        .chain(
-            7u64.to_le_bytes()
-                .into_iter()
-                .chain(b"symlink")
-                .chain(&[0u8; 1]),
-        )
-        .chain(
-            6u64.to_le_bytes()
-                .into_iter()
-                .chain(b"target")
-                .chain(&[0u8; 2]),
-        )
-        .chain(
-            5u64.to_le_bytes()
-                .into_iter()
-                .chain(b"./foo")
-                .chain(&[0u8; 3]),
-        )
-        .chain(1u64.to_le_bytes().into_iter().chain(b")").chain(&[0u8; 7]))
+        .chain(1u64.to_le_bytes().iter().chain(b"(").chain(&[0u8; 7]))
+        .chain(4u64.to_le_bytes().iter().chain(b"type").chain(&[0u8; 4]))
+        .chain(7u64.to_le_bytes().iter().chain(b"symlink").chain(&[0u8; 1]))
+        .chain(6u64.to_le_bytes().iter().chain(b"target").chain(&[0u8; 2]))
+        .chain(5u64.to_le_bytes().iter().chain(b"./foo").chain(&[0u8; 3]))
+        .chain(1u64.to_le_bytes().iter().chain(b")").chain(&[0u8; 7]))
         .copied()
         .collect();
 
@@ -158,115 +123,358 @@ fn serializes_directory() {
         .chain(
             13u64
                 .to_le_bytes()
-                .into_iter()
+                .iter()
                 .chain(b"nix-archive-1")
                 .chain(&[0u8; 3]),
         )
-        .chain(1u64.to_le_bytes().into_iter().chain(b"(").chain(&[0u8; 7]))
-        .chain(
-            4u64.to_le_bytes()
-                .into_iter()
-                .chain(b"type")
-                .chain(&[0u8; 4]),
-        )
+        .chain(1u64.to_le_bytes().iter().chain(b"(").chain(&[0u8; 7]))
+        .chain(4u64.to_le_bytes().iter().chain(b"type").chain(&[0u8; 4]))
         .chain(
             9u64.to_le_bytes()
-                .into_iter()
+                .iter()
                 .chain(b"directory")
                 .chain(&[0u8; 7]),
         )
-        .chain(
-            5u64.to_le_bytes()
-                .into_iter()
-                .chain(b"entry")
-                .chain(&[0u8; 3]),
-        )
-        .chain(1u64.to_le_bytes().into_iter().chain(b"(").chain(&[0u8; 7]))
-        .chain(
-            4u64.to_le_bytes()
-                .into_iter()
-                .chain(b"name")
-                .chain(&[0u8; 4]),
-        )
-        .chain(
-            6u64.to_le_bytes()
-                .into_iter()
-                .chain(b"subdir")
-                .chain(&[0u8; 2]),
-        )
-        .chain(
-            4u64.to_le_bytes()
-                .into_iter()
-                .chain(b"node")
-                .chain(&[0u8; 4]),
-        )
-        .chain(1u64.to_le_bytes().into_iter().chain(b"(").chain(&[0u8; 7]))
-        .chain(
-            4u64.to_le_bytes()
-                .into_iter()
-                .chain(b"type")
-                .chain(&[0u8; 4]),
-        )
+        .chain(5u64.to_le_bytes().iter().chain(b"entry").chain(&[0u8; 3]))
+        .chain(1u64.to_le_bytes().iter().chain(b"(").chain(&[0u8; 7]))
+        .chain(4u64.to_le_bytes().iter().chain(b"name").chain(&[0u8; 4]))
+        .chain(6u64.to_le_bytes().iter().chain(b"subdir").chain(&[0u8; 2]))
+        .chain(4u64.to_le_bytes().iter().chain(b"node").chain(&[0u8; 4]))
+        .chain(1u64.to_le_bytes().iter().chain(b"(").chain(&[0u8; 7]))
+        .chain(4u64.to_le_bytes().iter().chain(b"type").chain(&[0u8; 4]))
         .chain(
             9u64.to_le_bytes()
-                .into_iter()
+                .iter()
                 .chain(b"directory")
                 .chain(&[0u8; 7]),
         )
-        .chain(
-            5u64.to_le_bytes()
-                .into_iter()
-                .chain(b"entry")
-                .chain(&[0u8; 3]),
-        )
-        .chain(1u64.to_le_bytes().into_iter().chain(b"(").chain(&[0u8; 7]))
-        .chain(
-            4u64.to_le_bytes()
-                .into_iter()
-                .chain(b"name")
-                .chain(&[0u8; 4]),
-        )
-        .chain(
-            4u64.to_le_bytes()
-                .into_iter()
-                .chain(b"file")
-                .chain(&[0u8; 4]),
-        )
-        .chain(
-            4u64.to_le_bytes()
-                .into_iter()
-                .chain(b"node")
-                .chain(&[0u8; 4]),
-        )
-        .chain(1u64.to_le_bytes().into_iter().chain(b"(").chain(&[0u8; 7]))
-        .chain(
-            4u64.to_le_bytes()
-                .into_iter()
-                .chain(b"type")
-                .chain(&[0u8; 4]),
-        )
-        .chain(
-            7u64.to_le_bytes()
-                .into_iter()
-                .chain(b"regular")
-                .chain(&[0u8; 1]),
-        )
-        .chain(8u64.to_le_bytes().into_iter().chain(b"contents"))
+        .chain(5u64.to_le_bytes().iter().chain(b"entry").chain(&[0u8; 3]))
+        .chain(1u64.to_le_bytes().iter().chain(b"(").chain(&[0u8; 7]))
+        .chain(4u64.to_le_bytes().iter().chain(b"name").chain(&[0u8; 4]))
+        .chain(4u64.to_le_bytes().iter().chain(b"file").chain(&[0u8; 4]))
+        .chain(4u64.to_le_bytes().iter().chain(b"node").chain(&[0u8; 4]))
+        .chain(1u64.to_le_bytes().iter().chain(b"(").chain(&[0u8; 7]))
+        .chain(4u64.to_le_bytes().iter().chain(b"type").chain(&[0u8; 4]))
+        .chain(7u64.to_le_bytes().iter().chain(b"regular").chain(&[0u8; 1]))
+        .chain(8u64.to_le_bytes().iter().chain(b"contents"))
         .chain(
             11u64
                 .to_le_bytes()
-                .into_iter()
+                .iter()
                 .chain("hello world".as_bytes())
                 .chain(&[0u8; 5]),
         )
-        .chain(1u64.to_le_bytes().into_iter().chain(b")").chain(&[0u8; 7]))
-        .chain(1u64.to_le_bytes().into_iter().chain(b")").chain(&[0u8; 7]))
-        .chain(1u64.to_le_bytes().into_iter().chain(b")").chain(&[0u8; 7]))
-        .chain(1u64.to_le_bytes().into_iter().chain(b")").chain(&[0u8; 7]))
-        .chain(1u64.to_le_bytes().into_iter().chain(b")").chain(&[0u8; 7]))
+        .chain(1u64.to_le_bytes().iter().chain(b")").chain(&[0u8; 7]))
+        .chain(1u64.to_le_bytes().iter().chain(b")").chain(&[0u8; 7]))
+        .chain(1u64.to_le_bytes().iter().chain(b")").chain(&[0u8; 7]))
+        .chain(1u64.to_le_bytes().iter().chain(b")").chain(&[0u8; 7]))
+        .chain(1u64.to_le_bytes().iter().chain(b")").chain(&[0u8; 7]))
         .copied()
         .collect();
 
     let output = libnar::to_vec(dir.path()).unwrap();
     assert_eq!(output, expected);
 }
+
+#[test]
+fn computes_archive_len_without_encoding() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("bin")).unwrap();
+    fs::write(dir.path().join("bin").join("hello"), "#!/bin/sh\necho hi\n").unwrap();
+    fs::write(dir.path().join("README"), "lorem ipsum").unwrap();
+    std::os::unix::fs::symlink("bin/hello", dir.path().join("link")).unwrap();
+
+    let expected = libnar::to_vec(dir.path()).unwrap().len() as u64;
+    assert_eq!(libnar::ser::archive_len(dir.path()).unwrap(), expected);
+}
+
+#[test]
+fn computes_lenient_archive_len_without_special_files() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("file"), "contents").unwrap();
+    let status = std::process::Command::new("mkfifo")
+        .arg(dir.path().join("fifo"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let mut options = libnar::ser::PackOptions::new();
+    options.set_lenient(true);
+    let mut output = Vec::new();
+    options.to_writer(&mut output, dir.path()).unwrap();
+    assert_eq!(
+        options.archive_len(dir.path()).unwrap(),
+        output.len() as u64
+    );
+}
+
+#[test]
+fn packs_hard_linked_files_like_copies() {
+    let linked = tempfile::tempdir().unwrap();
+    fs::write(linked.path().join("a"), "shared contents").unwrap();
+    fs::hard_link(linked.path().join("a"), linked.path().join("b")).unwrap();
+
+    let copied = tempfile::tempdir().unwrap();
+    fs::write(copied.path().join("a"), "shared contents").unwrap();
+    fs::write(copied.path().join("b"), "shared contents").unwrap();
+
+    assert_eq!(
+        libnar::to_vec(linked.path()).unwrap(),
+        libnar::to_vec(copied.path()).unwrap()
+    );
+}
+
+#[test]
+fn skips_special_files_with_warnings_when_lenient() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("file"), "contents").unwrap();
+    let socket = dir.path().join("socket");
+    let _listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+
+    assert!(libnar::to_vec(dir.path()).is_err());
+
+    let mut lenient = Vec::new();
+    let warnings = libnar::ser::to_writer_lenient(&mut lenient, dir.path()).unwrap();
+    assert_eq!(
+        warnings,
+        [libnar::Warning::SpecialFileSkipped {
+            path: socket.clone()
+        }]
+    );
+
+    fs::remove_file(&socket).unwrap();
+    assert_eq!(lenient, libnar::to_vec(dir.path()).unwrap());
+}
+
+#[test]
+fn packs_repeatedly_with_shared_options() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("file"), "contents").unwrap();
+
+    let mut options = libnar::ser::PackOptions::new();
+    options.set_hard_link_cache_len(0);
+    for _ in 0..2 {
+        let (bytes, warnings) = options.to_vec(dir.path()).unwrap();
+        assert_eq!(bytes, libnar::to_vec(dir.path()).unwrap());
+        assert!(warnings.is_empty());
+    }
+}
+
+#[test]
+fn detects_executables_with_configured_rules() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("bin")).unwrap();
+    let files = [
+        ("bin/tool", 0o744),
+        ("bin/noisy", 0o654),
+        ("script.sh", 0o644),
+        ("data.bin", 0o755),
+    ];
+    for (name, mode) in &files {
+        let path = dir.path().join(name);
+        fs::write(&path, name).unwrap();
+        fs::set_permissions(&path, std::os::unix::fs::PermissionsExt::from_mode(*mode)).unwrap();
+    }
+
+    let executables = |options: &libnar::ser::PackOptions| {
+        let (nar, _) = options.to_vec(dir.path()).unwrap();
+        assert_eq!(options.archive_len(dir.path()).unwrap(), nar.len() as u64);
+        let mut archive = libnar::Archive::new(&nar[..]);
+        let mut names = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let entry = entry.unwrap();
+            if entry.is_executable() {
+                names.push(entry.nar_path());
+            }
+        }
+        names
+    };
+
+    let mut options = libnar::ser::PackOptions::new();
+    assert_eq!(executables(&options), ["bin/noisy", "bin/tool", "data.bin"]);
+
+    let mut detection = libnar::ser::ExecutableDetection::new();
+    detection.set_owner_only(true);
+    detection.add_executable_glob("*.sh");
+    detection.add_plain_glob("*.bin");
+    options.set_executable_detection(detection.clone());
+    assert_eq!(executables(&options), ["bin/tool", "script.sh"]);
+
+    detection.set_callback(|path, _| path.starts_with("bin/"));
+    options.set_executable_detection(detection);
+    assert_eq!(
+        executables(&options),
+        ["bin/noisy", "bin/tool", "script.sh"]
+    );
+}
+
+#[test]
+fn packs_in_memory_files_like_a_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("bin")).unwrap();
+    fs::create_dir_all(dir.path().join("share/doc")).unwrap();
+    fs::write(dir.path().join("share/doc/README"), "docs").unwrap();
+    OpenOptions::new()
+        .create_new(true)
+        .write(true)
+        .mode(0o755)
+        .open(dir.path().join("bin/hello"))
+        .unwrap()
+        .write_all(b"#!/bin/sh\n")
+        .unwrap();
+
+    let mut map = HashMap::new();
+    map.insert(PathBuf::from("share/doc/README"), b"docs".to_vec());
+    map.insert(PathBuf::from("bin/hello"), b"#!/bin/sh\n".to_vec());
+    let executable: HashSet<_> = std::iter::once(PathBuf::from("bin/hello")).collect();
+
+    let packed = libnar::ser::from_map(&map, &executable).unwrap();
+    assert_eq!(packed, libnar::to_vec(dir.path()).unwrap());
+}
+
+#[test]
+fn rejects_invalid_in_memory_paths() {
+    let none = HashSet::new();
+    let invalid = |paths: &[&str]| {
+        let map: HashMap<_, _> = paths
+            .iter()
+            .map(|path| (PathBuf::from(path), Vec::new()))
+            .collect();
+        libnar::ser::from_map(&map, &none).unwrap_err()
+    };
+
+    assert!(invalid(&["/etc/passwd"]).to_string().contains("relative"));
+    assert!(invalid(&["../escape"]).to_string().contains("relative"));
+    assert!(invalid(&[""]).to_string().contains("empty"));
+    assert!(invalid(&["~"]).to_string().contains("`~`"));
+    assert!(invalid(&["a/~/b"]).to_string().contains("`~`"));
+    let conflict = invalid(&["a", "a/b"]).to_string();
+    assert!(conflict.contains("regular file") || conflict.contains("directory"));
+
+    let map: HashMap<_, _> = std::iter::once((PathBuf::from("a"), Vec::new())).collect();
+    let executable: HashSet<_> = std::iter::once(PathBuf::from("b")).collect();
+    assert!(libnar::ser::from_map(&map, &executable).is_err());
+    assert_eq!(
+        libnar::ser::from_map(&HashMap::new(), &none).unwrap(),
+        libnar::to_vec(tempfile::tempdir().unwrap().path()).unwrap()
+    );
+}
+
+/// Accepts a fixed number of bytes and then refuses to take any more.
+struct Pipe {
+    capacity: usize,
+    written: Vec<u8>,
+    flushes: usize,
+}
+
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(self.capacity - self.written.len());
+        self.written.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.flushes += 1;
+        Ok(())
+    }
+}
+
+#[test]
+fn reports_short_writes_with_the_path_being_packed() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("data")).unwrap();
+    fs::write(dir.path().join("data/large"), vec![7; 4096]).unwrap();
+    let len = libnar::to_vec(dir.path()).unwrap().len();
+
+    let mut pipe = Pipe {
+        capacity: len - 100,
+        written: Vec::new(),
+        flushes: 0,
+    };
+    let error = libnar::to_writer(&mut pipe, dir.path()).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::WriteZero);
+    assert!(error.to_string().contains("data/large"), "{}", error);
+    assert_eq!(pipe.flushes, 0);
+
+    let mut pipe = Pipe {
+        capacity: len,
+        written: Vec::new(),
+        flushes: 0,
+    };
+    libnar::to_writer(&mut pipe, dir.path()).unwrap();
+    assert_eq!(pipe.flushes, 1);
+    assert_eq!(pipe.written.len(), len);
+}
+
+#[test]
+fn packs_to_synced_files() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("file"), "contents").unwrap();
+    let mut output = File::create(dir.path().join("out.nar")).unwrap();
+
+    libnar::ser::PackOptions::new()
+        .to_file(&mut output, dir.path().join("file"))
+        .unwrap();
+    assert_eq!(
+        fs::read(dir.path().join("out.nar")).unwrap(),
+        libnar::to_vec(dir.path().join("file")).unwrap()
+    );
+}
+
+#[test]
+fn rejects_invalid_symlink_targets() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::symlink;
+
+    let dir = tempfile::tempdir().unwrap();
+    symlink("a".repeat(100), dir.path().join("long")).unwrap();
+
+    let mut options = libnar::ser::PackOptions::new();
+    assert!(options.to_vec(dir.path()).is_ok());
+    options.set_max_symlink_target_len(Some(99));
+    let err = options.to_vec(dir.path()).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert!(err
+        .to_string()
+        .contains("long: symlink target exceeds 99 bytes"));
+    assert!(options.archive_len(dir.path()).is_err());
+
+    let dir = tempfile::tempdir().unwrap();
+    symlink(OsStr::from_bytes(b"bad\xff"), dir.path().join("bad")).unwrap();
+    let mut options = libnar::ser::PackOptions::new();
+    options.set_strict_utf8_symlinks(true);
+    let err = options.to_vec(dir.path()).unwrap_err();
+    assert!(err
+        .to_string()
+        .ends_with("bad: symlink target is not valid UTF-8"));
+}
+
+#[test]
+fn preserves_non_utf8_symlink_targets() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::symlink;
+
+    let target = OsStr::from_bytes(b"bad\xff");
+    let dir = tempfile::tempdir().unwrap();
+    symlink(target, dir.path().join("bad")).unwrap();
+    let nar = libnar::to_vec(dir.path()).unwrap();
+    assert!(nar.windows(4).any(|token| token == b"bad\xff"));
+    assert_eq!(
+        libnar::ser::archive_len(dir.path()).unwrap(),
+        nar.len() as u64
+    );
+
+    let out = tempfile::tempdir().unwrap();
+    let dst = out.path().join("out");
+    libnar::Archive::new(&nar[..]).unpack(&dst).unwrap();
+    assert_eq!(std::fs::read_link(dst.join("bad")).unwrap(), target);
+    assert_eq!(libnar::to_vec(&dst).unwrap(), nar);
+
+    let mut archive = libnar::Archive::new(&nar[..]);
+    archive.set_strict_utf8_symlinks(true);
+    let err = archive.unpack(out.path().join("strict")).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,192 @@
+#![cfg(feature = "signing")]
+
+use ed25519_dalek::{Signer, SigningKey};
+use libnar::hash::Sha256Hash;
+use libnar::signing::{self, Keypair, PathInfo, PublicKey, Signature, TrustPolicy};
+use libnar::store_path::StoreDir;
+
+const GLIBC: &str = "/nix/store/4nlgxhb09sdr51nc9hdm8az5b08vzkgx-glibc-2.37";
+const STORE_PATH: &str = "/nix/store/syd87l2rxw8cbsxmxl853h0r6pdwhw0q-hello-2.12";
+
+fn nar_hash() -> Sha256Hash {
+    "sha256:1b8m03r63zqhnjf7l5wnldhh7c134ap5vpj0850ymkq1iyzicy5s"
+        .parse()
+        .unwrap()
+}
+
+#[test]
+fn computes_nix_fingerprint() {
+    let refs = [GLIBC, STORE_PATH, GLIBC];
+    let fingerprint =
+        signing::fingerprint(&StoreDir::default(), STORE_PATH, &nar_hash(), 226560, refs).unwrap();
+    assert_eq!(
+        fingerprint,
+        format!(
+            "1;{};sha256:1b8m03r63zqhnjf7l5wnldhh7c134ap5vpj0850ymkq1iyzicy5s;226560;{},{}",
+            STORE_PATH, GLIBC, STORE_PATH
+        )
+    );
+
+    let empty: [&str; 0] = [];
+    assert!(signing::fingerprint(&StoreDir::default(), "hello", &nar_hash(), 1, empty).is_err());
+    assert!(
+        signing::fingerprint(&StoreDir::default(), STORE_PATH, &nar_hash(), 1, ["a,b"]).is_err()
+    );
+
+    let gnu = StoreDir::new("/gnu/store").unwrap();
+    assert!(signing::fingerprint(&gnu, STORE_PATH, &nar_hash(), 1, empty).is_err());
+    let relocated = STORE_PATH.replace("/nix/", "/gnu/");
+    assert!(signing::fingerprint(&gnu, &relocated, &nar_hash(), 1, empty).is_ok());
+}
+
+#[test]
+fn signs_through_callback_and_verifies() {
+    let secret = SigningKey::from_bytes(&[42; 32]);
+    let public = PublicKey::from_bytes("cache-1", secret.verifying_key().as_bytes()).unwrap();
+    let public: PublicKey = public.to_string().parse().unwrap();
+
+    let fingerprint = signing::fingerprint(
+        &StoreDir::default(),
+        STORE_PATH,
+        &nar_hash(),
+        10,
+        [STORE_PATH],
+    )
+    .unwrap();
+    let signature = signing::sign_with("cache-1", &fingerprint, |msg| {
+        Ok(secret.sign(msg).to_bytes())
+    })
+    .unwrap();
+
+    let parsed: Signature = signature.to_string().parse().unwrap();
+    assert_eq!(parsed, signature);
+    assert!(public.verify(&fingerprint, &parsed));
+    assert!(!public.verify(&fingerprint.replace(";10;", ";11;"), &parsed));
+
+    let renamed = Signature::new("other-1", *signature.as_bytes());
+    assert!(!public.verify(&fingerprint, &renamed));
+    assert!("no-colon".parse::<Signature>().is_err());
+}
+
+#[test]
+#[cfg(feature = "fs")]
+fn generates_keys_and_round_trips_secret_key_files() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let keypair = Keypair::generate("cache.example.org-1").unwrap();
+    let public = keypair.public_key();
+    assert_eq!(public.name(), "cache.example.org-1");
+    assert!(public.to_string().starts_with("cache.example.org-1:"));
+    assert!(!format!("{:?}", keypair).contains(&keypair.to_secret_key()));
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("secret");
+    keypair.write_secret_key_file(&path).unwrap();
+    assert!(keypair.write_secret_key_file(&path).is_err());
+
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let loaded = Keypair::read_secret_key_file(&path).unwrap();
+    assert_eq!(loaded.public_key(), public);
+
+    let fingerprint = signing::fingerprint(
+        &StoreDir::default(),
+        STORE_PATH,
+        &nar_hash(),
+        10,
+        [STORE_PATH],
+    )
+    .unwrap();
+    assert!(public.verify(&fingerprint, &loaded.sign(&fingerprint)));
+
+    assert!(Keypair::generate("bad:name").is_err());
+    assert!(Keypair::from_secret_key(&public.to_string()).is_err());
+}
+
+#[test]
+fn accepts_secret_keys_with_trailing_newline() {
+    let keypair = Keypair::from_seed("test-1", &[7; 32]).unwrap();
+    let text = format!("{}\n", keypair.to_secret_key());
+    let parsed = Keypair::from_secret_key(&text).unwrap();
+    assert_eq!(parsed.public_key(), keypair.public_key());
+}
+
+#[test]
+fn applies_trust_policy() {
+    let first = Keypair::from_seed("first-1", &[1; 32]).unwrap();
+    let second = Keypair::from_seed("second-1", &[2; 32]).unwrap();
+    let untrusted = Keypair::from_seed("third-1", &[3; 32]).unwrap();
+    let keys = vec![first.public_key(), second.public_key()];
+
+    let info = PathInfo::new(STORE_PATH, nar_hash(), 10, [STORE_PATH]);
+    let fingerprint = info.fingerprint(&StoreDir::default()).unwrap();
+    let one = vec![first.sign(&fingerprint), untrusted.sign(&fingerprint)];
+    let both = vec![first.sign(&fingerprint), second.sign(&fingerprint)];
+
+    let any = TrustPolicy::new(keys.clone());
+    any.check(&info, &one).unwrap();
+    assert!(any.check(&info, &[]).is_err());
+    assert!(any.check(&info, &[untrusted.sign(&fingerprint)]).is_err());
+
+    // Signatures only count for the path info they were made over.
+    let other = PathInfo::new(STORE_PATH, nar_hash(), 11, [STORE_PATH]);
+    assert!(any.check(&other, &one).is_err());
+
+    let two_of_two = TrustPolicy::new(keys.clone()).with_threshold(2);
+    assert!(two_of_two.check(&info, &one).is_err());
+    two_of_two.check(&info, &both).unwrap();
+
+    let required = TrustPolicy::new(keys).with_required_signer("second-1");
+    let err = required.check(&info, &one).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+
+    let unsigned = TrustPolicy::new(Vec::new()).with_unsigned_allowed("/nix/store/*-hello-*");
+    unsigned.check(&info, &[]).unwrap();
+    let bash = PathInfo::new("/nix/store/abc-bash", nar_hash(), 10, Vec::<String>::new());
+    assert!(unsigned.check(&bash, &[]).is_err());
+}
+
+#[test]
+fn counts_each_trusted_key_once() {
+    let first = Keypair::from_seed("first-1", &[1; 32]).unwrap();
+    let alias = Keypair::from_seed("alias-1", &[1; 32]).unwrap();
+    let second = Keypair::from_seed("second-1", &[2; 32]).unwrap();
+    let keys = vec![first.public_key(), alias.public_key(), second.public_key()];
+
+    let info = PathInfo::new(STORE_PATH, nar_hash(), 10, [STORE_PATH]);
+    let fingerprint = info.fingerprint(&StoreDir::default()).unwrap();
+    let policy = TrustPolicy::new(keys).with_threshold(2);
+
+    let same_key = [first.sign(&fingerprint), alias.sign(&fingerprint)];
+    let err = policy.check(&info, &same_key).unwrap_err();
+    assert!(err.to_string().contains("1 of 2"), "{}", err);
+
+    let distinct = [first.sign(&fingerprint), second.sign(&fingerprint)];
+    policy.check(&info, &distinct).unwrap();
+}
+
+#[test]
+fn never_trusts_without_a_signature() {
+    let info = PathInfo::new(STORE_PATH, nar_hash(), 10, [STORE_PATH]);
+    let zero = TrustPolicy::new(Vec::new()).with_threshold(0);
+    assert_eq!(zero.threshold(), 1);
+    assert!(zero.check(&info, &[]).is_err());
+    assert!(TrustPolicy::default().check(&info, &[]).is_err());
+}
+
+#[test]
+fn validates_store_paths_before_allowing_unsigned() {
+    let policy = TrustPolicy::default().with_unsigned_allowed("*");
+    let info = PathInfo::new(STORE_PATH, nar_hash(), 10, [STORE_PATH]);
+    policy.check(&info, &[]).unwrap();
+
+    for path in &[
+        "/tmp/evil",
+        "/nix/store/../../etc/passwd",
+        "/nix/store/abc-bash",
+    ] {
+        let info = PathInfo::new(*path, nar_hash(), 10, Vec::<String>::new());
+        assert!(policy.check(&info, &[]).is_err(), "{}", path);
+    }
+}
@@ -0,0 +1,137 @@
+#![cfg(feature = "fs")]
+
+use std::fs;
+
+use libnar::split::{join_parts, split_nar, Joiner};
+
+fn example_nar() -> Vec<u8> {
+    let dir = tempfile::tempdir().unwrap();
+    for i in 0..20 {
+        fs::write(dir.path().join(format!("file{}", i)), vec![i as u8; i * 7]).unwrap();
+    }
+    fs::write(dir.path().join("large"), vec![42; 1000]).unwrap();
+    libnar::to_vec(dir.path()).unwrap()
+}
+
+fn holds_whole_tokens(mut data: &[u8]) -> bool {
+    while data.len() >= 8 {
+        let mut len = [0; 8];
+        len.copy_from_slice(&data[..8]);
+        let token_len = libnar::wire::encoded_len(u64::from_le_bytes(len));
+        if token_len > data.len() as u64 {
+            return false;
+        }
+        data = &data[token_len as usize..];
+    }
+    data.is_empty()
+}
+
+#[test]
+fn splits_on_token_boundaries_and_rejoins() {
+    let nar = example_nar();
+    let parts: Vec<_> = split_nar(&nar[..], 256)
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+
+    assert!(parts.iter().all(|p| p.data().len() <= 256));
+    let mut offset = 0;
+    for (i, part) in parts.iter().enumerate() {
+        assert_eq!(part.index(), i as u64);
+        assert_eq!(part.offset(), offset);
+        part.verify().unwrap();
+        offset += part.data().len() as u64;
+    }
+
+    // Apart from those carrying the oversized token, every part holds only whole tokens.
+    let whole = parts
+        .iter()
+        .filter(|p| holds_whole_tokens(p.data()))
+        .count();
+    assert!(whole >= parts.len() - 4);
+
+    assert_eq!(join_parts(parts, Vec::new()).unwrap(), nar);
+}
+
+#[test]
+fn joiner_rejects_corrupt_or_misordered_parts_and_resumes() {
+    let nar = example_nar();
+    let parts: Vec<_> = split_nar(&nar[..], 512)
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert!(parts.len() > 2);
+
+    let mut joiner = Joiner::new(Vec::new());
+    assert!(joiner.push(&parts[1]).is_err());
+    joiner.push(&parts[0]).unwrap();
+
+    let bad = &parts[1];
+    let mut data = bad.data().to_vec();
+    data[0] ^= 1;
+    let corrupt = libnar::split::Part::from_parts(bad.index(), bad.offset(), *bad.hash(), data);
+    assert!(joiner.push(&corrupt).is_err());
+
+    let (next_index, offset) = (joiner.next_index(), joiner.offset());
+    let mut output = joiner.into_inner();
+    let mut resumed = Joiner::resume(&mut output, next_index, offset);
+    for part in &parts[1..] {
+        resumed.push(part).unwrap();
+    }
+    assert_eq!(output, nar);
+}
+
+#[test]
+fn rejects_tiny_part_sizes() {
+    assert!(split_nar(&b""[..], 4).is_err());
+}
+
+#[test]
+fn rejects_out_of_range_token_lengths() {
+    // The first prefix overflows once padded, the second only once added to the part before it.
+    let small_token = [&8u64.to_le_bytes()[..], &[0; 8]].concat();
+    for (prefix, len) in [(&[][..], u64::MAX - 2), (&small_token[..], u64::MAX - 16)] {
+        let nar = [prefix, &len.to_le_bytes(), &[0; 16]].concat();
+        let err = split_nar(&nar[..], 256)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
+
+#[test]
+fn resumes_interrupted_uploads_from_checkpoint() {
+    use libnar::split::{upload_resumable, Part, UploadCheckpoint};
+
+    let nar = example_nar();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("upload.checkpoint");
+    let mut store: Vec<Part> = Vec::new();
+
+    let mut checkpoint = UploadCheckpoint::open(&path, 256).unwrap();
+    let mut failing = |part: &Part| {
+        if part.index() == 3 {
+            return Err(std::io::Error::other("connection reset"));
+        }
+        store.push(part.clone());
+        Ok(())
+    };
+    assert!(upload_resumable(&nar[..], &mut checkpoint, &mut failing).is_err());
+    assert_eq!(checkpoint.uploaded_parts(), 3);
+    drop(checkpoint);
+
+    let mut checkpoint = UploadCheckpoint::open(&path, 256).unwrap();
+    let mut sink = |part: &Part| {
+        store.push(part.clone());
+        Ok(())
+    };
+    let summary = upload_resumable(&nar[..], &mut checkpoint, &mut sink).unwrap();
+    assert_eq!(summary.skipped, 3);
+    assert_eq!(summary.uploaded as usize + 3, store.len());
+    checkpoint.remove().unwrap();
+
+    assert_eq!(join_parts(store, Vec::new()).unwrap(), nar);
+    assert!(UploadCheckpoint::open(&path, 256).is_ok());
+    assert!(UploadCheckpoint::open(&path, 512).is_err());
+}
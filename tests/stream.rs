@@ -0,0 +1,59 @@
+#![cfg(feature = "stream")]
+
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake};
+use std::thread::{self, Thread};
+
+use futures_core::Stream;
+use libnar::de::{to_map, FileEntry};
+use libnar::tree::NarTree;
+use libnar::Archive;
+
+struct Unpark(Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Drains `stream` on the current thread, parking whenever it is pending.
+fn collect<S: Stream + Unpin>(mut stream: S) -> Vec<S::Item> {
+    let waker = Arc::new(Unpark(thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    let mut items = Vec::new();
+    loop {
+        match Pin::new(&mut stream).poll_next(&mut cx) {
+            Poll::Ready(Some(item)) => items.push(item),
+            Poll::Ready(None) => return items,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[test]
+fn streams_entries_from_a_blocking_task() {
+    let mut builder = NarTree::builder();
+    for i in 0..100 {
+        builder = builder.file(format!("file{:03}", i), vec![i as u8; 1000], false);
+    }
+    let nar = builder.build().unwrap().to_vec();
+
+    let items = collect(Archive::new(std::io::Cursor::new(nar.clone())).into_blocking_task());
+    let entries: Vec<_> = items.into_iter().map(Result::unwrap).collect();
+    assert_eq!(entries.len(), 101);
+    assert_eq!(
+        entries.into_iter().collect::<Vec<_>>(),
+        to_map(&nar[..]).unwrap().into_iter().collect::<Vec<_>>()
+    );
+
+    let truncated = nar[..nar.len() / 2].to_vec();
+    let items = collect(Archive::new(std::io::Cursor::new(truncated)).into_blocking_task());
+    assert!(items.last().unwrap().is_err());
+    assert!(items[..items.len() - 1].iter().all(Result::is_ok));
+    assert!(matches!(
+        items[1].as_ref().unwrap().1,
+        FileEntry::Regular { .. }
+    ));
+}
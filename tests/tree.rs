@@ -0,0 +1,301 @@
+#![cfg(feature = "fs")]
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::symlink;
+
+use libnar::tree::{self, CompareOptions, NarTree, Node};
+use libnar::Archive;
+
+fn example_nar() -> Vec<u8> {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("bin")).unwrap();
+    fs::create_dir_all(dir.path().join("share/doc")).unwrap();
+    fs::write(dir.path().join("bin/hello"), "hello").unwrap();
+    fs::write(dir.path().join("bin/a-tool"), "tool").unwrap();
+    fs::write(dir.path().join("share/doc/README"), "docs").unwrap();
+    symlink("bin/hello", dir.path().join("hello")).unwrap();
+    libnar::to_vec(dir.path()).unwrap()
+}
+
+fn file(contents: &str) -> Node {
+    Node::Regular {
+        executable: false,
+        contents: contents.as_bytes().to_vec(),
+    }
+}
+
+#[test]
+fn round_trips_archive_through_tree() {
+    let nar = example_nar();
+    let tree = NarTree::from_bytes(&nar).unwrap();
+    assert_eq!(tree.to_vec(), nar);
+    assert_eq!(tree.get("bin/hello"), Some(&file("hello")));
+    assert_eq!(tree.get("/share/doc/"), tree.get("share/doc"));
+    assert!(tree.get("bin/missing").is_none());
+}
+
+#[test]
+fn walks_in_parser_order() {
+    let nar = example_nar();
+    let tree = NarTree::from_bytes(&nar).unwrap();
+
+    let mut archive = Archive::new(&nar[..]);
+    let parsed: Vec<String> = archive
+        .entries()
+        .unwrap()
+        .map(|entry| entry.unwrap().nar_path())
+        .collect();
+    let walked: Vec<String> = tree.walk_with_paths().map(|(path, _)| path).collect();
+    assert_eq!(walked, parsed);
+    assert_eq!(tree.walk().count(), parsed.len());
+
+    let post: Vec<String> = tree
+        .walk_with_paths()
+        .post_order()
+        .map(|(path, _)| path)
+        .collect();
+    let expected = [
+        "bin/a-tool",
+        "bin/hello",
+        "bin",
+        "hello",
+        "share/doc/README",
+        "share/doc",
+        "share",
+        "",
+    ];
+    assert_eq!(post, expected);
+}
+
+#[test]
+fn cursor_tolerates_mutation_between_steps() {
+    let mut tree = NarTree::from_bytes(&example_nar()).unwrap();
+    let mut cursor = tree.cursor();
+    let mut visited = Vec::new();
+
+    while let Some(path) = cursor.advance(&tree) {
+        match path.as_str() {
+            "bin" => {
+                let entries = tree.get_mut("bin").and_then(Node::entries_mut).unwrap();
+                entries.remove("hello");
+                entries.insert("z-new".to_owned(), file("new"));
+            }
+            "bin/a-tool" => {
+                let root = tree.root_mut().entries_mut().unwrap();
+                root.remove("bin");
+            }
+            "hello" => {
+                let entries = BTreeMap::new();
+                let root = tree.root_mut().entries_mut().unwrap();
+                root.insert("include".to_owned(), Node::Directory { entries });
+            }
+            _ => {}
+        }
+        visited.push(path);
+    }
+
+    let expected = [
+        "",
+        "bin",
+        "bin/a-tool",
+        "hello",
+        "include",
+        "share",
+        "share/doc",
+    ];
+    assert_eq!(visited[..expected.len()], expected);
+    assert_eq!(visited.last().unwrap(), "share/doc/README");
+    assert_eq!(cursor.path(), None);
+    assert_eq!(cursor.advance(&tree), None);
+}
+
+#[test]
+fn builds_trees_without_touching_the_filesystem() {
+    let tree = NarTree::builder()
+        .dir("bin", |d| {
+            d.file("hello", "hello", false)
+                .file("a-tool", "tool", false)
+        })
+        .dir("share", |d| {
+            d.dir("doc", |d| d.file("README", "docs", false))
+        })
+        .symlink("hello", "bin/hello")
+        .build()
+        .unwrap();
+    assert_eq!(tree.to_vec(), example_nar());
+
+    let tree = NarTree::builder()
+        .file("run", b"#!/bin/sh\n".to_vec(), true)
+        .build()
+        .unwrap();
+    let run = tree.get("run").unwrap();
+    assert!(matches!(
+        run,
+        Node::Regular {
+            executable: true,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn rejects_invalid_names_on_build() {
+    let duplicate = NarTree::builder()
+        .file("a", "1", false)
+        .dir("d", |d| d.file("x", "", false).symlink("x", "a"))
+        .build()
+        .unwrap_err();
+    assert_eq!(
+        duplicate.to_string(),
+        r#"Invalid tree at "d/x": duplicate entry"#
+    );
+
+    for name in &["", "/", "~", ".", "..", "a/b", "nul\0"] {
+        assert!(NarTree::builder().file(*name, "", false).build().is_err());
+    }
+    assert!(NarTree::builder().symlink("link", "").build().is_err());
+}
+
+#[test]
+fn compares_archives_structurally() {
+    let nar = example_nar();
+    let options = CompareOptions::new();
+    assert!(tree::compare(&nar[..], &nar[..], options)
+        .unwrap()
+        .is_empty());
+
+    let other = NarTree::builder()
+        .dir("bin", |d| {
+            d.file("hello", "hello", true).file("extra", "", false)
+        })
+        .dir("share", |d| d.file("doc", "not a dir", false))
+        .symlink("hello", "bin/goodbye")
+        .build()
+        .unwrap();
+    let differences = tree::compare(&nar[..], &other.to_vec()[..], options).unwrap();
+    assert_eq!(
+        differences,
+        vec!["bin/a-tool", "bin/extra", "bin/hello", "hello", "share/doc"]
+    );
+}
+
+#[test]
+fn compares_with_relaxed_options() {
+    let unix = NarTree::builder()
+        .dir("bin", |d| d.file("tool", "#!/bin/sh\n", true))
+        .symlink("sbin", "bin")
+        .dir("lib", |d| {
+            d.symlink("self", "../lib").symlink("abs", "/nix/store")
+        })
+        .build()
+        .unwrap();
+    let windows = NarTree::builder()
+        .dir("bin", |d| d.file("tool", "#!/bin/sh\n", false))
+        .dir("sbin", |d| d.file("tool", "#!/bin/sh\n", false))
+        .dir("lib", |d| {
+            d.symlink("self", ".").symlink("abs", "/nix/store")
+        })
+        .build()
+        .unwrap();
+
+    let mut options = CompareOptions::new();
+    assert_eq!(
+        unix.compare(&windows, options),
+        vec!["bin/tool", "lib/self", "sbin"]
+    );
+
+    options.set_ignore_executable(true);
+    assert_eq!(unix.compare(&windows, options), vec!["lib/self", "sbin"]);
+
+    options.set_resolve_symlinks(true);
+    assert!(unix.compare(&windows, options).is_empty());
+
+    let dangling = NarTree::builder()
+        .symlink("sbin", "missing")
+        .build()
+        .unwrap();
+    let dir = NarTree::builder().dir("sbin", |d| d).build().unwrap();
+    assert_eq!(dangling.compare(&dir, options), vec!["sbin"]);
+}
+
+#[test]
+fn unpacks_changes_as_image_layer() {
+    let base = NarTree::from_bytes(&example_nar()).unwrap();
+    let mut target = base.clone();
+    let bin = target.get_mut("bin").and_then(Node::entries_mut).unwrap();
+    bin.remove("a-tool");
+    bin.insert("hello".into(), file("hello, world"));
+    let root = target.root_mut().entries_mut().unwrap();
+    root.remove("share");
+    root.insert("etc".into(), file("config"));
+
+    let dst = tempfile::tempdir().unwrap();
+    let layer = target.unpack_layer(&base, dst.path()).unwrap();
+    assert_eq!(layer.changed, ["bin/hello", "etc"]);
+    assert_eq!(layer.removed, ["bin/a-tool", "share"]);
+
+    assert_eq!(
+        fs::read(dst.path().join("bin/hello")).unwrap(),
+        b"hello, world"
+    );
+    assert_eq!(fs::read(dst.path().join("etc")).unwrap(), b"config");
+    assert_eq!(fs::read(dst.path().join("bin/.wh.a-tool")).unwrap(), b"");
+    assert!(dst.path().join(".wh.share").is_file());
+    assert!(!dst.path().join("hello").exists());
+
+    let mut reserved = target.clone();
+    let root = reserved.root_mut().entries_mut().unwrap();
+    root.insert(".wh.hello".into(), file(""));
+    let err = reserved.unpack_layer(&base, dst.path()).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn encodes_without_reallocating() {
+    let tree = NarTree::from_bytes(&example_nar()).unwrap();
+    let bytes = tree.to_vec();
+    assert_eq!(bytes, example_nar());
+    assert_eq!(tree.encoded_len(), bytes.len() as u64);
+    assert_eq!(bytes.capacity(), bytes.len());
+
+    let mut writer: Box<dyn std::io::Write> = Box::new(Vec::new());
+    tree.to_writer(&mut *writer).unwrap();
+}
+
+#[test]
+fn resolves_contents_while_writing() {
+    let expected = NarTree::from_bytes(&example_nar()).unwrap();
+    let mut blobs = BTreeMap::new();
+    let mut placeholders = expected.clone();
+    for (path, node) in expected.walk_with_paths() {
+        if let Node::Regular { contents, .. } = node {
+            let key = format!("blob-{}", blobs.len());
+            blobs.insert(key.clone(), contents.clone());
+            match placeholders.get_mut(&path).unwrap() {
+                Node::Regular { contents, .. } => *contents = key.into_bytes(),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    let mut resolved_paths = Vec::new();
+    let mut bytes = Vec::new();
+    placeholders
+        .to_writer_resolving(&mut bytes, |path, key| {
+            resolved_paths.push(path.to_owned());
+            let blob = &blobs[std::str::from_utf8(key).unwrap()];
+            Ok(Some((blob.len() as u64, &blob[..])))
+        })
+        .unwrap();
+    assert_eq!(bytes, expected.to_vec());
+    assert_eq!(
+        resolved_paths,
+        ["bin/a-tool", "bin/hello", "share/doc/README"]
+    );
+
+    let err = placeholders
+        .to_writer_resolving(&mut Vec::new(), |_, _| Ok(Some((100, &b"short"[..]))))
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+}
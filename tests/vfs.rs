@@ -0,0 +1,129 @@
+#![cfg(feature = "fs")]
+
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+use filetime::FileTime;
+use libnar::clock::FixedEpoch;
+use libnar::de::UnpackOptions;
+use libnar::tree::NarTree;
+use libnar::vfs::{FileKind, Filesystem, MemoryFilesystem};
+use libnar::{Archive, Warning};
+
+fn example_nar() -> Vec<u8> {
+    NarTree::builder()
+        .dir("bin", |d| d.file("hello", "hello", true))
+        .file("README", "docs", false)
+        .symlink("link", "bin/hello")
+        .build()
+        .unwrap()
+        .to_vec()
+}
+
+#[test]
+fn unpacks_into_memory() {
+    let fs = MemoryFilesystem::new();
+    let nar = example_nar();
+    let mut archive = Archive::new(&nar[..]);
+    archive.set_filesystem(fs.clone());
+    archive.unpack("/out").unwrap();
+
+    let paths: Vec<PathBuf> = [
+        "/out",
+        "/out/README",
+        "/out/bin",
+        "/out/bin/hello",
+        "/out/link",
+    ]
+    .iter()
+    .map(PathBuf::from)
+    .collect();
+    assert_eq!(fs.paths(), paths);
+    assert_eq!(
+        fs.file(Path::new("/out/bin/hello")),
+        Some((b"hello".to_vec(), true))
+    );
+    assert_eq!(
+        fs.file(Path::new("/out/README")),
+        Some((b"docs".to_vec(), false))
+    );
+    assert_eq!(
+        fs.read_link(Path::new("/out/link")),
+        Some(PathBuf::from("bin/hello"))
+    );
+
+    for path in &paths {
+        let metadata = fs.metadata(path).unwrap();
+        assert_eq!(metadata.modified(), FileTime::zero(), "{:?}", path);
+    }
+    assert_eq!(
+        fs.metadata(Path::new("/out/link")).unwrap().kind(),
+        FileKind::Symlink
+    );
+}
+
+#[test]
+fn canonicalizes_to_configured_epoch() {
+    let fs = MemoryFilesystem::new();
+    let mut options = UnpackOptions::new();
+    options.set_filesystem(fs.clone());
+    options.set_clock(FixedEpoch::from_unix_time(315_532_800));
+    options.unpack(&example_nar()[..], "out").unwrap();
+
+    let expected = FileTime::from_unix_time(315_532_800, 0);
+    for path in fs.paths() {
+        assert_eq!(fs.metadata(&path).unwrap().modified(), expected);
+    }
+
+    let untouched = MemoryFilesystem::new();
+    let mut options = UnpackOptions::new();
+    options.set_filesystem(untouched.clone());
+    options.set_canonicalize_mtime(false);
+    options.unpack(&example_nar()[..], "out").unwrap();
+    let metadata = untouched.metadata(Path::new("out/README")).unwrap();
+    assert_ne!(metadata.modified(), FileTime::zero());
+}
+
+#[test]
+fn removes_xattrs_with_warnings() {
+    let nar = NarTree::builder().build().unwrap().to_vec();
+    let fs = MemoryFilesystem::new();
+    fs.create_dir(Path::new("out")).unwrap();
+    fs.set_xattr(Path::new("out"), OsStr::new("user.origin"), b"download")
+        .unwrap();
+
+    let mut archive = Archive::new(&nar[..]);
+    archive.set_filesystem(fs.clone());
+    archive.unpack("out").unwrap();
+
+    assert!(fs.xattrs(Path::new("out")).unwrap().is_empty());
+    let warnings = archive.take_warnings();
+    assert_eq!(
+        warnings,
+        vec![Warning::XattrDropped {
+            path: PathBuf::from("out"),
+            name: "user.origin".into(),
+        }]
+    );
+}
+
+#[test]
+fn refuses_symlinks_and_rolls_back_in_memory() {
+    let fs = MemoryFilesystem::new();
+    fs.create_dir(Path::new("out")).unwrap();
+    fs.create_symlink(Path::new("out/bin"), Path::new("/etc"))
+        .unwrap();
+
+    let nar = example_nar();
+    let mut archive = Archive::new(&nar[..]);
+    archive.set_filesystem(fs.clone());
+    archive.set_rollback_on_error(true);
+    assert!(archive.unpack("out").is_err());
+
+    let remaining: Vec<PathBuf> = vec!["out".into(), "out/bin".into()];
+    assert_eq!(fs.paths(), remaining);
+    assert_eq!(
+        fs.read_link(Path::new("out/bin")),
+        Some(PathBuf::from("/etc"))
+    );
+}